@@ -13,7 +13,8 @@ use std::sync::{Arc, RwLock};
 
 use libbz2_rs_sys::{
     BZ2_bzRead, BZ2_bzReadClose, BZ2_bzReadGetUnused, BZ2_bzReadOpen, BZ2_bzWrite,
-    BZ2_bzWriteClose64, BZ2_bzWriteOpen, BZ2_bzlibVersion, BZFILE,
+    BZ2_bzWriteClose64, BZ2_bzWriteOpen, BZ2_bzWriteSetBlockCallback, BZ2_bzlibVersion, BlockInfo,
+    BZFILE,
 };
 
 use libc::{
@@ -97,6 +98,7 @@ struct Config {
     // compress
     blockSize100k: i32,
     workFactor: i32,
+    write_index: bool,
 
     // uncompress
     decompress_mode: DecompressMode,
@@ -108,6 +110,7 @@ impl Config {
             OperationMode::Zip => self.with_compress_input(name),
             OperationMode::Unzip => self.with_uncompress_input(name),
             OperationMode::Test => self.with_test_input(name),
+            OperationMode::Index => self.with_index_input(name),
         }
 
         const FILE_NAME_LEN: usize = 1034;
@@ -210,6 +213,16 @@ impl Config {
             (None, SourceMode::F2O | SourceMode::F2F) => panic!("testf: bad modes"),
         }
     }
+
+    fn with_index_input(&mut self, name: Option<&str>) {
+        match (name, self.src_mode) {
+            (Some(name), SourceMode::F2F) => {
+                self.input = Path::new(name).to_owned();
+                self.output = libbz2_rs_sys::index::index_path(&self.input);
+            }
+            _ => panic!("build_index: bad modes"),
+        }
+    }
 }
 
 /// source modes
@@ -229,6 +242,7 @@ enum OperationMode {
     Zip = 1,
     Unzip = 2,
     Test = 3,
+    Index = 4,
 }
 
 /// Strictly for compatibility with the original bzip2 output
@@ -262,11 +276,16 @@ impl std::io::Read for InputStream {
     }
 }
 
+unsafe extern "C" fn record_block(opaque: *mut libc::c_void, info: *const BlockInfo) {
+    (*opaque.cast::<Vec<BlockInfo>>()).push(*info);
+}
+
 fn compressStream(
     config: &Config,
     mut stream: InputStream,
     zStream: CFile,
     metadata: Option<&Metadata>,
+    blocks: Option<&mut Vec<BlockInfo>>,
 ) {
     let mut ibuf: [u8; 5000] = [0; 5000];
     let mut nbytes_in_lo32: u32 = 0;
@@ -298,6 +317,21 @@ fn compressStream(
             break 'errhandler;
         }
 
+        if let Some(blocks) = blocks {
+            unsafe {
+                BZ2_bzWriteSetBlockCallback(
+                    &mut bzerr,
+                    bzf,
+                    Some(record_block),
+                    (blocks as *mut Vec<BlockInfo>).cast(),
+                )
+            };
+
+            if bzerr != libbz2_rs_sys::BZ_OK {
+                break 'errhandler;
+            }
+        }
+
         if config.verbosity >= 2 {
             eprintln!();
         }
@@ -1350,6 +1384,20 @@ fn compress(config: &Config) {
             return;
         }
     }
+    let index_path = libbz2_rs_sys::index::index_path(&config.output);
+    if config.write_index && index_path.exists() {
+        if config.force_overwrite {
+            let _ = std::fs::remove_file(&index_path);
+        } else {
+            eprintln!(
+                "{}: Output file {} already exists.",
+                config.program_name.display(),
+                index_path.display(),
+            );
+            setExit(1);
+            return;
+        }
+    }
 
     if config.src_mode == SourceMode::F2F && !config.force_overwrite {
         match count_hardlinks(&config.input) {
@@ -1463,7 +1511,21 @@ fn compress(config: &Config) {
         pad(config);
     }
     delete_output_on_interrupt.store(true, Ordering::SeqCst);
-    compressStream(config, input_stream, outStr, metadata.as_ref());
+    let mut blocks = Vec::new();
+    let record_blocks = config.write_index.then_some(&mut blocks);
+    compressStream(
+        config,
+        input_stream,
+        outStr,
+        metadata.as_ref(),
+        record_blocks,
+    );
+
+    if config.write_index {
+        if let Err(error) = write_index_file(&index_path, &blocks) {
+            exit_with_io_error(config, error);
+        }
+    }
 
     if let Some(metadata) = metadata {
         if let Err(error) = apply_saved_time_info_to_output_file(&config.output, metadata) {
@@ -1811,6 +1873,93 @@ fn testf(config: &Config) -> bool {
     allOK
 }
 
+fn write_index_file(path: &Path, blocks: &[BlockInfo]) -> io::Result<()> {
+    let mut file = std::fs::File::create_new(path)?;
+    libbz2_rs_sys::index::write_index(&mut file, blocks)?;
+    file.sync_all()
+}
+
+fn build_index(config: &Config) -> bool {
+    if contains_dubious_chars_safe(&config.input) {
+        if config.noisy {
+            eprintln!(
+                "{}: There are no files matching `{}'.",
+                config.program_name.display(),
+                config.input.display(),
+            );
+        }
+        setExit(1);
+        return true;
+    }
+    if config.input.is_dir() {
+        eprintln!(
+            "{}: Input file {} is a directory.",
+            config.program_name.display(),
+            config.input.display(),
+        );
+        setExit(1);
+        return true;
+    }
+    if config.output.exists() {
+        if config.force_overwrite {
+            let _ = std::fs::remove_file(&config.output);
+        } else {
+            eprintln!(
+                "{}: Output file {} already exists.",
+                config.program_name.display(),
+                config.output.display(),
+            );
+            setExit(1);
+            return true;
+        }
+    }
+
+    let file = match std::fs::File::open(&config.input) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!(
+                "{}: Can't open input file {}: {}.",
+                config.program_name.display(),
+                config.input.display(),
+                display_os_error(e),
+            );
+            setExit(1);
+            return true;
+        }
+    };
+
+    if config.verbosity >= 1 {
+        eprint!("  {}: ", config.input.display());
+        pad(config);
+    }
+
+    let blocks = match libbz2_rs_sys::index::scan(file) {
+        Ok(blocks) => blocks,
+        Err(e) => {
+            if config.verbosity >= 1 {
+                eprintln!();
+            }
+            eprintln!(
+                "{}: {}: {}",
+                config.program_name.display(),
+                config.input.display(),
+                e,
+            );
+            return false;
+        }
+    };
+
+    if let Err(error) = write_index_file(&config.output, &blocks) {
+        exit_with_io_error(config, error);
+    }
+
+    if config.verbosity >= 1 {
+        eprintln!("{} blocks", blocks.len());
+    }
+
+    true
+}
+
 const BZLIB_VERSION: &str = unsafe {
     match CStr::from_ptr(BZ2_bzlibVersion()).to_str() {
         Ok(s) => s,
@@ -1861,6 +2010,8 @@ fn usage(full_program_name: &Path) {
             "   -1 .. -9            set block size to 100k .. 900k\n",
             "   --fast              alias for -1\n",
             "   --best              alias for -9\n",
+            "   --index             also write a block index (.idx) when compressing\n",
+            "   --build-index       write block indexes (.idx) for compressed files\n",
             "\n",
             "   If invoked as `bzip2', default action is to compress.\n",
             "              as `bunzip2',  default action is to decompress.\n",
@@ -1908,6 +2059,7 @@ fn main() {
     // compress config
     let mut blockSize100k = 9;
     let mut workFactor = 30;
+    let mut write_index = false;
 
     // uncompress config
     let mut decompress_mode = DecompressMode::Fast;
@@ -2022,6 +2174,8 @@ fn main() {
             "--repetitive-fast" => redundant(program_name, flag_name),
             "--fast" => blockSize100k = 1,
             "--best" => blockSize100k = 9,
+            "--index" => write_index = true,
+            "--build-index" => op_mode = OperationMode::Index,
             "--verbose" => verbosity += 1,
             "--help" => {
                 usage(program_name);
@@ -2057,6 +2211,20 @@ fn main() {
     if src_mode == SourceMode::F2O && num_files_total == 0 {
         src_mode = SourceMode::I2O;
     }
+    if op_mode == OperationMode::Zip && write_index && src_mode != SourceMode::F2F {
+        eprintln!(
+            "{}: --index can only be used when compressing to files.",
+            program_name.display(),
+        );
+        exit(1);
+    }
+    if op_mode == OperationMode::Index && src_mode != SourceMode::F2F {
+        eprintln!(
+            "{}: --build-index needs input files, and cannot be used with -c.",
+            program_name.display(),
+        );
+        exit(1);
+    }
     if op_mode != OperationMode::Zip {
         blockSize100k = 0;
     }
@@ -2083,6 +2251,7 @@ fn main() {
         // compress
         blockSize100k,
         workFactor,
+        write_index,
 
         // uncompress
         decompress_mode,
@@ -2171,6 +2340,26 @@ fn main() {
                 exit(exitValue.load(Ordering::SeqCst));
             }
         }
+        OperationMode::Index => {
+            let mut all_ok = true;
+            decode = true;
+            for name in arg_list {
+                if name == "--" {
+                    decode = false;
+                } else if !(name.starts_with('-') && decode) {
+                    {
+                        let mut config = config.write().unwrap();
+                        config.num_files_processed += 1;
+                        config.with_input(Some(name.as_str()));
+                    }
+                    all_ok &= build_index(&config.read().unwrap());
+                }
+            }
+            if !all_ok {
+                setExit(2);
+                exit(exitValue.load(Ordering::SeqCst));
+            }
+        }
     }
 
     exit(exitValue.load(Ordering::SeqCst))
@@ -5,7 +5,8 @@ use core::ffi::{c_int, c_uint};
 
 use crate::{
     assert_h,
-    bzlib::{Arr2, EState, BZ_N_OVERSHOOT, BZ_N_QSORT, BZ_N_RADIX, FTAB_LEN},
+    bzlib::{Arr2, EState, SortAlgorithm, BZ_N_OVERSHOOT, BZ_N_QSORT, BZ_N_RADIX, FTAB_LEN},
+    sais::sais_sort,
 };
 use crate::{debug_log, debug_logln};
 
@@ -978,7 +979,28 @@ pub(crate) fn block_sort(s: &mut EState) {
     let ptr = s.arr1.ptr();
     let ftab = s.ftab.ftab();
 
-    BZ2_blockSortHelp(ptr, &mut s.arr2, ftab, nblock, s.workFactor, s.verbosity);
+    match s.sortAlgorithm {
+        SortAlgorithm::Classic => {
+            BZ2_blockSortHelp(ptr, &mut s.arr2, ftab, nblock, s.workFactor, s.verbosity)
+        }
+        SortAlgorithm::Sais => {
+            let (block, work) = s.arr2.block_and_work(nblock);
+
+            // small blocks leave little room in arr2, but then ftab is large enough
+            let work = match work.len() >= ftab.len() {
+                true => work,
+                false => &mut ftab[..],
+            };
+
+            if !sais_sort(ptr, block, work, s.verbosity) {
+                if s.verbosity >= 2 {
+                    debug_logln!("    periodic block; using fallback sorting algorithm");
+                }
+
+                fallbackSort(ptr, &mut s.arr2, ftab, nblock as i32, s.verbosity);
+            }
+        }
+    }
 
     s.origPtr = -1 as c_int;
     for i in 0..s.nblock {
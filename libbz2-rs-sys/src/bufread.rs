@@ -0,0 +1,251 @@
+//! # decompressing buffered readers
+//!
+//! Types that decompress bzip2 data read from an underlying [`BufRead`] implementation.
+
+use std::io::{self, BufRead, Read};
+
+use crate::{Decompress, Error, Status};
+
+/// A decoder that decompresses a single bzip2 stream read from `R`.
+///
+/// The decoder only consumes the bytes of `R` that are part of the bzip2 stream: after the end of
+/// the stream has been reached, the data following it can be read from the underlying reader (see
+/// [`BzDecoder::into_inner`]). This makes it possible to decompress a bzip2 stream that is embedded
+/// in a larger container format.
+pub struct BzDecoder<R> {
+    inner: Decoder<R>,
+}
+
+impl<R: BufRead> BzDecoder<R> {
+    /// Creates a new decoder that decompresses the data read from `inner`.
+    pub fn new(inner: R) -> Self {
+        Self::with_small(inner, false)
+    }
+
+    /// Creates a new decoder, using the slower, low-memory decompression algorithm when `small` is
+    /// true.
+    ///
+    /// # Panics
+    ///
+    /// Panics when no default allocator is configured.
+    pub fn with_small(inner: R, small: bool) -> Self {
+        Self {
+            inner: Decoder::new(inner, small, false),
+        }
+    }
+
+    /// Gets a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner.inner
+    }
+
+    /// Gets a mutable reference to the underlying reader.
+    ///
+    /// Reading from the underlying reader directly will likely corrupt the decompressed output,
+    /// unless the end of the stream has been reached.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner.inner
+    }
+
+    /// Consumes the decoder, returning the underlying reader.
+    ///
+    /// When the end of the stream was reached, the reader is positioned at the first byte after
+    /// the stream.
+    pub fn into_inner(self) -> R {
+        self.inner.inner
+    }
+
+    /// Whether the end of the bzip2 stream has been reached.
+    pub fn is_finished(&self) -> bool {
+        self.inner.state == DecoderState::Done
+    }
+
+    /// The number of compressed bytes consumed by the decoder so far.
+    pub fn total_in(&self) -> u64 {
+        self.inner.total_in()
+    }
+
+    /// The number of decompressed bytes produced by the decoder so far.
+    pub fn total_out(&self) -> u64 {
+        self.inner.total_out()
+    }
+}
+
+impl<R: BufRead> Read for BzDecoder<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(out)
+    }
+}
+
+/// A decoder that decompresses a sequence of concatenated bzip2 streams read from `R`, like the
+/// output of `cat a.bz2 b.bz2` or of parallel compressors.
+///
+/// The same rules as the `bzip2` binary are used for data following a stream: when it does not
+/// start with the bzip2 magic bytes, it is ignored as trailing garbage. A stream that is truncated
+/// or corrupted is an error.
+pub struct MultiBzDecoder<R> {
+    inner: Decoder<R>,
+}
+
+impl<R: BufRead> MultiBzDecoder<R> {
+    /// Creates a new decoder that decompresses the data read from `inner`.
+    pub fn new(inner: R) -> Self {
+        Self::with_small(inner, false)
+    }
+
+    /// Creates a new decoder, using the slower, low-memory decompression algorithm when `small` is
+    /// true.
+    ///
+    /// # Panics
+    ///
+    /// Panics when no default allocator is configured.
+    pub fn with_small(inner: R, small: bool) -> Self {
+        Self {
+            inner: Decoder::new(inner, small, true),
+        }
+    }
+
+    /// Gets a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner.inner
+    }
+
+    /// Gets a mutable reference to the underlying reader.
+    ///
+    /// Reading from the underlying reader directly will likely corrupt the decompressed output.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner.inner
+    }
+
+    /// Consumes the decoder, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.inner.inner
+    }
+
+    /// The number of compressed bytes consumed by the decoder so far, over all streams.
+    pub fn total_in(&self) -> u64 {
+        self.inner.total_in()
+    }
+
+    /// The number of decompressed bytes produced by the decoder so far, over all streams.
+    pub fn total_out(&self) -> u64 {
+        self.inner.total_out()
+    }
+}
+
+impl<R: BufRead> Read for MultiBzDecoder<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(out)
+    }
+}
+
+/// The shared implementation of the decoders in this module and in [`crate::read`].
+pub(crate) struct Decoder<R> {
+    pub(crate) inner: R,
+    stream: Decompress,
+    small: bool,
+    multi: bool,
+    state: DecoderState,
+    /// The number of completed streams
+    streams: u64,
+    /// Totals of the completed streams
+    total_in: u64,
+    total_out: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DecoderState {
+    /// In the middle of a stream
+    Stream,
+    /// At the end of a stream; more streams may follow
+    StreamEnd,
+    /// No more output will be produced
+    Done,
+}
+
+fn new_stream(small: bool) -> Decompress {
+    match Decompress::new(small) {
+        Ok(stream) => stream,
+        Err(err) => panic!("failed to initialize the decompression stream: {err}"),
+    }
+}
+
+impl<R: BufRead> Decoder<R> {
+    pub(crate) fn new(inner: R, small: bool, multi: bool) -> Self {
+        Self {
+            inner,
+            stream: new_stream(small),
+            small,
+            multi,
+            state: DecoderState::Stream,
+            streams: 0,
+            total_in: 0,
+            total_out: 0,
+        }
+    }
+
+    pub(crate) fn total_in(&self) -> u64 {
+        self.total_in + self.stream.total_in()
+    }
+
+    pub(crate) fn total_out(&self) -> u64 {
+        self.total_out + self.stream.total_out()
+    }
+
+    pub(crate) fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if out.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            if self.state == DecoderState::Done {
+                return Ok(0);
+            }
+
+            let input = self.inner.fill_buf()?;
+            let eof = input.is_empty();
+
+            if self.state == DecoderState::StreamEnd {
+                if eof {
+                    self.state = DecoderState::Done;
+                    return Ok(0);
+                }
+
+                self.total_in += self.stream.total_in();
+                self.total_out += self.stream.total_out();
+                self.stream = new_stream(self.small);
+                self.state = DecoderState::Stream;
+            }
+
+            let before_in = self.stream.total_in();
+            let before_out = self.stream.total_out();
+            let result = self.stream.decompress(input, out);
+            let consumed = (self.stream.total_in() - before_in) as usize;
+            let produced = (self.stream.total_out() - before_out) as usize;
+
+            self.inner.consume(consumed);
+
+            match result {
+                Err(Error::DataMagic) if self.streams > 0 => {
+                    // like `bzip2`, ignore trailing garbage after the last stream
+                    self.state = DecoderState::Done;
+                    return Ok(0);
+                }
+                Err(err) => return Err(err.into()),
+                Ok(Status::StreamEnd) => {
+                    self.streams += 1;
+                    self.state = match self.multi {
+                        true => DecoderState::StreamEnd,
+                        false => DecoderState::Done,
+                    };
+                }
+                Ok(_) if produced == 0 && eof => return Err(Error::UnexpectedEof.into()),
+                Ok(_) => {}
+            }
+
+            if produced > 0 {
+                return Ok(produced);
+            }
+        }
+    }
+}
@@ -4,9 +4,10 @@ use core::{mem, ptr};
 
 use crate::allocator::Allocator;
 use crate::compress::compress_block;
-use crate::crctable::BZ2_CRC32TABLE;
+use crate::crc32;
 use crate::debug_log;
 use crate::decompress::{self, decompress};
+use crate::huffman;
 #[cfg(feature = "stdio")]
 use crate::libbz2_rs_sys_version;
 
@@ -31,11 +32,19 @@ pub(crate) const BZ_RUNB: u16 = 1;
 
 pub(crate) const BZ_MAX_UNUSED_U32: u32 = 5000;
 
+/// The magic number at the start of every block.
+#[cfg(feature = "std")]
+pub(crate) const BLOCK_MAGIC: u64 = 0x3141_5926_5359;
+
+/// The magic number at the end of every stream, followed by the combined CRC.
+#[cfg(feature = "std")]
+pub(crate) const END_MAGIC: u64 = 0x1772_4538_5090;
+
 #[cfg(doc)]
 use crate::{
     BZ_CONFIG_ERROR, BZ_DATA_ERROR, BZ_DATA_ERROR_MAGIC, BZ_FINISH, BZ_FINISH_OK, BZ_FLUSH,
-    BZ_FLUSH_OK, BZ_IO_ERROR, BZ_MEM_ERROR, BZ_OK, BZ_OUTBUFF_FULL, BZ_PARAM_ERROR, BZ_RUN,
-    BZ_RUN_OK, BZ_SEQUENCE_ERROR, BZ_STREAM_END, BZ_UNEXPECTED_EOF,
+    BZ_FLUSH_OK, BZ_IO_ERROR, BZ_MEM_ERROR, BZ_OK, BZ_OUTBUFF_FULL, BZ_OUTPUT_LIMIT,
+    BZ_PARAM_ERROR, BZ_RUN, BZ_RUN_OK, BZ_SEQUENCE_ERROR, BZ_STREAM_END, BZ_UNEXPECTED_EOF,
 };
 
 #[cfg(feature = "custom-prefix")]
@@ -267,6 +276,14 @@ mod stream {
             unsafe { Allocator::from_bz_stream(self) }
         }
 
+        pub(crate) fn total_in(&self) -> u64 {
+            u64::from(self.total_in_hi32) << 32 | u64::from(self.total_in_lo32)
+        }
+
+        pub(crate) fn total_out(&self) -> u64 {
+            u64::from(self.total_out_hi32) << 32 | u64::from(self.total_out_lo32)
+        }
+
         /// Read up to 7 bytes into the bit buffer.
         ///
         /// The caller is responsible for updating `self.total_in`!
@@ -334,21 +351,6 @@ mod stream {
             Some((bit_buffer, bits_used + 8))
         }
 
-        #[must_use]
-        pub(crate) fn read_byte(&mut self) -> Option<u8> {
-            if self.avail_in == 0 {
-                return None;
-            }
-            let b = unsafe { *(self.next_in as *mut u8) };
-            self.next_in = unsafe { (self.next_in).offset(1) };
-            self.avail_in -= 1;
-            self.total_in_lo32 = (self.total_in_lo32).wrapping_add(1);
-            if self.total_in_lo32 == 0 {
-                self.total_in_hi32 = (self.total_in_hi32).wrapping_add(1);
-            }
-            Some(b)
-        }
-
         #[must_use]
         pub(super) fn write_byte(&mut self, byte: u8) -> bool {
             if self.avail_out == 0 {
@@ -425,6 +427,105 @@ pub(crate) enum ReturnCode {
     BZ_UNEXPECTED_EOF = -7,
     BZ_OUTBUFF_FULL = -8,
     BZ_CONFIG_ERROR = -9,
+    BZ_OUTPUT_LIMIT = -10,
+}
+
+/// The kind of data integrity error that was detected in a compressed stream.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataErrorKind {
+    /// No data integrity error was detected.
+    None = 0,
+    /// The stream does not start with the `BZh` magic bytes and a valid block size.
+    StreamMagic = 1,
+    /// A block header or the end-of-stream marker is invalid.
+    BlockMagic = 2,
+    /// The `origPtr` of a block is out of range.
+    OrigPtr = 3,
+    /// A block does not use any symbols.
+    Mapping = 4,
+    /// The number of Huffman tables of a block is not in `2..=6`.
+    TableCount = 5,
+    /// A block has no selectors.
+    SelectorCount = 6,
+    /// A selector refers to a Huffman table that does not exist, or a block needs more selectors
+    /// than it provides.
+    Selector = 7,
+    /// A Huffman code length is not in `1..=20`.
+    CodeLength = 8,
+    /// The block data contains an invalid Huffman code.
+    HuffmanCode = 9,
+    /// The block data is larger than the block size.
+    BlockOverflow = 10,
+    /// The block data is inconsistent, and cannot be reversed into the original data.
+    BlockData = 11,
+    /// The CRC of a block does not match its decompressed data.
+    BlockCrc = 12,
+    /// The combined CRC of the stream does not match the CRCs of its blocks.
+    CombinedCrc = 13,
+}
+
+/// A detailed description of the last data integrity error detected by the decompressor.
+///
+/// See [`BZ2_bzDecompressGetDataError`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataError {
+    /// What was wrong with the data.
+    pub kind: DataErrorKind,
+    /// The (1-based) number of the block in which the error was detected, or 0 when the error was
+    /// detected before the first block.
+    pub block_number: c_int,
+    /// The offset in bits, relative to the start of the compressed input, at which the error was
+    /// detected.
+    pub bit_offset: u64,
+    /// For CRC mismatches, the CRC stored in the compressed stream. Otherwise 0.
+    pub stored_crc: c_uint,
+    /// For CRC mismatches, the CRC computed from the decompressed data. Otherwise 0.
+    pub computed_crc: c_uint,
+}
+
+impl DataErrorKind {
+    const fn as_str(self) -> &'static str {
+        match self {
+            DataErrorKind::None => "no error",
+            DataErrorKind::StreamMagic => "invalid stream header",
+            DataErrorKind::BlockMagic => "invalid block header",
+            DataErrorKind::OrigPtr => "invalid origPtr",
+            DataErrorKind::Mapping => "empty symbol mapping table",
+            DataErrorKind::TableCount => "invalid number of huffman tables",
+            DataErrorKind::SelectorCount => "invalid number of selectors",
+            DataErrorKind::Selector => "invalid selector",
+            DataErrorKind::CodeLength => "invalid huffman code length",
+            DataErrorKind::HuffmanCode => "invalid huffman code",
+            DataErrorKind::BlockOverflow => "block exceeds the block size",
+            DataErrorKind::BlockData => "inconsistent block data",
+            DataErrorKind::BlockCrc => "block CRC mismatch",
+            DataErrorKind::CombinedCrc => "combined CRC mismatch",
+        }
+    }
+}
+
+impl core::fmt::Display for DataError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} in block {} at bit offset {}",
+            self.kind.as_str(),
+            self.block_number,
+            self.bit_offset
+        )?;
+
+        if let DataErrorKind::BlockCrc | DataErrorKind::CombinedCrc = self.kind {
+            write!(
+                f,
+                " (stored: {:#010x}, computed: {:#010x})",
+                self.stored_crc, self.computed_crc
+            )?;
+        }
+
+        Ok(())
+    }
 }
 
 #[repr(u8)]
@@ -482,6 +583,43 @@ pub(crate) struct EState {
     pub code: [[u32; 258]; 6],
     pub rfreq: [[i32; 258]; 6],
     pub len_pack: [[u32; 4]; 258],
+    pub blockReporter: BlockReporter,
+    pub sortAlgorithm: SortAlgorithm,
+    pub extreme: bool,
+    pub huffmanAlgorithm: HuffmanAlgorithm,
+}
+
+/// Where a block was written in the compressed stream, and what it contains.
+///
+/// See [`BZ2_bzCompressSetBlockCallback`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockInfo {
+    /// The offset in bits of the block header, relative to the start of the compressed stream.
+    pub compressed_bit_offset: u64,
+    /// The offset of the first byte of the block in the uncompressed data.
+    pub uncompressed_offset: u64,
+    /// The number of uncompressed bytes in the block.
+    pub uncompressed_len: c_uint,
+    /// The CRC of the uncompressed data of the block, as stored in the block header.
+    pub block_crc: c_uint,
+    /// The block size of the stream, in `1..=9`.
+    pub block_size100k: c_int,
+}
+
+/// The callback that is called with a [`BlockInfo`] for every block that is written.
+pub(crate) type BlockFunc = unsafe extern "C" fn(*mut c_void, *const BlockInfo);
+
+/// Reports the blocks that the compressor writes to a [`BlockFunc`].
+///
+/// All fields are valid when zeroed, which disables reporting.
+pub(crate) struct BlockReporter {
+    pub(crate) func: Option<BlockFunc>,
+    pub(crate) opaque: *mut c_void,
+    /// The number of input bytes in the blocks that were written so far
+    pub(crate) uncompressed_offset: u64,
+    /// The offset of the current block in the output buffer, set by `compress_block`
+    pub(crate) block_bit_offset: u64,
 }
 
 /// Creates a new pointer that is dangling, but well-aligned.
@@ -582,6 +720,18 @@ impl Arr2 {
 
         (block, quadrant)
     }
+
+    /// The block, and the remainder of the array as scratch space.
+    pub(crate) fn block_and_work(&mut self, nblock: usize) -> (&mut [u8], &mut [u32]) {
+        let start = nblock.div_ceil(4);
+        assert!(start <= self.len);
+
+        let block = unsafe { core::slice::from_raw_parts_mut(self.ptr.cast(), nblock) };
+        let work =
+            unsafe { core::slice::from_raw_parts_mut(self.ptr.add(start), self.len - start) };
+
+        (block, work)
+    }
 }
 
 pub(crate) struct Ftab {
@@ -637,10 +787,17 @@ pub(crate) struct DState {
     pub tt: DSlice<u32>,
     pub ll16: DSlice<u16>,
     pub ll4: DSlice<u8>,
+    /// The block buffers are allocated for at least this block size, see [`crate::workspace`]
+    pub reservedBlockSize100k: u8,
     pub storedBlockCRC: u32,
     pub storedCombinedCRC: u32,
     pub calculatedBlockCRC: u32,
     pub calculatedCombinedCRC: u32,
+    pub dataError: DataError,
+    pub maxOutput: u64,
+    pub maxRatio: u32,
+    pub skipBits: u8,
+    pub singleBlock: bool,
     pub nInUse: u16,
     pub inUse: [bool; 256],
     pub inUse16: [bool; 16],
@@ -653,6 +810,7 @@ pub(crate) struct DState {
     pub limit: [[i32; 258]; 6],
     pub base: [[i32; 258]; 6],
     pub perm: [[u16; 258]; 6],
+    pub lookup: [[u8; 1 << huffman::LOOKUP_BITS]; 6],
     pub minLens: [u8; 6],
     pub save: SaveArea,
 }
@@ -682,6 +840,54 @@ pub(crate) struct SaveArea {
     pub gSel: u8,
 }
 
+impl DState {
+    /// Records the details of a data integrity error, after `strm.total_in` has been updated.
+    pub(crate) fn record_data_error(&mut self, strm: &BzStream<DState>, kind: DataErrorKind) {
+        self.dataError = DataError {
+            kind,
+            block_number: self.currBlockNo,
+            bit_offset: strm.total_in() * 8 - self.bsLive as u64,
+            stored_crc: 0,
+            computed_crc: 0,
+        };
+    }
+
+    fn record_crc_error(&mut self, strm: &BzStream<DState>, kind: DataErrorKind) {
+        let (stored_crc, computed_crc) = match kind {
+            DataErrorKind::BlockCrc => (self.storedBlockCRC, self.calculatedBlockCRC),
+            _ => (self.storedCombinedCRC, self.calculatedCombinedCRC),
+        };
+
+        self.record_data_error(strm, kind);
+        self.dataError.stored_crc = stored_crc;
+        self.dataError.computed_crc = computed_crc;
+    }
+
+    /// Deallocates the buffers that hold the decoded block, with the allocator of the stream.
+    pub(crate) fn deallocate_block_buffers(&mut self, allocator: &Allocator) {
+        // SAFETY: the buffers were allocated with the allocator of the stream
+        unsafe {
+            self.tt.dealloc(allocator);
+            self.ll16.dealloc(allocator);
+            self.ll4.dealloc(allocator);
+        }
+    }
+
+    /// The number of bytes that can still be written before an output limit is exceeded.
+    fn output_budget(&self, strm: &BzStream<DState>) -> u64 {
+        let mut limit = self.maxOutput;
+
+        if self.maxRatio != 0 {
+            limit = Ord::min(
+                limit,
+                strm.total_in().saturating_mul(u64::from(self.maxRatio)),
+            );
+        }
+
+        limit.saturating_sub(strm.total_out())
+    }
+}
+
 pub(crate) struct DSlice<T> {
     ptr: *mut T,
     len: usize,
@@ -711,6 +917,11 @@ impl<T> DSlice<T> {
         unsafe { core::slice::from_raw_parts(self.ptr, self.len) }
     }
 
+    /// The size of the allocation in bytes.
+    fn size(&self) -> usize {
+        self.len * mem::size_of::<T>()
+    }
+
     pub(crate) fn as_mut_slice(&mut self) -> &mut [T] {
         unsafe { core::slice::from_raw_parts_mut(self.ptr, self.len) }
     }
@@ -720,7 +931,7 @@ const _C_INT_SIZE: () = assert!(core::mem::size_of::<core::ffi::c_int>() == 4);
 const _C_SHORT_SIZE: () = assert!(core::mem::size_of::<core::ffi::c_short>() == 2);
 const _C_CHAR_SIZE: () = assert!(core::mem::size_of::<core::ffi::c_char>() == 1);
 
-fn prepare_new_block(s: &mut EState) {
+pub(crate) fn prepare_new_block(s: &mut EState) {
     s.nblock = 0;
     s.writer.num_z = 0;
     s.state_out_pos = 0;
@@ -729,12 +940,12 @@ fn prepare_new_block(s: &mut EState) {
     s.blockNo += 1;
 }
 
-fn init_rl(s: &mut EState) {
+pub(crate) fn init_rl(s: &mut EState) {
     s.state_in_ch = 256 as c_int as u32;
     s.state_in_len = 0 as c_int;
 }
 
-fn isempty_rl(s: &mut EState) -> bool {
+fn isempty_rl(s: &EState) -> bool {
     !(s.state_in_ch < 256 && s.state_in_len > 0)
 }
 
@@ -804,7 +1015,7 @@ pub(crate) fn BZ2_bzCompressInitHelp(
     let arr1_len = n as usize;
     let arr1 = Arr1::alloc(&allocator, arr1_len);
 
-    let arr2_len = n as usize + (2 + 12 + 18 + 2);
+    let arr2_len = n as usize + BZ_N_OVERSHOOT;
     let arr2 = Arr2::alloc(&allocator, arr2_len);
 
     let ftab = Ftab::alloc(&allocator);
@@ -843,12 +1054,6 @@ pub(crate) fn BZ2_bzCompressInitHelp(
     // then it is set to zero.
     let s = unsafe { &mut *s };
 
-    s.blockNo = 0;
-    s.state = State::Output;
-    s.mode = Mode::Running;
-    s.combinedCRC = 0;
-    s.blockSize100k = blockSize100k;
-    s.nblockMAX = 100000 * blockSize100k - 19;
     s.verbosity = verbosity;
     s.workFactor = workFactor;
 
@@ -857,26 +1062,29 @@ pub(crate) fn BZ2_bzCompressInitHelp(
     strm.total_out_lo32 = 0;
     strm.total_out_hi32 = 0;
 
-    init_rl(s);
-    prepare_new_block(s);
+    start_stream(s, blockSize100k);
 
     ReturnCode::BZ_OK
 }
 
-macro_rules! BZ_UPDATE_CRC {
-    ($crcVar:expr, $cha:expr) => {
-        let index = ($crcVar >> 24) ^ ($cha as core::ffi::c_uint);
-        $crcVar = ($crcVar << 8) ^ BZ2_CRC32TABLE[index as usize];
-    };
+/// Prepares the compressor for a new stream with the given block size.
+fn start_stream(s: &mut EState, blockSize100k: c_int) {
+    s.blockNo = 0;
+    s.state = State::Output;
+    s.mode = Mode::Running;
+    s.avail_in_expect = 0;
+    s.combinedCRC = 0;
+    s.blockSize100k = blockSize100k;
+    s.nblockMAX = 100000 * blockSize100k - 19;
+    s.blockReporter.uncompressed_offset = 0;
+
+    init_rl(s);
+    prepare_new_block(s);
 }
 
 fn add_pair_to_block(s: &mut EState) {
     let ch: u8 = s.state_in_ch as u8;
 
-    for _ in 0..s.state_in_len {
-        BZ_UPDATE_CRC!(s.blockCRC, ch);
-    }
-
     let block = s.arr2.raw_block();
     s.inUse[s.state_in_ch as usize] = true;
     match s.state_in_len {
@@ -904,8 +1112,9 @@ fn add_pair_to_block(s: &mut EState) {
     };
 }
 
-fn flush_rl(s: &mut EState) {
+pub(crate) fn flush_rl(s: &mut EState) {
     if s.state_in_ch < 256 {
+        s.blockCRC = crc32::update_run(s.blockCRC, s.state_in_ch as u8, s.state_in_len as usize);
         add_pair_to_block(s);
     }
     init_rl(s);
@@ -919,7 +1128,6 @@ macro_rules! ADD_CHAR_TO_BLOCK {
             /*-- fast track the common case --*/
 
             let ch: u8 = $zs.state_in_ch as u8;
-            BZ_UPDATE_CRC!($zs.blockCRC, ch);
             $zs.inUse[$zs.state_in_ch as usize] = true;
             $zs.arr2.raw_block()[$zs.nblock as usize] = ch;
             $zs.nblock += 1;
@@ -939,38 +1147,65 @@ macro_rules! ADD_CHAR_TO_BLOCK {
     };
 }
 
+/// Adds bytes from `input` to the block until it is full, and returns the number of bytes that
+/// were consumed. Also used by the parallel compressor, which fills the blocks itself.
+///
+/// The block CRC covers the bytes that have been added to the block, but not the run that is
+/// still pending, which may end up in the next block. The added bytes are the run that was pending
+/// before, followed by the consumed input, minus the run that is pending now, so the CRC is
+/// computed over that part of `input` at once.
+pub(crate) fn fill_block(s: &mut EState, input: &[u8]) -> usize {
+    let pending_len = |s: &EState| match isempty_rl(s) {
+        true => 0,
+        false => s.state_in_len as usize,
+    };
+
+    let (run_ch, run_len) = (s.state_in_ch as u8, pending_len(s));
+
+    let mut consumed = 0;
+    for &b in input {
+        if s.nblock >= s.nblockMAX {
+            break;
+        }
+        ADD_CHAR_TO_BLOCK!(s, b as u32);
+        consumed += 1;
+    }
+
+    let added = run_len + consumed - pending_len(s);
+    let from_run = Ord::min(run_len, added);
+    s.blockCRC = crc32::update_run(s.blockCRC, run_ch, from_run);
+    s.blockCRC = crc32::update(s.blockCRC, &input[..added - from_run]);
+
+    consumed
+}
+
 fn copy_input_until_stop(strm: &mut BzStream<EState>, s: &mut EState) -> bool {
-    let mut progress_in = false;
+    let avail_in = match s.mode {
+        Mode::Running => strm.avail_in,
+        Mode::Idle | Mode::Flushing | Mode::Finishing => Ord::min(strm.avail_in, s.avail_in_expect),
+    };
 
-    match s.mode {
-        Mode::Running => loop {
-            if s.nblock >= s.nblockMAX {
-                break;
-            }
-            if let Some(b) = strm.read_byte() {
-                progress_in = true;
-                ADD_CHAR_TO_BLOCK!(s, b as u32);
-            } else {
-                break;
-            }
-        },
-        Mode::Idle | Mode::Flushing | Mode::Finishing => loop {
-            if s.nblock >= s.nblockMAX {
-                break;
-            }
-            if s.avail_in_expect == 0 {
-                break;
-            }
-            if let Some(b) = strm.read_byte() {
-                progress_in = true;
-                ADD_CHAR_TO_BLOCK!(s, b as u32);
-            } else {
-                break;
-            }
-            s.avail_in_expect -= 1;
-        },
+    if avail_in == 0 {
+        return false;
+    }
+
+    // SAFETY: the caller guarantees that `next_in` points to `avail_in` readable bytes
+    let input =
+        unsafe { core::slice::from_raw_parts(strm.next_in.cast::<u8>(), avail_in as usize) };
+    let consumed = fill_block(s, input);
+
+    strm.next_in = unsafe { strm.next_in.add(consumed) };
+    strm.avail_in -= consumed as c_uint;
+
+    let old_total_in_lo32 = strm.total_in_lo32;
+    strm.total_in_lo32 = strm.total_in_lo32.wrapping_add(consumed as c_uint);
+    strm.total_in_hi32 += (strm.total_in_lo32 < old_total_in_lo32) as u32;
+
+    if !matches!(s.mode, Mode::Running) {
+        s.avail_in_expect -= consumed as c_uint;
     }
-    progress_in
+
+    consumed > 0
 }
 
 fn copy_output_until_stop(strm: &mut BzStream<EState>, s: &mut EState) -> bool {
@@ -1017,10 +1252,10 @@ fn handle_compress(strm: &mut BzStream<EState>, s: &mut EState) -> bool {
         if !matches!(s.mode, Mode::Running) && s.avail_in_expect == 0 {
             flush_rl(s);
             let is_last_block = matches!(s.mode, Mode::Finishing);
-            compress_block(s, is_last_block);
+            compress_and_report_block(strm, s, is_last_block);
             s.state = State::Input;
         } else if s.nblock >= s.nblockMAX {
-            compress_block(s, false);
+            compress_and_report_block(strm, s, false);
             s.state = State::Input;
         } else if strm.avail_in == 0 {
             break;
@@ -1030,9 +1265,46 @@ fn handle_compress(strm: &mut BzStream<EState>, s: &mut EState) -> bool {
     progress_in || progress_out
 }
 
-pub(crate) enum Action {
+/// Compresses the current block, and reports it to the block callback.
+fn compress_and_report_block(strm: &BzStream<EState>, s: &mut EState, is_last_block: bool) {
+    // a run that is not yet flushed is part of the next block
+    let pending = match isempty_rl(s) {
+        true => 0,
+        false => s.state_in_len as u64,
+    };
+
+    // all output of the previous block has been copied out at this point
+    let compressed_offset = strm.total_out();
+    let uncompressed_end = strm.total_in() - pending;
+    let has_data = s.nblock > 0;
+
+    compress_block(s, is_last_block);
+
+    let reporter = &mut s.blockReporter;
+    if let (true, Some(func)) = (has_data, reporter.func) {
+        let info = BlockInfo {
+            compressed_bit_offset: compressed_offset * 8 + reporter.block_bit_offset,
+            uncompressed_offset: reporter.uncompressed_offset,
+            uncompressed_len: (uncompressed_end - reporter.uncompressed_offset) as c_uint,
+            block_crc: s.blockCRC,
+            block_size100k: s.blockSize100k,
+        };
+
+        // SAFETY: the caller of `BZ2_bzCompressSetBlockCallback` guarantees that `func` can be called
+        unsafe { func(reporter.opaque, &info) };
+    }
+
+    reporter.uncompressed_offset = uncompressed_end;
+}
+
+/// The action to perform when calling [`BZ2_bzCompress`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Compress the input, and produce output when a block is complete. Corresponds to [`BZ_RUN`].
     Run = 0,
+    /// Finish the current block, and write all compressed data to the output. Corresponds to [`BZ_FLUSH`].
     Flush = 1,
+    /// Finish the stream, writing the end-of-stream marker. Corresponds to [`BZ_FINISH`].
     Finish = 2,
 }
 
@@ -1049,6 +1321,69 @@ impl TryFrom<i32> for Action {
     }
 }
 
+/// The algorithm that sorts the rotations of a block, see [`BZ2_bzCompressSetSortAlgorithm`].
+///
+/// All algorithms produce exactly the same compressed output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortAlgorithm {
+    /// The algorithm of the original bzip2, which switches to a slower algorithm when a block
+    /// turns out to be very repetitive, as controlled by the work factor. This is the default,
+    /// because it is as fast or faster on typical (binary and text) data, as measured by the
+    /// `compress-rs-sais` benchmark. Corresponds to [`BZ_SORT_CLASSIC`].
+    ///
+    /// [`BZ_SORT_CLASSIC`]: crate::BZ_SORT_CLASSIC
+    #[default]
+    Classic = 0,
+    /// A linear-time suffix sorting algorithm (SA-IS), which is much faster on repetitive blocks.
+    /// The work factor is ignored. Blocks that consist of a repetition of a shorter string still
+    /// use the slower algorithm of [`SortAlgorithm::Classic`]. Corresponds to [`BZ_SORT_SAIS`].
+    ///
+    /// [`BZ_SORT_SAIS`]: crate::BZ_SORT_SAIS
+    Sais = 1,
+}
+
+impl TryFrom<i32> for SortAlgorithm {
+    type Error = ();
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Classic),
+            1 => Ok(Self::Sais),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The algorithm that computes the lengths of the Huffman codes, see
+/// [`BZ2_bzCompressSetHuffmanAlgorithm`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HuffmanAlgorithm {
+    /// The algorithm of the original bzip2, which builds a Huffman tree, and when a code is longer
+    /// than the maximum length, flattens the frequencies and tries again. Corresponds to
+    /// [`BZ_HUFFMAN_CLASSIC`].
+    ///
+    /// [`BZ_HUFFMAN_CLASSIC`]: crate::BZ_HUFFMAN_CLASSIC
+    #[default]
+    Classic = 0,
+    /// The package-merge algorithm, which computes the optimal code lengths under the maximum
+    /// length. Corresponds to [`BZ_HUFFMAN_PACKAGE_MERGE`].
+    ///
+    /// [`BZ_HUFFMAN_PACKAGE_MERGE`]: crate::BZ_HUFFMAN_PACKAGE_MERGE
+    PackageMerge = 1,
+}
+
+impl TryFrom<i32> for HuffmanAlgorithm {
+    type Error = ();
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Classic),
+            1 => Ok(Self::PackageMerge),
+            _ => Err(()),
+        }
+    }
+}
+
 /// Compresses as much data as possible, and stops when the input buffer becomes empty or the output buffer becomes full.
 ///
 /// # Returns
@@ -1167,14 +1502,18 @@ fn compress_loop(strm: &mut BzStream<EState>, s: &mut EState, action: i32) -> Re
     }
 }
 
-/// Deallocates all dynamically allocated data structures for this stream.
+/// Sets a function that is called for every block that the compressor writes.
+///
+/// When a block has been written, `callback` is called with `opaque` and a description of the
+/// block, which is only valid for the duration of the call. The descriptions can be used to build
+/// an index of the compressed data, that makes it possible to start decompressing at any block.
+/// Passing `None` for `callback` stops reporting blocks.
 ///
 /// # Returns
 ///
 /// - [`BZ_PARAM_ERROR`] if any of
 ///     - `strm.is_null()`
 ///     - `strm.s.is_null()`
-///     - no [valid allocator](bz_stream#custom-allocators) could be configured
 /// - [`BZ_OK`] otherwise
 ///
 /// # Safety
@@ -1182,103 +1521,441 @@ fn compress_loop(strm: &mut BzStream<EState>, s: &mut EState, action: i32) -> Re
 /// * Either
 ///     - `strm` is `NULL`
 ///     - `strm` satisfies the requirements of `&mut *strm` and was initialized with [`BZ2_bzCompressInit`]
-#[export_name = prefix!(BZ2_bzCompressEnd)]
-pub unsafe extern "C" fn BZ2_bzCompressEnd(strm: *mut bz_stream) -> c_int {
+/// * Either
+///     - `callback` is `None`
+///     - `callback` can be called with `opaque` until it is replaced, or the stream is ended
+#[export_name = prefix!(BZ2_bzCompressSetBlockCallback)]
+pub unsafe extern "C" fn BZ2_bzCompressSetBlockCallback(
+    strm: *mut bz_stream,
+    callback: Option<unsafe extern "C" fn(opaque: *mut c_void, info: *const BlockInfo)>,
+    opaque: *mut c_void,
+) -> c_int {
     let Some(strm) = (unsafe { BzStream::from_ptr(strm) }) else {
         return ReturnCode::BZ_PARAM_ERROR as c_int;
     };
-    BZ2_bzCompressEndHelp(strm)
+    BZ2_bzCompressSetBlockCallbackHelp(strm, callback, opaque) as c_int
 }
 
-fn BZ2_bzCompressEndHelp(strm: &mut BzStream<EState>) -> c_int {
+pub(crate) fn BZ2_bzCompressSetBlockCallbackHelp(
+    strm: &mut BzStream<EState>,
+    callback: Option<BlockFunc>,
+    opaque: *mut c_void,
+) -> ReturnCode {
     let Some(s) = (unsafe { strm.state.as_mut() }) else {
-        return ReturnCode::BZ_PARAM_ERROR as c_int;
+        return ReturnCode::BZ_PARAM_ERROR;
     };
 
     // FIXME use .addr() once stable
     if s.strm_addr != strm as *mut _ as usize {
-        return ReturnCode::BZ_PARAM_ERROR as c_int;
+        return ReturnCode::BZ_PARAM_ERROR;
     }
 
-    let Some(allocator) = strm.allocator() else {
+    s.blockReporter.func = callback;
+    s.blockReporter.opaque = opaque;
+
+    ReturnCode::BZ_OK
+}
+
+/// Selects the algorithm that sorts the rotations of the blocks that are compressed from now on.
+///
+/// - [`BZ_SORT_CLASSIC`] (the default) is the algorithm of the original bzip2. It is fast on
+///   typical data, but can be many times slower on very repetitive blocks.
+/// - [`BZ_SORT_SAIS`] is a linear-time suffix sorting algorithm, which is much faster on
+///   repetitive blocks, and somewhat slower on typical data.
+///
+/// The compressed output is exactly the same for both algorithms.
+///
+/// # Returns
+///
+/// - [`BZ_PARAM_ERROR`] if any of
+///     - `strm.is_null()`
+///     - `strm.s.is_null()`
+///     - `algorithm` is not one of the values listed above
+/// - [`BZ_OK`] otherwise
+///
+/// # Safety
+///
+/// * Either
+///     - `strm` is `NULL`
+///     - `strm` satisfies the requirements of `&mut *strm` and was initialized with [`BZ2_bzCompressInit`]
+///
+/// [`BZ_SORT_CLASSIC`]: crate::BZ_SORT_CLASSIC
+/// [`BZ_SORT_SAIS`]: crate::BZ_SORT_SAIS
+#[export_name = prefix!(BZ2_bzCompressSetSortAlgorithm)]
+pub unsafe extern "C" fn BZ2_bzCompressSetSortAlgorithm(
+    strm: *mut bz_stream,
+    algorithm: c_int,
+) -> c_int {
+    let Some(strm) = (unsafe { BzStream::from_ptr(strm) }) else {
+        return ReturnCode::BZ_PARAM_ERROR as c_int;
+    };
+    let Ok(algorithm) = SortAlgorithm::try_from(algorithm) else {
         return ReturnCode::BZ_PARAM_ERROR as c_int;
     };
+    BZ2_bzCompressSetSortAlgorithmHelp(strm, algorithm) as c_int
+}
 
-    unsafe {
-        s.arr1.dealloc(&allocator);
-        s.arr2.dealloc(&allocator);
-        s.ftab.dealloc(&allocator);
-    }
+pub(crate) fn BZ2_bzCompressSetSortAlgorithmHelp(
+    strm: &mut BzStream<EState>,
+    algorithm: SortAlgorithm,
+) -> ReturnCode {
+    let Some(s) = (unsafe { strm.state.as_mut() }) else {
+        return ReturnCode::BZ_PARAM_ERROR;
+    };
 
-    unsafe {
-        allocator.deallocate(strm.state.cast::<EState>(), 1);
+    // FIXME use .addr() once stable
+    if s.strm_addr != strm as *mut _ as usize {
+        return ReturnCode::BZ_PARAM_ERROR;
     }
-    strm.state = ptr::null_mut::<EState>();
 
-    ReturnCode::BZ_OK as c_int
-}
+    s.sortAlgorithm = algorithm;
 
-pub(crate) enum DecompressMode {
-    Small,
-    Fast,
+    ReturnCode::BZ_OK
 }
 
-/// Prepares the stream for decompression.
+/// Enables or disables the extreme mode for the blocks that are compressed from now on.
+///
+/// In extreme mode, the encoder spends more time searching for the Huffman coding tables that
+/// encode a block in the fewest bits: it tries every number of tables, refines them until the
+/// size stops improving, and takes the cost of switching tables into account. Compression is
+/// a few times slower, and the output is at most as large as in the default mode. The output is
+/// a standard bzip2 stream, that any bzip2 decompressor can decode.
+///
+/// `extreme` is interpreted as a boolean, where `0` (the default) disables the extreme mode.
 ///
 /// # Returns
 ///
 /// - [`BZ_PARAM_ERROR`] if any of
 ///     - `strm.is_null()`
-///     - `!(0..=1).contains(&small)`
-///     - `!(0..=4).contains(&verbosity)`
-///     - no [valid allocator](bz_stream#custom-allocators) could be configured
-/// - [`BZ_MEM_ERROR`] if insufficient memory is available
+///     - `strm.s.is_null()`
 /// - [`BZ_OK`] otherwise
 ///
 /// # Safety
 ///
-/// The caller must guarantee that
-///
 /// * Either
 ///     - `strm` is `NULL`
-///     - `strm` satisfies the requirements of `&mut *strm`
-/// * The `bzalloc`, `bzfree` and `opaque` fields form a [valid allocator](bz_stream#custom-allocators).
-#[export_name = prefix!(BZ2_bzDecompressInit)]
-pub unsafe extern "C" fn BZ2_bzDecompressInit(
-    strm: *mut bz_stream,
-    verbosity: c_int,
-    small: c_int,
-) -> c_int {
+///     - `strm` satisfies the requirements of `&mut *strm` and was initialized with [`BZ2_bzCompressInit`]
+#[export_name = prefix!(BZ2_bzCompressSetExtreme)]
+pub unsafe extern "C" fn BZ2_bzCompressSetExtreme(strm: *mut bz_stream, extreme: c_int) -> c_int {
     let Some(strm) = (unsafe { BzStream::from_ptr(strm) }) else {
         return ReturnCode::BZ_PARAM_ERROR as c_int;
     };
-    BZ2_bzDecompressInitHelp(strm, verbosity, small) as c_int
+    BZ2_bzCompressSetExtremeHelp(strm, extreme != 0) as c_int
 }
 
-pub(crate) fn BZ2_bzDecompressInitHelp(
-    strm: &mut BzStream<DState>,
-    verbosity: c_int,
-    small: c_int,
+pub(crate) fn BZ2_bzCompressSetExtremeHelp(
+    strm: &mut BzStream<EState>,
+    extreme: bool,
 ) -> ReturnCode {
-    let decompress_mode = match small {
-        0 => DecompressMode::Fast,
-        1 => DecompressMode::Small,
-        _ => return ReturnCode::BZ_PARAM_ERROR,
+    let Some(s) = (unsafe { strm.state.as_mut() }) else {
+        return ReturnCode::BZ_PARAM_ERROR;
     };
-    if !(0..=4).contains(&verbosity) {
+
+    // FIXME use .addr() once stable
+    if s.strm_addr != strm as *mut _ as usize {
         return ReturnCode::BZ_PARAM_ERROR;
     }
 
-    // return a param error when no [valid allocator](bz_stream#custom-allocators) could be configured
-    let Some(allocator) = configure_allocator(strm) else {
-        return ReturnCode::BZ_PARAM_ERROR;
-    };
+    s.extreme = extreme;
 
-    let Some(s) = allocator.allocate_zeroed::<DState>(1) else {
-        return ReturnCode::BZ_MEM_ERROR;
-    };
+    ReturnCode::BZ_OK
+}
 
-    // this `s.strm` pointer should _NEVER_ be used! it exists just as a consistency check to ensure
+/// Selects the algorithm that computes the lengths of the Huffman codes of the blocks that are
+/// compressed from now on.
+///
+/// - [`BZ_HUFFMAN_CLASSIC`] (the default) is the heuristic of the original bzip2, which is not
+///   optimal when a code would be longer than the maximum of 17 bits.
+/// - [`BZ_HUFFMAN_PACKAGE_MERGE`] computes the optimal lengths under the maximum length, with the
+///   package-merge algorithm. The tables are chosen like with [`BZ_HUFFMAN_CLASSIC`], and then
+///   replaced by the optimal lengths where that saves bits, so the output is never larger.
+///
+/// The output is a standard bzip2 stream in both cases.
+///
+/// # Returns
+///
+/// - [`BZ_PARAM_ERROR`] if any of
+///     - `strm.is_null()`
+///     - `strm.s.is_null()`
+///     - `algorithm` is not one of the values listed above
+/// - [`BZ_OK`] otherwise
+///
+/// # Safety
+///
+/// * Either
+///     - `strm` is `NULL`
+///     - `strm` satisfies the requirements of `&mut *strm` and was initialized with [`BZ2_bzCompressInit`]
+///
+/// [`BZ_HUFFMAN_CLASSIC`]: crate::BZ_HUFFMAN_CLASSIC
+/// [`BZ_HUFFMAN_PACKAGE_MERGE`]: crate::BZ_HUFFMAN_PACKAGE_MERGE
+#[export_name = prefix!(BZ2_bzCompressSetHuffmanAlgorithm)]
+pub unsafe extern "C" fn BZ2_bzCompressSetHuffmanAlgorithm(
+    strm: *mut bz_stream,
+    algorithm: c_int,
+) -> c_int {
+    let Some(strm) = (unsafe { BzStream::from_ptr(strm) }) else {
+        return ReturnCode::BZ_PARAM_ERROR as c_int;
+    };
+    let Ok(algorithm) = HuffmanAlgorithm::try_from(algorithm) else {
+        return ReturnCode::BZ_PARAM_ERROR as c_int;
+    };
+    BZ2_bzCompressSetHuffmanAlgorithmHelp(strm, algorithm) as c_int
+}
+
+pub(crate) fn BZ2_bzCompressSetHuffmanAlgorithmHelp(
+    strm: &mut BzStream<EState>,
+    algorithm: HuffmanAlgorithm,
+) -> ReturnCode {
+    let Some(s) = (unsafe { strm.state.as_mut() }) else {
+        return ReturnCode::BZ_PARAM_ERROR;
+    };
+
+    // FIXME use .addr() once stable
+    if s.strm_addr != strm as *mut _ as usize {
+        return ReturnCode::BZ_PARAM_ERROR;
+    }
+
+    s.huffmanAlgorithm = algorithm;
+
+    ReturnCode::BZ_OK
+}
+
+/// Prepares the stream for compressing a new stream, without reallocating its buffers.
+///
+/// Any input and output of the current stream that has not been processed yet is discarded, and
+/// the running totals are set to zero. The parameters of the stream are kept, except that the new
+/// stream uses a block size of `blockSize100k * 100k` when `blockSize100k` is not 0. That block
+/// size cannot be larger than the block size that the stream was initialized with.
+///
+/// # Returns
+///
+/// - [`BZ_PARAM_ERROR`] if any of
+///     - `strm.is_null()`
+///     - `strm.s.is_null()`
+///     - `!(0..=9).contains(&blockSize100k)`
+///     - `blockSize100k` is larger than the block size that the stream was initialized with
+/// - [`BZ_OK`] otherwise
+///
+/// # Safety
+///
+/// * Either
+///     - `strm` is `NULL`
+///     - `strm` satisfies the requirements of `&mut *strm` and was initialized with [`BZ2_bzCompressInit`]
+#[export_name = prefix!(BZ2_bzCompressReset)]
+pub unsafe extern "C" fn BZ2_bzCompressReset(strm: *mut bz_stream, blockSize100k: c_int) -> c_int {
+    let Some(strm) = (unsafe { BzStream::from_ptr(strm) }) else {
+        return ReturnCode::BZ_PARAM_ERROR as c_int;
+    };
+    BZ2_bzCompressResetHelp(strm, blockSize100k) as c_int
+}
+
+pub(crate) fn BZ2_bzCompressResetHelp(
+    strm: &mut BzStream<EState>,
+    blockSize100k: c_int,
+) -> ReturnCode {
+    let Some(s) = (unsafe { strm.state.as_mut() }) else {
+        return ReturnCode::BZ_PARAM_ERROR;
+    };
+
+    // FIXME use .addr() once stable
+    if s.strm_addr != strm as *mut _ as usize {
+        return ReturnCode::BZ_PARAM_ERROR;
+    }
+
+    let blockSize100k = match blockSize100k {
+        0 => s.blockSize100k,
+        n => n,
+    };
+
+    // the buffers were allocated for the block size that the stream was initialized with
+    let capacity100k = (s.arr1.len / 100000) as c_int;
+    if !(1..=capacity100k).contains(&blockSize100k) {
+        return ReturnCode::BZ_PARAM_ERROR;
+    }
+
+    strm.total_in_lo32 = 0;
+    strm.total_in_hi32 = 0;
+    strm.total_out_lo32 = 0;
+    strm.total_out_hi32 = 0;
+
+    start_stream(s, blockSize100k);
+
+    ReturnCode::BZ_OK
+}
+
+/// Prepares the stream for decompressing a new stream, without reallocating its buffers.
+///
+/// Any input and output of the current stream that has not been processed yet is discarded, and
+/// the running totals are set to zero. The parameters of the stream, including the limits of
+/// [`BZ2_bzDecompressInitWithLimits`], are kept. The buffers for the decompressed blocks are only
+/// reallocated when the new stream uses a larger block size.
+///
+/// # Returns
+///
+/// - [`BZ_PARAM_ERROR`] if any of
+///     - `strm.is_null()`
+///     - `strm.s.is_null()`
+/// - [`BZ_OK`] otherwise
+///
+/// # Safety
+///
+/// * Either
+///     - `strm` is `NULL`
+///     - `strm` satisfies the requirements of `&mut *strm` and was initialized with [`BZ2_bzDecompressInit`]
+#[export_name = prefix!(BZ2_bzDecompressReset)]
+pub unsafe extern "C" fn BZ2_bzDecompressReset(strm: *mut bz_stream) -> c_int {
+    let Some(strm) = (unsafe { BzStream::from_ptr(strm) }) else {
+        return ReturnCode::BZ_PARAM_ERROR as c_int;
+    };
+    BZ2_bzDecompressResetHelp(strm) as c_int
+}
+
+pub(crate) fn BZ2_bzDecompressResetHelp(strm: &mut BzStream<DState>) -> ReturnCode {
+    let Some(s) = (unsafe { strm.state.as_mut() }) else {
+        return ReturnCode::BZ_PARAM_ERROR;
+    };
+
+    // FIXME use .addr() once stable
+    if s.strm_addr != strm as *mut _ as usize {
+        return ReturnCode::BZ_PARAM_ERROR;
+    }
+
+    s.state = decompress::State::BZ_X_MAGIC_1;
+    s.bsLive = 0;
+    s.bsBuff = 0;
+    s.calculatedCombinedCRC = 0;
+    s.currBlockNo = 0;
+    s.skipBits = 0;
+    s.singleBlock = false;
+    s.save = SaveArea::default();
+    s.dataError = DataError {
+        kind: DataErrorKind::None,
+        block_number: 0,
+        bit_offset: 0,
+        stored_crc: 0,
+        computed_crc: 0,
+    };
+
+    strm.total_in_lo32 = 0;
+    strm.total_in_hi32 = 0;
+    strm.total_out_lo32 = 0;
+    strm.total_out_hi32 = 0;
+
+    ReturnCode::BZ_OK
+}
+
+/// Deallocates all dynamically allocated data structures for this stream.
+///
+/// # Returns
+///
+/// - [`BZ_PARAM_ERROR`] if any of
+///     - `strm.is_null()`
+///     - `strm.s.is_null()`
+///     - no [valid allocator](bz_stream#custom-allocators) could be configured
+/// - [`BZ_OK`] otherwise
+///
+/// # Safety
+///
+/// * Either
+///     - `strm` is `NULL`
+///     - `strm` satisfies the requirements of `&mut *strm` and was initialized with [`BZ2_bzCompressInit`]
+#[export_name = prefix!(BZ2_bzCompressEnd)]
+pub unsafe extern "C" fn BZ2_bzCompressEnd(strm: *mut bz_stream) -> c_int {
+    let Some(strm) = (unsafe { BzStream::from_ptr(strm) }) else {
+        return ReturnCode::BZ_PARAM_ERROR as c_int;
+    };
+    BZ2_bzCompressEndHelp(strm)
+}
+
+pub(crate) fn BZ2_bzCompressEndHelp(strm: &mut BzStream<EState>) -> c_int {
+    let Some(s) = (unsafe { strm.state.as_mut() }) else {
+        return ReturnCode::BZ_PARAM_ERROR as c_int;
+    };
+
+    // FIXME use .addr() once stable
+    if s.strm_addr != strm as *mut _ as usize {
+        return ReturnCode::BZ_PARAM_ERROR as c_int;
+    }
+
+    let Some(allocator) = strm.allocator() else {
+        return ReturnCode::BZ_PARAM_ERROR as c_int;
+    };
+
+    unsafe {
+        s.arr1.dealloc(&allocator);
+        s.arr2.dealloc(&allocator);
+        s.ftab.dealloc(&allocator);
+    }
+
+    unsafe {
+        allocator.deallocate(strm.state.cast::<EState>(), 1);
+    }
+    strm.state = ptr::null_mut::<EState>();
+
+    ReturnCode::BZ_OK as c_int
+}
+
+pub(crate) enum DecompressMode {
+    Small,
+    Fast,
+}
+
+/// Prepares the stream for decompression.
+///
+/// # Returns
+///
+/// - [`BZ_PARAM_ERROR`] if any of
+///     - `strm.is_null()`
+///     - `!(0..=1).contains(&small)`
+///     - `!(0..=4).contains(&verbosity)`
+///     - no [valid allocator](bz_stream#custom-allocators) could be configured
+/// - [`BZ_MEM_ERROR`] if insufficient memory is available
+/// - [`BZ_OK`] otherwise
+///
+/// # Safety
+///
+/// The caller must guarantee that
+///
+/// * Either
+///     - `strm` is `NULL`
+///     - `strm` satisfies the requirements of `&mut *strm`
+/// * The `bzalloc`, `bzfree` and `opaque` fields form a [valid allocator](bz_stream#custom-allocators).
+#[export_name = prefix!(BZ2_bzDecompressInit)]
+pub unsafe extern "C" fn BZ2_bzDecompressInit(
+    strm: *mut bz_stream,
+    verbosity: c_int,
+    small: c_int,
+) -> c_int {
+    let Some(strm) = (unsafe { BzStream::from_ptr(strm) }) else {
+        return ReturnCode::BZ_PARAM_ERROR as c_int;
+    };
+    BZ2_bzDecompressInitHelp(strm, verbosity, small) as c_int
+}
+
+pub(crate) fn BZ2_bzDecompressInitHelp(
+    strm: &mut BzStream<DState>,
+    verbosity: c_int,
+    small: c_int,
+) -> ReturnCode {
+    let decompress_mode = match small {
+        0 => DecompressMode::Fast,
+        1 => DecompressMode::Small,
+        _ => return ReturnCode::BZ_PARAM_ERROR,
+    };
+    if !(0..=4).contains(&verbosity) {
+        return ReturnCode::BZ_PARAM_ERROR;
+    }
+
+    // return a param error when no [valid allocator](bz_stream#custom-allocators) could be configured
+    let Some(allocator) = configure_allocator(strm) else {
+        return ReturnCode::BZ_PARAM_ERROR;
+    };
+
+    let Some(s) = allocator.allocate_zeroed::<DState>(1) else {
+        return ReturnCode::BZ_MEM_ERROR;
+    };
+
+    // this `s.strm` pointer should _NEVER_ be used! it exists just as a consistency check to ensure
     // that a given state belongs to a given strm.
     unsafe { (*s).strm_addr = strm as *const _ as usize }; // FIXME use .addr() once stable
 
@@ -1294,8 +1971,13 @@ pub(crate) fn BZ2_bzDecompressInitHelp(
         (*s).ll4 = DSlice::new();
         (*s).ll16 = DSlice::new();
         (*s).tt = DSlice::new();
+        (*s).reservedBlockSize100k = 0;
         (*s).currBlockNo = 0;
         (*s).verbosity = verbosity;
+        (*s).maxOutput = u64::MAX;
+        (*s).maxRatio = 0;
+        (*s).skipBits = 0;
+        (*s).singleBlock = false;
     }
 
     strm.state = s;
@@ -1359,7 +2041,6 @@ fn un_rle_obuf_to_output_fast(strm: &mut BzStream<DState>, s: &mut DState) -> bo
                 if !strm.write_byte(s.state_out_ch) {
                     return false;
                 }
-                BZ_UPDATE_CRC!(s.calculatedBlockCRC, s.state_out_ch);
                 s.state_out_len -= 1;
             }
 
@@ -1426,7 +2107,6 @@ fn un_rle_obuf_to_output_fast(strm: &mut BzStream<DState>, s: &mut DState) -> bo
         }
     } else {
         /* restore */
-        let mut c_calculatedBlockCRC: u32 = s.calculatedBlockCRC;
         let mut c_state_out_ch: u8 = s.state_out_ch;
         let mut c_state_out_len: u32 = s.state_out_len;
         let mut c_nblock_used: i32 = s.nblock_used;
@@ -1462,7 +2142,6 @@ fn un_rle_obuf_to_output_fast(strm: &mut BzStream<DState>, s: &mut DState) -> bo
                         break 'return_notr;
                     } else {
                         unsafe { *(cs_next_out as *mut u8) = $byte };
-                        BZ_UPDATE_CRC!(c_calculatedBlockCRC, $byte);
                         cs_next_out = unsafe { cs_next_out.add(1) };
                         cs_avail_out -= 1;
                     }
@@ -1477,10 +2156,6 @@ fn un_rle_obuf_to_output_fast(strm: &mut BzStream<DState>, s: &mut DState) -> bo
                     cs_next_out = cs_next_out.add(bound as usize);
                 };
 
-                for _ in 0..bound {
-                    BZ_UPDATE_CRC!(c_calculatedBlockCRC, c_state_out_ch);
-                }
-
                 cs_avail_out -= bound;
                 c_state_out_len -= bound;
 
@@ -1559,7 +2234,6 @@ fn un_rle_obuf_to_output_fast(strm: &mut BzStream<DState>, s: &mut DState) -> bo
         if strm.total_out_lo32 < total_out_lo32_old {
             strm.total_out_hi32 = (strm.total_out_hi32).wrapping_add(1);
         }
-        s.calculatedBlockCRC = c_calculatedBlockCRC;
         s.state_out_ch = c_state_out_ch;
         s.state_out_len = c_state_out_len;
         s.nblock_used = c_nblock_used;
@@ -1629,7 +2303,6 @@ fn un_rle_obuf_to_output_small(strm: &mut BzStream<DState>, s: &mut DState) -> b
                 if !strm.write_byte(s.state_out_ch) {
                     return false;
                 }
-                BZ_UPDATE_CRC!(s.calculatedBlockCRC, s.state_out_ch);
                 s.state_out_len -= 1;
             }
 
@@ -1707,7 +2380,6 @@ fn un_rle_obuf_to_output_small(strm: &mut BzStream<DState>, s: &mut DState) -> b
                 if !strm.write_byte(s.state_out_ch) {
                     return false;
                 }
-                BZ_UPDATE_CRC!(s.calculatedBlockCRC, s.state_out_ch);
                 s.state_out_len -= 1;
             }
             if s.nblock_used == s.save.nblock as i32 + 1 {
@@ -1760,6 +2432,114 @@ fn un_rle_obuf_to_output_small(strm: &mut BzStream<DState>, s: &mut DState) -> b
     }
 }
 
+/// Prepares the stream for decompression, with limits on the amount of decompressed output.
+///
+/// This function is like [`BZ2_bzDecompressInit`], but additionally protects against inputs that
+/// expand to a huge amount of output (so-called decompression bombs):
+///
+/// - `maxOutput` is the maximum total number of decompressed bytes
+/// - `maxRatio` is the maximum ratio between the number of decompressed bytes and the number of
+///   compressed bytes consumed so far
+///
+/// A value of 0 disables the corresponding limit. When decompressing further would exceed a limit,
+/// [`BZ2_bzDecompress`] returns [`BZ_OUTPUT_LIMIT`] instead of writing the excess output.
+///
+/// # Returns
+///
+/// - [`BZ_PARAM_ERROR`] if any of
+///     - `strm.is_null()`
+///     - `!(0..=1).contains(&small)`
+///     - `!(0..=4).contains(&verbosity)`
+///     - no [valid allocator](bz_stream#custom-allocators) could be configured
+/// - [`BZ_MEM_ERROR`] if insufficient memory is available
+/// - [`BZ_OK`] otherwise
+///
+/// # Safety
+///
+/// The caller must guarantee that
+///
+/// * Either
+///     - `strm` is `NULL`
+///     - `strm` satisfies the requirements of `&mut *strm`
+/// * The `bzalloc`, `bzfree` and `opaque` fields form a [valid allocator](bz_stream#custom-allocators).
+#[export_name = prefix!(BZ2_bzDecompressInitWithLimits)]
+pub unsafe extern "C" fn BZ2_bzDecompressInitWithLimits(
+    strm: *mut bz_stream,
+    verbosity: c_int,
+    small: c_int,
+    maxOutput: u64,
+    maxRatio: c_uint,
+) -> c_int {
+    let Some(strm) = (unsafe { BzStream::from_ptr(strm) }) else {
+        return ReturnCode::BZ_PARAM_ERROR as c_int;
+    };
+
+    let max_output = match maxOutput {
+        0 => u64::MAX,
+        n => n,
+    };
+
+    BZ2_bzDecompressInitWithLimitsHelp(strm, verbosity, small, max_output, maxRatio) as c_int
+}
+
+pub(crate) fn BZ2_bzDecompressInitWithLimitsHelp(
+    strm: &mut BzStream<DState>,
+    verbosity: c_int,
+    small: c_int,
+    max_output: u64,
+    max_ratio: u32,
+) -> ReturnCode {
+    let ret = BZ2_bzDecompressInitHelp(strm, verbosity, small);
+
+    if let Some(s) = unsafe { strm.state.as_mut() } {
+        s.maxOutput = max_output;
+        s.maxRatio = max_ratio;
+    }
+
+    ret
+}
+
+/// Prepares the stream for decoding a single block, for decoders that locate the blocks in the
+/// compressed data themselves.
+///
+/// The input must start `skip_bits` bits before the block header, and the block must belong to a
+/// stream with the given block size. Decompression returns [`BZ_STREAM_END`] when the end of the
+/// block has been reached and its CRC has been verified.
+#[cfg(feature = "std")]
+pub(crate) fn BZ2_bzDecompressInitBlockHelp(
+    strm: &mut BzStream<DState>,
+    small: c_int,
+    block_size100k: u8,
+    skip_bits: u8,
+) -> ReturnCode {
+    if !(1..=9).contains(&block_size100k) || skip_bits >= 8 {
+        return ReturnCode::BZ_PARAM_ERROR;
+    }
+
+    let ret = BZ2_bzDecompressInitHelp(strm, 0, small);
+    if ret != ReturnCode::BZ_OK {
+        return ret;
+    }
+
+    let Some(allocator) = strm.allocator() else {
+        return ReturnCode::BZ_PARAM_ERROR;
+    };
+
+    let s = unsafe { &mut *strm.state };
+    s.blockSize100k = block_size100k;
+
+    if !decompress::allocate_block_buffers(s, &allocator) {
+        BZ2_bzDecompressEndHelp(strm);
+        return ReturnCode::BZ_MEM_ERROR;
+    }
+
+    s.state = decompress::State::BZ_X_BLKHDR_1;
+    s.skipBits = skip_bits;
+    s.singleBlock = true;
+
+    ReturnCode::BZ_OK
+}
+
 /// Decompresses as much data as possible, and stops when the input buffer becomes empty or the output buffer becomes full.
 ///
 /// # Returns
@@ -1771,6 +2551,8 @@ fn un_rle_obuf_to_output_small(strm: &mut BzStream<DState>, s: &mut DState) -> b
 /// - [`BZ_DATA_ERROR`] if a data integrity error is detected in the compressed stream
 /// - [`BZ_DATA_ERROR_MAGIC`] if the compressed stream doesn't begin with the right magic bytes
 /// - [`BZ_MEM_ERROR`] if there wasn't enough memory available
+/// - [`BZ_OUTPUT_LIMIT`] if decompressing further would exceed a limit configured with
+///   [`BZ2_bzDecompressInitWithLimits`]
 /// - [`BZ_STREAM_END`] if the logical end of the data stream was detected and all output has been
 ///     written to the output buffer
 /// - [`BZ_OK`] otherwise
@@ -1815,12 +2597,34 @@ pub(crate) fn BZ2_bzDecompressHelp(strm: &mut BzStream<DState>) -> ReturnCode {
                 return ReturnCode::BZ_SEQUENCE_ERROR;
             }
             decompress::State::BZ_X_OUTPUT => {
+                // hide the output space beyond the output limit, so the limit is never exceeded
+                let budget = s.output_budget(strm);
+                let withheld = strm
+                    .avail_out
+                    .saturating_sub(budget.try_into().unwrap_or(u32::MAX));
+                strm.avail_out -= withheld;
+
+                let next_out = strm.next_out;
+
                 let corrupt = match s.smallDecompress {
                     DecompressMode::Small => un_rle_obuf_to_output_small(strm, s),
                     DecompressMode::Fast => un_rle_obuf_to_output_fast(strm, s),
                 };
 
+                strm.avail_out += withheld;
+
+                // the CRC is computed over all output of the block that was just written, rather
+                // than byte by byte
+                let written = unsafe { strm.next_out.offset_from(next_out) } as usize;
+                if written > 0 {
+                    // SAFETY: these bytes were just written to the output buffer
+                    let output =
+                        unsafe { core::slice::from_raw_parts(next_out.cast::<u8>(), written) };
+                    s.calculatedBlockCRC = crc32::update(s.calculatedBlockCRC, output);
+                }
+
                 if corrupt {
+                    s.record_data_error(strm, DataErrorKind::BlockData);
                     return ReturnCode::BZ_DATA_ERROR;
                 }
 
@@ -1838,13 +2642,22 @@ pub(crate) fn BZ2_bzDecompressHelp(strm: &mut BzStream<DState>) -> ReturnCode {
                     }
                     #[cfg(not(feature = "__internal-fuzz-disable-checksum"))]
                     if s.calculatedBlockCRC != s.storedBlockCRC {
+                        s.record_crc_error(strm, DataErrorKind::BlockCrc);
                         return ReturnCode::BZ_DATA_ERROR;
                     }
                     s.calculatedCombinedCRC = s.calculatedCombinedCRC.rotate_left(1);
                     s.calculatedCombinedCRC ^= s.calculatedBlockCRC;
+
+                    if s.singleBlock {
+                        s.state = decompress::State::BZ_X_IDLE;
+                        return ReturnCode::BZ_STREAM_END;
+                    }
+
                     s.state = decompress::State::BZ_X_BLKHDR_1;
 
                     continue;
+                } else if s.output_budget(strm) == 0 {
+                    return ReturnCode::BZ_OUTPUT_LIMIT;
                 } else {
                     return ReturnCode::BZ_OK;
                 }
@@ -1860,6 +2673,7 @@ pub(crate) fn BZ2_bzDecompressHelp(strm: &mut BzStream<DState>) -> ReturnCode {
                     }
                     #[cfg(not(feature = "__internal-fuzz-disable-checksum"))]
                     if s.calculatedCombinedCRC != s.storedCombinedCRC {
+                        s.record_crc_error(strm, DataErrorKind::CombinedCrc);
                         return ReturnCode::BZ_DATA_ERROR;
                     }
                     return ReturnCode::BZ_STREAM_END;
@@ -1896,7 +2710,7 @@ pub unsafe extern "C" fn BZ2_bzDecompressEnd(strm: *mut bz_stream) -> c_int {
     BZ2_bzDecompressEndHelp(strm) as c_int
 }
 
-fn BZ2_bzDecompressEndHelp(strm: &mut BzStream<DState>) -> ReturnCode {
+pub(crate) fn BZ2_bzDecompressEndHelp(strm: &mut BzStream<DState>) -> ReturnCode {
     let Some(s) = (unsafe { strm.state.as_mut() }) else {
         return ReturnCode::BZ_PARAM_ERROR;
     };
@@ -1910,11 +2724,7 @@ fn BZ2_bzDecompressEndHelp(strm: &mut BzStream<DState>) -> ReturnCode {
         return ReturnCode::BZ_PARAM_ERROR;
     };
 
-    unsafe {
-        s.tt.dealloc(&allocator);
-        s.ll16.dealloc(&allocator);
-        s.ll4.dealloc(&allocator);
-    }
+    s.deallocate_block_buffers(&allocator);
 
     unsafe { allocator.deallocate(strm.state, 1) };
     strm.state = ptr::null_mut::<DState>();
@@ -1922,6 +2732,215 @@ fn BZ2_bzDecompressEndHelp(strm: &mut BzStream<DState>) -> ReturnCode {
     ReturnCode::BZ_OK
 }
 
+/// Retrieves the details of the last data integrity error detected by the decompressor.
+///
+/// When [`BZ2_bzDecompress`] returns [`BZ_DATA_ERROR`] or [`BZ_DATA_ERROR_MAGIC`], this function
+/// describes what was wrong with the compressed data, and where the problem was found. When no
+/// data integrity error was detected, the `kind` field of `*error` is set to
+/// [`DataErrorKind::None`].
+///
+/// # Returns
+///
+/// - [`BZ_PARAM_ERROR`] if any of
+///     - `strm.is_null()`
+///     - `strm.s.is_null()`
+///     - `error.is_null()`
+/// - [`BZ_OK`] otherwise
+///
+/// # Safety
+///
+/// * Either
+///     - `strm` is `NULL`
+///     - `strm` satisfies the requirements of `&mut *strm` and was initialized with [`BZ2_bzDecompressInit`]
+/// * Either
+///     - `error` is `NULL`
+///     - `error` satisfies the requirements of `&mut *error`
+#[export_name = prefix!(BZ2_bzDecompressGetDataError)]
+pub unsafe extern "C" fn BZ2_bzDecompressGetDataError(
+    strm: *mut bz_stream,
+    error: *mut DataError,
+) -> c_int {
+    let Some(strm) = (unsafe { BzStream::from_ptr(strm) }) else {
+        return ReturnCode::BZ_PARAM_ERROR as c_int;
+    };
+
+    let Some(error) = (unsafe { error.as_mut() }) else {
+        return ReturnCode::BZ_PARAM_ERROR as c_int;
+    };
+
+    match BZ2_bzDecompressGetDataErrorHelp(strm) {
+        Some(data_error) => {
+            *error = data_error;
+            ReturnCode::BZ_OK as c_int
+        }
+        None => ReturnCode::BZ_PARAM_ERROR as c_int,
+    }
+}
+
+pub(crate) fn BZ2_bzDecompressGetDataErrorHelp(strm: &BzStream<DState>) -> Option<DataError> {
+    let s = unsafe { strm.state.as_ref() }?;
+
+    // FIXME use .addr() once stable
+    if s.strm_addr != strm as *const _ as usize {
+        return None;
+    }
+
+    Some(s.dataError)
+}
+
+/// The sizes in bytes of the allocations of a compressor with the given block size, in the order
+/// in which [`BZ2_bzCompressInit`] makes them.
+pub(crate) const fn compress_allocations(blockSize100k: usize) -> [usize; 4] {
+    let n = 100000 * blockSize100k;
+
+    [
+        mem::size_of::<EState>(),
+        n * mem::size_of::<u32>(),                    // arr1
+        (n + BZ_N_OVERSHOOT) * mem::size_of::<u32>(), // arr2
+        FTAB_LEN * mem::size_of::<u32>(),             // ftab
+    ]
+}
+
+/// The sizes in bytes of the allocations of a decompressor, after it has read the header of a
+/// stream with the given block size.
+pub(crate) const fn decompress_allocations(blockSize100k: usize, small: bool) -> [usize; 3] {
+    let n = 100000 * blockSize100k;
+
+    match small {
+        // ll16 and ll4
+        true => [
+            mem::size_of::<DState>(),
+            n * mem::size_of::<u16>(),
+            (1 + n) >> 1,
+        ],
+        // tt
+        false => [mem::size_of::<DState>(), n * mem::size_of::<u32>(), 0],
+    }
+}
+
+/// The total size of allocations of the given sizes.
+pub(crate) const fn total_size(sizes: &[usize]) -> usize {
+    let mut total = 0;
+
+    let mut i = 0;
+    while i < sizes.len() {
+        total += sizes[i];
+        i += 1;
+    }
+
+    total
+}
+
+/// The number of bytes that a compressor with the given block size allocates.
+///
+/// This does not include the `bz_stream` itself, which is owned by the caller.
+///
+/// # Returns
+///
+/// - 0 if `!(1..=9).contains(&blockSize100k)`
+/// - the number of bytes otherwise
+#[export_name = prefix!(BZ2_bzCompressMemoryUsage)]
+pub extern "C" fn BZ2_bzCompressMemoryUsage(blockSize100k: c_int) -> usize {
+    match blockSize100k {
+        1..=9 => total_size(&compress_allocations(blockSize100k as usize)),
+        _ => 0,
+    }
+}
+
+/// The number of bytes that a decompressor allocates for a stream with the given block size.
+///
+/// This does not include the `bz_stream` itself, which is owned by the caller. The block size of a
+/// stream is only known once its header has been read: before that, the decompressor only
+/// allocates its fixed-size state.
+///
+/// # Returns
+///
+/// - 0 if any of
+///     - `!(1..=9).contains(&blockSize100k)`
+///     - `!(0..=1).contains(&small)`
+/// - the number of bytes otherwise
+#[export_name = prefix!(BZ2_bzDecompressMemoryUsage)]
+pub extern "C" fn BZ2_bzDecompressMemoryUsage(blockSize100k: c_int, small: c_int) -> usize {
+    match (blockSize100k, small) {
+        (1..=9, 0..=1) => total_size(&decompress_allocations(blockSize100k as usize, small == 1)),
+        _ => 0,
+    }
+}
+
+/// The number of bytes that are currently allocated by a compression stream.
+///
+/// # Returns
+///
+/// - 0 if any of
+///     - `strm.is_null()`
+///     - `strm.s.is_null()`
+/// - the number of bytes otherwise
+///
+/// # Safety
+///
+/// * Either
+///     - `strm` is `NULL`
+///     - `strm` satisfies the requirements of `&mut *strm` and was initialized with [`BZ2_bzCompressInit`]
+#[export_name = prefix!(BZ2_bzCompressAllocatedMemory)]
+pub unsafe extern "C" fn BZ2_bzCompressAllocatedMemory(strm: *mut bz_stream) -> usize {
+    let Some(strm) = (unsafe { BzStream::from_ptr(strm) }) else {
+        return 0;
+    };
+    BZ2_bzCompressAllocatedMemoryHelp(strm).unwrap_or(0)
+}
+
+pub(crate) fn BZ2_bzCompressAllocatedMemoryHelp(strm: &BzStream<EState>) -> Option<usize> {
+    let s = unsafe { strm.state.as_ref() }?;
+
+    // FIXME use .addr() once stable
+    if s.strm_addr != strm as *const _ as usize {
+        return None;
+    }
+
+    let ftab_len = match s.ftab.ptr.is_null() {
+        true => 0,
+        false => FTAB_LEN,
+    };
+
+    Some(mem::size_of::<EState>() + (s.arr1.len + s.arr2.len + ftab_len) * mem::size_of::<u32>())
+}
+
+/// The number of bytes that are currently allocated by a decompression stream.
+///
+/// The buffers for the decompressed blocks are allocated when the header of the stream has been
+/// read, and are kept by [`BZ2_bzDecompressReset`].
+///
+/// # Returns
+///
+/// - 0 if any of
+///     - `strm.is_null()`
+///     - `strm.s.is_null()`
+/// - the number of bytes otherwise
+///
+/// # Safety
+///
+/// * Either
+///     - `strm` is `NULL`
+///     - `strm` satisfies the requirements of `&mut *strm` and was initialized with [`BZ2_bzDecompressInit`]
+#[export_name = prefix!(BZ2_bzDecompressAllocatedMemory)]
+pub unsafe extern "C" fn BZ2_bzDecompressAllocatedMemory(strm: *mut bz_stream) -> usize {
+    let Some(strm) = (unsafe { BzStream::from_ptr(strm) }) else {
+        return 0;
+    };
+    BZ2_bzDecompressAllocatedMemoryHelp(strm).unwrap_or(0)
+}
+
+pub(crate) fn BZ2_bzDecompressAllocatedMemoryHelp(strm: &BzStream<DState>) -> Option<usize> {
+    let s = unsafe { strm.state.as_ref() }?;
+
+    // FIXME use .addr() once stable
+    if s.strm_addr != strm as *const _ as usize {
+        return None;
+    }
+
+    Some(mem::size_of::<DState>() + s.tt.size() + s.ll16.size() + s.ll4.size())
+}
+
 /// Compress the input data into the destination buffer.
 ///
 /// This function attempts to compress the data in `source[0 .. sourceLen]` into `dest[0 .. *destLen]`.
@@ -1,7 +1,10 @@
 #![forbid(unsafe_code)]
 
 use crate::blocksort::block_sort;
-use crate::bzlib::{EState, BZ_MAX_SELECTORS, BZ_N_GROUPS, BZ_N_ITERS, BZ_RUNA, BZ_RUNB};
+use crate::bzlib::{
+    EState, HuffmanAlgorithm, BZ_MAX_ALPHA_SIZE, BZ_MAX_SELECTORS, BZ_N_GROUPS, BZ_N_ITERS,
+    BZ_RUNA, BZ_RUNB,
+};
 use crate::{assert_h, debug_log, debug_logln, huffman};
 
 pub(crate) struct EWriter {
@@ -10,6 +13,13 @@ pub(crate) struct EWriter {
     bs_buff: u32,
 }
 
+impl EWriter {
+    /// The number of bits in the output buffer, including the bits that are not yet flushed.
+    pub(crate) fn bit_position(&self) -> u64 {
+        u64::from(self.num_z) * 8 + self.bs_live as u64
+    }
+}
+
 pub(crate) struct LiveWriter<'a> {
     zbits: &'a mut [u8],
     writer: &'a mut EWriter,
@@ -214,10 +224,251 @@ fn generate_mtf_values(s: &mut EState) {
     s.nMTF = wr;
 }
 
-fn send_mtf_values(s: &mut EState) {
-    const BZ_LESSER_ICOST: u8 = 0;
-    const BZ_GREATER_ICOST: u8 = 15;
+const BZ_LESSER_ICOST: u8 = 0;
+const BZ_GREATER_ICOST: u8 = 15;
+
+/// Generates an initial set of `nGroups` coding tables, that each cover a range of symbols with
+/// roughly the same total frequency.
+fn initial_tables(
+    len: &mut [[u8; BZ_MAX_ALPHA_SIZE]; BZ_N_GROUPS],
+    mtfFreq: &[i32; 258],
+    nMTF: i32,
+    nGroups: usize,
+    alphaSize: usize,
+    verbosity: i32,
+) {
+    let mut tFreq: i32;
+    let mut aFreq: i32;
+    let mut ge: i32;
+
+    let mut nPart = nGroups;
+    let mut remF = nMTF;
+    let mut gs = 0i32;
+
+    while nPart > 0 {
+        tFreq = remF / nPart as i32;
+        ge = gs - 1;
+        aFreq = 0;
+        while aFreq < tFreq && ge < alphaSize as i32 - 1 {
+            ge += 1;
+            aFreq += mtfFreq[ge as usize];
+        }
+        if ge > gs && nPart != nGroups && nPart != 1 && (nGroups - nPart) % 2 == 1 {
+            aFreq -= mtfFreq[ge as usize];
+            ge -= 1;
+        }
+
+        if verbosity >= 3 {
+            debug_logln!(
+                "      initial group {}, [{} .. {}], has {} syms ({:4.1}%)",
+                nPart,
+                gs,
+                ge,
+                aFreq,
+                100.0f64 * aFreq as f64 / nMTF as f64,
+            );
+        }
+
+        for (v, l) in len[nPart - 1][..alphaSize].iter_mut().enumerate() {
+            *l = if (gs..=ge).contains(&(v as i32)) {
+                BZ_LESSER_ICOST
+            } else {
+                BZ_GREATER_ICOST
+            };
+        }
+        nPart -= 1;
+        gs = ge + 1;
+        remF -= aFreq;
+    }
+}
+
+/// The maximum number of refinement passes per number of coding tables in extreme mode.
+const EXTREME_MAX_ITERS: usize = 16;
+
+/// Searches for coding tables and selectors that encode the block in fewer bits than the tables
+/// in `s.len` and selectors in `s.selector`, which were found by the classic heuristics.
+///
+/// Every number of tables is tried, starting both from tables for ranges of symbols and from tables
+/// for contiguous segments of the block. The selectors are chosen taking the cost of encoding the
+/// selectors themselves into account, and the tables are refined until the size stops improving.
+/// Returns the number of tables of the best encoding, which is stored in `s.len` and `s.selector`.
+fn optimize_tables(s: &mut EState, nGroups: usize, nSelectors: usize, alphaSize: usize) -> usize {
+    let mtfv = &s.arr1.mtfv()[..s.nMTF as usize];
+
+    // the classic tables were computed from `s.rfreq`, which was counted with `s.selector`
+    let mut best_groups = nGroups;
+    let mut best_len = s.len;
+    let mut best_size = selector_bits(&s.selector[..nSelectors])
+        + encoded_bits(&s.len[..nGroups], &s.rfreq[..nGroups], alphaSize);
+
+    if s.verbosity >= 3 {
+        debug_logln!("      extreme: classic size is {}", best_size / 8);
+    }
+
+    for (groups, segments) in (2..=BZ_N_GROUPS).flat_map(|g| [(g, false), (g, true)]) {
+        if segments {
+            for t in 0..groups {
+                s.rfreq[t][..alphaSize].fill(0);
+            }
+            for (i, &v) in mtfv.iter().enumerate() {
+                s.rfreq[i * groups / mtfv.len()][usize::from(v)] += 1;
+            }
+            for t in 0..groups {
+                huffman::code_lengths(
+                    s.huffmanAlgorithm,
+                    &mut s.len[t],
+                    &s.rfreq[t],
+                    alphaSize,
+                    17,
+                );
+            }
+        } else {
+            initial_tables(&mut s.len, &s.mtfFreq, s.nMTF, groups, alphaSize, 0);
+        }
+
+        let mut previous_size = u32::MAX;
+        for _ in 0..EXTREME_MAX_ITERS {
+            // pack the lengths of two tables into a u32, to compute two costs at once
+            for v in 0..alphaSize {
+                for (lane, pack) in s.len_pack[v][..groups.div_ceil(2)].iter_mut().enumerate() {
+                    let high = match 2 * lane + 1 < groups {
+                        true => s.len[2 * lane + 1][v],
+                        false => 0,
+                    };
+                    *pack = u32::from(high) << 16 | u32::from(s.len[2 * lane][v]);
+                }
+            }
+
+            for t in 0..groups {
+                s.rfreq[t][..alphaSize].fill(0);
+            }
+
+            // choose selectors, using `s.selectorMtf` for the candidates. Switching tables is not
+            // free: the selector costs as many bits as its position in the MTF list, plus one.
+            let mut order: [u8; BZ_N_GROUPS] = [0, 1, 2, 3, 4, 5];
+            let mut selector_size = 0;
+
+            for (i, group) in mtfv.chunks(50).enumerate() {
+                let mut packed = [0u32; 3];
+                for &v in group {
+                    let pack = &s.len_pack[usize::from(v)];
+                    for (p, q) in packed.iter_mut().zip(pack) {
+                        *p += q;
+                    }
+                }
+
+                let mut best = (u32::MAX, 0, 0);
+                for (rank, &t) in order[..groups].iter().enumerate() {
+                    let t = usize::from(t);
+                    let cost = (packed[t / 2] >> (16 * (t % 2))) & 0xffff;
+                    let cost = cost + rank as u32 + 1;
+                    if cost < best.0 {
+                        best = (cost, t, rank);
+                    }
+                }
+
+                let (_, t, rank) = best;
+                order[..=rank].rotate_right(1);
+                selector_size += rank as u32 + 1;
+
+                s.selectorMtf[i] = t as u8;
+                for &v in group {
+                    s.rfreq[t][usize::from(v)] += 1;
+                }
+            }
+
+            for t in 0..groups {
+                huffman::code_lengths(
+                    s.huffmanAlgorithm,
+                    &mut s.len[t],
+                    &s.rfreq[t],
+                    alphaSize,
+                    17,
+                );
+            }
+
+            let size =
+                selector_size + encoded_bits(&s.len[..groups], &s.rfreq[..groups], alphaSize);
+
+            if size < best_size {
+                best_size = size;
+                best_groups = groups;
+                best_len = s.len;
+                s.selector[..nSelectors].copy_from_slice(&s.selectorMtf[..nSelectors]);
+            }
+
+            if size >= previous_size {
+                break;
+            }
+            previous_size = size;
+        }
+    }
+
+    if s.verbosity >= 3 {
+        debug_logln!(
+            "      extreme: {} tables, size is {}",
+            best_groups,
+            best_size / 8,
+        );
+    }
+
+    s.len = best_len;
+    best_groups
+}
+
+/// Replaces the tables in `s.len` by the optimal lengths for the frequencies in `s.rfreq`, that
+/// were counted with the final selectors.
+///
+/// The selectors, and hence the frequencies, are the same as with the classic lengths. A table is
+/// only replaced when that makes the table plus the symbols that it encodes smaller, so the block
+/// never gets larger.
+fn optimal_code_lengths(s: &mut EState, nGroups: usize, alphaSize: usize) {
+    for t in 0..nGroups {
+        let mut len = [0u8; BZ_MAX_ALPHA_SIZE];
+        huffman::package_merge(&mut len, &s.rfreq[t], alphaSize, 17);
 
+        let rfreq = core::slice::from_ref(&s.rfreq[t]);
+        if encoded_bits(&[len], rfreq, alphaSize) < encoded_bits(&s.len[t..=t], rfreq, alphaSize) {
+            s.len[t] = len;
+        }
+    }
+}
+
+/// The number of bits that the MTF-encoded `selectors` take.
+fn selector_bits(selectors: &[u8]) -> u32 {
+    let mut order: [u8; BZ_N_GROUPS] = [0, 1, 2, 3, 4, 5];
+
+    let mut bits = 0;
+    for &selector in selectors {
+        let rank = order.iter().position(|&t| t == selector).unwrap();
+        order[..=rank].rotate_right(1);
+        bits += rank as u32 + 1;
+    }
+
+    bits
+}
+
+/// The number of bits that the coding tables `len` take, plus the number of bits of the symbols
+/// that are encoded with them, with frequencies `rfreq`.
+fn encoded_bits(len: &[[u8; BZ_MAX_ALPHA_SIZE]], rfreq: &[[i32; 258]], alphaSize: usize) -> u32 {
+    let mut bits = 0;
+
+    for (len, rfreq) in len.iter().zip(rfreq) {
+        let len = &len[..alphaSize];
+
+        bits += 5;
+        let mut curr = len[0];
+        for (&l, &freq) in len.iter().zip(rfreq) {
+            bits += 1 + 2 * u32::from(curr.abs_diff(l));
+            curr = l;
+            bits += u32::from(l) * freq as u32;
+        }
+    }
+
+    bits
+}
+
+fn send_mtf_values(s: &mut EState) {
     let mut gs: i32;
     let mut ge: i32;
     let mut totc: i32;
@@ -272,50 +523,14 @@ fn send_mtf_values(s: &mut EState) {
     let fave = &mut fave[..nGroups];
 
     /*--- Generate an initial set of coding tables ---*/
-    {
-        let mut tFreq: i32;
-        let mut aFreq: i32;
-
-        let mut nPart = nGroups;
-        let mut remF = s.nMTF;
-        let mut gs = 0i32;
-
-        while nPart > 0 {
-            tFreq = remF / nPart as i32;
-            ge = gs - 1;
-            aFreq = 0;
-            while aFreq < tFreq && ge < alphaSize as i32 - 1 {
-                ge += 1;
-                aFreq += s.mtfFreq[ge as usize];
-            }
-            if ge > gs && nPart != nGroups && nPart != 1 && (nGroups - nPart) % 2 == 1 {
-                aFreq -= s.mtfFreq[ge as usize];
-                ge -= 1;
-            }
-
-            if s.verbosity >= 3 {
-                debug_logln!(
-                    "      initial group {}, [{} .. {}], has {} syms ({:4.1}%)",
-                    nPart,
-                    gs,
-                    ge,
-                    aFreq,
-                    100.0f64 * aFreq as f64 / s.nMTF as f64,
-                );
-            }
-
-            for v in 0..alphaSize {
-                s.len[nPart - 1][v] = if (gs..=ge).contains(&(v as i32)) {
-                    BZ_LESSER_ICOST
-                } else {
-                    BZ_GREATER_ICOST
-                };
-            }
-            nPart -= 1;
-            gs = ge + 1;
-            remF -= aFreq;
-        }
-    }
+    initial_tables(
+        &mut s.len,
+        &s.mtfFreq,
+        s.nMTF,
+        nGroups,
+        alphaSize,
+        s.verbosity,
+    );
 
     /*---
        Iterate up to BZ_N_ITERS times to improve the tables.
@@ -443,6 +658,16 @@ fn send_mtf_values(s: &mut EState) {
         }
     }
 
+    if s.huffmanAlgorithm == HuffmanAlgorithm::PackageMerge {
+        optimal_code_lengths(s, nGroups, alphaSize);
+    }
+
+    let nGroups = match s.extreme {
+        true => optimize_tables(s, nGroups, nSelectors, alphaSize),
+        false => nGroups,
+    };
+    let mtfv = s.arr1.mtfv();
+
     assert_h!(nGroups < 8, 3002);
     assert_h!(nSelectors < 32768, 3003);
     assert_h!(nSelectors <= usize::from(BZ_MAX_SELECTORS), 3003);
@@ -592,6 +817,63 @@ fn send_mtf_values(s: &mut EState) {
     }
 }
 
+/// Writes the block header and the compressed block data. The block must have been sorted.
+fn write_block(s: &mut EState) {
+    let mut writer = LiveWriter::new(&mut s.writer, s.arr2.zbits(s.nblock as usize));
+
+    writer.write_u8(0x31);
+    writer.write_u8(0x41);
+    writer.write_u8(0x59);
+    writer.write_u8(0x26);
+    writer.write_u8(0x53);
+    writer.write_u8(0x59);
+
+    /*-- Now the block's CRC, so it is in a known place. --*/
+    writer.write_u32(s.blockCRC);
+
+    /*--
+       Now a single bit indicating (non-)randomisation.
+       As of version 0.9.5, we use a better sorting algorithm
+       which makes randomisation unnecessary.  So always set
+       the randomised bit to 'no'.  Of course, the decoder
+       still needs to be able to handle randomised blocks
+       so as to maintain backwards compatibility with
+       older versions of bzip2.
+    --*/
+    writer.write(1, 0);
+
+    writer.write(24, s.origPtr as u32);
+
+    drop(writer);
+
+    generate_mtf_values(s);
+
+    send_mtf_values(s);
+}
+
+/// Compresses the block in `s` on its own, without the stream header and trailer. The block CRC
+/// must already be finalized.
+///
+/// The compressed block is written to the start of `s.arr2.zbits(s.nblock)`, padded with zero bits
+/// to a whole number of bytes. Returns the length of the compressed block in bits.
+#[cfg(feature = "parallel")]
+pub(crate) fn compress_block_standalone(s: &mut EState) -> u64 {
+    s.writer = EWriter {
+        num_z: 0,
+        bs_live: 0,
+        bs_buff: 0,
+    };
+
+    block_sort(s);
+    write_block(s);
+
+    let mut writer = LiveWriter::new(&mut s.writer, s.arr2.zbits(s.nblock as usize));
+    let bits = u64::from(writer.num_z) * 8 + writer.bs_live as u64;
+    writer.finish();
+
+    bits
+}
+
 pub(crate) fn compress_block(s: &mut EState, is_last_block: bool) {
     if s.nblock > 0 {
         s.blockCRC = !s.blockCRC;
@@ -627,36 +909,8 @@ pub(crate) fn compress_block(s: &mut EState, is_last_block: bool) {
         }
 
         if s.nblock > 0 {
-            let mut writer = LiveWriter::new(&mut s.writer, s.arr2.zbits(s.nblock as usize));
-
-            writer.write_u8(0x31);
-            writer.write_u8(0x41);
-            writer.write_u8(0x59);
-            writer.write_u8(0x26);
-            writer.write_u8(0x53);
-            writer.write_u8(0x59);
-
-            /*-- Now the block's CRC, so it is in a known place. --*/
-            writer.write_u32(s.blockCRC);
-
-            /*--
-               Now a single bit indicating (non-)randomisation.
-               As of version 0.9.5, we use a better sorting algorithm
-               which makes randomisation unnecessary.  So always set
-               the randomised bit to 'no'.  Of course, the decoder
-               still needs to be able to handle randomised blocks
-               so as to maintain backwards compatibility with
-               older versions of bzip2.
-            --*/
-            writer.write(1, 0);
-
-            writer.write(24, s.origPtr as u32);
-
-            drop(writer);
-
-            generate_mtf_values(s);
-
-            send_mtf_values(s);
+            s.blockReporter.block_bit_offset = s.writer.bit_position();
+            write_block(s);
         }
     }
 
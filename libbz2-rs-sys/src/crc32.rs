@@ -0,0 +1,236 @@
+//! Computing the crc32 checksum of bzip2 over a slice of bytes.
+//!
+//! The checksum is the non-reflected CRC-32 of [`POLYNOMIAL`]: the first byte of the input holds
+//! the highest powers of `x`. Short inputs use [`CRC32_SLICES`] to process 8 bytes per step. On
+//! x86_64 and aarch64, longer inputs are folded 64 bytes at a time with carry-less multiplication,
+//! when the CPU supports it. That is detected at runtime, and needs the `std` feature.
+
+use crate::crctable::{BZ2_CRC32TABLE, CRC32_SLICES, POLYNOMIAL};
+
+/// Updates `crc` with `bytes`, like updating it with every byte in turn.
+///
+/// The checksum of a block starts at `0xffffffff`, and is inverted at the end.
+pub(crate) fn update(crc: u32, bytes: &[u8]) -> u32 {
+    if bytes.len() >= FOLD_MIN_LEN {
+        #[cfg(all(feature = "std", target_arch = "x86_64"))]
+        if std::is_x86_feature_detected!("pclmulqdq") {
+            // SAFETY: the CPU supports the instructions
+            return unsafe { x86_64::update(crc, bytes) };
+        }
+
+        #[cfg(all(feature = "std", target_arch = "aarch64"))]
+        if std::arch::is_aarch64_feature_detected!("aes") {
+            // SAFETY: the CPU supports the instructions
+            return unsafe { aarch64::update(crc, bytes) };
+        }
+    }
+
+    update_slicing(crc, bytes)
+}
+
+/// Updates `crc` with `len` copies of `byte`.
+pub(crate) fn update_run(mut crc: u32, byte: u8, len: usize) -> u32 {
+    let run = [byte; 64];
+    for _ in 0..len / run.len() {
+        crc = update(crc, &run);
+    }
+
+    update(crc, &run[..len % run.len()])
+}
+
+/// Updates `crc` with `bytes`, 8 bytes at a time.
+fn update_slicing(mut crc: u32, bytes: &[u8]) -> u32 {
+    let [t0, t1, t2, t3, t4, t5, t6, t7] = &CRC32_SLICES;
+
+    let mut chunks = bytes.chunks_exact(8);
+    for chunk in &mut chunks {
+        let hi = crc ^ u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        let lo = u32::from_be_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+
+        crc = t7[(hi >> 24) as usize]
+            ^ t6[(hi >> 16 & 0xff) as usize]
+            ^ t5[(hi >> 8 & 0xff) as usize]
+            ^ t4[(hi & 0xff) as usize]
+            ^ t3[(lo >> 24) as usize]
+            ^ t2[(lo >> 16 & 0xff) as usize]
+            ^ t1[(lo >> 8 & 0xff) as usize]
+            ^ t0[(lo & 0xff) as usize];
+    }
+
+    for &b in chunks.remainder() {
+        crc = (crc << 8) ^ BZ2_CRC32TABLE[((crc >> 24) ^ u32::from(b)) as usize];
+    }
+
+    crc
+}
+
+/// The shortest input that is folded with carry-less multiplication.
+const FOLD_MIN_LEN: usize = 64;
+
+/// `x^n mod P`, for the polynomial `P` of the checksum.
+const fn x_pow_mod(n: u32) -> u64 {
+    let mut r = 1u32;
+
+    let mut i = 0;
+    while i < n {
+        r = match r & 0x80000000 {
+            0 => r << 1,
+            _ => (r << 1) ^ POLYNOMIAL,
+        };
+        i += 1;
+    }
+
+    r as u64
+}
+
+/// The constants that move the high and low half of a 128-bit value `n` bits further.
+const fn fold_constants(n: u32) -> (u64, u64) {
+    (x_pow_mod(n + 64), x_pow_mod(n))
+}
+
+const FOLD_BY_512: (u64, u64) = fold_constants(512);
+const FOLD_BY_128: (u64, u64) = fold_constants(128);
+
+/// Updates `crc` with `bytes` of at least [`FOLD_MIN_LEN`] bytes, using `clmul` to multiply
+/// polynomials.
+///
+/// The input is read as 128-bit polynomials, with the first byte in the highest bits. Four of them
+/// are reduced at a time: an accumulator `a` is replaced by a smaller value that is equal to
+/// `a * x^512` modulo `P`, to which the next 64 bytes are added. The remaining 128-bit value has
+/// the same checksum as all input up to that point, which is computed with the lookup tables.
+#[inline(always)]
+#[cfg_attr(
+    not(all(feature = "std", any(target_arch = "x86_64", target_arch = "aarch64"))),
+    allow(dead_code)
+)]
+fn update_folding(crc: u32, bytes: &[u8], clmul: impl Fn(u64, u64) -> u128) -> u32 {
+    debug_assert!(bytes.len() >= FOLD_MIN_LEN);
+
+    let fold =
+        |a: u128, (k_hi, k_lo): (u64, u64)| clmul((a >> 64) as u64, k_hi) ^ clmul(a as u64, k_lo);
+    let load = |chunk: &[u8]| u128::from_be_bytes(chunk.try_into().unwrap());
+
+    let mut blocks = bytes.chunks_exact(64);
+
+    // the current checksum is added to the first 4 bytes
+    let first = blocks.next().unwrap();
+    let mut acc: [u128; 4] = core::array::from_fn(|i| load(&first[16 * i..][..16]));
+    acc[0] ^= u128::from(crc) << 96;
+
+    for block in &mut blocks {
+        for (i, a) in acc.iter_mut().enumerate() {
+            *a = fold(*a, FOLD_BY_512) ^ load(&block[16 * i..][..16]);
+        }
+    }
+
+    let [a0, a1, a2, a3] = acc;
+    let mut a = fold(a0, FOLD_BY_128) ^ a1;
+    a = fold(a, FOLD_BY_128) ^ a2;
+    a = fold(a, FOLD_BY_128) ^ a3;
+
+    let mut chunks = blocks.remainder().chunks_exact(16);
+    for chunk in &mut chunks {
+        a = fold(a, FOLD_BY_128) ^ load(chunk);
+    }
+
+    let crc = update_slicing(0, &a.to_be_bytes());
+    update_slicing(crc, chunks.remainder())
+}
+
+#[cfg(all(feature = "std", target_arch = "x86_64"))]
+mod x86_64 {
+    use core::arch::x86_64::{__m128i, _mm_clmulepi64_si128, _mm_cvtsi64_si128};
+
+    /// # Safety
+    ///
+    /// The CPU must support `pclmulqdq`.
+    #[target_feature(enable = "pclmulqdq")]
+    pub(super) unsafe fn update(crc: u32, bytes: &[u8]) -> u32 {
+        super::update_folding(crc, bytes, |a, b| {
+            // SAFETY: the target feature is enabled, and both types are 128-bit integers, where the
+            // lowest lane holds the lowest bits
+            unsafe {
+                let a = _mm_cvtsi64_si128(a as i64);
+                let b = _mm_cvtsi64_si128(b as i64);
+                core::mem::transmute::<__m128i, u128>(_mm_clmulepi64_si128(a, b, 0x00))
+            }
+        })
+    }
+}
+
+#[cfg(all(feature = "std", target_arch = "aarch64"))]
+mod aarch64 {
+    use core::arch::aarch64::vmull_p64;
+
+    /// # Safety
+    ///
+    /// The CPU must support the `aes` extension, which includes `pmull`.
+    #[target_feature(enable = "neon,aes")]
+    #[allow(unused_unsafe)] // the intrinsic is safe in this context on recent compilers
+    pub(super) unsafe fn update(crc: u32, bytes: &[u8]) -> u32 {
+        // SAFETY: the target features are enabled
+        super::update_folding(crc, bytes, |a, b| unsafe { vmull_p64(a, b) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update_bytewise(mut crc: u32, bytes: &[u8]) -> u32 {
+        for &b in bytes {
+            crc = (crc << 8) ^ BZ2_CRC32TABLE[((crc >> 24) ^ u32::from(b)) as usize];
+        }
+        crc
+    }
+
+    /// Carry-less multiplication, one bit at a time.
+    fn clmul_portable(a: u64, b: u64) -> u128 {
+        (0..64)
+            .filter(|i| b >> i & 1 != 0)
+            .fold(0, |acc, i| acc ^ u128::from(a) << i)
+    }
+
+    fn bytes(len: usize) -> std::vec::Vec<u8> {
+        let mut state = 0x5eed_1234u32;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn check_value() {
+        // the check value of CRC-32/BZIP2
+        assert_eq!(!update(0xffffffff, b"123456789"), 0xfc891918);
+    }
+
+    #[test]
+    fn matches_bytewise() {
+        let input = bytes(1000);
+
+        for len in (0..200).chain([511, 512, 513, 1000]) {
+            for crc in [0, 0xffffffff, 0x1234_5678] {
+                let expected = update_bytewise(crc, &input[..len]);
+
+                assert_eq!(update(crc, &input[..len]), expected, "{len}");
+                assert_eq!(update_slicing(crc, &input[..len]), expected, "{len}");
+
+                if len >= FOLD_MIN_LEN {
+                    let folded = update_folding(crc, &input[..len], clmul_portable);
+                    assert_eq!(folded, expected, "{len}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn run() {
+        for len in [0, 1, 63, 64, 65, 255] {
+            let expected = update_bytewise(0xffffffff, &std::vec![b'z'; len]);
+            assert_eq!(update_run(0xffffffff, b'z', len), expected);
+        }
+    }
+}
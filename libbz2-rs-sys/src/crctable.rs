@@ -1,7 +1,7 @@
 /// The polynomial used for the crc32 lookup table.
 ///
 /// See also https://en.wikipedia.org/wiki/Cyclic_redundancy_check#Polynomial_representations
-const POLYNOMIAL: u32 = 0x04C11DB7;
+pub(crate) const POLYNOMIAL: u32 = 0x04C11DB7;
 
 /// Most implementations (ethernet, zlib) use the reflected version of this polynomial.
 const _: () = assert!(POLYNOMIAL.reverse_bits() == 0xEDB88320);
@@ -16,6 +16,11 @@ const _: () = assert!(POLYNOMIAL.reverse_bits() == 0xEDB88320);
 /// > comp.compression FAQ.
 pub(crate) static BZ2_CRC32TABLE: [u32; 256] = generate_crc32_table(POLYNOMIAL);
 
+/// Lookup tables to process 8 bytes at once ("slicing-by-8"). Entry `i` of table `k` is the
+/// contribution of byte `i` when it is followed by `k` more bytes, so table 0 is
+/// [`BZ2_CRC32TABLE`].
+pub(crate) static CRC32_SLICES: [[u32; 256]; 8] = generate_slicing_tables(POLYNOMIAL);
+
 /// Generate the crc32 lookup table.
 ///
 /// Note that contrary to most material you'll find on the internet, we're using the non-reflected
@@ -49,3 +54,25 @@ const fn generate_crc32_table(polynomial: u32) -> [u32; 256] {
 
     table
 }
+
+/// Generate the tables of [`CRC32_SLICES`].
+const fn generate_slicing_tables(polynomial: u32) -> [[u32; 256]; 8] {
+    let mut tables = [[0u32; 256]; 8];
+    tables[0] = generate_crc32_table(polynomial);
+
+    let mut k = 1;
+    while k < 8 {
+        let mut i = 0;
+        while i < 256 {
+            // append a zero byte
+            let crc = tables[k - 1][i];
+            tables[k][i] = (crc << 8) ^ tables[0][(crc >> 24) as usize];
+
+            i += 1;
+        }
+
+        k += 1;
+    }
+
+    tables
+}
@@ -4,8 +4,8 @@ use core::ffi::c_int;
 
 use crate::allocator::Allocator;
 use crate::bzlib::{
-    index_into_f, BzStream, DSlice, DState, DecompressMode, ReturnCode, SaveArea, BZ_MAX_SELECTORS,
-    BZ_RAND_UPD_MASK, BZ_RUNA, BZ_RUNB,
+    index_into_f, BzStream, DSlice, DState, DataErrorKind, DecompressMode, ReturnCode, SaveArea,
+    BZ_MAX_SELECTORS, BZ_RAND_UPD_MASK, BZ_RUNA, BZ_RUNB,
 };
 use crate::{debug_log, huffman};
 
@@ -135,6 +135,7 @@ pub(crate) fn decompress(
     let mut uc: u8;
 
     let old_avail_in = strm.avail_in;
+    let mut error_kind = DataErrorKind::None;
 
     if let State::BZ_X_MAGIC_1 = s.state {
         /*zero out the save area*/
@@ -211,13 +212,38 @@ pub(crate) fn decompress(
             };
         }
 
+        // when enough bits are buffered, the lookup table gives the length of the next code, so that
+        // `get_next_sym!` finds it without reading more bits. Otherwise, or for long codes, the code
+        // is read bit by bit, which can be suspended and resumed when the input runs out.
+        macro_rules! peek_code_length {
+            ($strm:expr, $s:expr) => {
+                let bits = i32::from(huffman::LOOKUP_BITS);
+
+                // the MTF states come before `State::BZ_X_ENDHDR_2`, so see `GET_BITS!`
+                if $s.bsLive < bits {
+                    if let Some((bit_buffer, bits_used)) = $strm.pull_u64($s.bsBuff, $s.bsLive) {
+                        $s.bsBuff = bit_buffer;
+                        $s.bsLive = bits_used;
+                    }
+                }
+
+                if $s.bsLive >= bits {
+                    let index = ($s.bsBuff >> ($s.bsLive - bits)) as usize & ((1 << bits) - 1);
+                    match $s.lookup[usize::from(gSel)][index] {
+                        0 => {}
+                        length => zn = length,
+                    }
+                }
+            };
+        }
+
         macro_rules! update_group_pos {
             ($s:expr) => {
                 if groupPos == 0 {
                     groupNo += 1;
                     gSel = match $s.selector[..usize::from(nSelectors)].get(groupNo as usize) {
                         Some(&gSel) => gSel,
-                        None => error!(BZ_DATA_ERROR),
+                        None => error!(BZ_DATA_ERROR, Selector),
                     };
                     gMinlen = $s.minLens[usize::from(gSel)];
                     groupPos = 50;
@@ -230,6 +256,10 @@ pub(crate) fn decompress(
             ($code:ident) => {{
                 break 'save_state_and_return ReturnCode::$code;
             }};
+            ($code:ident, $kind:ident) => {{
+                error_kind = DataErrorKind::$kind;
+                break 'save_state_and_return ReturnCode::$code;
+            }};
         }
 
         match s.state {
@@ -239,7 +269,7 @@ pub(crate) fn decompress(
                 uc = GET_BYTE!(strm, s);
 
                 if uc != b'B' {
-                    error!(BZ_DATA_ERROR_MAGIC);
+                    error!(BZ_DATA_ERROR_MAGIC, StreamMagic);
                 }
 
                 current_block = BZ_X_MAGIC_2;
@@ -292,7 +322,7 @@ pub(crate) fn decompress(
             uc = GET_BYTE!(strm, s);
 
             if uc != b'Z' {
-                error!(BZ_DATA_ERROR_MAGIC);
+                error!(BZ_DATA_ERROR_MAGIC, StreamMagic);
             }
 
             current_block = BZ_X_MAGIC_3;
@@ -303,7 +333,7 @@ pub(crate) fn decompress(
             uc = GET_BYTE!(strm, s);
 
             if uc != b'h' {
-                error!(BZ_DATA_ERROR_MAGIC);
+                error!(BZ_DATA_ERROR_MAGIC, StreamMagic);
             }
 
             current_block = BZ_X_MAGIC_4;
@@ -314,37 +344,13 @@ pub(crate) fn decompress(
             s.blockSize100k = GET_BYTE!(strm, s);
 
             if !(b'1'..=b'9').contains(&s.blockSize100k) {
-                error!(BZ_DATA_ERROR_MAGIC);
+                error!(BZ_DATA_ERROR_MAGIC, StreamMagic);
             }
 
             s.blockSize100k -= b'0';
 
-            match s.smallDecompress {
-                DecompressMode::Small => {
-                    // SAFETY: we assume allocation is safe
-                    let ll16_len = usize::from(s.blockSize100k) * 100000;
-                    let Some(ll16) = DSlice::alloc(allocator, ll16_len) else {
-                        error!(BZ_MEM_ERROR);
-                    };
-
-                    // SAFETY: we assume allocation is safe
-                    let ll4_len = (1 + usize::from(s.blockSize100k) * 100000) >> 1;
-                    let Some(ll4) = DSlice::alloc(allocator, ll4_len) else {
-                        error!(BZ_MEM_ERROR);
-                    };
-
-                    s.ll16 = ll16;
-                    s.ll4 = ll4;
-                }
-                DecompressMode::Fast => {
-                    // SAFETY: we assume allocation is safe
-                    let tt_len = usize::from(s.blockSize100k) * 100000;
-                    let Some(tt) = DSlice::alloc(allocator, tt_len) else {
-                        error!(BZ_MEM_ERROR);
-                    };
-
-                    s.tt = tt;
-                }
+            if !allocate_block_buffers(s, allocator) {
+                error!(BZ_MEM_ERROR);
             }
 
             current_block = BZ_X_BLKHDR_1;
@@ -352,12 +358,20 @@ pub(crate) fn decompress(
         if current_block == BZ_X_BLKHDR_1 {
             s.state = State::BZ_X_BLKHDR_1;
 
+            if s.skipBits > 0 {
+                // a decoder that starts at a block in the middle of the stream first skips the
+                // bits that precede the block header
+                let skip = i32::from(s.skipBits);
+                let _ = GET_BITS!(strm, s, skip);
+                s.skipBits = 0;
+            }
+
             uc = GET_BYTE!(strm, s);
 
             match uc {
                 0x17 => current_block = BZ_X_ENDHDR_2,
                 0x31 => current_block = BZ_X_BLKHDR_2,
-                _ => error!(BZ_DATA_ERROR),
+                _ => error!(BZ_DATA_ERROR, BlockMagic),
             };
         }
         match current_block {
@@ -367,7 +381,7 @@ pub(crate) fn decompress(
                 uc = GET_BYTE!(strm, s);
 
                 if uc != 0x72 {
-                    error!(BZ_DATA_ERROR);
+                    error!(BZ_DATA_ERROR, BlockMagic);
                 }
 
                 current_block = BZ_X_ENDHDR_3;
@@ -378,7 +392,7 @@ pub(crate) fn decompress(
                 uc = GET_BYTE!(strm, s);
 
                 if uc != 0x41 {
-                    error!(BZ_DATA_ERROR);
+                    error!(BZ_DATA_ERROR, BlockMagic);
                 }
                 current_block = BZ_X_BLKHDR_3;
             }
@@ -391,7 +405,7 @@ pub(crate) fn decompress(
                 uc = GET_BYTE!(strm, s);
 
                 if uc != 0x45 {
-                    error!(BZ_DATA_ERROR);
+                    error!(BZ_DATA_ERROR, BlockMagic);
                 }
 
                 current_block = BZ_X_ENDHDR_4;
@@ -402,7 +416,7 @@ pub(crate) fn decompress(
                 uc = GET_BYTE!(strm, s);
 
                 if uc != 0x59 {
-                    error!(BZ_DATA_ERROR);
+                    error!(BZ_DATA_ERROR, BlockMagic);
                 }
 
                 current_block = BZ_X_BLKHDR_4;
@@ -416,7 +430,7 @@ pub(crate) fn decompress(
                 uc = GET_BYTE!(strm, s);
 
                 if uc != 0x38 {
-                    error!(BZ_DATA_ERROR);
+                    error!(BZ_DATA_ERROR, BlockMagic);
                 }
 
                 current_block = BZ_X_ENDHDR_5;
@@ -427,7 +441,7 @@ pub(crate) fn decompress(
                 uc = GET_BYTE!(strm, s);
 
                 if uc != 0x26 {
-                    error!(BZ_DATA_ERROR);
+                    error!(BZ_DATA_ERROR, BlockMagic);
                 }
 
                 current_block = BZ_X_BLKHDR_5;
@@ -441,7 +455,7 @@ pub(crate) fn decompress(
                 uc = GET_BYTE!(strm, s);
 
                 if uc != 0x50 {
-                    error!(BZ_DATA_ERROR);
+                    error!(BZ_DATA_ERROR, BlockMagic);
                 }
 
                 current_block = BZ_X_ENDHDR_6;
@@ -452,7 +466,7 @@ pub(crate) fn decompress(
                 uc = GET_BYTE!(strm, s);
 
                 if uc != 0x53 {
-                    error!(BZ_DATA_ERROR);
+                    error!(BZ_DATA_ERROR, BlockMagic);
                 }
 
                 current_block = BZ_X_BLKHDR_6;
@@ -466,7 +480,7 @@ pub(crate) fn decompress(
                 uc = GET_BYTE!(strm, s);
 
                 if uc != 0x90 {
-                    error!(BZ_DATA_ERROR);
+                    error!(BZ_DATA_ERROR, BlockMagic);
                 }
 
                 s.storedCombinedCRC = 0_u32;
@@ -478,7 +492,7 @@ pub(crate) fn decompress(
                 uc = GET_BYTE!(strm, s);
 
                 if uc != 0x59 {
-                    error!(BZ_DATA_ERROR);
+                    error!(BZ_DATA_ERROR, BlockMagic);
                 }
 
                 s.currBlockNo += 1;
@@ -598,16 +612,23 @@ pub(crate) fn decompress(
 
             s.origPtr = s.origPtr << 8 | i32::from(uc);
             if !(0..10 + 100000 * i32::from(s.blockSize100k)).contains(&s.origPtr) {
-                error!(BZ_DATA_ERROR);
+                error!(BZ_DATA_ERROR, OrigPtr);
             }
 
             i = 0;
             current_block = Block43;
         }
 
+        // the buffers are larger than the block size when they are reused from a previous stream
+        let block_len = usize::from(s.blockSize100k) * 100000;
+
         // mutable because they need to be reborrowed
         let tt = s.tt.as_mut_slice();
+        let tt_len = Ord::min(tt.len(), block_len);
+        let tt = &mut tt[..tt_len];
         let ll16 = s.ll16.as_mut_slice();
+        let ll16_len = Ord::min(ll16.len(), block_len);
+        let ll16 = &mut ll16[..ll16_len];
         let ll4 = s.ll4.as_mut_slice();
 
         'state_machine: loop {
@@ -651,7 +672,7 @@ pub(crate) fn decompress(
                         current_block = BZ_X_SELECTOR_2;
                         continue;
                     }
-                    error!(BZ_DATA_ERROR);
+                    error!(BZ_DATA_ERROR, TableCount);
                 }
                 BZ_X_SELECTOR_2 => {
                     s.state = State::BZ_X_SELECTOR_2;
@@ -659,7 +680,7 @@ pub(crate) fn decompress(
                     nSelectors = GET_BITS!(strm, s, 15) as u16;
 
                     if nSelectors < 1 {
-                        error!(BZ_DATA_ERROR);
+                        error!(BZ_DATA_ERROR, SelectorCount);
                     } else {
                         i = 0;
                     }
@@ -675,7 +696,7 @@ pub(crate) fn decompress(
                     } else {
                         j += 1;
                         if j >= i32::from(nGroups) {
-                            error!(BZ_DATA_ERROR);
+                            error!(BZ_DATA_ERROR, Selector);
                         } else {
                             current_block = Block25;
                         }
@@ -715,6 +736,7 @@ pub(crate) fn decompress(
                 BZ_X_MTF_1 => {
                     s.state = State::BZ_X_MTF_1;
 
+                    peek_code_length!(strm, s);
                     zvec = GET_BITS!(strm, s, zn as i32) as i32;
 
                     current_block = Block56;
@@ -730,6 +752,7 @@ pub(crate) fn decompress(
                 BZ_X_MTF_3 => {
                     s.state = State::BZ_X_MTF_3;
 
+                    peek_code_length!(strm, s);
                     zvec = GET_BITS!(strm, s, zn as i32) as i32;
 
                     current_block = Block52;
@@ -745,6 +768,7 @@ pub(crate) fn decompress(
                 BZ_X_MTF_5 => {
                     s.state = State::BZ_X_MTF_5;
 
+                    peek_code_length!(strm, s);
                     zvec = GET_BITS!(strm, s, zn as i32) as i32;
 
                     current_block = Block24;
@@ -763,12 +787,12 @@ pub(crate) fn decompress(
                 ($next_block:ident) => {
                     if zn > 20 {
                         // zn is higher than the longest code, that's invalid input
-                        error!(BZ_DATA_ERROR);
+                        error!(BZ_DATA_ERROR, HuffmanCode);
                     } else if zvec <= s.limit[usize::from(gSel)][zn as usize] {
                         let index = zvec - s.base[usize::from(gSel)][zn as usize];
                         match s.perm[usize::from(gSel)].get(index as usize) {
                             Some(&nextSym) => nextSym,
-                            None => error!(BZ_DATA_ERROR),
+                            None => error!(BZ_DATA_ERROR, HuffmanCode),
                         }
                     } else {
                         zn += 1;
@@ -795,14 +819,14 @@ pub(crate) fn decompress(
                             DecompressMode::Small => {
                                 match ll16.get_mut(nblock as usize..(nblock + es) as usize) {
                                     Some(slice) => slice.fill(u16::from(uc)),
-                                    None => error!(BZ_DATA_ERROR),
+                                    None => error!(BZ_DATA_ERROR, BlockOverflow),
                                 };
                                 nblock += es;
                             }
                             DecompressMode::Fast => {
                                 match tt.get_mut(nblock as usize..(nblock + es) as usize) {
                                     Some(slice) => slice.fill(u32::from(uc)),
-                                    None => error!(BZ_DATA_ERROR),
+                                    None => error!(BZ_DATA_ERROR, BlockOverflow),
                                 };
                                 nblock += es;
                             }
@@ -824,7 +848,7 @@ pub(crate) fn decompress(
                     logN = 0;
                     current_block = Block46;
                 } else if nblock >= 100000 * u32::from(nblockMAX100k) {
-                    error!(BZ_DATA_ERROR);
+                    error!(BZ_DATA_ERROR, BlockOverflow);
                 } else {
                     let uc = usize::from(initialize_mtfa(&mut s.mtfa, &mut s.mtfbase, nextSym));
                     let index = s.seqToUnseq[uc];
@@ -843,10 +867,10 @@ pub(crate) fn decompress(
                     Block46 => {}
                     _ => {
                         if s.origPtr < 0 || s.origPtr >= nblock as i32 {
-                            error!(BZ_DATA_ERROR);
+                            error!(BZ_DATA_ERROR, OrigPtr);
                         } else {
                             if s.unzftab.iter().any(|e| !(0..=nblock).contains(e)) {
-                                error!(BZ_DATA_ERROR);
+                                error!(BZ_DATA_ERROR, BlockData);
                             }
                             s.cftab[0] = 0;
                             s.cftab[1..].copy_from_slice(&s.unzftab);
@@ -854,12 +878,12 @@ pub(crate) fn decompress(
                                 s.cftab[i] += s.cftab[i - 1];
                             }
                             if s.cftab.iter().any(|e| !(0..=nblock).contains(e)) {
-                                error!(BZ_DATA_ERROR);
+                                error!(BZ_DATA_ERROR, BlockData);
                             }
                             // FIXME: use https://doc.rust-lang.org/std/primitive.slice.html#method.is_sorted
                             // when available in our MSRV (requires >= 1.82.0)
                             if s.cftab.windows(2).any(|w| w[0] > w[1]) {
-                                error!(BZ_DATA_ERROR);
+                                error!(BZ_DATA_ERROR, BlockData);
                             }
                             s.state_out_len = 0;
                             s.state_out_ch = 0;
@@ -923,7 +947,7 @@ pub(crate) fn decompress(
 
                                     s.k0 = index_into_f(s.tPos, &s.cftab);
                                     s.tPos = match ll16.get(s.tPos as usize) {
-                                        None => error!(BZ_DATA_ERROR),
+                                        None => error!(BZ_DATA_ERROR, BlockData),
                                         Some(&low_bits) => {
                                             let high_bits = ll4[(s.tPos >> 1) as usize]
                                                 >> (s.tPos << 2 & 0x4)
@@ -951,7 +975,7 @@ pub(crate) fn decompress(
 
                                     s.tPos = match tt.get(s.tPos as usize) {
                                         Some(&tPos) => tPos,
-                                        None => error!(BZ_DATA_ERROR),
+                                        None => error!(BZ_DATA_ERROR, BlockData),
                                     };
                                     s.k0 = (s.tPos & 0xff) as u8;
                                     s.tPos >>= 8;
@@ -981,7 +1005,7 @@ pub(crate) fn decompress(
                 const LOG_2MB: u8 = 21; // 2 * 1024 * 1024
 
                 if logN >= LOG_2MB {
-                    error!(BZ_DATA_ERROR);
+                    error!(BZ_DATA_ERROR, BlockOverflow);
                 } else {
                     let mul = match nextSym {
                         BZ_RUNA => 1,
@@ -1030,7 +1054,7 @@ pub(crate) fn decompress(
                                     for i in 0..usize::from(alphaSize) {
                                         loop {
                                             if !(1..=20).contains(&curr) {
-                                                error!(BZ_DATA_ERROR);
+                                                error!(BZ_DATA_ERROR, CodeLength);
                                             }
                                             if !GET_BIT!(strm, s) {
                                                 break;
@@ -1117,7 +1141,7 @@ pub(crate) fn decompress(
                             current_block = BZ_X_CODING_2;
                             continue 'state_machine;
                         }
-                        error!(BZ_DATA_ERROR);
+                        error!(BZ_DATA_ERROR, CodeLength);
                     }
                 }
                 i += 1;
@@ -1129,7 +1153,7 @@ pub(crate) fn decompress(
                     current_block = BZ_X_SELECTOR_1;
                 }
                 Block11 => {
-                    error!(BZ_DATA_ERROR);
+                    error!(BZ_DATA_ERROR, Mapping);
                 }
                 _ => {
                     if t < nGroups {
@@ -1158,6 +1182,7 @@ pub(crate) fn decompress(
                             minLen,
                             maxLen,
                         );
+                        huffman::create_lookup_table(&mut s.lookup[t], &s.limit[t], minLen);
                     }
 
                     /*--- Now the MTF values ---*/
@@ -1218,9 +1243,63 @@ pub(crate) fn decompress(
     strm.total_in_lo32 = strm.total_in_lo32.wrapping_add(bytes_read);
     strm.total_in_hi32 += (strm.total_in_lo32 < old_total_in_lo32) as u32;
 
+    if error_kind != DataErrorKind::None {
+        s.record_data_error(strm, error_kind);
+    }
+
     ret_val
 }
 
+/// Allocates the buffers that hold the decoded block, based on `s.blockSize100k` and
+/// `s.reservedBlockSize100k`.
+///
+/// The buffers of a previous stream are reused when they are large enough.
+pub(crate) fn allocate_block_buffers(s: &mut DState, allocator: &Allocator) -> bool {
+    let blockSize100k = usize::from(Ord::max(s.blockSize100k, s.reservedBlockSize100k));
+
+    match s.smallDecompress {
+        DecompressMode::Small => {
+            let ll16_len = blockSize100k * 100000;
+            if s.ll16.as_slice().len() >= ll16_len {
+                return true;
+            }
+
+            s.deallocate_block_buffers(allocator);
+
+            // SAFETY: we assume allocation is safe
+            let Some(ll16) = DSlice::alloc(allocator, ll16_len) else {
+                return false;
+            };
+
+            // SAFETY: we assume allocation is safe
+            let ll4_len = (1 + blockSize100k * 100000) >> 1;
+            let Some(ll4) = DSlice::alloc(allocator, ll4_len) else {
+                return false;
+            };
+
+            s.ll16 = ll16;
+            s.ll4 = ll4;
+        }
+        DecompressMode::Fast => {
+            let tt_len = blockSize100k * 100000;
+            if s.tt.as_slice().len() >= tt_len {
+                return true;
+            }
+
+            s.deallocate_block_buffers(allocator);
+
+            // SAFETY: we assume allocation is safe
+            let Some(tt) = DSlice::alloc(allocator, tt_len) else {
+                return false;
+            };
+
+            s.tt = tt;
+        }
+    }
+
+    true
+}
+
 fn initialize_mtfa(mtfa: &mut [u8; 4096], mtfbase: &mut [u16; 16], nextSym: u16) -> u8 {
     let nn = usize::from(nextSym - 1);
 
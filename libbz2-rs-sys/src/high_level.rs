@@ -1,27 +1,29 @@
 #![allow(unsafe_op_in_unsafe_fn)]
 
-use core::ffi::{c_char, c_int, c_uint, c_void, CStr};
+use core::ffi::{c_char, c_int, c_long, c_uint, c_void, CStr};
 use core::{mem, ptr};
 
 use libc::FILE;
-use libc::{fclose, fdopen, ferror, fflush, fgetc, fopen, fread, fwrite, ungetc};
+use libc::{fclose, fdopen, ferror, fflush, fgetc, fopen, fread, fseek, ftell, fwrite, ungetc};
+use libc::{SEEK_CUR, SEEK_SET};
 
 use crate::allocator::Allocator;
 use crate::bzlib::prefix;
 use crate::bzlib::BZ_MAX_UNUSED_U32;
 use crate::bzlib::{bz_stream, BZ2_bzCompressEnd, BZ2_bzDecompressEnd};
-use crate::bzlib::{Action, BzStream, ReturnCode};
+use crate::bzlib::{Action, BlockInfo, BzStream, ReturnCode};
 use crate::bzlib::{
-    BZ2_bzCompressHelp, BZ2_bzCompressInitHelp, BZ2_bzDecompressHelp, BZ2_bzDecompressInitHelp,
+    BZ2_bzCompressHelp, BZ2_bzCompressInitHelp, BZ2_bzCompressSetBlockCallbackHelp,
+    BZ2_bzDecompressHelp, BZ2_bzDecompressInitHelp, BZ2_bzDecompressResetHelp,
 };
 use crate::BZ_MAX_UNUSED;
 
 #[cfg(doc)]
 use crate::{
-    BZ2_bzCompressInit, BZ2_bzDecompressInit, BZ_CONFIG_ERROR, BZ_DATA_ERROR, BZ_DATA_ERROR_MAGIC,
-    BZ_FINISH, BZ_FINISH_OK, BZ_FLUSH, BZ_FLUSH_OK, BZ_IO_ERROR, BZ_MEM_ERROR, BZ_OK,
-    BZ_OUTBUFF_FULL, BZ_PARAM_ERROR, BZ_RUN, BZ_RUN_OK, BZ_SEQUENCE_ERROR, BZ_STREAM_END,
-    BZ_UNEXPECTED_EOF,
+    BZ2_bzCompressInit, BZ2_bzCompressSetBlockCallback, BZ2_bzDecompressInit, BZ_CONFIG_ERROR,
+    BZ_DATA_ERROR, BZ_DATA_ERROR_MAGIC, BZ_FINISH, BZ_FINISH_OK, BZ_FLUSH, BZ_FLUSH_OK,
+    BZ_IO_ERROR, BZ_MEM_ERROR, BZ_OK, BZ_OUTBUFF_FULL, BZ_PARAM_ERROR, BZ_RUN, BZ_RUN_OK,
+    BZ_SEQUENCE_ERROR, BZ_STREAM_END, BZ_UNEXPECTED_EOF,
 };
 
 // FIXME remove this
@@ -71,7 +73,9 @@ macro_rules! STDOUT {
 /// This type is created by:
 ///
 /// - [`BZ2_bzReadOpen`]
+/// - [`BZ2_bzReadOpenCallbacks`]
 /// - [`BZ2_bzWriteOpen`]
+/// - [`BZ2_bzWriteOpenCallbacks`]
 /// - [`BZ2_bzopen`]
 ///
 /// And destructed by:
@@ -81,24 +85,174 @@ macro_rules! STDOUT {
 /// - [`BZ2_bzclose`]
 #[allow(non_camel_case_types)]
 pub struct BZFILE {
-    handle: *mut FILE,
+    handle: Handle,
     buf: [i8; BZ_MAX_UNUSED as usize],
     bufN: i32,
     strm: bz_stream,
     lastErr: ReturnCode,
     operation: Operation,
     initialisedOk: bool,
+    /// a byte that was pushed back by [`BZ2_bzungetc`]
+    ungot: Option<u8>,
+    /// the number of bytes read from the handle since it was opened for reading
+    nRead: c_long,
+    /// the bytes that [`BZ2_bzReadOpen`] decompresses before reading from the handle
+    unused: [i8; BZ_MAX_UNUSED as usize],
+    nUnused: i32,
 }
 
-unsafe fn myfeof(f: *mut FILE) -> bool {
-    let c = fgetc(f);
-    if c == -1 {
-        return true;
+/// The source or destination of the compressed data of a [`BZFILE`].
+///
+/// With `repr(C)`, a zeroed value is a `File` that holds a null pointer.
+#[repr(C)]
+enum Handle {
+    File(*mut FILE),
+    Callbacks(Callbacks),
+}
+
+/// The functions given to [`BZ2_bzReadOpenCallbacks`] or [`BZ2_bzWriteOpenCallbacks`].
+struct Callbacks {
+    read: Option<unsafe extern "C" fn(opaque: *mut c_void, buf: *mut c_void, len: c_int) -> c_int>,
+    write:
+        Option<unsafe extern "C" fn(opaque: *mut c_void, buf: *const c_void, len: c_int) -> c_int>,
+    error: Option<unsafe extern "C" fn(opaque: *mut c_void) -> c_int>,
+    opaque: *mut c_void,
+    /// `read` has reported the end of the input
+    eof: bool,
+    /// `read` or `write` returned a negative value
+    failed: bool,
+}
+
+impl Handle {
+    /// Whether the handle lacks a file, or a function that `operation` needs.
+    fn is_null(&self, operation: Operation) -> bool {
+        match self {
+            Handle::File(f) => f.is_null(),
+            Handle::Callbacks(callbacks) => {
+                callbacks.error.is_none()
+                    || match operation {
+                        Operation::Reading => callbacks.read.is_none(),
+                        Operation::Writing => callbacks.write.is_none(),
+                    }
+            }
+        }
+    }
+
+    /// Whether the error indicator of the handle is set. Analogous to [`libc::ferror`].
+    unsafe fn error(&self) -> bool {
+        match self {
+            Handle::File(f) => ferror(*f) != 0,
+            Handle::Callbacks(callbacks) => {
+                callbacks.failed
+                    || callbacks
+                        .error
+                        .is_some_and(|error| error(callbacks.opaque) != 0)
+            }
+        }
+    }
+
+    /// Whether the end of the input was reached.
+    unsafe fn eof(&mut self) -> bool {
+        match self {
+            Handle::File(f) => {
+                let c = fgetc(*f);
+                if c == -1 {
+                    return true;
+                }
+
+                ungetc(c, *f);
+
+                false
+            }
+            Handle::Callbacks(callbacks) => callbacks.eof,
+        }
+    }
+
+    /// Reads up to `buf.len()` bytes, and returns the number of bytes read.
+    unsafe fn read(&mut self, buf: &mut [i8]) -> usize {
+        match self {
+            Handle::File(f) => fread(
+                buf.as_mut_ptr().cast::<c_void>(),
+                mem::size_of::<u8>(),
+                buf.len(),
+                *f,
+            ),
+            Handle::Callbacks(callbacks) => {
+                let Some(read) = callbacks.read else {
+                    callbacks.failed = true;
+                    return 0;
+                };
+
+                match read(
+                    callbacks.opaque,
+                    buf.as_mut_ptr().cast::<c_void>(),
+                    buf.len() as c_int,
+                ) {
+                    0 => callbacks.eof = true,
+                    n if n < 0 || n as usize > buf.len() => callbacks.failed = true,
+                    n => return n as usize,
+                }
+
+                0
+            }
+        }
+    }
+
+    /// Writes the bytes of `buf`, and returns the number of bytes written.
+    unsafe fn write(&mut self, buf: &[i8]) -> usize {
+        match self {
+            Handle::File(f) => fwrite(
+                buf.as_ptr().cast::<c_void>(),
+                mem::size_of::<u8>(),
+                buf.len(),
+                *f,
+            ),
+            Handle::Callbacks(callbacks) => {
+                let Some(write) = callbacks.write else {
+                    callbacks.failed = true;
+                    return 0;
+                };
+
+                match write(
+                    callbacks.opaque,
+                    buf.as_ptr().cast::<c_void>(),
+                    buf.len() as c_int,
+                ) {
+                    n if n < 0 => {
+                        callbacks.failed = true;
+                        0
+                    }
+                    n => n as usize,
+                }
+            }
+        }
+    }
+
+    /// The current position of a file, or `None` if the handle is not seekable.
+    unsafe fn tell(&self) -> Option<c_long> {
+        match self {
+            Handle::File(f) => match ftell(*f) {
+                -1 => None,
+                pos => Some(pos),
+            },
+            Handle::Callbacks(_) => None,
+        }
     }
 
-    ungetc(c, f);
+    /// Moves a file to the position `pos`, and returns whether that succeeded.
+    unsafe fn seek(&mut self, pos: c_long) -> bool {
+        match self {
+            Handle::File(f) => fseek(*f, pos, SEEK_SET) == 0,
+            Handle::Callbacks(_) => false,
+        }
+    }
 
-    false
+    /// Flushes the buffered output of a file. Callbacks are not buffered.
+    unsafe fn flush(&mut self) {
+        if let Handle::File(f) = self {
+            fflush(*f);
+        }
+    }
 }
 
 macro_rules! BZ_SETERR_RAW {
@@ -164,12 +318,18 @@ pub unsafe extern "C" fn BZ2_bzWriteOpen(
     verbosity: c_int,
     workFactor: c_int,
 ) -> *mut BZFILE {
-    BZ2_bzWriteOpenHelp(bzerror.as_mut(), f, blockSize100k, verbosity, workFactor)
+    BZ2_bzWriteOpenHelp(
+        bzerror.as_mut(),
+        Handle::File(f),
+        blockSize100k,
+        verbosity,
+        workFactor,
+    )
 }
 
 unsafe fn BZ2_bzWriteOpenHelp(
     mut bzerror: Option<&mut c_int>,
-    f: *mut FILE,
+    handle: Handle,
     blockSize100k: c_int,
     verbosity: c_int,
     mut workFactor: c_int,
@@ -178,7 +338,7 @@ unsafe fn BZ2_bzWriteOpenHelp(
 
     BZ_SETERR_RAW!(bzerror, bzf, ReturnCode::BZ_OK);
 
-    if f.is_null()
+    if handle.is_null(Operation::Writing)
         || !(1..=9).contains(&blockSize100k)
         || !(0..=250).contains(&workFactor)
         || !(0..=4).contains(&verbosity)
@@ -187,7 +347,7 @@ unsafe fn BZ2_bzWriteOpenHelp(
         return ptr::null_mut();
     }
 
-    if ferror(f) != 0 {
+    if handle.error() {
         BZ_SETERR_RAW!(bzerror, bzf, ReturnCode::BZ_IO_ERROR);
         return ptr::null_mut();
     }
@@ -208,8 +368,9 @@ unsafe fn BZ2_bzWriteOpenHelp(
     BZ_SETERR!(bzerror, bzf, ReturnCode::BZ_OK);
 
     bzf.initialisedOk = false;
+    bzf.ungot = None;
     bzf.bufN = 0;
-    bzf.handle = f;
+    bzf.handle = handle;
     bzf.operation = Operation::Writing;
     bzf.strm.bzalloc = None;
     bzf.strm.bzfree = None;
@@ -240,6 +401,71 @@ unsafe fn BZ2_bzWriteOpenHelp(
     }
 }
 
+/// Prepare to write compressed data with the function `write`, instead of to a file handle.
+///
+/// The compressed data is passed to `write(opaque, buf, len)`, which should write all `len`
+/// bytes at `buf`, and return `len`. A negative return value, or a nonzero return value of
+/// `error(opaque)`, is reported as [`BZ_IO_ERROR`]. Otherwise the returned [`BZFILE`] behaves like
+/// one opened with [`BZ2_bzWriteOpen`], except that [`BZ2_bzclose`] does not close anything.
+///
+/// For the meaning of parameters `blockSize100k`, `verbosity` and `workFactor`, see [`BZ2_bzCompressInit`].
+///
+/// # Returns
+///
+/// - if `*bzerror` is [`BZ_OK`], a valid pointer to an abstract `BZFILE`
+/// - otherwise `NULL`
+///
+/// # Possible assignments to `bzerror`
+///
+/// - [`BZ_PARAM_ERROR`] if any of
+///     - `write.is_none()`
+///     - `error.is_none()`
+///     - `!(1..=9).contains(&blockSize100k)`
+///     - `!(0..=4).contains(&verbosity)`
+///     - `!(0..=250).contains(&workFactor)`
+/// - [`BZ_CONFIG_ERROR`] if no default allocator is configured
+/// - [`BZ_IO_ERROR`] if `error(opaque)` is nonzero
+/// - [`BZ_MEM_ERROR`] if insufficient memory is available
+/// - [`BZ_OK`] otherwise
+///
+/// # Safety
+///
+/// The caller must guarantee that
+///
+/// * `bzerror` satisfies the requirements of [`pointer::as_mut`]
+/// * `write` and `error` can be called with `opaque` until the `BZFILE` is closed
+///
+/// [`pointer::as_mut`]: https://doc.rust-lang.org/core/primitive.pointer.html#method.as_mut
+#[export_name = prefix!(BZ2_bzWriteOpenCallbacks)]
+pub unsafe extern "C" fn BZ2_bzWriteOpenCallbacks(
+    bzerror: *mut c_int,
+    write: Option<
+        unsafe extern "C" fn(opaque: *mut c_void, buf: *const c_void, len: c_int) -> c_int,
+    >,
+    error: Option<unsafe extern "C" fn(opaque: *mut c_void) -> c_int>,
+    opaque: *mut c_void,
+    blockSize100k: c_int,
+    verbosity: c_int,
+    workFactor: c_int,
+) -> *mut BZFILE {
+    let handle = Handle::Callbacks(Callbacks {
+        read: None,
+        write,
+        error,
+        opaque,
+        eof: false,
+        failed: false,
+    });
+
+    BZ2_bzWriteOpenHelp(
+        bzerror.as_mut(),
+        handle,
+        blockSize100k,
+        verbosity,
+        workFactor,
+    )
+}
+
 /// Absorbs `len` bytes from the buffer `buf`, eventually to be compressed and written to the file.
 ///
 /// # Returns
@@ -300,7 +526,7 @@ unsafe fn BZ2_bzWriteHelp(
         return;
     }
 
-    if ferror(bzf.handle) != 0 {
+    if bzf.handle.error() {
         BZ_SETERR!(bzerror, bzf, ReturnCode::BZ_IO_ERROR);
         return;
     }
@@ -323,13 +549,8 @@ unsafe fn BZ2_bzWriteHelp(
             ReturnCode::BZ_RUN_OK => {
                 if bzf.strm.avail_out < BZ_MAX_UNUSED_U32 {
                     let n1 = (BZ_MAX_UNUSED_U32 - bzf.strm.avail_out) as usize;
-                    let n2 = fwrite(
-                        bzf.buf.as_mut_ptr().cast::<c_void>(),
-                        mem::size_of::<u8>(),
-                        n1,
-                        bzf.handle,
-                    );
-                    if n1 != n2 || ferror(bzf.handle) != 0 {
+                    let n2 = bzf.handle.write(&bzf.buf[..n1]);
+                    if n1 != n2 || bzf.handle.error() {
                         BZ_SETERR!(bzerror, bzf, ReturnCode::BZ_IO_ERROR);
                         return;
                     }
@@ -347,6 +568,64 @@ unsafe fn BZ2_bzWriteHelp(
     }
 }
 
+/// Sets a function that is called for every block that is written to the compressed file.
+///
+/// See [`BZ2_bzCompressSetBlockCallback`] for the meaning of `callback` and `opaque`. The
+/// `compressed_bit_offset` of a block is relative to the position of the file when `b` was
+/// opened. The last block is reported by [`BZ2_bzWriteClose`].
+///
+/// # Possible assignments to `bzerror`
+///
+/// - [`BZ_PARAM_ERROR`] if `b.is_null()`
+/// - [`BZ_SEQUENCE_ERROR`] if b was opened with [`BZ2_bzReadOpen`]
+/// - [`BZ_OK`] otherwise
+///
+/// # Safety
+///
+/// The caller must guarantee that
+///
+/// * `bzerror` satisfies the requirements of [`pointer::as_mut`]
+/// * Either
+///     - `b` is `NULL`
+///     - `b` is initialized with [`BZ2_bzWriteOpen`] or [`BZ2_bzReadOpen`]
+/// * Either
+///     - `callback` is `None`
+///     - `callback` can be called with `opaque` until it is replaced, or `b` is closed
+///
+/// [`pointer::as_mut`]: https://doc.rust-lang.org/core/primitive.pointer.html#method.as_mut
+#[export_name = prefix!(BZ2_bzWriteSetBlockCallback)]
+pub unsafe extern "C" fn BZ2_bzWriteSetBlockCallback(
+    bzerror: *mut c_int,
+    b: *mut BZFILE,
+    callback: Option<unsafe extern "C" fn(opaque: *mut c_void, info: *const BlockInfo)>,
+    opaque: *mut c_void,
+) {
+    BZ2_bzWriteSetBlockCallbackHelp(bzerror.as_mut(), b.as_mut(), callback, opaque)
+}
+
+unsafe fn BZ2_bzWriteSetBlockCallbackHelp(
+    mut bzerror: Option<&mut c_int>,
+    mut b: Option<&mut BZFILE>,
+    callback: Option<unsafe extern "C" fn(opaque: *mut c_void, info: *const BlockInfo)>,
+    opaque: *mut c_void,
+) {
+    BZ_SETERR_RAW!(bzerror, b, ReturnCode::BZ_OK);
+
+    let Some(bzf) = b.as_mut() else {
+        BZ_SETERR_RAW!(bzerror, b, ReturnCode::BZ_PARAM_ERROR);
+        return;
+    };
+
+    if !matches!(bzf.operation, Operation::Writing) {
+        BZ_SETERR!(bzerror, bzf, ReturnCode::BZ_SEQUENCE_ERROR);
+        return;
+    }
+
+    let strm = BzStream::from_mut(&mut bzf.strm);
+    let ret = BZ2_bzCompressSetBlockCallbackHelp(strm, callback, opaque);
+    BZ_SETERR!(bzerror, bzf, ret);
+}
+
 /// Compresses and flushes to the compressed file all data so far supplied by [`BZ2_bzWrite`].
 ///
 /// The logical end-of-stream markers are also written, so subsequent calls to [`BZ2_bzWrite`] are illegal.
@@ -476,7 +755,7 @@ unsafe fn BZ2_bzWriteClose64Help(
         return;
     }
 
-    if ferror(bzf.handle) != 0 {
+    if bzf.handle.error() {
         BZ_SETERR!(bzerror, bzf, ReturnCode::BZ_IO_ERROR);
         return;
     }
@@ -502,13 +781,8 @@ unsafe fn BZ2_bzWriteClose64Help(
                 ret @ (ReturnCode::BZ_FINISH_OK | ReturnCode::BZ_STREAM_END) => {
                     if bzf.strm.avail_out < BZ_MAX_UNUSED_U32 {
                         let n1 = (BZ_MAX_UNUSED_U32 - bzf.strm.avail_out) as usize;
-                        let n2 = fwrite(
-                            bzf.buf.as_mut_ptr().cast::<c_void>(),
-                            mem::size_of::<u8>(),
-                            n1,
-                            bzf.handle,
-                        );
-                        if n1 != n2 || ferror(bzf.handle) != 0 {
+                        let n2 = bzf.handle.write(&bzf.buf[..n1]);
+                        if n1 != n2 || bzf.handle.error() {
                             BZ_SETERR!(bzerror, bzf, ReturnCode::BZ_IO_ERROR);
                         }
                     }
@@ -525,9 +799,9 @@ unsafe fn BZ2_bzWriteClose64Help(
         }
     }
 
-    if abandon == 0 && ferror(bzf.handle) == 0 {
-        fflush(bzf.handle);
-        if ferror(bzf.handle) != 0 {
+    if abandon == 0 && !bzf.handle.error() {
+        bzf.handle.flush();
+        if bzf.handle.error() {
             BZ_SETERR!(bzerror, bzf, ReturnCode::BZ_IO_ERROR);
             return;
         }
@@ -610,12 +884,19 @@ pub unsafe extern "C" fn BZ2_bzReadOpen(
     unused: *mut c_void,
     nUnused: c_int,
 ) -> *mut BZFILE {
-    BZ2_bzReadOpenHelp(bzerror.as_mut(), f, verbosity, small, unused, nUnused)
+    BZ2_bzReadOpenHelp(
+        bzerror.as_mut(),
+        Handle::File(f),
+        verbosity,
+        small,
+        unused,
+        nUnused,
+    )
 }
 
 unsafe fn BZ2_bzReadOpenHelp(
     mut bzerror: Option<&mut c_int>,
-    f: *mut FILE,
+    handle: Handle,
     verbosity: c_int,
     small: c_int,
     unused: *mut c_void,
@@ -625,7 +906,7 @@ unsafe fn BZ2_bzReadOpenHelp(
 
     BZ_SETERR_RAW!(bzerror, bzf, ReturnCode::BZ_OK);
 
-    if f.is_null()
+    if handle.is_null(Operation::Reading)
         || !(0..=1).contains(&small)
         || !(0..=4).contains(&verbosity)
         || (unused.is_null() && nUnused != 0)
@@ -635,7 +916,7 @@ unsafe fn BZ2_bzReadOpenHelp(
         return ptr::null_mut::<BZFILE>();
     }
 
-    if ferror(f) != 0 {
+    if handle.error() {
         BZ_SETERR_RAW!(bzerror, bzf, ReturnCode::BZ_IO_ERROR);
         return ptr::null_mut::<BZFILE>();
     }
@@ -656,7 +937,8 @@ unsafe fn BZ2_bzReadOpenHelp(
     BZ_SETERR!(bzerror, bzf, ReturnCode::BZ_OK);
 
     bzf.initialisedOk = false;
-    bzf.handle = f;
+    bzf.ungot = None;
+    bzf.handle = handle;
     bzf.bufN = 0;
     bzf.operation = Operation::Reading;
     bzf.strm.bzalloc = None;
@@ -672,6 +954,11 @@ unsafe fn BZ2_bzReadOpenHelp(
         bzf.bufN += nUnused;
     }
 
+    // needed to go back to the start of the compressed data
+    bzf.nRead = 0;
+    bzf.nUnused = bzf.bufN;
+    bzf.unused = bzf.buf;
+
     match BZ2_bzDecompressInitHelp(BzStream::from_mut(&mut bzf.strm), verbosity, small) {
         ReturnCode::BZ_OK => {
             bzf.strm.avail_in = bzf.bufN as c_uint;
@@ -690,6 +977,69 @@ unsafe fn BZ2_bzReadOpenHelp(
     bzf as *mut BZFILE
 }
 
+/// Prepare to read compressed data with the function `read`, instead of from a file handle.
+///
+/// The compressed data is obtained from `read(opaque, buf, len)`, which should store at most `len`
+/// bytes at `buf`, and return the number of bytes stored. It returns 0 at the end of the input,
+/// after which it is not called again. A negative return value, or a nonzero return value of
+/// `error(opaque)`, is reported as [`BZ_IO_ERROR`]. Otherwise the returned [`BZFILE`] behaves like
+/// one opened with [`BZ2_bzReadOpen`], except that [`BZ2_bzclose`] does not close anything.
+///
+/// For the meaning of parameters `verbosity`, `small`, `unused` and `nUnused`, see [`BZ2_bzReadOpen`].
+///
+/// # Returns
+///
+/// - if `*bzerror` is [`BZ_OK`], a valid pointer to an abstract `BZFILE`
+/// - otherwise `NULL`
+///
+/// # Possible assignments to `bzerror`
+///
+/// - [`BZ_PARAM_ERROR`] if any of
+///     - `read.is_none()`
+///     - `error.is_none()`
+///     - `(unused.is_null() && nUnused != 0)`
+///     - `(!unused.is_null() && !(0..=BZ_MAX_UNUSED).contains(&nUnused))`
+///     - `!(0..=1).contains(&small)`
+///     - `!(0..=4).contains(&verbosity)`
+/// - [`BZ_CONFIG_ERROR`] if no default allocator is configured
+/// - [`BZ_IO_ERROR`] if `error(opaque)` is nonzero
+/// - [`BZ_MEM_ERROR`] if insufficient memory is available
+/// - [`BZ_OK`] otherwise
+///
+/// # Safety
+///
+/// The caller must guarantee that
+///
+/// * `bzerror` satisfies the requirements of [`pointer::as_mut`]
+/// * `read` and `error` can be called with `opaque` until the `BZFILE` is closed
+/// * Either
+///     - `unused` is `NULL`
+///     - `unused` is readable for `nUnused` bytes
+///
+/// [`pointer::as_mut`]: https://doc.rust-lang.org/core/primitive.pointer.html#method.as_mut
+#[export_name = prefix!(BZ2_bzReadOpenCallbacks)]
+pub unsafe extern "C" fn BZ2_bzReadOpenCallbacks(
+    bzerror: *mut c_int,
+    read: Option<unsafe extern "C" fn(opaque: *mut c_void, buf: *mut c_void, len: c_int) -> c_int>,
+    error: Option<unsafe extern "C" fn(opaque: *mut c_void) -> c_int>,
+    opaque: *mut c_void,
+    verbosity: c_int,
+    small: c_int,
+    unused: *mut c_void,
+    nUnused: c_int,
+) -> *mut BZFILE {
+    let handle = Handle::Callbacks(Callbacks {
+        read,
+        write: None,
+        error,
+        opaque,
+        eof: false,
+        failed: false,
+    });
+
+    BZ2_bzReadOpenHelp(bzerror.as_mut(), handle, verbosity, small, unused, nUnused)
+}
+
 /// Releases all memory associated with a [`BZFILE`] opened with [`BZ2_bzReadOpen`].
 ///
 /// This function does not call `fclose` on the underlying file handle, the caller should close the
@@ -819,20 +1169,16 @@ unsafe fn BZ2_bzReadHelp(
     bzf.strm.avail_out = len as c_uint;
     bzf.strm.next_out = buf as *mut c_char;
     loop {
-        if ferror(bzf.handle) != 0 {
+        if bzf.handle.error() {
             BZ_SETERR!(bzerror, bzf, ReturnCode::BZ_IO_ERROR);
             return 0;
         }
 
-        if bzf.strm.avail_in == 0 && !myfeof(bzf.handle) {
-            let n = fread(
-                (bzf.buf).as_mut_ptr() as *mut c_void,
-                ::core::mem::size_of::<u8>(),
-                5000,
-                bzf.handle,
-            ) as i32;
+        if bzf.strm.avail_in == 0 && !bzf.handle.eof() {
+            let n = bzf.handle.read(&mut bzf.buf) as i32;
+            bzf.nRead = bzf.nRead.saturating_add(c_long::from(n));
 
-            if ferror(bzf.handle) != 0 {
+            if bzf.handle.error() {
                 BZ_SETERR!(bzerror, bzf, ReturnCode::BZ_IO_ERROR);
                 return 0;
             }
@@ -844,7 +1190,7 @@ unsafe fn BZ2_bzReadHelp(
 
         match BZ2_bzDecompressHelp(unsafe { BzStream::from_mut(&mut bzf.strm) }) {
             ReturnCode::BZ_OK => {
-                if myfeof(bzf.handle) && bzf.strm.avail_in == 0 && bzf.strm.avail_out > 0 {
+                if bzf.handle.eof() && bzf.strm.avail_in == 0 && bzf.strm.avail_out > 0 {
                     BZ_SETERR!(bzerror, bzf, ReturnCode::BZ_UNEXPECTED_EOF);
                     return 0;
                 } else if bzf.strm.avail_out == 0 {
@@ -951,42 +1297,125 @@ enum OpenMode {
     FileDescriptor(i32),
 }
 
-unsafe fn bzopen_or_bzdopen(path: Option<&CStr>, open_mode: OpenMode, mode: &CStr) -> *mut BZFILE {
-    let mut bzerr = 0;
-    let mut unused: [c_char; BZ_MAX_UNUSED as usize] = [0; BZ_MAX_UNUSED as usize];
+/// The settings of [`BZ2_bzopen`] and [`BZ2_bzdopen`] that are given by their `mode` string.
+struct Mode {
+    operation: Operation,
+    append: bool,
+    exclusive: bool,
+    closeOnExec: bool,
+    small: bool,
+    blockSize100k: c_int,
+    verbosity: c_int,
+    workFactor: c_int,
+}
 
-    let mut blockSize100k = 9;
-    let verbosity = 0;
-    let workFactor = 30;
-    let nUnused = 0;
+impl Mode {
+    /// Parses a `mode` string, or returns `None` if it is not valid.
+    fn parse(mode: &[u8]) -> Option<Self> {
+        let mut parsed = Self {
+            operation: Operation::Reading,
+            append: false,
+            exclusive: false,
+            closeOnExec: false,
+            small: false,
+            blockSize100k: 9,
+            verbosity: 0,
+            workFactor: 30,
+        };
 
-    let mut smallMode = false;
-    let mut operation = Operation::Reading;
+        let mut bytes = mode.iter().copied().peekable();
+        while let Some(c) = bytes.next() {
+            match c {
+                b'r' => {
+                    parsed.operation = Operation::Reading;
+                    parsed.append = false;
+                }
+                b'w' => {
+                    parsed.operation = Operation::Writing;
+                    parsed.append = false;
+                }
+                b'a' => {
+                    parsed.operation = Operation::Writing;
+                    parsed.append = true;
+                }
+                b'x' => parsed.exclusive = true,
+                b'e' => parsed.closeOnExec = true,
+                b's' => parsed.small = true,
+                b'v' => parsed.verbosity += 1,
+                b'0'..=b'9' => parsed.blockSize100k = c_int::from(c - b'0'),
+                b'f' => {
+                    let mut workFactor = None;
+                    while let Some(digit) = bytes.next_if(u8::is_ascii_digit) {
+                        let value = workFactor.unwrap_or(0) * 10 + c_int::from(digit - b'0');
+                        workFactor = Some(value.min(1000));
+                    }
+                    parsed.workFactor = workFactor?;
+                }
+                _ => {}
+            }
+        }
 
-    for c in mode.to_bytes() {
-        match c {
-            b'r' => operation = Operation::Reading,
-            b'w' => operation = Operation::Writing,
-            b's' => smallMode = true,
-            b'0'..=b'9' => blockSize100k = (*c - b'0') as i32,
-            _ => {}
+        if parsed.verbosity > 4 || parsed.workFactor > 250 {
+            return None;
         }
+
+        Some(parsed)
     }
 
-    let mode = match open_mode {
-        OpenMode::Pointer => match operation {
-            Operation::Reading => b"rbe\0".as_slice(),
-            Operation::Writing => b"rbe\0".as_slice(),
-        },
-        OpenMode::FileDescriptor(_) => match operation {
-            Operation::Reading => b"rb\0".as_slice(),
-            Operation::Writing => b"rb\0".as_slice(),
-        },
+    /// The `mode` argument of `fopen` or `fdopen`.
+    fn stdio_mode(&self, open_mode: &OpenMode) -> [u8; 5] {
+        let mut mode = [0; 5];
+        let mut len = 0;
+        let mut push = |c| {
+            mode[len] = c;
+            len += 1;
+        };
+
+        push(match (self.operation, self.append) {
+            (Operation::Reading, _) => b'r',
+            (Operation::Writing, false) => b'w',
+            (Operation::Writing, true) => b'a',
+        });
+        push(b'b');
+
+        // an open file descriptor can not be created exclusively. Files are opened with
+        // `O_CLOEXEC` only when we are the initiator, and `fdopen` does not generally support `e`
+        if let OpenMode::Pointer = open_mode {
+            if self.exclusive && matches!(self.operation, Operation::Writing) {
+                push(b'x');
+            }
+
+            push(b'e');
+        }
+
+        mode
+    }
+}
+
+#[cfg(unix)]
+unsafe fn set_close_on_exec(fd: c_int) {
+    let flags = libc::fcntl(fd, libc::F_GETFD);
+    if flags != -1 {
+        libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC);
+    }
+}
+
+#[cfg(not(unix))]
+unsafe fn set_close_on_exec(_fd: c_int) {}
+
+unsafe fn bzopen_or_bzdopen(path: Option<&CStr>, open_mode: OpenMode, mode: &CStr) -> *mut BZFILE {
+    let mut bzerr = 0;
+    let mut unused: [c_char; BZ_MAX_UNUSED as usize] = [0; BZ_MAX_UNUSED as usize];
+    let nUnused = 0;
+
+    let Some(mode) = Mode::parse(mode.to_bytes()) else {
+        return ptr::null_mut();
     };
 
-    let mode2 = mode.as_ptr().cast_mut().cast::<c_char>();
+    let stdio_mode = mode.stdio_mode(&open_mode);
+    let mode2 = stdio_mode.as_ptr().cast::<c_char>();
 
-    let default_file = match operation {
+    let default_file = match mode.operation {
         Operation::Reading => STDIN!(),
         Operation::Writing => STDOUT!(),
     };
@@ -997,28 +1426,33 @@ unsafe fn bzopen_or_bzdopen(path: Option<&CStr>, open_mode: OpenMode, mode: &CSt
             Some(path) if path.is_empty() => default_file,
             Some(path) => fopen(path.as_ptr(), mode2),
         },
-        OpenMode::FileDescriptor(fd) => fdopen(fd, mode2),
+        OpenMode::FileDescriptor(fd) => {
+            if mode.closeOnExec {
+                set_close_on_exec(fd);
+            }
+            fdopen(fd, mode2)
+        }
     };
 
     if fp.is_null() {
         return ptr::null_mut();
     }
 
-    let bzfp = match operation {
+    let bzfp = match mode.operation {
         Operation::Reading => BZ2_bzReadOpen(
             &mut bzerr,
             fp,
-            verbosity,
-            smallMode as i32,
+            mode.verbosity,
+            mode.small as i32,
             unused.as_mut_ptr() as *mut c_void,
             nUnused,
         ),
         Operation::Writing => BZ2_bzWriteOpen(
             &mut bzerr,
             fp,
-            blockSize100k.clamp(1, 9),
-            verbosity,
-            workFactor,
+            mode.blockSize100k.clamp(1, 9),
+            mode.verbosity,
+            mode.workFactor,
         ),
     };
 
@@ -1034,6 +1468,26 @@ unsafe fn bzopen_or_bzdopen(path: Option<&CStr>, open_mode: OpenMode, mode: &CSt
 
 /// Opens a `.bz2` file for reading or writing using its name. Analogous to [`libc::fopen`].
 ///
+/// The `mode` string consists of the following characters, of which unknown ones are ignored:
+///
+/// - `r` to decompress the file (the default), `w` to compress to it, or `a` to append a new
+///   compressed stream to it. When these conflict, the last one is used
+/// - `1` to `9` for the block size of the compressor, `9` by default
+/// - `s` to decompress using less memory, see [`BZ2_bzDecompressInit`]
+/// - `v` to increase the verbosity by one, at most four times
+/// - `f` followed by a number for the work factor of the compressor, at most 250
+/// - `x` to fail if the file to write already exists
+/// - `e` to close the file when a new program is executed. [`BZ2_bzopen`] always does this, for C
+///   libraries that support it
+///
+/// A file that is appended to is a multi-stream archive. [`BZ2_bzread`] only reads its first
+/// stream, while e.g. the `bzip2` program decompresses all of them.
+///
+/// # Returns
+///
+/// - `NULL` if `mode` is `NULL` or not valid, or the file cannot be opened
+/// - a [`BZFILE`] otherwise
+///
 /// # Safety
 ///
 /// The caller must guarantee that
@@ -1065,6 +1519,14 @@ pub unsafe extern "C" fn BZ2_bzopen(path: *const c_char, mode: *const c_char) ->
 
 /// Opens a `.bz2` file for reading or writing using a pre-existing file descriptor. Analogous to [`libc::fdopen`].
 ///
+/// The `mode` string is the same as for [`BZ2_bzopen`], except that `x` is ignored, and `e` sets
+/// the close-on-exec flag of `fd` on unix.
+///
+/// # Returns
+///
+/// - `NULL` if `mode` is `NULL` or not valid, or the file cannot be opened
+/// - a [`BZFILE`] otherwise
+///
 /// # Safety
 ///
 /// The caller must guarantee that
@@ -1111,18 +1573,33 @@ pub unsafe extern "C" fn BZ2_bzread(b: *mut BZFILE, buf: *mut c_void, len: c_int
     BZ2_bzreadHelp(b.as_mut(), buf, len)
 }
 
-unsafe fn BZ2_bzreadHelp(mut b: Option<&mut BZFILE>, buf: *mut c_void, len: c_int) -> c_int {
+unsafe fn BZ2_bzreadHelp(
+    mut b: Option<&mut BZFILE>,
+    mut buf: *mut c_void,
+    mut len: c_int,
+) -> c_int {
     let mut bzerr = 0;
+    let mut ungot = 0;
 
     if let Some(b) = b.as_deref_mut() {
+        // a byte that was pushed back by `BZ2_bzungetc` comes first
+        if !buf.is_null() && len > 0 {
+            if let Some(c) = b.ungot.take() {
+                buf.cast::<u8>().write(c);
+                buf = buf.cast::<u8>().add(1).cast::<c_void>();
+                len -= 1;
+                ungot = 1;
+            }
+        }
+
         if b.lastErr == ReturnCode::BZ_STREAM_END {
-            return 0;
+            return ungot;
         }
     }
 
     let nread = BZ2_bzReadHelp(Some(&mut bzerr), b, buf, len);
     if bzerr == 0 || bzerr == ReturnCode::BZ_STREAM_END as i32 {
-        nread
+        ungot + nread
     } else {
         -1
     }
@@ -1163,9 +1640,13 @@ unsafe fn BZ2_bzwriteHelp(b: Option<&mut BZFILE>, buf: *const c_void, len: c_int
     }
 }
 
-/// Flushes a [`BZFILE`].
+/// Reads one (uncompressed) byte from the compressed file `b`.
 ///
-/// Analogous to [`libc::fflush`].
+/// Analogous to [`libc::fgetc`].
+///
+/// # Returns
+///
+/// The byte as an `unsigned char` cast to `int`, or `-1` at the end of the file or on failure.
 ///
 /// # Safety
 ///
@@ -1174,15 +1655,33 @@ unsafe fn BZ2_bzwriteHelp(b: Option<&mut BZFILE>, buf: *const c_void, len: c_int
 /// * Either
 ///     - `b` is `NULL`
 ///     - `b` is initialized with [`BZ2_bzReadOpen`] or [`BZ2_bzWriteOpen`]
-#[export_name = prefix!(BZ2_bzflush)]
-pub unsafe extern "C" fn BZ2_bzflush(mut _b: *mut BZFILE) -> c_int {
-    /* do nothing now... */
-    0
+#[export_name = prefix!(BZ2_bzgetc)]
+pub unsafe extern "C" fn BZ2_bzgetc(b: *mut BZFILE) -> c_int {
+    BZ2_bzgetcHelp(b.as_mut())
 }
 
-/// Closes a [`BZFILE`].
+unsafe fn BZ2_bzgetcHelp(b: Option<&mut BZFILE>) -> c_int {
+    let mut c = 0u8;
+
+    match BZ2_bzreadHelp(b, ptr::addr_of_mut!(c).cast::<c_void>(), 1) {
+        1 => c_int::from(c),
+        _ => -1,
+    }
+}
+
+/// Pushes the byte `c` back into the compressed file `b`, to be read by the next read operation.
 ///
-/// Analogous to [`libc::fclose`].
+/// Analogous to [`libc::ungetc`]. One byte can be pushed back at a time. Pushing back a byte at
+/// the end of the file is allowed, and clears the end-of-file indicator of [`BZ2_bzeof`].
+///
+/// # Returns
+///
+/// - `c` on success
+/// - `-1` if any of
+///     - `b.is_null()`
+///     - `c < 0`
+///     - b was opened with [`BZ2_bzWriteOpen`]
+///     - a byte was already pushed back and has not been read yet
 ///
 /// # Safety
 ///
@@ -1191,35 +1690,601 @@ pub unsafe extern "C" fn BZ2_bzflush(mut _b: *mut BZFILE) -> c_int {
 /// * Either
 ///     - `b` is `NULL`
 ///     - `b` is initialized with [`BZ2_bzReadOpen`] or [`BZ2_bzWriteOpen`]
-#[export_name = prefix!(BZ2_bzclose)]
-pub unsafe extern "C" fn BZ2_bzclose(b: *mut BZFILE) {
-    BZ2_bzcloseHelp(b.as_mut())
+#[export_name = prefix!(BZ2_bzungetc)]
+pub unsafe extern "C" fn BZ2_bzungetc(c: c_int, b: *mut BZFILE) -> c_int {
+    BZ2_bzungetcHelp(c, b.as_mut())
 }
 
-unsafe fn BZ2_bzcloseHelp(mut b: Option<&mut BZFILE>) {
-    let mut bzerr: c_int = 0;
-
-    let operation = if let Some(bzf) = &mut b {
-        bzf.operation
-    } else {
-        return;
+fn BZ2_bzungetcHelp(c: c_int, b: Option<&mut BZFILE>) -> c_int {
+    let Some(bzf) = b else {
+        return -1;
     };
 
-    match operation {
-        Operation::Reading => {
-            BZ2_bzReadCloseHelp(Some(&mut bzerr), b.as_deref_mut());
-        }
+    if c < 0 || !matches!(bzf.operation, Operation::Reading) || bzf.ungot.is_some() {
+        return -1;
+    }
+
+    bzf.ungot = Some(c as u8);
+
+    c_int::from(c as u8)
+}
+
+/// Reads a line of (uncompressed) bytes from the compressed file `b` into the buffer `buf`.
+///
+/// Analogous to [`libc::fgets`]. Bytes are read until `len - 1` bytes have been read, a newline
+/// has been read, or the end of the file is reached. The newline is stored in `buf`, and the
+/// bytes are followed by a null byte.
+///
+/// # Returns
+///
+/// - `buf` on success
+/// - `NULL` if any of
+///     - `b.is_null()`
+///     - `buf.is_null()`
+///     - `len < 1`
+///     - the end of the file is reached before any byte is read, in which case `buf` is unchanged
+///     - an error occurred, in which case the contents of `buf` are unspecified
+///
+/// # Safety
+///
+/// The caller must guarantee that
+///
+/// * Either
+///     - `b` is `NULL`
+///     - `b` is initialized with [`BZ2_bzReadOpen`] or [`BZ2_bzWriteOpen`]
+/// * Either
+///     - `buf` is `NULL`
+///     - `buf` is writable for `len` bytes
+#[export_name = prefix!(BZ2_bzgets)]
+pub unsafe extern "C" fn BZ2_bzgets(b: *mut BZFILE, buf: *mut c_char, len: c_int) -> *mut c_char {
+    BZ2_bzgetsHelp(b.as_mut(), buf, len)
+}
+
+unsafe fn BZ2_bzgetsHelp(mut b: Option<&mut BZFILE>, buf: *mut c_char, len: c_int) -> *mut c_char {
+    if b.is_none() || buf.is_null() || len < 1 {
+        return ptr::null_mut();
+    }
+
+    let mut n = 0;
+    while n < len as usize - 1 {
+        let c = BZ2_bzgetcHelp(b.as_deref_mut());
+        if c == -1 {
+            break;
+        }
+
+        *buf.add(n) = c as c_char;
+        n += 1;
+
+        if c == c_int::from(b'\n') {
+            break;
+        }
+    }
+
+    let failed = b.is_some_and(|bzf| (bzf.lastErr as c_int) < 0);
+    if failed || (n == 0 && len > 1) {
+        return ptr::null_mut();
+    }
+
+    *buf.add(n) = 0;
+
+    buf
+}
+
+/// Absorbs the byte `c`, converted to an `unsigned char`, eventually to be compressed and written
+/// to the file.
+///
+/// Analogous to [`libc::fputc`].
+///
+/// # Returns
+///
+/// The byte that was written, or `-1` on failure.
+///
+/// # Safety
+///
+/// The caller must guarantee that
+///
+/// * Either
+///     - `b` is `NULL`
+///     - `b` is initialized with [`BZ2_bzWriteOpen`] or [`BZ2_bzReadOpen`]
+#[export_name = prefix!(BZ2_bzputc)]
+pub unsafe extern "C" fn BZ2_bzputc(b: *mut BZFILE, c: c_int) -> c_int {
+    BZ2_bzputcHelp(b.as_mut(), c)
+}
+
+unsafe fn BZ2_bzputcHelp(b: Option<&mut BZFILE>, c: c_int) -> c_int {
+    let c = c as u8;
+
+    match BZ2_bzwriteHelp(b, ptr::addr_of!(c).cast::<c_void>(), 1) {
+        1 => c_int::from(c),
+        _ => -1,
+    }
+}
+
+/// Absorbs the bytes of the null-terminated string `s`, without the null byte, eventually to be
+/// compressed and written to the file.
+///
+/// Analogous to [`libc::fputs`].
+///
+/// # Returns
+///
+/// The number of bytes written, or `-1` on failure.
+///
+/// # Safety
+///
+/// The caller must guarantee that
+///
+/// * Either
+///     - `b` is `NULL`
+///     - `b` is initialized with [`BZ2_bzWriteOpen`] or [`BZ2_bzReadOpen`]
+/// * Either
+///     - `s` is `NULL`
+///     - `s` is a null-terminated sequence of bytes
+#[export_name = prefix!(BZ2_bzputs)]
+pub unsafe extern "C" fn BZ2_bzputs(b: *mut BZFILE, s: *const c_char) -> c_int {
+    if s.is_null() {
+        return -1;
+    }
+
+    BZ2_bzputsHelp(b.as_mut(), CStr::from_ptr(s))
+}
+
+unsafe fn BZ2_bzputsHelp(b: Option<&mut BZFILE>, s: &CStr) -> c_int {
+    let Ok(len) = c_int::try_from(s.count_bytes()) else {
+        return -1;
+    };
+
+    BZ2_bzwriteHelp(b, s.as_ptr().cast::<c_void>(), len)
+}
+
+/// The maximum number of bytes that [`BZ2_bzvprintf`] writes.
+const BZ_PRINTF_MAX: usize = 8191;
+
+// like `libc` does for the printf family, see https://github.com/rust-lang/libc/issues/2860
+#[cfg_attr(
+    all(windows, target_env = "msvc"),
+    link(name = "legacy_stdio_definitions")
+)]
+extern "C" {
+    #[link_name = "vsnprintf"]
+    fn vsnprintf_opaque(s: *mut c_char, n: usize, format: *const c_char, ap: *mut c_void) -> c_int;
+}
+
+#[cfg(libbz2_rs_sys_printf)]
+extern "C" {
+    // the same function as above, with the `va_list` in its nightly rust type
+    #[allow(clashing_extern_declarations)]
+    fn vsnprintf(s: *mut c_char, n: usize, format: *const c_char, ap: core::ffi::VaList) -> c_int;
+}
+
+/// Formats the arguments in `ap` like [`libc::printf`], and absorbs the result, eventually to be
+/// compressed and written to the file.
+///
+/// Analogous to `vfprintf`. At most 8191 bytes are written per call. The `va_list` is passed on to
+/// `vsnprintf` unchanged, so `ap` must be a `va_list` in the calling convention of the platform's C
+/// compiler: a C caller passes its `va_list` directly.
+///
+/// # Returns
+///
+/// - the number of bytes written on success
+/// - `0` if the formatted output is empty, or longer than 8191 bytes, and nothing is written
+/// - `-1` on failure
+///
+/// # Safety
+///
+/// The caller must guarantee that
+///
+/// * Either
+///     - `b` is `NULL`
+///     - `b` is initialized with [`BZ2_bzWriteOpen`] or [`BZ2_bzReadOpen`]
+/// * Either
+///     - `format` is `NULL`
+///     - `format` is a valid format string for [`libc::printf`], and `ap` is a `va_list` with
+///       arguments for it, that was started with `va_start` and not yet ended with `va_end`
+#[export_name = prefix!(BZ2_bzvprintf)]
+pub unsafe extern "C" fn BZ2_bzvprintf(
+    b: *mut BZFILE,
+    format: *const c_char,
+    ap: *mut c_void,
+) -> c_int {
+    if format.is_null() {
+        return -1;
+    }
+
+    let mut buf = [0u8; BZ_PRINTF_MAX + 1];
+    let len = vsnprintf_opaque(buf.as_mut_ptr().cast::<c_char>(), buf.len(), format, ap);
+
+    BZ2_bzprintfHelp(b.as_mut(), &buf, len)
+}
+
+/// Formats the arguments like [`libc::printf`], and absorbs the result, eventually to be compressed
+/// and written to the file.
+///
+/// Analogous to [`libc::fprintf`]. At most 8191 bytes are written per call. Defining a C-variadic
+/// function requires a nightly compiler, so this function is only available when compiling with
+/// `--cfg libbz2_rs_sys_printf`. With a stable compiler, use [`BZ2_bzvprintf`].
+///
+/// # Returns
+///
+/// - the number of bytes written on success
+/// - `0` if the formatted output is empty, or longer than 8191 bytes, and nothing is written
+/// - `-1` on failure
+///
+/// # Safety
+///
+/// The caller must guarantee that
+///
+/// * Either
+///     - `b` is `NULL`
+///     - `b` is initialized with [`BZ2_bzWriteOpen`] or [`BZ2_bzReadOpen`]
+/// * Either
+///     - `format` is `NULL`
+///     - `format` is a valid format string for [`libc::printf`], for the given arguments
+#[cfg(libbz2_rs_sys_printf)]
+#[export_name = prefix!(BZ2_bzprintf)]
+pub unsafe extern "C" fn BZ2_bzprintf(b: *mut BZFILE, format: *const c_char, args: ...) -> c_int {
+    if format.is_null() {
+        return -1;
+    }
+
+    let mut buf = [0u8; BZ_PRINTF_MAX + 1];
+    let len = vsnprintf(buf.as_mut_ptr().cast::<c_char>(), buf.len(), format, args);
+
+    BZ2_bzprintfHelp(b.as_mut(), &buf, len)
+}
+
+/// Writes the first `len` bytes of `buf`, as formatted by `vsnprintf`.
+fn BZ2_bzprintfHelp(b: Option<&mut BZFILE>, buf: &[u8; BZ_PRINTF_MAX + 1], len: c_int) -> c_int {
+    match len {
+        ..0 => -1,
+        0 => 0,
+        len if len as usize > BZ_PRINTF_MAX => 0,
+        len => unsafe { BZ2_bzwriteHelp(b, buf.as_ptr().cast::<c_void>(), len) },
+    }
+}
+
+/// Tests the end-of-file indicator of the compressed file `b`.
+///
+/// Analogous to [`libc::feof`]. The indicator is set when a read operation reached the logical
+/// end-of-stream, and cleared by [`BZ2_bzungetc`].
+///
+/// # Returns
+///
+/// - `1` if `b` is opened for reading and the end-of-file indicator is set
+/// - `0` otherwise
+///
+/// # Safety
+///
+/// The caller must guarantee that
+///
+/// * Either
+///     - `b` is `NULL`
+///     - `b` is initialized with [`BZ2_bzReadOpen`] or [`BZ2_bzWriteOpen`]
+#[export_name = prefix!(BZ2_bzeof)]
+pub unsafe extern "C" fn BZ2_bzeof(b: *const BZFILE) -> c_int {
+    BZ2_bzeofHelp(b.as_ref())
+}
+
+fn BZ2_bzeofHelp(b: Option<&BZFILE>) -> c_int {
+    let Some(bzf) = b else {
+        return 0;
+    };
+
+    let eof = matches!(bzf.operation, Operation::Reading)
+        && bzf.lastErr == ReturnCode::BZ_STREAM_END
+        && bzf.ungot.is_none();
+
+    eof as c_int
+}
+
+/// Moves the (uncompressed) read position of the compressed file `b`.
+///
+/// Analogous to [`libc::fseek`]. The new position is `offset` bytes from the start of the file if
+/// `whence` is [`libc::SEEK_SET`], or from the current position if `whence` is [`libc::SEEK_CUR`].
+/// [`libc::SEEK_END`] is not supported.
+///
+/// Moving forward decompresses and discards the bytes in between, so it can be slow. Moving
+/// backward goes back to the start of the compressed data, and decompresses it again. That is
+/// only possible for a `BZFILE` whose `FILE` was seekable when it was opened, and not for one that
+/// was opened with [`BZ2_bzReadOpenCallbacks`]. Moving past the end of the file moves to the end.
+///
+/// # Returns
+///
+/// - the new position on success
+/// - `-1` if any of the following, in which case the position and error state are unchanged
+///     - `b.is_null()`
+///     - b was opened with [`BZ2_bzWriteOpen`]
+///     - `whence` is not supported, or the new position would be negative
+///     - the position is moved backward, but the handle is not seekable
+/// - `-1` if an error occurs while decompressing, which is described by [`BZ2_bzerror`]
+///
+/// # Safety
+///
+/// The caller must guarantee that
+///
+/// * Either
+///     - `b` is `NULL`
+///     - `b` is initialized with [`BZ2_bzReadOpen`] or [`BZ2_bzWriteOpen`]
+#[export_name = prefix!(BZ2_bzseek)]
+pub unsafe extern "C" fn BZ2_bzseek(b: *mut BZFILE, offset: c_long, whence: c_int) -> c_long {
+    BZ2_bzseekHelp(b.as_mut(), offset, whence)
+}
+
+unsafe fn BZ2_bzseekHelp(b: Option<&mut BZFILE>, offset: c_long, whence: c_int) -> c_long {
+    let Some(bzf) = b else {
+        return -1;
+    };
+
+    if !matches!(bzf.operation, Operation::Reading) {
+        return -1;
+    }
+
+    let Some(current) = position(bzf) else {
+        return -1;
+    };
+
+    let target = match whence {
+        SEEK_SET => offset,
+        SEEK_CUR => current.saturating_add(offset),
+        _ => -1,
+    };
+
+    if target < 0 {
+        return -1;
+    }
+
+    let mut skip = target - current;
+    if skip < 0 {
+        if !rewind(bzf) {
+            return -1;
+        }
+
+        skip = target;
+    }
+
+    if skip > 0 && bzf.ungot.take().is_some() {
+        skip -= 1;
+    }
+
+    // decompress and discard the bytes up to the new position
+    let mut discard = [0u8; 4096];
+    while skip > 0 {
+        let len = Ord::min(skip, discard.len() as c_long) as c_int;
+        match BZ2_bzreadHelp(Some(bzf), discard.as_mut_ptr().cast::<c_void>(), len) {
+            -1 => return -1,
+            0 => break,
+            n => skip -= c_long::from(n),
+        }
+    }
+
+    target - skip
+}
+
+/// Goes back to the start of the compressed data of `bzf`, which is opened for reading, and
+/// returns whether that succeeded. Nothing changes when the handle is not seekable.
+unsafe fn rewind(bzf: &mut BZFILE) -> bool {
+    // the position is not asked for when the file is opened, because a failing `ftell` sets `errno`
+    let Some(start) = bzf.handle.tell().and_then(|pos| pos.checked_sub(bzf.nRead)) else {
+        return false;
+    };
+
+    if !bzf.handle.seek(start) {
+        return false;
+    }
+
+    // the stream was initialized when the file was opened, so this does not fail
+    BZ2_bzDecompressResetHelp(BzStream::from_mut(&mut bzf.strm));
+
+    bzf.nRead = 0;
+    bzf.buf = bzf.unused;
+    bzf.bufN = bzf.nUnused;
+    bzf.strm.avail_in = bzf.bufN as c_uint;
+    bzf.strm.next_in = bzf.buf.as_mut_ptr().cast::<c_char>();
+    bzf.ungot = None;
+    bzf.lastErr = ReturnCode::BZ_OK;
+
+    true
+}
+
+/// The (uncompressed) position of `bzf`, or `None` if it does not fit in a `c_long`.
+fn position(bzf: &BZFILE) -> Option<c_long> {
+    let total = match bzf.operation {
+        Operation::Reading => {
+            let total_out =
+                u64::from(bzf.strm.total_out_hi32) << 32 | u64::from(bzf.strm.total_out_lo32);
+
+            // a byte that was pushed back is read again
+            total_out - u64::from(bzf.ungot.is_some())
+        }
+        Operation::Writing => {
+            u64::from(bzf.strm.total_in_hi32) << 32 | u64::from(bzf.strm.total_in_lo32)
+        }
+    };
+
+    c_long::try_from(total).ok()
+}
+
+/// The (uncompressed) position of the compressed file `b`.
+///
+/// Analogous to [`libc::ftell`]. For a `BZFILE` that is opened for reading, this is the number of
+/// bytes that has been read, less a byte that was pushed back by [`BZ2_bzungetc`]. For a `BZFILE`
+/// that is opened for writing, this is the number of bytes that has been written.
+///
+/// # Returns
+///
+/// - the position on success
+/// - `-1` if `b` is `NULL`, or if the position does not fit in a `long`
+///
+/// # Safety
+///
+/// The caller must guarantee that
+///
+/// * Either
+///     - `b` is `NULL`
+///     - `b` is initialized with [`BZ2_bzReadOpen`] or [`BZ2_bzWriteOpen`]
+#[export_name = prefix!(BZ2_bztell)]
+pub unsafe extern "C" fn BZ2_bztell(b: *const BZFILE) -> c_long {
+    match b.as_ref() {
+        Some(bzf) => position(bzf).unwrap_or(-1),
+        None => -1,
+    }
+}
+
+/// Moves the (uncompressed) read position of the compressed file `b` back to the start.
+///
+/// Analogous to [`libc::rewind`], and equivalent to `BZ2_bzseek(b, 0, SEEK_SET)`. See
+/// [`BZ2_bzseek`] for which handles can be rewound.
+///
+/// # Returns
+///
+/// - `0` on success
+/// - `-1` on failure, see [`BZ2_bzseek`]
+///
+/// # Safety
+///
+/// The caller must guarantee that
+///
+/// * Either
+///     - `b` is `NULL`
+///     - `b` is initialized with [`BZ2_bzReadOpen`] or [`BZ2_bzWriteOpen`]
+#[export_name = prefix!(BZ2_bzrewind)]
+pub unsafe extern "C" fn BZ2_bzrewind(b: *mut BZFILE) -> c_int {
+    match BZ2_bzseekHelp(b.as_mut(), 0, SEEK_SET) {
+        0 => 0,
+        _ => -1,
+    }
+}
+
+/// Flushes a [`BZFILE`].
+///
+/// Analogous to [`libc::fflush`].
+///
+/// For a `BZFILE` that is opened for writing, the current block is ended, the compressed data is
+/// written, and [`libc::fflush`] is called on the compressed file. Afterwards all data so far
+/// supplied by [`BZ2_bzWrite`] is in complete blocks on disk, except for the last at most 7 bits
+/// of the last block: blocks are not byte-aligned, so those bits are written along with the next
+/// block, or when the file is closed. Flushing often makes the compression worse, because every
+/// flush ends a block.
+///
+/// For a `BZFILE` that is opened for reading, this function does nothing.
+///
+/// # Returns
+///
+/// - `0` on success, or when `b` is `NULL`
+/// - `-1` if an earlier error occurred, or if there is an error writing to the compressed file
+///
+/// # Safety
+///
+/// The caller must guarantee that
+///
+/// * Either
+///     - `b` is `NULL`
+///     - `b` is initialized with [`BZ2_bzReadOpen`] or [`BZ2_bzWriteOpen`]
+#[export_name = prefix!(BZ2_bzflush)]
+pub unsafe extern "C" fn BZ2_bzflush(b: *mut BZFILE) -> c_int {
+    BZ2_bzflushHelp(b.as_mut())
+}
+
+unsafe fn BZ2_bzflushHelp(b: Option<&mut BZFILE>) -> c_int {
+    let Some(bzf) = b else {
+        return 0;
+    };
+
+    if !matches!(bzf.operation, Operation::Writing) {
+        return 0;
+    }
+
+    if bzf.lastErr != ReturnCode::BZ_OK {
+        return -1;
+    }
+
+    if bzf.handle.error() {
+        bzf.lastErr = ReturnCode::BZ_IO_ERROR;
+        return -1;
+    }
+
+    // all input of earlier calls to `BZ2_bzWrite` has been consumed
+    bzf.strm.avail_in = 0;
+
+    loop {
+        bzf.strm.avail_out = BZ_MAX_UNUSED_U32;
+        bzf.strm.next_out = bzf.buf.as_mut_ptr().cast::<c_char>();
+        match BZ2_bzCompressHelp(BzStream::from_mut(&mut bzf.strm), Action::Flush as c_int) {
+            ret @ (ReturnCode::BZ_FLUSH_OK | ReturnCode::BZ_RUN_OK) => {
+                if bzf.strm.avail_out < BZ_MAX_UNUSED_U32 {
+                    let n1 = (BZ_MAX_UNUSED_U32 - bzf.strm.avail_out) as usize;
+                    let n2 = bzf.handle.write(&bzf.buf[..n1]);
+                    if n1 != n2 || bzf.handle.error() {
+                        bzf.lastErr = ReturnCode::BZ_IO_ERROR;
+                        return -1;
+                    }
+                }
+
+                if let ReturnCode::BZ_RUN_OK = ret {
+                    break;
+                }
+            }
+            error => {
+                bzf.lastErr = error;
+                return -1;
+            }
+        }
+    }
+
+    bzf.handle.flush();
+    if bzf.handle.error() {
+        bzf.lastErr = ReturnCode::BZ_IO_ERROR;
+        return -1;
+    }
+
+    0
+}
+
+/// Closes a [`BZFILE`].
+///
+/// Analogous to [`libc::fclose`].
+///
+/// # Safety
+///
+/// The caller must guarantee that
+///
+/// * Either
+///     - `b` is `NULL`
+///     - `b` is initialized with [`BZ2_bzReadOpen`] or [`BZ2_bzWriteOpen`]
+#[export_name = prefix!(BZ2_bzclose)]
+pub unsafe extern "C" fn BZ2_bzclose(b: *mut BZFILE) {
+    BZ2_bzcloseHelp(b.as_mut())
+}
+
+unsafe fn BZ2_bzcloseHelp(mut b: Option<&mut BZFILE>) {
+    let mut bzerr: c_int = 0;
+
+    let (operation, file) = if let Some(bzf) = &mut b {
+        let file = match bzf.handle {
+            Handle::File(f) => Some(f),
+            Handle::Callbacks(_) => None,
+        };
+
+        (bzf.operation, file)
+    } else {
+        return;
+    };
+
+    match operation {
+        Operation::Reading => {
+            BZ2_bzReadCloseHelp(Some(&mut bzerr), b.as_deref_mut());
+        }
         Operation::Writing => {
             BZ2_bzWriteCloseHelp(Some(&mut bzerr), b.as_deref_mut(), false as i32, None, None);
             if bzerr != 0 {
-                BZ2_bzWriteCloseHelp(None, b.as_deref_mut(), true as i32, None, None);
+                BZ2_bzWriteCloseHelp(None, b, true as i32, None, None);
             }
         }
     }
 
-    if let Some(bzf) = b {
-        if bzf.handle != STDIN!() && bzf.handle != STDOUT!() {
-            fclose(bzf.handle);
+    // `b` is deallocated by now
+    if let Some(f) = file {
+        if f != STDIN!() && f != STDOUT!() {
+            fclose(f);
         }
     }
 }
@@ -1235,7 +2300,7 @@ const BZERRORSTRINGS: [&str; 16] = [
     "UNEXPECTED_EOF\0",
     "OUTBUFF_FULL\0",
     "CONFIG_ERROR\0",
-    "???\0",
+    "OUTPUT_LIMIT\0",
     "???\0",
     "???\0",
     "???\0",
@@ -1286,13 +2351,17 @@ mod tests {
     #[test]
     fn error_messages() {
         let mut bz_file = BZFILE {
-            handle: core::ptr::null_mut(),
+            handle: Handle::File(core::ptr::null_mut()),
             buf: [0; 5000],
             bufN: 0,
             strm: bz_stream::zeroed(),
             lastErr: ReturnCode::BZ_OK,
             operation: Operation::Reading,
             initialisedOk: false,
+            ungot: None,
+            nRead: 0,
+            unused: [0; 5000],
+            nUnused: 0,
         };
 
         let return_codes = [
@@ -1310,6 +2379,7 @@ mod tests {
             ReturnCode::BZ_UNEXPECTED_EOF,
             ReturnCode::BZ_OUTBUFF_FULL,
             ReturnCode::BZ_CONFIG_ERROR,
+            ReturnCode::BZ_OUTPUT_LIMIT,
         ];
 
         for return_code in return_codes {
@@ -1337,6 +2407,7 @@ mod tests {
                 ReturnCode::BZ_UNEXPECTED_EOF => "UNEXPECTED_EOF",
                 ReturnCode::BZ_OUTBUFF_FULL => "OUTBUFF_FULL",
                 ReturnCode::BZ_CONFIG_ERROR => "CONFIG_ERROR",
+                ReturnCode::BZ_OUTPUT_LIMIT => "OUTPUT_LIMIT",
             };
 
             assert_eq!(msg, expected);
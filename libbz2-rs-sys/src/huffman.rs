@@ -2,7 +2,7 @@
 
 use crate::{
     assert_h,
-    bzlib::{BZ_MAX_ALPHA_SIZE, BZ_MAX_CODE_LEN},
+    bzlib::{HuffmanAlgorithm, BZ_MAX_ALPHA_SIZE, BZ_MAX_CODE_LEN},
 };
 
 #[inline]
@@ -155,6 +155,97 @@ pub(crate) fn make_code_lengths(len: &mut [u8], freq: &[i32], alphaSize: usize,
     }
 }
 
+/// Computes code lengths of at most `maxLen` bits for the symbols with frequencies `freq`, with
+/// the given `algorithm`.
+pub(crate) fn code_lengths(
+    algorithm: HuffmanAlgorithm,
+    len: &mut [u8],
+    freq: &[i32],
+    alphaSize: usize,
+    maxLen: i32,
+) {
+    match algorithm {
+        HuffmanAlgorithm::Classic => make_code_lengths(len, freq, alphaSize, maxLen),
+        HuffmanAlgorithm::PackageMerge => package_merge(len, freq, alphaSize, maxLen),
+    }
+}
+
+/// The longest code length that [`package_merge`] supports.
+const PM_MAX_LEN: usize = 20;
+
+/// Computes optimal code lengths of at most `maxLen` bits with the package-merge algorithm.
+///
+/// Every symbol gets a code, even when its frequency is zero. The lengths minimize the number of
+/// bits of the encoded symbols, so they are never worse than those of [`make_code_lengths`].
+/// Among the optimal solutions, the one with the shortest total code length is chosen, which
+/// keeps the codes of unused symbols short.
+pub(crate) fn package_merge(len: &mut [u8], freq: &[i32], alphaSize: usize, maxLen: i32) {
+    let n = alphaSize;
+    let maxLen = maxLen as usize;
+
+    assert_h!((2..=BZ_MAX_ALPHA_SIZE).contains(&n), 2003);
+    assert_h!(maxLen <= PM_MAX_LEN && n <= 1 << maxLen, 2004);
+
+    // the low bits break ties between solutions with the same number of bits of encoded symbols
+    let weight = |i: usize| (freq[i].max(0) as u64) << 16 | 1;
+
+    // the symbols, sorted by weight
+    let mut leaves: [u16; BZ_MAX_ALPHA_SIZE] = core::array::from_fn(|i| i as u16);
+    let leaves = &mut leaves[..n];
+    leaves.sort_unstable_by_key(|&i| (weight(usize::from(i)), i));
+
+    // for every level, whether the items of the merged list are packages (or leaves)
+    let mut is_package = [[false; 2 * BZ_MAX_ALPHA_SIZE]; PM_MAX_LEN];
+
+    // the weights of the merged list of the current level, starting with the deepest level
+    let mut items = [0u64; 2 * BZ_MAX_ALPHA_SIZE];
+    let mut packages = [0u64; BZ_MAX_ALPHA_SIZE];
+
+    for (i, &leaf) in leaves.iter().enumerate() {
+        items[i] = weight(usize::from(leaf));
+    }
+    let mut n_items = n;
+
+    for level in (0..maxLen - 1).rev() {
+        let n_packages = n_items / 2;
+        for (p, package) in packages[..n_packages].iter_mut().enumerate() {
+            *package = items[2 * p] + items[2 * p + 1];
+        }
+
+        // merge the leaves with the packages
+        let (mut l, mut p) = (0, 0);
+        n_items = n + n_packages;
+        for k in 0..n_items {
+            let take_leaf =
+                p == n_packages || (l < n && weight(usize::from(leaves[l])) <= packages[p]);
+
+            if take_leaf {
+                items[k] = weight(usize::from(leaves[l]));
+                l += 1;
+            } else {
+                items[k] = packages[p];
+                p += 1;
+            }
+            is_package[level][k] = !take_leaf;
+        }
+    }
+
+    // take the first 2n - 2 items of the top level, and the items in the packages that they contain
+    len[..n].fill(0);
+
+    let mut taken = 2 * n - 2;
+    for is_package in &is_package[..maxLen] {
+        let n_packages = is_package[..taken].iter().filter(|&&b| b).count();
+
+        // the leaves in a merged list are in the order of their weights
+        for &leaf in &leaves[..taken - n_packages] {
+            len[usize::from(leaf)] += 1;
+        }
+
+        taken = 2 * n_packages;
+    }
+}
+
 #[inline]
 pub(crate) fn assign_codes(code: &mut [u32], length: &[u8], minLen: u8, maxLen: u8) {
     let mut vec: u32 = 0;
@@ -213,3 +304,173 @@ pub(crate) fn create_decode_tables(
         base[i + 1] = (2 * (limit[i] + 1)) - base[i + 1];
     }
 }
+
+/// The number of bits that index the lookup tables of [`create_lookup_table`].
+pub(crate) const LOOKUP_BITS: u8 = 12;
+
+/// Fills `lookup` with the length of the code that starts with every possible sequence of
+/// [`LOOKUP_BITS`] bits, or 0 when that code is longer. A code of that length can then be read at
+/// once, and be decoded with `limit`, `base` and `perm` without reading it bit by bit.
+///
+/// The lengths are those at which the bit-by-bit search, starting at `minLen`, finds a code, so
+/// both ways of decoding agree even on invalid tables.
+pub(crate) fn create_lookup_table(
+    lookup: &mut [u8; 1 << LOOKUP_BITS],
+    limit: &[i32; 258],
+    minLen: u8,
+) {
+    lookup.fill(0);
+
+    // the search continues to the next length for all codes after `limit`, so the codes that are
+    // not found yet are those from `first` up
+    let mut first = 0i64;
+    for zn in minLen..=LOOKUP_BITS {
+        let last = Ord::min(i64::from(limit[usize::from(zn)]), (1 << zn) - 1);
+        let shift = LOOKUP_BITS - zn;
+
+        if first <= last {
+            lookup[(first << shift) as usize..((last + 1) << shift) as usize].fill(zn);
+        }
+
+        first = Ord::max(first, last + 1) * 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pseudo-random frequencies, with a skewed distribution and some zeroes. Their sum stays
+    /// below the size of a block, like the frequencies of a group of selectors.
+    fn frequencies(seed: u32, n: usize) -> [i32; BZ_MAX_ALPHA_SIZE] {
+        let mut state = seed;
+        let mut freq = [0; BZ_MAX_ALPHA_SIZE];
+
+        for f in freq[..n].iter_mut() {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let r = state >> 8;
+            *f = match r % 8 {
+                0 => 0,
+                1 => (r >> 4) as i32 % 3000,
+                _ => 1 << ((r >> 4) % 12),
+            };
+        }
+
+        freq
+    }
+
+    fn cost(len: &[u8], freq: &[i32]) -> u64 {
+        len.iter()
+            .zip(freq)
+            .map(|(&l, &f)| l as u64 * f as u64)
+            .sum()
+    }
+
+    fn kraft_sum(len: &[u8], maxLen: i32) -> u64 {
+        len.iter().map(|&l| 1u64 << (maxLen - i32::from(l))).sum()
+    }
+
+    #[test]
+    fn package_merge_is_never_worse() {
+        let mut better = 0;
+        for seed in 0..500 {
+            let n = 2 + seed as usize % (BZ_MAX_ALPHA_SIZE - 1);
+            let freq = frequencies(seed, n);
+
+            let mut classic = [0u8; BZ_MAX_ALPHA_SIZE];
+            let mut optimal = [0u8; BZ_MAX_ALPHA_SIZE];
+            make_code_lengths(&mut classic, &freq, n, 17);
+            package_merge(&mut optimal, &freq, n, 17);
+
+            let optimal = &optimal[..n];
+            assert!(optimal.iter().all(|&l| (1..=17).contains(&l)));
+            assert_eq!(kraft_sum(optimal, 17), 1 << 17, "the code is complete");
+            assert!(
+                cost(optimal, &freq) <= cost(&classic[..n], &freq),
+                "seed {seed}"
+            );
+            better += usize::from(cost(optimal, &freq) < cost(&classic[..n], &freq));
+        }
+
+        // the length limit of 17 bits is binding for some of these distributions
+        assert!(better > 0);
+    }
+
+    #[test]
+    fn package_merge_unconstrained() {
+        // without a binding length limit, the result has the cost of a Huffman code
+        for seed in 0..100 {
+            let n = 2 + seed as usize % 30;
+            let mut freq = frequencies(seed, n);
+            for f in freq.iter_mut() {
+                *f = *f % 1000 + 1;
+            }
+
+            let mut classic = [0u8; BZ_MAX_ALPHA_SIZE];
+            let mut optimal = [0u8; BZ_MAX_ALPHA_SIZE];
+            make_code_lengths(&mut classic, &freq, n, 20);
+            package_merge(&mut optimal, &freq, n, 20);
+
+            assert_eq!(cost(&optimal[..n], &freq), cost(&classic[..n], &freq));
+        }
+    }
+
+    #[test]
+    fn package_merge_small() {
+        let mut len = [0u8; 4];
+
+        package_merge(&mut len, &[5, 0], 2, 17);
+        assert_eq!(len[..2], [1, 1]);
+
+        package_merge(&mut len, &[1, 2, 4, 8], 4, 17);
+        assert_eq!(len, [3, 3, 2, 1]);
+
+        package_merge(&mut len, &[1, 2, 4, 8], 4, 2);
+        assert_eq!(len, [2, 2, 2, 2]);
+    }
+
+    /// The length at which the bit-by-bit search of the decoder finds the code at the start of
+    /// `bits`, which has [`LOOKUP_BITS`] bits.
+    fn search(limit: &[i32; 258], minLen: u8, bits: usize) -> u8 {
+        (minLen..=LOOKUP_BITS)
+            .find(|&zn| (bits >> (LOOKUP_BITS - zn)) as i32 <= limit[usize::from(zn)])
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn lookup_table_matches_search() {
+        let mut state = 0x1234_5678u32;
+
+        for round in 0..300 {
+            let n = 2 + round % 40;
+
+            let mut len = [0u8; 258];
+            if round % 3 == 0 {
+                // arbitrary lengths, which need not form a valid code
+                for l in len[..n].iter_mut() {
+                    state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                    *l = 1 + (state >> 16) as u8 % 20;
+                }
+            } else {
+                let freq = frequencies(round as u32, n);
+                make_code_lengths(&mut len, &freq, n, 17);
+            }
+            let len = &len[..n];
+
+            let minLen = *len.iter().min().unwrap();
+            let maxLen = *len.iter().max().unwrap();
+
+            let mut limit = [0; 258];
+            let mut base = [0; 258];
+            let mut perm = [0; 258];
+            create_decode_tables(&mut limit, &mut base, &mut perm, len, minLen, maxLen);
+
+            let mut lookup = [0; 1 << LOOKUP_BITS];
+            create_lookup_table(&mut lookup, &limit, minLen);
+
+            for (bits, &length) in lookup.iter().enumerate() {
+                assert_eq!(length, search(&limit, minLen, bits), "round {round}");
+            }
+        }
+    }
+}
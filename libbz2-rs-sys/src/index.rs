@@ -0,0 +1,230 @@
+//! # block index files
+//!
+//! An index file records where the blocks of a bzip2 file start, so that a
+//! [`SeekableBzDecoder`] can start decompressing at any block without first decompressing the
+//! blocks before it. By convention, the index of `file.bz2` is stored next to it, as
+//! `file.bz2.idx` (see [`index_path`]).
+//!
+//! The blocks can be recorded while compressing, with [`Compress::record_blocks`] or
+//! [`BZ2_bzCompressSetBlockCallback`], or found in an existing file with [`scan`].
+//!
+//! ## Format
+//!
+//! All integers are unsigned and big-endian, like in the bzip2 format itself.
+//!
+//! | offset        | size     | contents                                                  |
+//! |---------------|----------|-----------------------------------------------------------|
+//! | 0             | 4        | the magic bytes `BZIX`                                    |
+//! | 4             | 1        | the format version, currently 1                           |
+//! | 5             | 3        | reserved, written as zero                                 |
+//! | 8             | 8        | the number of blocks `n`                                  |
+//! | 16            | `32 * n` | a record for every block, in order                        |
+//! | `16 + 32 * n` | 4        | the CRC of all preceding bytes, computed like a block CRC |
+//!
+//! Every block record consists of
+//!
+//! | offset | size | contents                                                                 |
+//! |--------|------|--------------------------------------------------------------------------|
+//! | 0      | 8    | the offset in bits of the block header, relative to the start of the file |
+//! | 8      | 8    | the offset of the first byte of the block in the uncompressed data       |
+//! | 16     | 4    | the number of uncompressed bytes in the block                            |
+//! | 20     | 4    | the CRC of the block, as stored in the block header                      |
+//! | 24     | 1    | the block size of the stream that contains the block, in `1..=9`         |
+//! | 25     | 7    | reserved, written as zero                                                |
+//!
+//! The uncompressed data of the blocks must be contiguous, and the first block must start at
+//! uncompressed offset 0.
+//!
+//! [`BZ2_bzCompressSetBlockCallback`]: crate::BZ2_bzCompressSetBlockCallback
+
+use std::ffi::OsString;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::vec::Vec;
+
+use crate::crc32;
+use crate::seek::SeekableBzDecoder;
+use crate::BlockInfo;
+
+#[cfg(doc)]
+use crate::Compress;
+
+/// The magic bytes at the start of an index file.
+const MAGIC: [u8; 4] = *b"BZIX";
+
+/// The version of the index file format.
+const VERSION: u8 = 1;
+
+/// The size of a block record.
+const RECORD_SIZE: usize = 32;
+
+/// The conventional path of the index file of the bzip2 file at `path`, which is `path` with
+/// `.idx` appended.
+pub fn index_path(path: &Path) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(".idx");
+    PathBuf::from(path)
+}
+
+/// Writes an index file that describes `blocks` to `writer`.
+pub fn write_index<W: Write>(mut writer: W, blocks: &[BlockInfo]) -> io::Result<()> {
+    let mut bytes = Vec::with_capacity(16 + blocks.len() * RECORD_SIZE + 4);
+
+    bytes.extend_from_slice(&MAGIC);
+    bytes.extend_from_slice(&[VERSION, 0, 0, 0]);
+    bytes.extend_from_slice(&(blocks.len() as u64).to_be_bytes());
+
+    for block in blocks {
+        bytes.extend_from_slice(&block.compressed_bit_offset.to_be_bytes());
+        bytes.extend_from_slice(&block.uncompressed_offset.to_be_bytes());
+        bytes.extend_from_slice(&block.uncompressed_len.to_be_bytes());
+        bytes.extend_from_slice(&block.block_crc.to_be_bytes());
+        bytes.push(block.block_size100k as u8);
+        bytes.extend_from_slice(&[0; 7]);
+    }
+
+    bytes.extend_from_slice(&crc32(&bytes).to_be_bytes());
+
+    writer.write_all(&bytes)
+}
+
+/// Reads an index file from `reader`.
+///
+/// Returns an error of kind [`io::ErrorKind::InvalidData`] when the data is not a valid index file.
+pub fn read_index<R: Read>(mut reader: R) -> io::Result<Vec<BlockInfo>> {
+    let mut header = [0u8; 16];
+    reader.read_exact(&mut header)?;
+
+    if header[..4] != MAGIC {
+        return Err(invalid_data("not a bzip2 index file"));
+    }
+
+    if header[4] != VERSION {
+        return Err(invalid_data("unsupported bzip2 index file version"));
+    }
+
+    let count = u64::from_be_bytes(header[8..16].try_into().unwrap());
+
+    let mut crc = Crc::new();
+    crc.update(&header);
+
+    // don't trust the count for the initial allocation
+    let mut blocks: Vec<BlockInfo> = Vec::with_capacity(Ord::min(count, 1 << 16) as usize);
+    let mut uncompressed_end = 0u64;
+
+    for _ in 0..count {
+        let mut record = [0u8; RECORD_SIZE];
+        reader.read_exact(&mut record)?;
+        crc.update(&record);
+
+        let block = BlockInfo {
+            compressed_bit_offset: u64::from_be_bytes(record[0..8].try_into().unwrap()),
+            uncompressed_offset: u64::from_be_bytes(record[8..16].try_into().unwrap()),
+            uncompressed_len: u32::from_be_bytes(record[16..20].try_into().unwrap()),
+            block_crc: u32::from_be_bytes(record[20..24].try_into().unwrap()),
+            block_size100k: i32::from(record[24]),
+        };
+
+        let after_previous = match blocks.last() {
+            Some(previous) => block.compressed_bit_offset > previous.compressed_bit_offset,
+            None => true,
+        };
+
+        if !(1..=9).contains(&block.block_size100k)
+            || block.uncompressed_len == 0
+            || block.uncompressed_offset != uncompressed_end
+            || !after_previous
+        {
+            return Err(invalid_data("invalid block in bzip2 index file"));
+        }
+
+        uncompressed_end = match uncompressed_end.checked_add(u64::from(block.uncompressed_len)) {
+            Some(end) => end,
+            None => return Err(invalid_data("invalid block in bzip2 index file")),
+        };
+
+        blocks.push(block);
+    }
+
+    let mut stored_crc = [0u8; 4];
+    reader.read_exact(&mut stored_crc)?;
+
+    if u32::from_be_bytes(stored_crc) != crc.finish() {
+        return Err(invalid_data("bzip2 index file CRC mismatch"));
+    }
+
+    Ok(blocks)
+}
+
+/// Finds the blocks of the concatenated bzip2 streams read from `inner`, which must start at
+/// offset 0 of `inner`.
+///
+/// This decompresses all of the data (verifying its integrity along the way), so it takes as long
+/// as testing the file with `bzip2 -t`.
+pub fn scan<R: Read + Seek>(inner: R) -> io::Result<Vec<BlockInfo>> {
+    let mut decoder = SeekableBzDecoder::new(inner);
+    decoder.build_index()?;
+
+    let index = decoder.index().clone();
+    let mut inner = decoder.into_inner();
+
+    let entries = index.entries();
+    let uncompressed_len = index.uncompressed_len().unwrap_or_default();
+
+    let mut blocks = Vec::with_capacity(entries.len());
+    for (i, entry) in entries.iter().enumerate() {
+        let uncompressed_end = entries
+            .get(i + 1)
+            .map_or(uncompressed_len, |next| next.uncompressed_offset);
+
+        // the block CRC follows the 48-bit block magic
+        let block_crc = read_u32_at_bit(&mut inner, entry.compressed_bit_offset + 48)?;
+
+        blocks.push(BlockInfo {
+            compressed_bit_offset: entry.compressed_bit_offset,
+            uncompressed_offset: entry.uncompressed_offset,
+            uncompressed_len: (uncompressed_end - entry.uncompressed_offset) as u32,
+            block_crc,
+            block_size100k: i32::from(entry.block_size100k),
+        });
+    }
+
+    Ok(blocks)
+}
+
+/// Reads the 32 bits at `bit_offset` of `inner`.
+fn read_u32_at_bit<R: Read + Seek>(inner: &mut R, bit_offset: u64) -> io::Result<u32> {
+    let mut bytes = [0u8; 8];
+    inner.seek(SeekFrom::Start(bit_offset / 8))?;
+    inner.read_exact(&mut bytes[..5])?;
+
+    let bits = u64::from_be_bytes(bytes) << (bit_offset % 8);
+    Ok((bits >> 32) as u32)
+}
+
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(bytes);
+    crc.finish()
+}
+
+/// The CRC that bzip2 uses for blocks.
+struct Crc(u32);
+
+impl Crc {
+    fn new() -> Self {
+        Self(0xffffffff)
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        self.0 = crc32::update(self.0, bytes);
+    }
+
+    fn finish(&self) -> u32 {
+        !self.0
+    }
+}
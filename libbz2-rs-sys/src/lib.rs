@@ -3,6 +3,7 @@
 #![allow(clippy::too_many_arguments)]
 #![deny(unreachable_pub)]
 #![deny(unsafe_op_in_unsafe_fn)]
+#![cfg_attr(libbz2_rs_sys_printf, feature(c_variadic))]
 
 //! A drop-in compatible rust implementation of bzip2
 
@@ -17,14 +18,33 @@ mod allocator;
 mod blocksort;
 mod bzlib;
 mod compress;
+mod crc32;
 mod crctable;
 mod decompress;
 #[cfg(feature = "stdio")]
 mod high_level;
 mod huffman;
+mod mem;
+#[cfg(feature = "parallel")]
+pub mod parallel;
 mod randtable;
+mod sais;
+mod workspace;
 
-pub(crate) use bzlib::{Action, ReturnCode};
+#[cfg(feature = "std")]
+pub mod bufread;
+#[cfg(feature = "std")]
+pub mod index;
+#[cfg(feature = "std")]
+pub mod read;
+#[cfg(feature = "std")]
+pub mod seek;
+#[cfg(feature = "std")]
+pub mod write;
+
+pub(crate) use bzlib::ReturnCode;
+
+pub use bzlib::Action;
 
 pub const BZ_OK: c_int = ReturnCode::BZ_OK as c_int;
 pub const BZ_RUN_OK: c_int = ReturnCode::BZ_RUN_OK as c_int;
@@ -40,11 +60,22 @@ pub const BZ_IO_ERROR: c_int = ReturnCode::BZ_IO_ERROR as c_int;
 pub const BZ_UNEXPECTED_EOF: c_int = ReturnCode::BZ_UNEXPECTED_EOF as c_int;
 pub const BZ_OUTBUFF_FULL: c_int = ReturnCode::BZ_OUTBUFF_FULL as c_int;
 pub const BZ_CONFIG_ERROR: c_int = ReturnCode::BZ_CONFIG_ERROR as c_int;
+pub const BZ_OUTPUT_LIMIT: c_int = ReturnCode::BZ_OUTPUT_LIMIT as c_int;
 
 pub const BZ_RUN: c_int = Action::Run as c_int;
 pub const BZ_FLUSH: c_int = Action::Flush as c_int;
 pub const BZ_FINISH: c_int = Action::Finish as c_int;
 
+pub use bzlib::SortAlgorithm;
+
+pub const BZ_SORT_CLASSIC: c_int = SortAlgorithm::Classic as c_int;
+pub const BZ_SORT_SAIS: c_int = SortAlgorithm::Sais as c_int;
+
+pub use bzlib::HuffmanAlgorithm;
+
+pub const BZ_HUFFMAN_CLASSIC: c_int = HuffmanAlgorithm::Classic as c_int;
+pub const BZ_HUFFMAN_PACKAGE_MERGE: c_int = HuffmanAlgorithm::PackageMerge as c_int;
+
 pub const BZ_MAX_UNUSED: c_int = bzlib::BZ_MAX_UNUSED_U32 as c_int;
 
 // types
@@ -56,6 +87,35 @@ pub use bzlib::BZFILE;
 pub use bzlib::{BZ2_bzCompress, BZ2_bzCompressEnd, BZ2_bzCompressInit};
 pub use bzlib::{BZ2_bzDecompress, BZ2_bzDecompressEnd, BZ2_bzDecompressInit};
 
+// decompression with output limits
+pub use bzlib::BZ2_bzDecompressInitWithLimits;
+
+// reusing a stream and its buffers for a new stream
+pub use bzlib::{BZ2_bzCompressReset, BZ2_bzDecompressReset};
+
+// the memory used by streams
+pub use bzlib::{BZ2_bzCompressAllocatedMemory, BZ2_bzDecompressAllocatedMemory};
+pub use bzlib::{BZ2_bzCompressMemoryUsage, BZ2_bzDecompressMemoryUsage};
+
+// streams that carve their memory from a caller-supplied workspace
+pub use workspace::{BZ2_bzCompressInitWorkspace, BZ2_bzDecompressInitWorkspace};
+pub use workspace::{BZ2_bzCompressWorkspaceSize, BZ2_bzDecompressWorkspaceSize};
+
+// encoder parameters: the sorting and Huffman algorithms, and the extreme mode
+pub use bzlib::BZ2_bzCompressSetExtreme;
+pub use bzlib::BZ2_bzCompressSetHuffmanAlgorithm;
+pub use bzlib::BZ2_bzCompressSetSortAlgorithm;
+
+// reporting the blocks written by the compressor
+pub use bzlib::BZ2_bzCompressSetBlockCallback;
+#[cfg(feature = "stdio")]
+pub use bzlib::BZ2_bzWriteSetBlockCallback;
+pub use bzlib::BlockInfo;
+
+// detailed data integrity errors
+pub use bzlib::BZ2_bzDecompressGetDataError;
+pub use bzlib::{DataError, DataErrorKind};
+
 // utility functions
 pub use bzlib::{BZ2_bzBuffToBuffCompress, BZ2_bzBuffToBuffDecompress};
 
@@ -65,12 +125,29 @@ pub use bzlib::{BZ2_bzRead, BZ2_bzReadClose, BZ2_bzReadGetUnused, BZ2_bzReadOpen
 #[cfg(feature = "stdio")]
 pub use bzlib::{BZ2_bzWrite, BZ2_bzWriteClose, BZ2_bzWriteClose64, BZ2_bzWriteOpen};
 
+// the high-level interface, with callbacks instead of a `FILE`
+#[cfg(feature = "stdio")]
+pub use bzlib::{BZ2_bzReadOpenCallbacks, BZ2_bzWriteOpenCallbacks};
+
 // zlib compatibility functions
+#[cfg(all(feature = "stdio", libbz2_rs_sys_printf))]
+pub use bzlib::BZ2_bzprintf;
 #[cfg(feature = "stdio")]
 pub use bzlib::{
     BZ2_bzclose, BZ2_bzdopen, BZ2_bzerror, BZ2_bzflush, BZ2_bzlibVersion, BZ2_bzopen, BZ2_bzread,
     BZ2_bzwrite,
 };
+#[cfg(feature = "stdio")]
+pub use bzlib::{BZ2_bzeof, BZ2_bzgetc, BZ2_bzgets, BZ2_bzungetc};
+#[cfg(feature = "stdio")]
+pub use bzlib::{BZ2_bzputc, BZ2_bzputs, BZ2_bzvprintf};
+#[cfg(feature = "stdio")]
+pub use bzlib::{BZ2_bzrewind, BZ2_bzseek, BZ2_bztell};
+
+// the safe rust interface
+pub use mem::{compress_bound, Compress, Decompress, Error, InWorkspace, Status};
+#[cfg(feature = "std")]
+pub use mem::{compress_to_vec, decompress_to_vec, decompress_to_vec_with_limit};
 
 // --- version number logic
 
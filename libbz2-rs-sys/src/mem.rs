@@ -0,0 +1,871 @@
+//! # safe rust interface
+//!
+//! The [`Compress`] and [`Decompress`] types wrap the low-level stream state in an owned value, so
+//! that rust users don't need to write `unsafe` glue around [`bz_stream`]. Input and output are
+//! passed as slices, and the (64-bit) running totals can be used to find out how much of the input
+//! was consumed and how much output was produced by a call.
+//!
+//! For data that fits in memory, [`compress_to_vec`] and [`decompress_to_vec`] compress or
+//! decompress a whole buffer in one call.
+
+use core::ffi::{c_char, c_int, c_uint};
+use core::marker::PhantomData;
+use core::ops::Deref;
+use core::ptr::{self, NonNull};
+
+#[cfg(feature = "std")]
+use std::{boxed::Box, vec, vec::Vec};
+
+use crate::allocator::Allocator;
+use crate::bzlib::{compress_allocations, decompress_allocations, total_size};
+use crate::bzlib::{
+    Action, BzStream, DState, DataError, EState, HuffmanAlgorithm, ReturnCode, SortAlgorithm,
+    StreamState,
+};
+use crate::bzlib::{
+    BZ2_bzCompressAllocatedMemoryHelp, BZ2_bzCompressEndHelp, BZ2_bzCompressHelp,
+    BZ2_bzCompressInitHelp, BZ2_bzCompressResetHelp, BZ2_bzCompressSetExtremeHelp,
+    BZ2_bzCompressSetHuffmanAlgorithmHelp, BZ2_bzCompressSetSortAlgorithmHelp,
+    BZ2_bzDecompressAllocatedMemoryHelp, BZ2_bzDecompressEndHelp, BZ2_bzDecompressGetDataErrorHelp,
+    BZ2_bzDecompressHelp, BZ2_bzDecompressInitWithLimitsHelp, BZ2_bzDecompressResetHelp,
+};
+#[cfg(feature = "std")]
+use crate::bzlib::{BZ2_bzCompressSetBlockCallbackHelp, BZ2_bzDecompressInitBlockHelp, BlockInfo};
+
+use crate::workspace;
+
+#[cfg(doc)]
+use crate::bz_stream;
+
+/// The (non-error) outcome of a call to [`Compress::compress`] or [`Decompress::decompress`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// Decompression made progress, but the end of the stream has not been reached yet.
+    Ok,
+    /// Compression with [`Action::Run`] made progress.
+    RunOk,
+    /// Not all data has been flushed to the output yet: call again with [`Action::Flush`].
+    FlushOk,
+    /// Not all data has been written to the output yet: call again with [`Action::Finish`].
+    FinishOk,
+    /// The logical end of the stream was reached, and all output has been written.
+    StreamEnd,
+}
+
+/// An error returned by [`Compress`], [`Decompress`] and the other functions of the rust interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The stream was used in the wrong order, e.g. compressing after the stream was finished.
+    Sequence,
+    /// An invalid parameter was given.
+    Param,
+    /// Insufficient memory was available.
+    Mem,
+    /// A data integrity error was detected in the compressed stream.
+    Data(DataError),
+    /// The compressed stream does not start with the right magic bytes.
+    DataMagic,
+    /// No default allocator is configured.
+    Config,
+    /// The compressed input ends before the end of the stream was reached.
+    UnexpectedEof,
+    /// The decompressed output exceeds the configured limit.
+    OutputLimit,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let msg = match self {
+            Error::Sequence => "sequence error",
+            Error::Param => "invalid parameter",
+            Error::Mem => "insufficient memory",
+            Error::Data(data_error) => {
+                return write!(
+                    f,
+                    "data integrity error in the compressed stream: {data_error}"
+                );
+            }
+            Error::DataMagic => "compressed stream does not start with the bzip2 magic bytes",
+            Error::Config => "no default allocator is configured",
+            Error::UnexpectedEof => "compressed file ends unexpectedly",
+            Error::OutputLimit => "decompressed output exceeds the limit",
+        };
+
+        f.write_str(msg)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+#[cfg(feature = "std")]
+impl From<Error> for std::io::Error {
+    fn from(error: Error) -> Self {
+        let kind = match error {
+            Error::Data(_) | Error::DataMagic => std::io::ErrorKind::InvalidData,
+            Error::Mem => std::io::ErrorKind::OutOfMemory,
+            Error::UnexpectedEof => std::io::ErrorKind::UnexpectedEof,
+            Error::Sequence | Error::Param | Error::Config | Error::OutputLimit => {
+                std::io::ErrorKind::Other
+            }
+        };
+
+        std::io::Error::new(kind, error)
+    }
+}
+
+fn from_return_code(return_code: ReturnCode) -> Result<Status, Error> {
+    match return_code {
+        ReturnCode::BZ_OK => Ok(Status::Ok),
+        ReturnCode::BZ_RUN_OK => Ok(Status::RunOk),
+        ReturnCode::BZ_FLUSH_OK => Ok(Status::FlushOk),
+        ReturnCode::BZ_FINISH_OK => Ok(Status::FinishOk),
+        ReturnCode::BZ_STREAM_END => Ok(Status::StreamEnd),
+        ReturnCode::BZ_SEQUENCE_ERROR => Err(Error::Sequence),
+        ReturnCode::BZ_PARAM_ERROR => Err(Error::Param),
+        ReturnCode::BZ_MEM_ERROR => Err(Error::Mem),
+        ReturnCode::BZ_DATA_ERROR_MAGIC => Err(Error::DataMagic),
+        ReturnCode::BZ_CONFIG_ERROR => Err(Error::Config),
+        ReturnCode::BZ_OUTPUT_LIMIT => Err(Error::OutputLimit),
+        ReturnCode::BZ_DATA_ERROR => {
+            unreachable!("only returned by the decompressor, which handles it separately")
+        }
+        ReturnCode::BZ_IO_ERROR | ReturnCode::BZ_UNEXPECTED_EOF | ReturnCode::BZ_OUTBUFF_FULL => {
+            unreachable!("only returned by the high-level interface")
+        }
+    }
+}
+
+/// An owned, heap-allocated [`BzStream`].
+///
+/// The stream state stores the address of its stream as a consistency check, so the stream must
+/// not move after initialization. Boxing it with the default allocator guarantees that.
+struct OwnedStream<S: StreamState> {
+    ptr: NonNull<BzStream<S>>,
+    /// Whether the stream is allocated with the default allocator, rather than in a workspace.
+    allocated: bool,
+}
+
+impl<S: StreamState> OwnedStream<S> {
+    fn new() -> Result<Self, Error> {
+        let Some(allocator) = Allocator::DEFAULT else {
+            return Err(Error::Config);
+        };
+
+        // NOTE: an all-zeroes BzStream is equivalent to `BzStream::zeroed()`
+        let Some(ptr) = allocator.allocate_zeroed::<BzStream<S>>(1) else {
+            return Err(Error::Mem);
+        };
+
+        Ok(Self {
+            ptr: NonNull::new(ptr).unwrap(),
+            allocated: true,
+        })
+    }
+
+    /// Places the stream in `workspace`, and makes it allocate its state from there too.
+    ///
+    /// The caller must make sure that the stream is not used after the workspace is released.
+    fn in_workspace(workspace: &mut [u8]) -> Result<Self, Error> {
+        // SAFETY: the workspace is borrowed mutably for as long as the stream exists
+        let Some(opaque) = (unsafe { workspace::init(workspace.as_mut_ptr(), workspace.len()) })
+        else {
+            return Err(Error::Mem);
+        };
+
+        let Some(ptr) = workspace::allocator(opaque).allocate_zeroed::<BzStream<S>>(1) else {
+            return Err(Error::Mem);
+        };
+
+        let mut stream = Self {
+            ptr: NonNull::new(ptr).unwrap(),
+            allocated: false,
+        };
+        workspace::configure(stream.get_mut(), opaque);
+
+        Ok(stream)
+    }
+
+    fn get(&self) -> &BzStream<S> {
+        unsafe { self.ptr.as_ref() }
+    }
+
+    fn get_mut(&mut self) -> &mut BzStream<S> {
+        unsafe { self.ptr.as_mut() }
+    }
+
+    fn set_buffers(&mut self, input: &[u8], output: &mut [u8]) {
+        let strm = self.get_mut();
+
+        strm.next_in = input.as_ptr().cast::<c_char>();
+        strm.avail_in = Ord::min(input.len(), c_uint::MAX as usize) as c_uint;
+        strm.next_out = output.as_mut_ptr().cast::<c_char>();
+        strm.avail_out = Ord::min(output.len(), c_uint::MAX as usize) as c_uint;
+    }
+
+    fn clear_buffers(&mut self) {
+        // don't keep pointers into the (borrowed) slices around
+        let strm = self.get_mut();
+
+        strm.next_in = ptr::null();
+        strm.avail_in = 0;
+        strm.next_out = ptr::null_mut();
+        strm.avail_out = 0;
+    }
+
+    fn total_in(&self) -> u64 {
+        self.get().total_in()
+    }
+
+    fn total_out(&self) -> u64 {
+        self.get().total_out()
+    }
+}
+
+impl<S: StreamState> Drop for OwnedStream<S> {
+    fn drop(&mut self) {
+        if !self.allocated {
+            return;
+        }
+
+        // the allocation was made with the default allocator, so it must exist
+        if let Some(allocator) = Allocator::DEFAULT {
+            unsafe { allocator.deallocate(self.ptr.as_ptr(), 1) };
+        }
+    }
+}
+
+/// A bzip2 compression stream.
+///
+/// All memory associated with the stream is released when it is dropped.
+pub struct Compress {
+    stream: OwnedStream<EState>,
+    /// The blocks written so far, when recording is enabled. Boxed, because the block callback
+    /// holds a pointer to the vector.
+    #[cfg(feature = "std")]
+    #[allow(clippy::box_collection)]
+    blocks: Option<Box<Vec<BlockInfo>>>,
+}
+
+// SAFETY: the stream state is owned exclusively by this value, and is only ever accessed through
+// `&mut self`. The default allocators can be used from any thread.
+unsafe impl Send for Compress {}
+unsafe impl Sync for Compress {}
+
+impl Compress {
+    /// Creates a new compression stream.
+    ///
+    /// - `block_size100k` is the block size in units of 100k, and must be in `1..=9`.
+    /// - `work_factor` controls the fallback to the slower, more robust sorting algorithm on highly
+    ///   repetitive input. It must be in `0..=250`, where `0` selects the default value of `30`.
+    ///
+    /// See also [`BZ2_bzCompressInit`](crate::BZ2_bzCompressInit).
+    pub fn new(block_size100k: u32, work_factor: u32) -> Result<Self, Error> {
+        let (Ok(block_size100k), Ok(work_factor)) = (
+            c_int::try_from(block_size100k),
+            c_int::try_from(work_factor),
+        ) else {
+            return Err(Error::Param);
+        };
+
+        let mut stream = OwnedStream::new()?;
+
+        from_return_code(BZ2_bzCompressInitHelp(
+            stream.get_mut(),
+            block_size100k,
+            0,
+            work_factor,
+        ))?;
+
+        Ok(Self {
+            stream,
+            #[cfg(feature = "std")]
+            blocks: None,
+        })
+    }
+
+    /// Creates a new compression stream that carves all of its memory from `workspace`, rather
+    /// than using an allocator.
+    ///
+    /// The workspace must be at least [`Compress::workspace_size`] bytes, otherwise
+    /// [`Error::Mem`] is returned. See [`Compress::new`] for the parameters, and
+    /// [`BZ2_bzCompressInitWorkspace`](crate::BZ2_bzCompressInitWorkspace) for the C interface.
+    pub fn in_workspace(
+        workspace: &mut [u8],
+        block_size100k: u32,
+        work_factor: u32,
+    ) -> Result<InWorkspace<'_, Self>, Error> {
+        let (Ok(block_size100k), Ok(work_factor)) = (
+            c_int::try_from(block_size100k),
+            c_int::try_from(work_factor),
+        ) else {
+            return Err(Error::Param);
+        };
+
+        let mut stream = OwnedStream::in_workspace(workspace)?;
+
+        from_return_code(BZ2_bzCompressInitHelp(
+            stream.get_mut(),
+            block_size100k,
+            0,
+            work_factor,
+        ))?;
+
+        Ok(InWorkspace::new(Self {
+            stream,
+            #[cfg(feature = "std")]
+            blocks: None,
+        }))
+    }
+
+    /// The size in bytes of a workspace for [`Compress::in_workspace`].
+    ///
+    /// # Panics
+    ///
+    /// Panics when `block_size100k` is not in `1..=9`.
+    pub const fn workspace_size(block_size100k: u32) -> usize {
+        assert!(1 <= block_size100k && block_size100k <= 9);
+
+        workspace::compress_size(block_size100k as usize) + workspace::STREAM_SIZE
+    }
+
+    /// Compresses data from `input` into `output`.
+    ///
+    /// Use [`Compress::total_in`] and [`Compress::total_out`] to find out how much input was
+    /// consumed and how much output was produced. When `action` is [`Action::Flush`] or
+    /// [`Action::Finish`], subsequent calls must pass the same `action` and the remaining input
+    /// until [`Status::RunOk`] respectively [`Status::StreamEnd`] is returned.
+    ///
+    /// See also [`BZ2_bzCompress`](crate::BZ2_bzCompress).
+    pub fn compress(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        action: Action,
+    ) -> Result<Status, Error> {
+        self.stream.set_buffers(input, output);
+        let ret = BZ2_bzCompressHelp(self.stream.get_mut(), action as c_int);
+        self.stream.clear_buffers();
+
+        match ret {
+            // with an initialized stream, this error means that no progress could be made
+            ReturnCode::BZ_PARAM_ERROR if action == Action::Run => Ok(Status::RunOk),
+            ret => from_return_code(ret),
+        }
+    }
+
+    /// The total number of input bytes consumed by this stream.
+    pub fn total_in(&self) -> u64 {
+        self.stream.total_in()
+    }
+
+    /// The total number of output bytes produced by this stream.
+    pub fn total_out(&self) -> u64 {
+        self.stream.total_out()
+    }
+
+    /// The number of bytes that a compression stream with a block size of `block_size100k * 100k`
+    /// allocates, not counting the small stream object itself.
+    ///
+    /// See also [`BZ2_bzCompressMemoryUsage`](crate::BZ2_bzCompressMemoryUsage).
+    ///
+    /// # Panics
+    ///
+    /// Panics when `block_size100k` is not in `1..=9`.
+    pub const fn memory_usage(block_size100k: u32) -> usize {
+        assert!(1 <= block_size100k && block_size100k <= 9);
+
+        total_size(&compress_allocations(block_size100k as usize))
+    }
+
+    /// The number of bytes that are currently allocated by this stream, not counting the small
+    /// stream object itself.
+    ///
+    /// See also [`BZ2_bzCompressAllocatedMemory`](crate::BZ2_bzCompressAllocatedMemory).
+    pub fn allocated_memory(&self) -> usize {
+        BZ2_bzCompressAllocatedMemoryHelp(self.stream.get()).unwrap_or(0)
+    }
+
+    /// Prepares the stream for compressing a new stream, keeping its buffers and parameters.
+    ///
+    /// Any input and output of the current stream that has not been processed yet is discarded, and
+    /// the recorded blocks are cleared. See also
+    /// [`BZ2_bzCompressReset`](crate::BZ2_bzCompressReset).
+    pub fn reset(&mut self) {
+        // zero keeps the block size, which always fits in the buffers
+        let ret = self.reset_stream(0);
+        debug_assert_eq!(ret, Ok(()));
+    }
+
+    /// Like [`Compress::reset`], but the new stream uses a block size of `block_size100k * 100k`.
+    ///
+    /// Returns [`Error::Param`] when `block_size100k` is not in `1..=9`, or when it is larger than
+    /// the block size that the stream was created with.
+    pub fn reset_with_block_size(&mut self, block_size100k: u32) -> Result<(), Error> {
+        match c_int::try_from(block_size100k) {
+            Ok(n @ 1..=9) => self.reset_stream(n),
+            _ => Err(Error::Param),
+        }
+    }
+
+    fn reset_stream(&mut self, block_size100k: c_int) -> Result<(), Error> {
+        from_return_code(BZ2_bzCompressResetHelp(
+            self.stream.get_mut(),
+            block_size100k,
+        ))?;
+
+        #[cfg(feature = "std")]
+        if let Some(blocks) = self.blocks.as_mut() {
+            blocks.clear();
+        }
+
+        Ok(())
+    }
+
+    /// Selects the algorithm that sorts the rotations of the blocks that are compressed from now
+    /// on. The compressed output is the same for every algorithm.
+    ///
+    /// See also [`BZ2_bzCompressSetSortAlgorithm`](crate::BZ2_bzCompressSetSortAlgorithm).
+    pub fn set_sort_algorithm(&mut self, algorithm: SortAlgorithm) {
+        BZ2_bzCompressSetSortAlgorithmHelp(self.stream.get_mut(), algorithm);
+    }
+
+    /// Enables or disables the extreme mode for the blocks that are compressed from now on, which
+    /// spends more time to make the output a little smaller.
+    ///
+    /// See also [`BZ2_bzCompressSetExtreme`](crate::BZ2_bzCompressSetExtreme).
+    pub fn set_extreme(&mut self, extreme: bool) {
+        BZ2_bzCompressSetExtremeHelp(self.stream.get_mut(), extreme);
+    }
+
+    /// Selects the algorithm that computes the lengths of the Huffman codes of the blocks that are
+    /// compressed from now on.
+    ///
+    /// See also [`BZ2_bzCompressSetHuffmanAlgorithm`](crate::BZ2_bzCompressSetHuffmanAlgorithm).
+    pub fn set_huffman_algorithm(&mut self, algorithm: HuffmanAlgorithm) {
+        BZ2_bzCompressSetHuffmanAlgorithmHelp(self.stream.get_mut(), algorithm);
+    }
+
+    /// Starts recording a [`BlockInfo`] for every block that is written from now on.
+    ///
+    /// The recorded blocks can be saved as an index file with
+    /// [`index::write_index`](crate::index::write_index). See also
+    /// [`BZ2_bzCompressSetBlockCallback`](crate::BZ2_bzCompressSetBlockCallback).
+    #[cfg(feature = "std")]
+    pub fn record_blocks(&mut self) {
+        use core::ffi::c_void;
+
+        unsafe extern "C" fn record(opaque: *mut c_void, info: *const BlockInfo) {
+            // SAFETY: `opaque` points to the vector owned by the `Compress`, and `info` is valid
+            // for the duration of the call
+            unsafe { (*opaque.cast::<Vec<BlockInfo>>()).push(*info) };
+        }
+
+        let blocks = self.blocks.get_or_insert_with(Box::default);
+        let opaque = (&mut **blocks as *mut Vec<BlockInfo>).cast::<c_void>();
+        BZ2_bzCompressSetBlockCallbackHelp(self.stream.get_mut(), Some(record), opaque);
+    }
+
+    /// The blocks that were recorded since [`Compress::record_blocks`] was called, or since the
+    /// last reset.
+    ///
+    /// The last block is written, and hence recorded, by the call with [`Action::Finish`] that
+    /// returns [`Status::StreamEnd`].
+    #[cfg(feature = "std")]
+    pub fn blocks(&self) -> &[BlockInfo] {
+        self.blocks.as_deref().map_or(&[], Vec::as_slice)
+    }
+
+    /// The compressor state, for the parallel compressor that drives the compression of blocks
+    /// itself.
+    #[cfg(feature = "parallel")]
+    pub(crate) fn state_mut(&mut self) -> &mut EState {
+        // SAFETY: a successfully initialized stream has a valid state, which is owned by `self`
+        unsafe { &mut *self.stream.get_mut().state }
+    }
+}
+
+impl Drop for Compress {
+    fn drop(&mut self) {
+        BZ2_bzCompressEndHelp(self.stream.get_mut());
+    }
+}
+
+/// A bzip2 decompression stream.
+///
+/// All memory associated with the stream is released when it is dropped.
+pub struct Decompress {
+    stream: OwnedStream<DState>,
+}
+
+// SAFETY: the stream state is owned exclusively by this value, and is only ever accessed through
+// `&mut self`. The default allocators can be used from any thread.
+unsafe impl Send for Decompress {}
+unsafe impl Sync for Decompress {}
+
+impl Decompress {
+    /// Creates a new decompression stream.
+    ///
+    /// When `small` is true, an alternative decompression algorithm is used that uses less memory,
+    /// at the expense of speed.
+    ///
+    /// See also [`BZ2_bzDecompressInit`](crate::BZ2_bzDecompressInit).
+    pub fn new(small: bool) -> Result<Self, Error> {
+        Self::with_limits(small, None, None)
+    }
+
+    /// Creates a new decompression stream that limits the amount of decompressed output.
+    ///
+    /// - `max_output` is the maximum total number of decompressed bytes.
+    /// - `max_ratio` is the maximum ratio between the number of decompressed bytes and the number
+    ///   of compressed bytes consumed so far. It must not be `Some(0)`.
+    ///
+    /// When decompressing further would exceed a limit, [`Decompress::decompress`] returns
+    /// [`Error::OutputLimit`] instead of writing the excess output. Such untrusted inputs are
+    /// known as decompression bombs: a small input can otherwise expand to many gigabytes.
+    ///
+    /// See also [`BZ2_bzDecompressInitWithLimits`](crate::BZ2_bzDecompressInitWithLimits).
+    pub fn with_limits(
+        small: bool,
+        max_output: Option<u64>,
+        max_ratio: Option<u32>,
+    ) -> Result<Self, Error> {
+        if max_ratio == Some(0) {
+            return Err(Error::Param);
+        }
+
+        let mut stream = OwnedStream::new()?;
+
+        from_return_code(BZ2_bzDecompressInitWithLimitsHelp(
+            stream.get_mut(),
+            0,
+            small as c_int,
+            max_output.unwrap_or(u64::MAX),
+            max_ratio.unwrap_or(0),
+        ))?;
+
+        Ok(Self { stream })
+    }
+
+    /// Creates a new decompression stream that carves all of its memory from `workspace`, rather
+    /// than using an allocator.
+    ///
+    /// A workspace of [`Decompress::workspace_size`]`(block_size100k, small)` bytes can decompress
+    /// streams with a block size of at most `block_size100k * 100k`, also after
+    /// [`Decompress::reset`]. For streams with a larger block size, [`Decompress::decompress`]
+    /// returns [`Error::Mem`]. See also
+    /// [`BZ2_bzDecompressInitWorkspace`](crate::BZ2_bzDecompressInitWorkspace).
+    pub fn in_workspace(workspace: &mut [u8], small: bool) -> Result<InWorkspace<'_, Self>, Error> {
+        let mut stream = OwnedStream::in_workspace(workspace)?;
+
+        from_return_code(BZ2_bzDecompressInitWithLimitsHelp(
+            stream.get_mut(),
+            0,
+            small as c_int,
+            u64::MAX,
+            0,
+        ))?;
+        workspace::reserve_block_buffers(stream.get_mut());
+
+        Ok(InWorkspace::new(Self { stream }))
+    }
+
+    /// The size in bytes of a workspace for [`Decompress::in_workspace`].
+    ///
+    /// # Panics
+    ///
+    /// Panics when `block_size100k` is not in `1..=9`.
+    pub const fn workspace_size(block_size100k: u32, small: bool) -> usize {
+        assert!(1 <= block_size100k && block_size100k <= 9);
+
+        workspace::decompress_size(block_size100k as usize, small) + workspace::STREAM_SIZE
+    }
+
+    /// Decompresses data from `input` into `output`.
+    ///
+    /// Use [`Decompress::total_in`] and [`Decompress::total_out`] to find out how much input was
+    /// consumed and how much output was produced. Returns [`Status::StreamEnd`] when the logical end
+    /// of the stream was reached and all output has been written.
+    ///
+    /// See also [`BZ2_bzDecompress`](crate::BZ2_bzDecompress).
+    pub fn decompress(&mut self, input: &[u8], output: &mut [u8]) -> Result<Status, Error> {
+        self.stream.set_buffers(input, output);
+        let ret = BZ2_bzDecompressHelp(self.stream.get_mut());
+        self.stream.clear_buffers();
+
+        match ret {
+            ReturnCode::BZ_DATA_ERROR => {
+                let data_error = BZ2_bzDecompressGetDataErrorHelp(self.stream.get());
+                Err(Error::Data(data_error.unwrap()))
+            }
+            ret => from_return_code(ret),
+        }
+    }
+
+    /// The total number of input bytes consumed by this stream.
+    pub fn total_in(&self) -> u64 {
+        self.stream.total_in()
+    }
+
+    /// The total number of output bytes produced by this stream.
+    pub fn total_out(&self) -> u64 {
+        self.stream.total_out()
+    }
+
+    /// The number of bytes that a decompression stream allocates for a stream with a block size of
+    /// `block_size100k * 100k`, not counting the small stream object itself.
+    ///
+    /// See also [`BZ2_bzDecompressMemoryUsage`](crate::BZ2_bzDecompressMemoryUsage).
+    ///
+    /// # Panics
+    ///
+    /// Panics when `block_size100k` is not in `1..=9`.
+    pub const fn memory_usage(block_size100k: u32, small: bool) -> usize {
+        assert!(1 <= block_size100k && block_size100k <= 9);
+
+        total_size(&decompress_allocations(block_size100k as usize, small))
+    }
+
+    /// The number of bytes that are currently allocated by this stream, not counting the small
+    /// stream object itself. The buffers for the decompressed blocks are allocated once the
+    /// header of the stream has been read.
+    ///
+    /// See also [`BZ2_bzDecompressAllocatedMemory`](crate::BZ2_bzDecompressAllocatedMemory).
+    pub fn allocated_memory(&self) -> usize {
+        BZ2_bzDecompressAllocatedMemoryHelp(self.stream.get()).unwrap_or(0)
+    }
+
+    /// Prepares the stream for decompressing a new stream, keeping its buffers and limits.
+    ///
+    /// Any input and output of the current stream that has not been processed yet is discarded.
+    /// See also [`BZ2_bzDecompressReset`](crate::BZ2_bzDecompressReset).
+    pub fn reset(&mut self) {
+        let ret = BZ2_bzDecompressResetHelp(self.stream.get_mut());
+        debug_assert_eq!(ret, ReturnCode::BZ_OK);
+    }
+
+    /// Creates a decompression stream that decodes a single block, which starts `skip_bits` bits
+    /// into the input. [`Decompress::decompress`] returns [`Status::StreamEnd`] at the end of the
+    /// block.
+    #[cfg(feature = "std")]
+    pub(crate) fn new_block(small: bool, block_size100k: u8, skip_bits: u8) -> Result<Self, Error> {
+        let mut stream = OwnedStream::new()?;
+
+        from_return_code(BZ2_bzDecompressInitBlockHelp(
+            stream.get_mut(),
+            small as c_int,
+            block_size100k,
+            skip_bits,
+        ))?;
+
+        Ok(Self { stream })
+    }
+
+    /// The stored CRC of the block, and the number of input bits that it occupies (including the
+    /// skipped bits), for a stream created with [`Decompress::new_block`].
+    #[cfg(feature = "std")]
+    pub(crate) fn block_end(&self) -> (u32, u64) {
+        // SAFETY: a successfully initialized stream has a valid state, which is owned by `self`
+        let s = unsafe { &*self.stream.get().state };
+
+        (s.storedBlockCRC, self.total_in() * 8 - s.bsLive as u64)
+    }
+}
+
+impl Drop for Decompress {
+    fn drop(&mut self) {
+        BZ2_bzDecompressEndHelp(self.stream.get_mut());
+    }
+}
+
+/// A [`Compress`] or [`Decompress`] stream whose memory is carved from a borrowed workspace.
+///
+/// The stream dereferences to the wrapped stream for the methods that only read it. The methods
+/// that modify the stream are provided by `InWorkspace` itself, because a mutable reference would
+/// allow the stream to be moved out, and to outlive the workspace:
+///
+/// ```compile_fail
+/// use libbz2_rs_sys::Decompress;
+///
+/// let mut other = Decompress::new(false).unwrap();
+/// {
+///     let mut workspace = vec![0u8; Decompress::workspace_size(1, false)];
+///     let mut stream = Decompress::in_workspace(&mut workspace, false).unwrap();
+///     core::mem::swap(&mut *stream, &mut other);
+/// }
+/// ```
+///
+/// The workspace is released when the stream is dropped.
+pub struct InWorkspace<'a, T> {
+    stream: T,
+    _workspace: PhantomData<&'a mut [u8]>,
+}
+
+impl<T> InWorkspace<'_, T> {
+    fn new(stream: T) -> Self {
+        Self {
+            stream,
+            _workspace: PhantomData,
+        }
+    }
+}
+
+impl<T> Deref for InWorkspace<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.stream
+    }
+}
+
+impl InWorkspace<'_, Compress> {
+    /// See [`Compress::compress`].
+    pub fn compress(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        action: Action,
+    ) -> Result<Status, Error> {
+        self.stream.compress(input, output, action)
+    }
+
+    /// See [`Compress::reset`].
+    pub fn reset(&mut self) {
+        self.stream.reset()
+    }
+
+    /// See [`Compress::reset_with_block_size`].
+    pub fn reset_with_block_size(&mut self, block_size100k: u32) -> Result<(), Error> {
+        self.stream.reset_with_block_size(block_size100k)
+    }
+
+    /// See [`Compress::set_sort_algorithm`].
+    pub fn set_sort_algorithm(&mut self, algorithm: SortAlgorithm) {
+        self.stream.set_sort_algorithm(algorithm)
+    }
+
+    /// See [`Compress::set_extreme`].
+    pub fn set_extreme(&mut self, extreme: bool) {
+        self.stream.set_extreme(extreme)
+    }
+
+    /// See [`Compress::set_huffman_algorithm`].
+    pub fn set_huffman_algorithm(&mut self, algorithm: HuffmanAlgorithm) {
+        self.stream.set_huffman_algorithm(algorithm)
+    }
+
+    /// See [`Compress::record_blocks`].
+    #[cfg(feature = "std")]
+    pub fn record_blocks(&mut self) {
+        self.stream.record_blocks()
+    }
+}
+
+impl InWorkspace<'_, Decompress> {
+    /// See [`Decompress::decompress`].
+    pub fn decompress(&mut self, input: &[u8], output: &mut [u8]) -> Result<Status, Error> {
+        self.stream.decompress(input, output)
+    }
+
+    /// See [`Decompress::reset`].
+    pub fn reset(&mut self) {
+        self.stream.reset()
+    }
+}
+
+/// An upper bound on the size of the compressed data for an input of `len` bytes, when it is
+/// compressed with a block size of `block_size100k * 100k`.
+///
+/// This is the bound documented for [`BZ2_bzBuffToBuffCompress`](crate::BZ2_bzBuffToBuffCompress)
+/// (1% larger than the input, plus 600 bytes), with an additional 600 bytes for every further
+/// block, which accounts for the per-block overhead when small blocks are used.
+///
+/// # Panics
+///
+/// Panics when `block_size100k` is not in `1..=9`.
+pub const fn compress_bound(len: usize, block_size100k: u32) -> usize {
+    assert!(1 <= block_size100k && block_size100k <= 9);
+
+    // the compressor starts a new block when fewer than 19 bytes of space are left, and the
+    // run-length encoding can expand the input by a factor 5/4.
+    let block_capacity = (block_size100k as usize * 100000 - 19) / 5 * 4;
+    let blocks = len / block_capacity + 1;
+
+    len.saturating_add(len / 100)
+        .saturating_add(blocks.saturating_mul(600))
+}
+
+/// Compresses `input` into a new [`Vec`], using a block size of `block_size100k * 100k`.
+///
+/// See also [`BZ2_bzBuffToBuffCompress`](crate::BZ2_bzBuffToBuffCompress).
+#[cfg(feature = "std")]
+pub fn compress_to_vec(input: &[u8], block_size100k: u32) -> Result<Vec<u8>, Error> {
+    if !(1..=9).contains(&block_size100k) {
+        return Err(Error::Param);
+    }
+
+    let mut stream = Compress::new(block_size100k, 0)?;
+    let mut output = vec![0u8; compress_bound(input.len(), block_size100k)];
+
+    loop {
+        let consumed = stream.total_in() as usize;
+        let produced = stream.total_out() as usize;
+
+        if produced == output.len() {
+            output.resize(output.len() + output.len() / 2, 0);
+        }
+
+        let status =
+            stream.compress(&input[consumed..], &mut output[produced..], Action::Finish)?;
+
+        if status == Status::StreamEnd {
+            output.truncate(stream.total_out() as usize);
+            return Ok(output);
+        }
+    }
+}
+
+/// Decompresses the bzip2 stream in `input` into a new [`Vec`].
+///
+/// Decompression stops at the end of the first bzip2 stream: any data following it is ignored.
+///
+/// See also [`BZ2_bzBuffToBuffDecompress`](crate::BZ2_bzBuffToBuffDecompress).
+#[cfg(feature = "std")]
+pub fn decompress_to_vec(input: &[u8]) -> Result<Vec<u8>, Error> {
+    decompress_to_vec_with_limit(input, usize::MAX)
+}
+
+/// Decompresses the bzip2 stream in `input` into a new [`Vec`] of at most `max_output` bytes.
+///
+/// Returns [`Error::OutputLimit`] when the decompressed data is larger than `max_output` bytes.
+/// The output buffer never grows beyond `max_output` bytes.
+///
+/// Decompression stops at the end of the first bzip2 stream: any data following it is ignored.
+#[cfg(feature = "std")]
+pub fn decompress_to_vec_with_limit(input: &[u8], max_output: usize) -> Result<Vec<u8>, Error> {
+    let mut stream = Decompress::with_limits(false, Some(max_output as u64), None)?;
+    let mut output = vec![0u8; Ord::min(input.len().saturating_mul(4).max(1024), max_output)];
+
+    loop {
+        let consumed = stream.total_in() as usize;
+        let produced = stream.total_out() as usize;
+
+        if produced == output.len() {
+            let new_len = Ord::min(output.len().saturating_mul(2).max(1024), max_output);
+            output.resize(new_len, 0);
+        }
+
+        let status = stream.decompress(&input[consumed..], &mut output[produced..])?;
+
+        match status {
+            Status::StreamEnd => {
+                output.truncate(stream.total_out() as usize);
+                return Ok(output);
+            }
+            _ if stream.total_out() as usize == produced && consumed == input.len() => {
+                return Err(Error::UnexpectedEof);
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,865 @@
+//! # parallel compression and decompression
+//!
+//! Compression and decompression that spread the work over multiple threads.
+//!
+//! Every bzip2 block is compressed independently, so the expensive steps (sorting and huffman
+//! coding) of several blocks can run at the same time. The output is byte-identical to the output
+//! of the serial compressor with the same block size.
+//!
+//! Blocks are not byte-aligned, and their length is not stored anywhere. The parallel decoder
+//! instead scans the compressed data for the 48-bit magic number that starts every block, and
+//! decodes the blocks starting at those positions on worker threads. The magic number can also
+//! occur by chance in the compressed data: such false positives are recognized because they fail to
+//! decode or their CRC does not match, and because they do not start where the previous block ends.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle, ScopedJoinHandle};
+use std::{vec, vec::Vec};
+
+use crate::bzlib::{fill_block, flush_rl, init_rl, prepare_new_block, BLOCK_MAGIC, END_MAGIC};
+use crate::compress::compress_block_standalone;
+use crate::{Compress, DataError, DataErrorKind, Decompress, Error, Status};
+
+/// Compresses `input` into a new [`Vec`], using a block size of `block_size100k * 100k` and up to
+/// `threads` worker threads.
+///
+/// The output is identical to the output of [`compress_to_vec`](crate::compress_to_vec).
+pub fn compress_to_vec(
+    input: &[u8],
+    block_size100k: u32,
+    threads: NonZeroUsize,
+) -> Result<Vec<u8>, Error> {
+    let mut engine = Engine::new(block_size100k, threads)?;
+
+    engine.compress(input, true)?;
+    engine.finish()?;
+
+    Ok(engine.writer.out)
+}
+
+/// An encoder that compresses the data written to it into a single bzip2 stream using multiple
+/// threads, and writes the stream to `W`.
+///
+/// This encoder produces the same output as [`write::BzEncoder`](crate::write::BzEncoder), but
+/// uses more memory: up to `threads + 1` blocks are in memory at the same time. The compressed
+/// data is written to `W` in whole blocks.
+///
+/// Call [`BzEncoder::finish`] to write the end of the stream and get the underlying writer back.
+/// When the encoder is dropped without being finished, the stream is finished on a best-effort
+/// basis: any errors are ignored.
+pub struct BzEncoder<W: Write> {
+    inner: Option<W>,
+    engine: Engine,
+    done: bool,
+}
+
+impl<W: Write> BzEncoder<W> {
+    /// Creates a new encoder that writes the compressed data to `inner`, using a block size of
+    /// `block_size100k * 100k` and up to `threads` worker threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `block_size100k` is not in `1..=9`, or when no default allocator is configured.
+    pub fn new(inner: W, block_size100k: u32, threads: NonZeroUsize) -> Self {
+        let engine = match Engine::new(block_size100k, threads) {
+            Ok(engine) => engine,
+            Err(err) => panic!("failed to initialize the compression stream: {err}"),
+        };
+
+        Self {
+            inner: Some(inner),
+            engine,
+            done: false,
+        }
+    }
+
+    /// Gets a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    /// Gets a mutable reference to the underlying writer.
+    ///
+    /// Writing to the underlying writer directly will likely corrupt the compressed output.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.as_mut().unwrap()
+    }
+
+    /// Writes the end of the bzip2 stream, and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.inner.take().unwrap())
+    }
+
+    /// The number of uncompressed bytes written to the encoder so far.
+    pub fn total_in(&self) -> u64 {
+        self.engine.total_in
+    }
+
+    /// The number of compressed bytes produced by the encoder so far.
+    ///
+    /// Some of these bytes may still be buffered, and not yet written to the underlying writer.
+    pub fn total_out(&self) -> u64 {
+        self.engine.writer.total_out
+    }
+
+    /// Writes all buffered output to the underlying writer.
+    fn dump(&mut self) -> io::Result<()> {
+        let inner = self.inner.as_mut().unwrap();
+        let buf = &mut self.engine.writer.out;
+
+        while !buf.is_empty() {
+            match inner.write(buf) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => drop(buf.drain(..n)),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn try_finish(&mut self) -> io::Result<()> {
+        if !self.done {
+            self.engine.finish()?;
+            self.done = true;
+        }
+
+        self.dump()
+    }
+}
+
+impl<W: Write> Write for BzEncoder<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.engine.compress(data, false)?;
+        self.dump()?;
+
+        Ok(data.len())
+    }
+
+    /// Ends the current block, and writes all compressed data to the underlying writer.
+    ///
+    /// Like [`Action::Flush`](crate::Action::Flush), this makes the compression less effective
+    /// when it is used often.
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.done {
+            self.engine.flush()?;
+        }
+
+        self.dump()?;
+        self.inner.as_mut().unwrap().flush()
+    }
+}
+
+impl<W: Write> Drop for BzEncoder<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+/// A block that is being compressed on a worker thread. Returns the block and the length of the
+/// compressed block in bits.
+type Job = JoinHandle<(Compress, u64)>;
+
+/// Fills blocks on the calling thread, compresses them on worker threads, and stitches the
+/// compressed blocks together in order.
+struct Engine {
+    block_size100k: u32,
+    threads: usize,
+    /// The block that is currently being filled
+    current: Compress,
+    /// Blocks that are being compressed, in stream order
+    jobs: VecDeque<Job>,
+    /// Blocks that can be reused
+    free: Vec<Compress>,
+    combined_crc: u32,
+    total_in: u64,
+    writer: BitWriter,
+}
+
+impl Engine {
+    fn new(block_size100k: u32, threads: NonZeroUsize) -> Result<Self, Error> {
+        let current = Compress::new(block_size100k, 0)?;
+
+        let mut writer = BitWriter::default();
+        writer.write_u8(b'B');
+        writer.write_u8(b'Z');
+        writer.write_u8(b'h');
+        writer.write_u8(b'0' + block_size100k as u8);
+
+        Ok(Self {
+            block_size100k,
+            threads: threads.get(),
+            current,
+            jobs: VecDeque::new(),
+            free: Vec::new(),
+            combined_crc: 0,
+            total_in: 0,
+            writer,
+        })
+    }
+
+    /// Adds `input` to the stream. When `finishing`, the end of `input` is the end of the stream.
+    fn compress(&mut self, mut input: &[u8], finishing: bool) -> Result<(), Error> {
+        loop {
+            let s = self.current.state_mut();
+            let consumed = fill_block(s, input);
+            input = &input[consumed..];
+            self.total_in += consumed as u64;
+
+            // like the serial compressor, the last run is added to a full block at the end of the
+            // stream rather than starting a new block
+            if s.nblock >= s.nblockMAX && !(finishing && input.is_empty()) {
+                self.start_job()?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Ends the current block, and waits until all blocks have been compressed.
+    fn flush(&mut self) -> Result<(), Error> {
+        let s = self.current.state_mut();
+        flush_rl(s);
+
+        if s.nblock > 0 {
+            self.start_job()?;
+        }
+
+        while !self.jobs.is_empty() {
+            self.finish_job();
+        }
+
+        Ok(())
+    }
+
+    /// Writes the end of the stream.
+    fn finish(&mut self) -> Result<(), Error> {
+        self.flush()?;
+
+        self.writer.write_u8(0x17);
+        self.writer.write_u8(0x72);
+        self.writer.write_u8(0x45);
+        self.writer.write_u8(0x38);
+        self.writer.write_u8(0x50);
+        self.writer.write_u8(0x90);
+        self.writer.write_u32(self.combined_crc);
+        self.writer.finish();
+
+        Ok(())
+    }
+
+    /// Starts compressing the current block on a worker thread, and continues with a new block.
+    fn start_job(&mut self) -> Result<(), Error> {
+        if self.jobs.len() == self.threads {
+            self.finish_job();
+        }
+
+        let next = match self.free.pop() {
+            Some(next) => next,
+            None => Compress::new(self.block_size100k, 0)?,
+        };
+
+        let mut block = core::mem::replace(&mut self.current, next);
+        let s = block.state_mut();
+
+        // a run of equal bytes may continue in the next block
+        let next = self.current.state_mut();
+        next.state_in_ch = s.state_in_ch;
+        next.state_in_len = s.state_in_len;
+
+        s.blockCRC = !s.blockCRC;
+        self.combined_crc = self.combined_crc.rotate_left(1) ^ s.blockCRC;
+
+        self.jobs.push_back(thread::spawn(move || {
+            let bits = compress_block_standalone(block.state_mut());
+            (block, bits)
+        }));
+
+        Ok(())
+    }
+
+    /// Waits for the oldest block to be compressed, and appends it to the output.
+    fn finish_job(&mut self) {
+        let Some(job) = self.jobs.pop_front() else {
+            return;
+        };
+
+        let (mut block, bits) = match job.join() {
+            Ok(result) => result,
+            Err(payload) => std::panic::resume_unwind(payload),
+        };
+
+        let s = block.state_mut();
+        let zbits = s.arr2.zbits(s.nblock as usize);
+        self.writer.write_bits(zbits, bits);
+
+        prepare_new_block(s);
+        init_rl(s);
+        self.free.push(block);
+    }
+}
+
+/// Appends bit strings to a byte buffer.
+#[derive(Default)]
+struct BitWriter {
+    /// Complete bytes that have not yet been written out
+    out: Vec<u8>,
+    total_out: u64,
+    /// The last `live` bits are not yet part of `out`
+    buff: u64,
+    live: u32,
+}
+
+impl BitWriter {
+    /// Appends the lowest `n <= 32` bits of `v`.
+    fn write(&mut self, n: u32, v: u32) {
+        self.buff = self.buff << n | u64::from(v) & ((1 << n) - 1);
+        self.live += n;
+
+        while self.live >= 8 {
+            self.live -= 8;
+            self.out.push((self.buff >> self.live) as u8);
+            self.total_out += 1;
+        }
+    }
+
+    fn write_u8(&mut self, c: u8) {
+        self.write(8, u32::from(c));
+    }
+
+    fn write_u32(&mut self, u: u32) {
+        self.write(32, u);
+    }
+
+    /// Appends the first `bits` bits of `bytes`, most significant bit first.
+    fn write_bits(&mut self, bytes: &[u8], bits: u64) {
+        let whole = (bits / 8) as usize;
+        let rest = (bits % 8) as u32;
+
+        if self.live == 0 {
+            self.out.extend_from_slice(&bytes[..whole]);
+            self.total_out += whole as u64;
+        } else {
+            for &b in &bytes[..whole] {
+                self.write_u8(b);
+            }
+        }
+
+        if rest > 0 {
+            self.write(rest, u32::from(bytes[whole] >> (8 - rest)));
+        }
+    }
+
+    /// Pads the output with zero bits to a whole number of bytes.
+    fn finish(&mut self) {
+        if self.live > 0 {
+            self.write(8 - self.live, 0);
+        }
+    }
+}
+
+/// Decompresses the concatenated bzip2 streams in `input` into a new [`Vec`], using up to
+/// `threads` worker threads.
+///
+/// Like [`read::MultiBzDecoder`](crate::read::MultiBzDecoder), data following a stream that does not
+/// start with the bzip2 magic bytes is ignored.
+pub fn decompress_to_vec(input: &[u8], threads: NonZeroUsize) -> Result<Vec<u8>, Error> {
+    thread::scope(|scope| {
+        let mut decoder = BlockDecoder::new(Scoped { scope, data: input }, threads);
+        let mut output = Vec::new();
+
+        while let Some(block) = decoder.next_block()? {
+            output.extend_from_slice(&block);
+        }
+
+        Ok(output)
+    })
+}
+
+/// A decoder that decompresses the concatenated bzip2 streams in `T` using multiple threads.
+///
+/// The compressed data must be available up front, for instance as a [`Vec<u8>`] or as a
+/// memory-mapped file. Like [`read::MultiBzDecoder`](crate::read::MultiBzDecoder), data following a
+/// stream that does not start with the bzip2 magic bytes is ignored.
+///
+/// Up to `threads` blocks are decoded at the same time, and the decompressed blocks are kept in
+/// memory until they are read.
+pub struct BzDecoder<T: AsRef<[u8]> + Send + Sync + 'static> {
+    decoder: BlockDecoder<Arc<T>>,
+    /// The decompressed block that is currently being read
+    block: Vec<u8>,
+    pos: usize,
+    total_out: u64,
+}
+
+impl<T: AsRef<[u8]> + Send + Sync + 'static> BzDecoder<T> {
+    /// Creates a new decoder that decompresses `data`, using up to `threads` worker threads.
+    pub fn new(data: T, threads: NonZeroUsize) -> Self {
+        Self {
+            decoder: BlockDecoder::new(Arc::new(data), threads),
+            block: Vec::new(),
+            pos: 0,
+            total_out: 0,
+        }
+    }
+
+    /// Gets a reference to the compressed data.
+    pub fn get_ref(&self) -> &T {
+        &self.decoder.source
+    }
+
+    /// The number of decompressed bytes produced by the decoder so far.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+}
+
+impl<T: AsRef<[u8]> + Send + Sync + 'static> Read for BzDecoder<T> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.block.len() {
+            match self.decoder.next_block()? {
+                Some(block) => {
+                    self.block = block;
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+
+        let n = Ord::min(out.len(), self.block.len() - self.pos);
+        out[..n].copy_from_slice(&self.block[self.pos..][..n]);
+        self.pos += n;
+        self.total_out += n as u64;
+
+        Ok(n)
+    }
+}
+
+/// A decoded block, with the stored CRC and the bit offset of the end of the block.
+struct DecodedBlock {
+    data: Vec<u8>,
+    crc: u32,
+    end: u64,
+}
+
+type DecodeResult = Result<DecodedBlock, Error>;
+
+/// The compressed data of a [`BlockDecoder`], and the threads that decode its blocks.
+trait Source {
+    type Handle: DecodeHandle;
+
+    fn data(&self) -> &[u8];
+
+    /// Decodes the block at bit `offset` on a new thread, which stops early when `cancel` is set.
+    fn spawn(&self, offset: u64, block_size100k: u8, cancel: Arc<AtomicBool>) -> Self::Handle;
+}
+
+/// A thread that decodes a block.
+trait DecodeHandle {
+    fn join(self) -> thread::Result<DecodeResult>;
+}
+
+impl DecodeHandle for JoinHandle<DecodeResult> {
+    fn join(self) -> thread::Result<DecodeResult> {
+        JoinHandle::join(self)
+    }
+}
+
+impl DecodeHandle for ScopedJoinHandle<'_, DecodeResult> {
+    fn join(self) -> thread::Result<DecodeResult> {
+        ScopedJoinHandle::join(self)
+    }
+}
+
+/// Owned data, that is shared with detached threads.
+impl<T: AsRef<[u8]> + Send + Sync + 'static> Source for Arc<T> {
+    type Handle = JoinHandle<DecodeResult>;
+
+    fn data(&self) -> &[u8] {
+        (**self).as_ref()
+    }
+
+    fn spawn(&self, offset: u64, block_size100k: u8, cancel: Arc<AtomicBool>) -> Self::Handle {
+        let data = Arc::clone(self);
+        thread::spawn(move || decode_block((*data).as_ref(), offset, block_size100k, &cancel))
+    }
+}
+
+/// Borrowed data, that is decoded by threads of a [`thread::Scope`].
+struct Scoped<'scope, 'env> {
+    scope: &'scope thread::Scope<'scope, 'env>,
+    data: &'env [u8],
+}
+
+impl<'scope> Source for Scoped<'scope, '_> {
+    type Handle = ScopedJoinHandle<'scope, DecodeResult>;
+
+    fn data(&self) -> &[u8] {
+        self.data
+    }
+
+    fn spawn(&self, offset: u64, block_size100k: u8, cancel: Arc<AtomicBool>) -> Self::Handle {
+        let data = self.data;
+        self.scope
+            .spawn(move || decode_block(data, offset, block_size100k, &cancel))
+    }
+}
+
+/// A position where a magic number was found.
+enum Candidate<H> {
+    /// A block that is being decoded on a worker thread, assuming the given block size
+    Block {
+        offset: u64,
+        block_size100k: u8,
+        job: H,
+        cancel: Arc<AtomicBool>,
+    },
+    /// The end of a stream
+    End { offset: u64 },
+}
+
+impl<H> Candidate<H> {
+    fn offset(&self) -> u64 {
+        match self {
+            Candidate::Block { offset, .. } | Candidate::End { offset } => *offset,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum DecoderState {
+    /// A stream header is expected at this byte offset
+    StreamStart(usize),
+    /// A block or the end of the stream is expected at bit offset `expected`
+    Blocks {
+        expected: u64,
+        block_size100k: u8,
+        /// The number of the expected block, counting from 1 in every stream
+        block_number: i32,
+        combined_crc: u32,
+    },
+    Done,
+    /// Decoding failed with this error, which is returned again on later calls
+    Failed(Error),
+}
+
+/// Scans the compressed data for magic numbers, decodes the blocks on worker threads, and returns
+/// the blocks that form the streams in order.
+///
+/// Every thread that is started is joined before the decoder forgets about it, so that no more
+/// than `threads` blocks are decoded at the same time, also when many candidates are discarded.
+struct BlockDecoder<S: Source> {
+    source: S,
+    threads: usize,
+    state: DecoderState,
+    /// The number of completed streams
+    streams: u64,
+    /// The next byte to scan
+    scan_pos: usize,
+    /// The last bytes that were scanned
+    window: u64,
+    /// The magic numbers that were found, in order
+    candidates: VecDeque<Candidate<S::Handle>>,
+    /// The number of blocks in `candidates`
+    jobs: usize,
+    /// The block size of the most recent stream header, used for new jobs
+    block_size100k: u8,
+}
+
+impl<S: Source> BlockDecoder<S> {
+    fn new(source: S, threads: NonZeroUsize) -> Self {
+        Self {
+            source,
+            threads: threads.get(),
+            state: DecoderState::StreamStart(0),
+            streams: 0,
+            scan_pos: 0,
+            window: 0,
+            candidates: VecDeque::new(),
+            jobs: 0,
+            block_size100k: 9,
+        }
+    }
+
+    /// Returns the next decompressed block, or `None` at the end of the last stream.
+    fn next_block(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        self.next_block_help().inspect_err(|&err| {
+            self.discard_all();
+            self.state = DecoderState::Failed(err);
+        })
+    }
+
+    fn next_block_help(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        loop {
+            match self.state {
+                DecoderState::Done => return Ok(None),
+                DecoderState::Failed(err) => return Err(err),
+                DecoderState::StreamStart(pos) => {
+                    let data = self.source.data();
+
+                    let block_size100k = match data.get(pos..pos + 4) {
+                        Some(&[b'B', b'Z', b'h', n @ b'1'..=b'9']) => n - b'0',
+                        _ if self.streams > 0 => {
+                            // like `bzip2`, ignore trailing garbage after the last stream
+                            self.discard_all();
+                            self.state = DecoderState::Done;
+                            return Ok(None);
+                        }
+                        None if b"BZh".starts_with(&data[pos..]) => {
+                            return Err(Error::UnexpectedEof);
+                        }
+                        _ => return Err(Error::DataMagic),
+                    };
+
+                    self.block_size100k = block_size100k;
+                    self.state = DecoderState::Blocks {
+                        expected: (pos as u64 + 4) * 8,
+                        block_size100k,
+                        block_number: 1,
+                        combined_crc: 0,
+                    };
+                }
+                DecoderState::Blocks {
+                    expected,
+                    block_size100k,
+                    block_number,
+                    combined_crc,
+                } => {
+                    self.scan();
+
+                    let Some(candidate) = self.candidates.pop_front() else {
+                        return Err(Error::UnexpectedEof);
+                    };
+
+                    if candidate.offset() < expected {
+                        // a false positive inside the previous block
+                        self.discard(candidate);
+                        continue;
+                    }
+
+                    if candidate.offset() > expected {
+                        self.discard(candidate);
+                        return Err(Error::Data(DataError {
+                            kind: DataErrorKind::BlockMagic,
+                            block_number: block_number - 1,
+                            bit_offset: expected,
+                            stored_crc: 0,
+                            computed_crc: 0,
+                        }));
+                    }
+
+                    match candidate {
+                        Candidate::Block {
+                            offset,
+                            block_size100k: assumed,
+                            job,
+                            cancel,
+                        } => {
+                            let result = match assumed == block_size100k {
+                                true => self.join(job),
+                                // the block belongs to a stream with a different block size
+                                false => {
+                                    self.stop(job, &cancel);
+
+                                    let data = self.source.data();
+                                    let cancel = AtomicBool::new(false);
+                                    decode_block(data, offset, block_size100k, &cancel)
+                                }
+                            };
+
+                            let block = result.map_err(|err| match err {
+                                Error::Data(mut error) => {
+                                    error.block_number = block_number;
+                                    error.bit_offset += offset / 8 * 8;
+                                    Error::Data(error)
+                                }
+                                err => err,
+                            })?;
+
+                            self.state = DecoderState::Blocks {
+                                expected: block.end,
+                                block_size100k,
+                                block_number: block_number + 1,
+                                combined_crc: combined_crc.rotate_left(1) ^ block.crc,
+                            };
+
+                            return Ok(Some(block.data));
+                        }
+                        Candidate::End { offset } => {
+                            let data = self.source.data();
+
+                            let Some(stored_crc) = read_bits(data, offset + 48, 32) else {
+                                return Err(Error::UnexpectedEof);
+                            };
+
+                            let stored_crc = stored_crc as u32;
+                            if stored_crc != combined_crc
+                                && !cfg!(feature = "__internal-fuzz-disable-checksum")
+                            {
+                                return Err(Error::Data(DataError {
+                                    kind: DataErrorKind::CombinedCrc,
+                                    block_number: block_number - 1,
+                                    bit_offset: offset + 80,
+                                    stored_crc,
+                                    computed_crc: combined_crc,
+                                }));
+                            }
+
+                            self.streams += 1;
+                            self.state =
+                                DecoderState::StreamStart((offset + 80).div_ceil(8) as usize);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Waits for the thread of a job, which then no longer counts towards the `threads` limit.
+    fn join(&mut self, job: S::Handle) -> DecodeResult {
+        let result = job.join();
+        self.jobs -= 1;
+
+        match result {
+            Ok(result) => result,
+            Err(payload) => std::panic::resume_unwind(payload),
+        }
+    }
+
+    /// Stops a job whose result is not needed, and waits for its thread.
+    fn stop(&mut self, job: S::Handle, cancel: &AtomicBool) {
+        cancel.store(true, Ordering::Relaxed);
+        let _ = job.join();
+        self.jobs -= 1;
+    }
+
+    /// Stops decoding a candidate that is not needed.
+    fn discard(&mut self, candidate: Candidate<S::Handle>) {
+        if let Candidate::Block { job, cancel, .. } = candidate {
+            self.stop(job, &cancel);
+        }
+    }
+
+    /// Discards all candidates, e.g. when the data that follows is not needed.
+    fn discard_all(&mut self) {
+        while let Some(candidate) = self.candidates.pop_front() {
+            self.discard(candidate);
+        }
+    }
+
+    /// Scans for magic numbers until `threads` blocks are being decoded, or all data was scanned.
+    fn scan(&mut self) {
+        let data = self.source.data();
+
+        while self.jobs < self.threads && self.scan_pos < data.len() {
+            self.window = self.window << 8 | u64::from(data[self.scan_pos]);
+            self.scan_pos += 1;
+
+            let end = self.scan_pos as u64 * 8;
+
+            // the magic numbers that end in this byte, in order
+            for shift in (0..8).rev() {
+                let Some(offset) = end.checked_sub(48 + shift) else {
+                    continue;
+                };
+
+                match (self.window >> shift) & 0xffff_ffff_ffff {
+                    BLOCK_MAGIC => {
+                        let block_size100k = self.block_size100k;
+                        let cancel = Arc::new(AtomicBool::new(false));
+                        let job = self
+                            .source
+                            .spawn(offset, block_size100k, Arc::clone(&cancel));
+
+                        self.candidates.push_back(Candidate::Block {
+                            offset,
+                            block_size100k,
+                            job,
+                            cancel,
+                        });
+                        self.jobs += 1;
+                    }
+                    END_MAGIC => self.candidates.push_back(Candidate::End { offset }),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+impl<S: Source> Drop for BlockDecoder<S> {
+    fn drop(&mut self) {
+        self.discard_all();
+    }
+}
+
+/// The number of input bytes that [`decode_block`] consumes before it checks whether it should
+/// stop.
+const CANCEL_INTERVAL: usize = 64 * 1024;
+
+/// Decodes the block that starts at bit `offset` of `data`, and verifies its CRC.
+///
+/// When `cancel` is set, decoding stops early with an error. The result is then not used.
+fn decode_block(
+    data: &[u8],
+    offset: u64,
+    block_size100k: u8,
+    cancel: &AtomicBool,
+) -> Result<DecodedBlock, Error> {
+    let start = (offset / 8) as usize;
+    let mut stream = Decompress::new_block(false, block_size100k, (offset % 8) as u8)?;
+    let input = &data[start..];
+
+    let mut output = vec![0u8; usize::from(block_size100k) * 100_000];
+
+    loop {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(Error::Sequence);
+        }
+
+        let consumed = stream.total_in() as usize;
+        let produced = stream.total_out() as usize;
+
+        if produced == output.len() {
+            output.resize(2 * output.len(), 0);
+        }
+
+        let chunk = &input[consumed..][..Ord::min(CANCEL_INTERVAL, input.len() - consumed)];
+        match stream.decompress(chunk, &mut output[produced..])? {
+            Status::StreamEnd => break,
+            _ if stream.total_out() as usize == produced && consumed == input.len() => {
+                return Err(Error::UnexpectedEof);
+            }
+            _ => {}
+        }
+    }
+
+    output.truncate(stream.total_out() as usize);
+    let (crc, bits) = stream.block_end();
+
+    Ok(DecodedBlock {
+        data: output,
+        crc,
+        end: start as u64 * 8 + bits,
+    })
+}
+
+/// Reads `n <= 32` bits starting at bit `offset` of `data`.
+fn read_bits(data: &[u8], offset: u64, n: u32) -> Option<u64> {
+    let mut value = 0u64;
+
+    for bit in offset..offset + u64::from(n) {
+        let byte = *data.get((bit / 8) as usize)?;
+        value = value << 1 | u64::from(byte >> (7 - bit % 8) & 1);
+    }
+
+    Some(value)
+}
@@ -0,0 +1,139 @@
+//! # decompressing readers
+//!
+//! Types that decompress bzip2 data read from an underlying [`Read`] implementation.
+
+use std::io::{self, BufReader, Read};
+
+use crate::bufread::Decoder;
+
+/// The size of the internal input buffer, the same as the one used by [`BZ2_bzRead`](crate::BZ2_bzRead).
+const BUFFER_SIZE: usize = 5000;
+
+/// A decoder that decompresses a single bzip2 stream read from `R`.
+///
+/// The decoder reads ahead into an internal buffer, so it may have consumed bytes from `R` beyond
+/// the end of the bzip2 stream. Use [`bufread::BzDecoder`](crate::bufread::BzDecoder) when the
+/// data following the stream is needed.
+pub struct BzDecoder<R> {
+    inner: Decoder<BufReader<R>>,
+}
+
+impl<R: Read> BzDecoder<R> {
+    /// Creates a new decoder that decompresses the data read from `inner`.
+    pub fn new(inner: R) -> Self {
+        Self::with_small(inner, false)
+    }
+
+    /// Creates a new decoder, using the slower, low-memory decompression algorithm when `small` is
+    /// true.
+    ///
+    /// # Panics
+    ///
+    /// Panics when no default allocator is configured.
+    pub fn with_small(inner: R, small: bool) -> Self {
+        let inner = BufReader::with_capacity(BUFFER_SIZE, inner);
+
+        Self {
+            inner: Decoder::new(inner, small, false),
+        }
+    }
+
+    /// Gets a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        self.inner.inner.get_ref()
+    }
+
+    /// Gets a mutable reference to the underlying reader.
+    ///
+    /// Reading from the underlying reader directly will likely corrupt the decompressed output.
+    pub fn get_mut(&mut self) -> &mut R {
+        self.inner.inner.get_mut()
+    }
+
+    /// Consumes the decoder, returning the underlying reader.
+    ///
+    /// Any input that was read ahead into the internal buffer is lost.
+    pub fn into_inner(self) -> R {
+        self.inner.inner.into_inner()
+    }
+
+    /// The number of compressed bytes consumed by the decoder so far.
+    pub fn total_in(&self) -> u64 {
+        self.inner.total_in()
+    }
+
+    /// The number of decompressed bytes produced by the decoder so far.
+    pub fn total_out(&self) -> u64 {
+        self.inner.total_out()
+    }
+}
+
+impl<R: Read> Read for BzDecoder<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(out)
+    }
+}
+
+/// A decoder that decompresses a sequence of concatenated bzip2 streams read from `R`.
+///
+/// See [`bufread::MultiBzDecoder`](crate::bufread::MultiBzDecoder) for how data following a
+/// stream is handled.
+pub struct MultiBzDecoder<R> {
+    inner: Decoder<BufReader<R>>,
+}
+
+impl<R: Read> MultiBzDecoder<R> {
+    /// Creates a new decoder that decompresses the data read from `inner`.
+    pub fn new(inner: R) -> Self {
+        Self::with_small(inner, false)
+    }
+
+    /// Creates a new decoder, using the slower, low-memory decompression algorithm when `small` is
+    /// true.
+    ///
+    /// # Panics
+    ///
+    /// Panics when no default allocator is configured.
+    pub fn with_small(inner: R, small: bool) -> Self {
+        let inner = BufReader::with_capacity(BUFFER_SIZE, inner);
+
+        Self {
+            inner: Decoder::new(inner, small, true),
+        }
+    }
+
+    /// Gets a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        self.inner.inner.get_ref()
+    }
+
+    /// Gets a mutable reference to the underlying reader.
+    ///
+    /// Reading from the underlying reader directly will likely corrupt the decompressed output.
+    pub fn get_mut(&mut self) -> &mut R {
+        self.inner.inner.get_mut()
+    }
+
+    /// Consumes the decoder, returning the underlying reader.
+    ///
+    /// Any input that was read ahead into the internal buffer is lost.
+    pub fn into_inner(self) -> R {
+        self.inner.inner.into_inner()
+    }
+
+    /// The number of compressed bytes consumed by the decoder so far, over all streams.
+    pub fn total_in(&self) -> u64 {
+        self.inner.total_in()
+    }
+
+    /// The number of decompressed bytes produced by the decoder so far, over all streams.
+    pub fn total_out(&self) -> u64 {
+        self.inner.total_out()
+    }
+}
+
+impl<R: Read> Read for MultiBzDecoder<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(out)
+    }
+}
@@ -0,0 +1,325 @@
+#![forbid(unsafe_code)]
+
+//! Sorting the rotations of a block in linear time, with the SA-IS suffix sorting algorithm.
+//!
+//! Unlike `mainSort`, the running time hardly depends on how repetitive the block is, so there is
+//! no need for a budget and a fallback to a slower algorithm.
+//!
+//! The rotations of a block are sorted by sorting the suffixes of its smallest rotation. That
+//! rotation is a power `L^m` of a Lyndon word `L`, and the suffixes of a Lyndon word are in the same
+//! order as its rotations.
+//!
+//! When `m > 1`, the block has `m` copies of every rotation. The other algorithms leave such
+//! identical rotations in an order that is determined by the partitioning of `fallbackQSort3`,
+//! and that order decides `origPtr`. So these blocks are left to `fallbackSort`.
+
+use crate::debug_logln;
+
+/// Marks an empty slot in the suffix array.
+const EMPTY: u32 = u32::MAX;
+
+/// Sorts the rotations of `block` into `ptr`, like `mainSort` and `fallbackSort` do.
+///
+/// Returns `false`, without sorting, when `block` is a repetition of a shorter string.
+///
+/// `block` is rotated while sorting, but restored afterwards. `work` is scratch space of at least
+/// `256 + block.len() / 2 + block.len() / 32` elements.
+pub(crate) fn sais_sort(
+    ptr: &mut [u32],
+    block: &mut [u8],
+    work: &mut [u32],
+    verbosity: i32,
+) -> bool {
+    let n = block.len();
+    let ptr = &mut ptr[..n];
+
+    let shift = min_rotation(block);
+    block.rotate_left(shift);
+
+    let period = lyndon_period(block);
+    if period < n {
+        block.rotate_right(shift);
+        return false;
+    }
+
+    if verbosity >= 3 {
+        debug_logln!("      sais: smallest rotation at {}", shift);
+    }
+
+    sais(block, ptr, 256, work);
+
+    for p in ptr.iter_mut() {
+        *p += shift as u32;
+        if *p as usize >= n {
+            *p -= n as u32;
+        }
+    }
+
+    block.rotate_right(shift);
+
+    true
+}
+
+/// The start of the smallest rotation of `block`, using the two-pointer algorithm.
+fn min_rotation(block: &[u8]) -> usize {
+    let n = block.len();
+    let at = |i: usize| block[if i >= n { i - n } else { i }];
+
+    let (mut i, mut j, mut k) = (0, 1, 0);
+    while i < n && j < n && k < n {
+        let (a, b) = (at(i + k), at(j + k));
+        if a == b {
+            k += 1;
+            continue;
+        }
+
+        if a > b {
+            i += k + 1;
+        } else {
+            j += k + 1;
+        }
+        if i == j {
+            j += 1;
+        }
+        k = 0;
+    }
+
+    Ord::min(i, j)
+}
+
+/// The length of the Lyndon word `L`, when `block` is a smallest rotation `L^m`.
+fn lyndon_period(block: &[u8]) -> usize {
+    // the first iteration of Duval's Lyndon factorization algorithm
+    let (mut k, mut j) = (0, 1);
+    while j < block.len() && block[k] <= block[j] {
+        if block[k] < block[j] {
+            k = 0;
+        } else {
+            k += 1;
+        }
+        j += 1;
+    }
+
+    j - k
+}
+
+/// A character of a text that is suffix sorted.
+trait Symbol: Copy + Eq + Ord {
+    fn index(self) -> usize;
+}
+
+impl Symbol for u8 {
+    fn index(self) -> usize {
+        usize::from(self)
+    }
+}
+
+impl Symbol for u32 {
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Whether each position of a text is S-type (its suffix is smaller than the next suffix), as a
+/// bitset.
+struct Types<'a>(&'a mut [u32]);
+
+impl Types<'_> {
+    fn compute<T: Symbol>(&mut self, text: &[T]) {
+        let n = text.len();
+        self.0.fill(0);
+
+        // the last suffix is larger than the empty suffix, so it is L-type
+        let mut next_is_s = false;
+        for i in (0..n.saturating_sub(1)).rev() {
+            let is_s = text[i] < text[i + 1] || (text[i] == text[i + 1] && next_is_s);
+            self.0[i / 32] |= u32::from(is_s) << (i % 32);
+            next_is_s = is_s;
+        }
+    }
+
+    fn is_s(&self, i: usize) -> bool {
+        self.0[i / 32] & (1 << (i % 32)) != 0
+    }
+
+    /// Whether `i` is a leftmost S-type position.
+    fn is_lms(&self, i: usize) -> bool {
+        i > 0 && self.is_s(i) && !self.is_s(i - 1)
+    }
+}
+
+/// Sets `buckets` to the start (or end) of the bucket of every character of `text`.
+fn find_buckets<T: Symbol>(text: &[T], buckets: &mut [u32], ends: bool) {
+    buckets.fill(0);
+    for &c in text {
+        buckets[c.index()] += 1;
+    }
+
+    let mut sum = 0;
+    for bucket in buckets.iter_mut() {
+        sum += *bucket;
+        *bucket = if ends { sum } else { sum - *bucket };
+    }
+}
+
+/// Splits `work` into the buckets for an alphabet of size `k` and the types of a text of length `n`.
+fn split_work(work: &mut [u32], k: usize, n: usize) -> (&mut [u32], Types<'_>) {
+    assert!(
+        work.len() >= k + n.div_ceil(32),
+        "sais: work space too small"
+    );
+
+    let (buckets, rest) = work.split_at_mut(k);
+    (buckets, Types(&mut rest[..n.div_ceil(32)]))
+}
+
+/// Sorts all suffixes, given the sorted LMS suffixes at the ends of their buckets.
+fn induce<T: Symbol>(text: &[T], sa: &mut [u32], buckets: &mut [u32], types: &Types) {
+    let n = text.len();
+
+    // the L-type suffixes, from left to right
+    find_buckets(text, buckets, false);
+
+    let c = text[n - 1].index();
+    sa[buckets[c] as usize] = (n - 1) as u32;
+    buckets[c] += 1;
+
+    for i in 0..n {
+        // `j - 1` is L-type if it is not smaller than `j`, because `j` is either L-type or LMS
+        let j = sa[i].wrapping_sub(1) as usize;
+        if j < n - 1 && text[j] >= text[j + 1] {
+            let c = text[j].index();
+            sa[buckets[c] as usize] = j as u32;
+            buckets[c] += 1;
+        }
+    }
+
+    // the S-type suffixes, from right to left
+    find_buckets(text, buckets, true);
+
+    for i in (0..n).rev() {
+        let j = sa[i].wrapping_sub(1) as usize;
+        if j < n - 1 && types.is_s(j) {
+            let c = text[j].index();
+            buckets[c] -= 1;
+            sa[buckets[c] as usize] = j as u32;
+        }
+    }
+}
+
+/// Sorts the suffixes of `text`, whose characters are smaller than `k`, into `sa`.
+fn sais<T: Symbol>(text: &[T], sa: &mut [u32], k: usize, work: &mut [u32]) {
+    let n = text.len();
+    if n == 0 {
+        return;
+    }
+
+    // stage 1: sort the LMS substrings
+    let (buckets, mut types) = split_work(work, k, n);
+    types.compute(text);
+
+    sa.fill(EMPTY);
+    find_buckets(text, buckets, true);
+    for (i, c) in text.iter().enumerate().skip(1) {
+        if types.is_lms(i) {
+            let c = c.index();
+            buckets[c] -= 1;
+            sa[buckets[c] as usize] = i as u32;
+        }
+    }
+
+    induce(text, sa, buckets, &types);
+
+    let mut n1 = 0;
+    for i in 0..n {
+        let j = sa[i];
+        if types.is_lms(j as usize) {
+            sa[n1] = j;
+            n1 += 1;
+        }
+    }
+
+    // LMS positions are at least 2 apart, so `pos / 2` is unique. First store the length of every
+    // LMS substring there, up to and including the next LMS position. The last LMS substring ends
+    // with the unique empty suffix, and gets length 0 so that it is not equal to any other.
+    sa[n1..].fill(EMPTY);
+    let mut next = n;
+    for i in (1..n).rev() {
+        if types.is_lms(i) {
+            sa[n1 + i / 2] = if next == n { 0 } else { (next - i) as u32 };
+            next = i;
+        }
+    }
+
+    // name the LMS substrings
+    let mut names = 0;
+    let mut previous: Option<(usize, usize)> = None;
+    for i in 0..n1 {
+        let pos = sa[i] as usize;
+        let len = sa[n1 + pos / 2] as usize;
+
+        let equal = match previous {
+            Some((prev, prev_len)) => {
+                len != 0 && len == prev_len && text[prev..=prev + len] == text[pos..=pos + len]
+            }
+            None => false,
+        };
+
+        if !equal {
+            names += 1;
+            previous = Some((pos, len));
+        }
+        sa[n1 + pos / 2] = names - 1;
+    }
+
+    // gather the names in text order at the end of `sa`
+    let mut j = n;
+    for i in (n1..n).rev() {
+        if sa[i] != EMPTY {
+            j -= 1;
+            sa[j] = sa[i];
+        }
+    }
+
+    // stage 2: sort the LMS suffixes, by sorting the suffixes of the reduced text
+    let (head, reduced) = sa.split_at_mut(n - n1);
+    let sa1 = &mut head[..n1];
+
+    if (names as usize) < n1 {
+        sais(reduced, sa1, names as usize, work);
+    } else {
+        for (i, &name) in reduced.iter().enumerate() {
+            sa1[name as usize] = i as u32;
+        }
+    }
+
+    // the recursion used the work space
+    let (buckets, mut types) = split_work(work, k, n);
+    types.compute(text);
+
+    let mut j = 0;
+    for i in 1..n {
+        if types.is_lms(i) {
+            reduced[j] = i as u32;
+            j += 1;
+        }
+    }
+
+    for p in sa1.iter_mut() {
+        *p = reduced[*p as usize];
+    }
+
+    // stage 3: put the sorted LMS suffixes at the ends of their buckets, and induce the rest
+    sa[n1..].fill(EMPTY);
+    find_buckets(text, buckets, true);
+    for i in (0..n1).rev() {
+        let j = sa[i];
+        sa[i] = EMPTY;
+
+        let c = text[j as usize].index();
+        buckets[c] -= 1;
+        sa[buckets[c] as usize] = j;
+    }
+
+    induce(text, sa, buckets, &types);
+}
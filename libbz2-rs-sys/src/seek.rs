@@ -0,0 +1,517 @@
+//! # seekable decompression
+//!
+//! Random access into bzip2 data. The blocks of a bzip2 stream can be decompressed independently,
+//! so a reader that knows where the blocks start can begin decompressing at the block that contains
+//! the requested position, instead of at the start of the data.
+//!
+//! The block boundaries are recorded in a [`BlockIndex`], which [`SeekableBzDecoder`] builds while it
+//! decompresses the data.
+
+use std::io::{self, Read, Seek, SeekFrom};
+use std::{vec, vec::Vec};
+
+use crate::bzlib::{BLOCK_MAGIC, END_MAGIC};
+use crate::{BlockInfo, DataError, DataErrorKind, Decompress, Error, Status};
+
+/// The size of the internal input buffer, the same as the one used by [`BZ2_bzRead`](crate::BZ2_bzRead).
+const BUFFER_SIZE: usize = 5000;
+
+/// The position of a block in the compressed and in the decompressed data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BlockIndexEntry {
+    /// The offset of the block header in the compressed data, in bits.
+    pub compressed_bit_offset: u64,
+    /// The offset of the first decompressed byte of the block.
+    pub uncompressed_offset: u64,
+    /// The block size of the stream that contains the block, in `1..=9`.
+    pub block_size100k: u8,
+}
+
+/// The positions of the blocks of (a prefix of) a sequence of concatenated bzip2 streams.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockIndex {
+    entries: Vec<BlockIndexEntry>,
+    uncompressed_len: Option<u64>,
+}
+
+impl BlockIndex {
+    /// Creates an empty index.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a complete index from a description of all blocks, for instance one that was read
+    /// from an [index file](crate::index).
+    pub fn from_blocks(blocks: &[BlockInfo]) -> Self {
+        let entries = blocks
+            .iter()
+            .map(|block| BlockIndexEntry {
+                compressed_bit_offset: block.compressed_bit_offset,
+                uncompressed_offset: block.uncompressed_offset,
+                block_size100k: block.block_size100k as u8,
+            })
+            .collect();
+
+        let uncompressed_len = blocks.last().map_or(0, |block| {
+            block.uncompressed_offset + u64::from(block.uncompressed_len)
+        });
+
+        Self {
+            entries,
+            uncompressed_len: Some(uncompressed_len),
+        }
+    }
+
+    /// The blocks that are known so far, in order.
+    pub fn entries(&self) -> &[BlockIndexEntry] {
+        &self.entries
+    }
+
+    /// The total length of the decompressed data, when all blocks are known.
+    pub fn uncompressed_len(&self) -> Option<u64> {
+        self.uncompressed_len
+    }
+
+    /// Whether all blocks are known.
+    pub fn is_complete(&self) -> bool {
+        self.uncompressed_len.is_some()
+    }
+
+    /// The number of the last known block that starts at or before `pos`.
+    fn find(&self, pos: u64) -> Option<usize> {
+        self.entries
+            .partition_point(|entry| entry.uncompressed_offset <= pos)
+            .checked_sub(1)
+    }
+}
+
+/// A decoder that decompresses the concatenated bzip2 streams read from `R`, and that supports
+/// seeking to any position in the decompressed data.
+///
+/// Seeking restarts decompression at the start of the block that contains the new position, so
+/// at most one block (at most 900k bytes before the initial run-length decoding) has to be
+/// decompressed before the data at that position is available. Blocks that are not yet in the
+/// [index](SeekableBzDecoder::index) are decompressed to find out where they end, so the first seek
+/// to a far-away position, and any seek relative to the end, can be slow.
+///
+/// Like [`read::MultiBzDecoder`](crate::read::MultiBzDecoder), data following a stream that does not
+/// start with the bzip2 magic bytes is ignored. The combined CRC of a stream is only verified when
+/// the stream is decompressed from its first block up to its end without seeking.
+pub struct SeekableBzDecoder<R> {
+    inner: R,
+    index: BlockIndex,
+    /// The position in the decompressed data
+    pos: u64,
+    /// The block that is being decompressed, if it is positioned at `pos`
+    current: Option<CurrentBlock>,
+    /// The combined CRC of the stream so far, if it was decompressed from its first block
+    combined_crc: Option<u32>,
+    buf: Vec<u8>,
+}
+
+/// The state of the block that is being decompressed.
+struct CurrentBlock {
+    /// The number of the block in the index
+    number: usize,
+    stream: Decompress,
+    /// The input that was read from the underlying reader, starting at the block header
+    consumed: usize,
+    filled: usize,
+    finished: bool,
+}
+
+impl<R: Read + Seek> SeekableBzDecoder<R> {
+    /// Creates a new decoder that decompresses the data read from `inner`. The first stream must
+    /// start at offset 0 of `inner`.
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            index: BlockIndex::new(),
+            pos: 0,
+            current: None,
+            combined_crc: None,
+            buf: vec![0u8; BUFFER_SIZE],
+        }
+    }
+
+    /// Creates a new decoder that uses an index that was built before, for instance with
+    /// [`BlockIndex::from_blocks`]. The index must describe the data read from `inner`.
+    pub fn with_index(inner: R, index: BlockIndex) -> Self {
+        Self {
+            index,
+            ..Self::new(inner)
+        }
+    }
+
+    /// Gets a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Gets a mutable reference to the underlying reader.
+    ///
+    /// Reading from the underlying reader directly will likely corrupt the decompressed output.
+    pub fn get_mut(&mut self) -> &mut R {
+        // the position of the underlying reader may change
+        self.current = None;
+        &mut self.inner
+    }
+
+    /// Consumes the decoder, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// The index of the blocks that are known so far.
+    pub fn index(&self) -> &BlockIndex {
+        &self.index
+    }
+
+    /// Decompresses all blocks that are not yet in the index, so that the index is complete.
+    ///
+    /// The position in the decompressed data does not change.
+    pub fn build_index(&mut self) -> io::Result<&BlockIndex> {
+        let pos = self.pos;
+        let result = self.seek_to_end();
+        self.pos = pos;
+        self.current = None;
+
+        result?;
+        Ok(&self.index)
+    }
+
+    /// Reads `buf.len()` bytes at the given byte offset of the compressed data. Returns the number
+    /// of bytes that were available.
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.seek(SeekFrom::Start(offset))?;
+
+        let mut filled = 0;
+        while filled < buf.len() {
+            match self.inner.read(&mut buf[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(filled)
+    }
+
+    /// Finds the block at or after bit `offset`, where a stream header is expected when
+    /// `block_size100k` is `None`, and a block or the end of the stream otherwise. Returns `None`
+    /// at the end of the last stream.
+    fn locate(
+        &mut self,
+        mut offset: u64,
+        mut block_size100k: Option<u8>,
+    ) -> io::Result<Option<(u64, u8)>> {
+        loop {
+            let Some(n) = block_size100k else {
+                let mut header = [0u8; 4];
+                let len = self.read_at(offset / 8, &mut header)?;
+
+                match (header, offset) {
+                    ([b'B', b'Z', b'h', n @ b'1'..=b'9'], _) if len == 4 => {
+                        block_size100k = Some(n - b'0');
+                        self.combined_crc = Some(0);
+                        offset += 32;
+                        continue;
+                    }
+                    (_, 0) if b"BZh".starts_with(&header[..len]) => {
+                        return Err(Error::UnexpectedEof.into());
+                    }
+                    (_, 0) => return Err(Error::DataMagic.into()),
+                    // like `bzip2`, ignore trailing garbage after the last stream
+                    _ => return Ok(None),
+                }
+            };
+
+            // the magic number, and the combined CRC at the end of a stream
+            let mut bytes = [0u8; 11];
+            let len = self.read_at(offset / 8, &mut bytes)?;
+            let bits = u128::from_be_bytes({
+                let mut padded = [0u8; 16];
+                padded[..11].copy_from_slice(&bytes);
+                padded
+            }) << (offset % 8);
+            let available = (len as u64 * 8).saturating_sub(offset % 8);
+
+            if available < 48 {
+                return Err(Error::UnexpectedEof.into());
+            }
+
+            match (bits >> 80) as u64 {
+                BLOCK_MAGIC => return Ok(Some((offset, n))),
+                END_MAGIC if available < 80 => return Err(Error::UnexpectedEof.into()),
+                END_MAGIC => {
+                    let stored_crc = (bits >> 48) as u32;
+
+                    match self.combined_crc {
+                        Some(computed_crc)
+                            if computed_crc != stored_crc
+                                && !cfg!(feature = "__internal-fuzz-disable-checksum") =>
+                        {
+                            return Err(Error::Data(DataError {
+                                kind: DataErrorKind::CombinedCrc,
+                                block_number: self.index.entries.len() as i32,
+                                bit_offset: offset + 80,
+                                stored_crc,
+                                computed_crc,
+                            })
+                            .into());
+                        }
+                        _ => {}
+                    }
+
+                    // the next stream starts at the next byte boundary
+                    offset = (offset + 80).div_ceil(8) * 8;
+                    block_size100k = None;
+                }
+                _ => {
+                    return Err(Error::Data(DataError {
+                        kind: DataErrorKind::BlockMagic,
+                        block_number: self.index.entries.len() as i32,
+                        bit_offset: offset,
+                        stored_crc: 0,
+                        computed_crc: 0,
+                    })
+                    .into());
+                }
+            }
+        }
+    }
+
+    /// Starts decompressing the block that contains `self.pos`, extending the index when needed.
+    /// Returns `false` when `self.pos` is at or beyond the end of the data.
+    fn start_block(&mut self) -> io::Result<bool> {
+        if self.index.entries.is_empty() && !self.index.is_complete() {
+            match self.locate(0, None)? {
+                Some((offset, block_size100k)) => self.index.entries.push(BlockIndexEntry {
+                    compressed_bit_offset: offset,
+                    uncompressed_offset: 0,
+                    block_size100k,
+                }),
+                None => self.index.uncompressed_len = Some(0),
+            }
+        }
+
+        if matches!(self.index.uncompressed_len, Some(len) if self.pos >= len) {
+            return Ok(false);
+        }
+
+        let Some(number) = self.index.find(self.pos) else {
+            return Ok(false);
+        };
+
+        // the combined CRC can only be verified when decompression starts at the first block of
+        // the stream
+        self.combined_crc = match number {
+            0 => Some(0),
+            _ => None,
+        };
+
+        let target = self.pos;
+        self.pos = self.index.entries[number].uncompressed_offset;
+        self.begin(number)?;
+
+        // skip the part of the block before the target position
+        let mut scratch = [0u8; 4096];
+        while self.pos < target {
+            let len = Ord::min(target - self.pos, scratch.len() as u64) as usize;
+            if self.decode(&mut scratch[..len])? == 0 {
+                // the target position is beyond the end of the data
+                self.pos = target;
+                break;
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Starts decompressing block `number` of the index from its beginning.
+    fn begin(&mut self, number: usize) -> io::Result<()> {
+        let entry = self.index.entries[number];
+        let offset = entry.compressed_bit_offset;
+
+        self.inner.seek(SeekFrom::Start(offset / 8))?;
+
+        let stream = Decompress::new_block(false, entry.block_size100k, (offset % 8) as u8)?;
+        self.current = Some(CurrentBlock {
+            number,
+            stream,
+            consumed: 0,
+            filled: 0,
+            finished: false,
+        });
+
+        Ok(())
+    }
+
+    /// Decompresses data from the current block into `out`, and continues with the next block
+    /// when the current block is finished. Returns 0 at the end of the data.
+    fn decode(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let Some(current) = self.current.as_mut() else {
+                return Ok(0);
+            };
+
+            if !current.finished {
+                if current.consumed == current.filled {
+                    current.consumed = 0;
+                    current.filled = loop {
+                        match self.inner.read(&mut self.buf) {
+                            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                            result => break result?,
+                        }
+                    };
+                }
+
+                let input = &self.buf[current.consumed..current.filled];
+                let before_in = current.stream.total_in();
+                let before_out = current.stream.total_out();
+                let result = current.stream.decompress(input, out);
+                current.consumed += (current.stream.total_in() - before_in) as usize;
+                let produced = (current.stream.total_out() - before_out) as usize;
+
+                match result {
+                    Ok(Status::StreamEnd) => current.finished = true,
+                    Ok(_) if produced == 0 && input.is_empty() => {
+                        return Err(Error::UnexpectedEof.into());
+                    }
+                    Ok(_) => {}
+                    Err(Error::Data(mut error)) => {
+                        let entry = self.index.entries[current.number];
+                        error.block_number = current.number as i32 + 1;
+                        error.bit_offset += entry.compressed_bit_offset / 8 * 8;
+                        return Err(Error::Data(error).into());
+                    }
+                    Err(err) => return Err(err.into()),
+                }
+
+                self.pos += produced as u64;
+
+                if produced > 0 || out.is_empty() {
+                    return Ok(produced);
+                }
+
+                continue;
+            }
+
+            // the block is finished, continue with the next block
+            let number = current.number;
+            let entry = self.index.entries[number];
+            let (crc, bits) = current.stream.block_end();
+            let block_len = current.stream.total_out();
+
+            self.combined_crc = self
+                .combined_crc
+                .map(|combined_crc| combined_crc.rotate_left(1) ^ crc);
+
+            if number + 1 < self.index.entries.len() {
+                // the end of the stream is not visited when the next block is already known
+                self.combined_crc = None;
+            } else {
+                let end = entry.compressed_bit_offset / 8 * 8 + bits;
+                let uncompressed_end = entry.uncompressed_offset + block_len;
+
+                match self.locate(end, Some(entry.block_size100k))? {
+                    Some((offset, block_size100k)) => self.index.entries.push(BlockIndexEntry {
+                        compressed_bit_offset: offset,
+                        uncompressed_offset: uncompressed_end,
+                        block_size100k,
+                    }),
+                    None => {
+                        self.index.uncompressed_len = Some(uncompressed_end);
+                        self.current = None;
+                        return Ok(0);
+                    }
+                }
+            }
+
+            self.begin(number + 1)?;
+        }
+    }
+
+    /// Moves to the end of the data, completing the index.
+    fn seek_to_end(&mut self) -> io::Result<u64> {
+        while !self.index.is_complete() {
+            // continue at the start of the last known block
+            self.pos = self
+                .index
+                .entries
+                .last()
+                .map_or(0, |e| e.uncompressed_offset);
+            self.current = None;
+
+            if !self.start_block()? {
+                continue;
+            }
+
+            let mut scratch = vec![0u8; 64 * 1024];
+            while self.decode(&mut scratch)? > 0 {}
+        }
+
+        self.current = None;
+        self.pos = self.index.uncompressed_len.unwrap();
+        Ok(self.pos)
+    }
+}
+
+impl<R: Read + Seek> Read for SeekableBzDecoder<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if out.is_empty() {
+            return Ok(0);
+        }
+
+        if self.current.is_none() && !self.start_block()? {
+            return Ok(0);
+        }
+
+        self.decode(out)
+    }
+}
+
+impl<R: Read + Seek> Seek for SeekableBzDecoder<R> {
+    /// Moves to a position in the decompressed data.
+    ///
+    /// Seeking relative to the end of the data decompresses all blocks that are not yet in the
+    /// index. Seeking beyond the end of the data is allowed; reading at such a position returns no
+    /// data.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+            SeekFrom::End(n) => {
+                let len = match self.index.uncompressed_len {
+                    Some(len) => len,
+                    None => {
+                        let pos = self.pos;
+                        let len = self.seek_to_end()?;
+                        self.pos = pos;
+                        len
+                    }
+                };
+                len.checked_add_signed(n)
+            }
+        };
+
+        let Some(target) = target else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            ));
+        };
+
+        if target != self.pos {
+            self.pos = target;
+            self.current = None;
+        }
+
+        Ok(target)
+    }
+
+    fn stream_position(&mut self) -> io::Result<u64> {
+        Ok(self.pos)
+    }
+}
@@ -0,0 +1,315 @@
+//! # caller-supplied workspace
+//!
+//! Streams normally allocate their memory with the default allocator, or with the `bzalloc` and
+//! `bzfree` functions of the stream. Targets without a heap can instead give a stream a single
+//! buffer, the workspace, from which all of its memory is carved. [`BZ2_bzCompressWorkspaceSize`]
+//! and [`BZ2_bzDecompressWorkspaceSize`] report how large that buffer must be.
+//!
+//! The workspace starts with a header that records which part of it is still free. The allocation
+//! functions of a workspace hand out the next part of the buffer, and never free anything: the
+//! memory is released when its owner reuses the buffer after the stream has ended. A decompressor
+//! therefore allocates its block buffers for the largest block size that fits in the workspace,
+//! so that after a reset, a stream with a larger block size than the previous one still fits.
+
+use core::ffi::{c_int, c_void};
+use core::mem::{align_of, size_of};
+use core::ptr;
+
+use crate::allocator::Allocator;
+use crate::bzlib::prefix;
+use crate::bzlib::{bz_stream, BzStream, DState, DecompressMode, EState, ReturnCode, StreamState};
+use crate::bzlib::{compress_allocations, decompress_allocations};
+use crate::bzlib::{BZ2_bzCompressInitHelp, BZ2_bzDecompressInitHelp};
+
+#[cfg(doc)]
+use crate::{
+    BZ2_bzCompressEnd, BZ2_bzCompressInit, BZ2_bzDecompressEnd, BZ2_bzDecompressInit,
+    BZ2_bzDecompressReset, BZ_MEM_ERROR, BZ_OK, BZ_PARAM_ERROR,
+};
+
+/// The alignment of the header and of every allocation.
+const ALIGN: usize = 16;
+
+const _: () = assert!(align_of::<EState>() <= ALIGN);
+const _: () = assert!(align_of::<DState>() <= ALIGN);
+const _: () = assert!(align_of::<bz_stream>() <= ALIGN);
+
+/// The start of a workspace.
+struct Header {
+    /// The start of the free part of the workspace.
+    next: *mut u8,
+    /// The end of the workspace.
+    end: *mut u8,
+}
+
+const fn padded(len: usize) -> usize {
+    len.next_multiple_of(ALIGN)
+}
+
+/// The size of a workspace for allocations of the given sizes, which are each padded to [`ALIGN`].
+const fn workspace_size(allocations: &[usize]) -> usize {
+    // the workspace itself is not necessarily aligned
+    let mut size = (ALIGN - 1) + padded(size_of::<Header>());
+
+    let mut i = 0;
+    while i < allocations.len() {
+        size += padded(allocations[i]);
+        i += 1;
+    }
+
+    size
+}
+
+/// The size of a workspace for a compressor.
+pub(crate) const fn compress_size(block_size100k: usize) -> usize {
+    workspace_size(&compress_allocations(block_size100k))
+}
+
+/// The size of a workspace for a decompressor, for blocks of at most `block_size100k * 100k` bytes.
+pub(crate) const fn decompress_size(block_size100k: usize, small: bool) -> usize {
+    workspace_size(&decompress_allocations(block_size100k, small))
+}
+
+/// The additional size of a workspace that also holds the stream itself.
+pub(crate) const STREAM_SIZE: usize = padded(size_of::<bz_stream>());
+
+/// Prepares `len` bytes at `ptr` for use as a workspace, and returns the `opaque` pointer of its
+/// allocation functions. Returns `None` when there is no room for the header.
+///
+/// # Safety
+///
+/// `ptr` must be writable for `len` bytes, for as long as the workspace is used.
+pub(crate) unsafe fn init(ptr: *mut u8, len: usize) -> Option<*mut c_void> {
+    let offset = ptr.align_offset(ALIGN);
+    let header_end = offset.checked_add(padded(size_of::<Header>()))?;
+    if header_end > len {
+        return None;
+    }
+
+    // SAFETY: the header and the free part are within the workspace, and the header is aligned
+    unsafe {
+        let header = ptr.add(offset).cast::<Header>();
+        header.write(Header {
+            next: ptr.add(header_end),
+            end: ptr.add(len),
+        });
+
+        Some(header.cast())
+    }
+}
+
+/// Makes `strm` allocate its memory from the workspace of `opaque`.
+pub(crate) fn configure<S: StreamState>(strm: &mut BzStream<S>, opaque: *mut c_void) {
+    strm.bzalloc = Some(allocate);
+    strm.bzfree = Some(deallocate);
+    strm.opaque = opaque;
+}
+
+/// Makes the decompressor `strm`, which was just initialized in a workspace, allocate its block
+/// buffers for the largest block size that fits in the rest of the workspace.
+pub(crate) fn reserve_block_buffers(strm: &mut BzStream<DState>) {
+    // SAFETY: `opaque` is the header of the workspace of the stream
+    let header = unsafe { &*strm.opaque.cast::<Header>() };
+
+    // SAFETY: the stream was initialized successfully
+    let Some(s) = (unsafe { strm.state.as_mut() }) else {
+        return;
+    };
+
+    let small = matches!(s.smallDecompress, DecompressMode::Small);
+    s.reservedBlockSize100k = (1..=9)
+        .rev()
+        .find(|&n| fits(header, &decompress_allocations(usize::from(n), small)[1..]))
+        .unwrap_or(0);
+}
+
+/// Whether allocations of the given sizes fit in the free part of the workspace.
+fn fits(header: &Header, allocations: &[usize]) -> bool {
+    let mut next = header.next as usize;
+    for len in allocations {
+        next = next.next_multiple_of(ALIGN) + len;
+    }
+
+    next <= header.end as usize
+}
+
+/// The allocator of the workspace of `opaque`.
+pub(crate) fn allocator(opaque: *mut c_void) -> Allocator {
+    Allocator::custom(allocate, deallocate, opaque)
+}
+
+unsafe extern "C" fn allocate(opaque: *mut c_void, count: c_int, size: c_int) -> *mut c_void {
+    // SAFETY: `opaque` is the header of a workspace, which is only used by one stream
+    let header = unsafe { &mut *opaque.cast::<Header>() };
+
+    let (Ok(count), Ok(size)) = (usize::try_from(count), usize::try_from(size)) else {
+        return ptr::null_mut();
+    };
+
+    // SAFETY: `next` and `end` are in the same workspace, and `next <= end`
+    let available = unsafe { header.end.offset_from(header.next) } as usize;
+    let offset = header.next.align_offset(ALIGN);
+
+    match count
+        .checked_mul(size)
+        .and_then(|len| len.checked_add(offset))
+    {
+        Some(used) if used <= available => {
+            // SAFETY: the allocation is within the free part of the workspace
+            unsafe {
+                let ptr = header.next.add(offset);
+                header.next = header.next.add(used);
+                ptr.cast()
+            }
+        }
+        _ => ptr::null_mut(),
+    }
+}
+
+unsafe extern "C" fn deallocate(_opaque: *mut c_void, _ptr: *mut c_void) {
+    // the workspace is released all at once, by its owner
+}
+
+/// The size in bytes of a workspace for [`BZ2_bzCompressInitWorkspace`].
+///
+/// # Returns
+///
+/// - 0 if `!(1..=9).contains(&blockSize100k)`
+/// - the size of the workspace otherwise
+#[export_name = prefix!(BZ2_bzCompressWorkspaceSize)]
+pub extern "C" fn BZ2_bzCompressWorkspaceSize(blockSize100k: c_int) -> usize {
+    match blockSize100k {
+        1..=9 => compress_size(blockSize100k as usize),
+        _ => 0,
+    }
+}
+
+/// The size in bytes of a workspace for [`BZ2_bzDecompressInitWorkspace`], that can decompress
+/// streams with a block size of at most `blockSize100k * 100k`.
+///
+/// # Returns
+///
+/// - 0 if any of
+///     - `!(1..=9).contains(&blockSize100k)`
+///     - `!(0..=1).contains(&small)`
+/// - the size of the workspace otherwise
+#[export_name = prefix!(BZ2_bzDecompressWorkspaceSize)]
+pub extern "C" fn BZ2_bzDecompressWorkspaceSize(blockSize100k: c_int, small: c_int) -> usize {
+    match (blockSize100k, small) {
+        (1..=9, 0..=1) => decompress_size(blockSize100k as usize, small == 1),
+        _ => 0,
+    }
+}
+
+/// Prepares the stream for compression, like [`BZ2_bzCompressInit`], but carves all of its memory
+/// from the `workspaceSize` bytes at `workspace` instead of using an allocator.
+///
+/// The `bzalloc`, `bzfree` and `opaque` fields of `strm` are overwritten. The workspace is in use
+/// until [`BZ2_bzCompressEnd`] is called, and its size should be at least
+/// [`BZ2_bzCompressWorkspaceSize`]`(blockSize100k)` bytes.
+///
+/// # Returns
+///
+/// - [`BZ_PARAM_ERROR`] if any of
+///     - `strm.is_null()`
+///     - `workspace.is_null()`
+///     - `!(1..=9).contains(&blockSize100k)`
+///     - `!(0..=4).contains(&verbosity)`
+///     - `!(0..=250).contains(&workFactor)`
+/// - [`BZ_MEM_ERROR`] if the workspace is too small
+/// - [`BZ_OK`] otherwise
+///
+/// # Safety
+///
+/// The caller must guarantee that
+///
+/// * Either
+///     - `strm` is `NULL`
+///     - `strm` satisfies the requirements of `&mut *strm`
+/// * Either
+///     - `workspace` is `NULL`
+///     - `workspace` is writable for `workspaceSize` bytes, and is not used for anything else
+///       until the stream has ended
+#[export_name = prefix!(BZ2_bzCompressInitWorkspace)]
+pub unsafe extern "C" fn BZ2_bzCompressInitWorkspace(
+    strm: *mut bz_stream,
+    blockSize100k: c_int,
+    verbosity: c_int,
+    workFactor: c_int,
+    workspace: *mut c_void,
+    workspaceSize: usize,
+) -> c_int {
+    let Some(strm) = (unsafe { BzStream::from_ptr(strm) }) else {
+        return ReturnCode::BZ_PARAM_ERROR as c_int;
+    };
+
+    if workspace.is_null() {
+        return ReturnCode::BZ_PARAM_ERROR as c_int;
+    }
+
+    let Some(opaque) = (unsafe { init(workspace.cast(), workspaceSize) }) else {
+        return ReturnCode::BZ_MEM_ERROR as c_int;
+    };
+
+    configure(strm, opaque);
+    BZ2_bzCompressInitHelp(strm, blockSize100k, verbosity, workFactor) as c_int
+}
+
+/// Prepares the stream for decompression, like [`BZ2_bzDecompressInit`], but carves all of its
+/// memory from the `workspaceSize` bytes at `workspace` instead of using an allocator.
+///
+/// The `bzalloc`, `bzfree` and `opaque` fields of `strm` are overwritten. The workspace is in use
+/// until [`BZ2_bzDecompressEnd`] is called. With a workspace of
+/// [`BZ2_bzDecompressWorkspaceSize`]`(blockSize100k, small)` bytes, streams with a block size of at
+/// most `blockSize100k * 100k` can be decompressed, also after [`BZ2_bzDecompressReset`].
+/// Decompressing a stream with a larger block size fails with [`BZ_MEM_ERROR`].
+///
+/// # Returns
+///
+/// - [`BZ_PARAM_ERROR`] if any of
+///     - `strm.is_null()`
+///     - `workspace.is_null()`
+///     - `!(0..=1).contains(&small)`
+///     - `!(0..=4).contains(&verbosity)`
+/// - [`BZ_MEM_ERROR`] if the workspace is too small
+/// - [`BZ_OK`] otherwise
+///
+/// # Safety
+///
+/// The caller must guarantee that
+///
+/// * Either
+///     - `strm` is `NULL`
+///     - `strm` satisfies the requirements of `&mut *strm`
+/// * Either
+///     - `workspace` is `NULL`
+///     - `workspace` is writable for `workspaceSize` bytes, and is not used for anything else
+///       until the stream has ended
+#[export_name = prefix!(BZ2_bzDecompressInitWorkspace)]
+pub unsafe extern "C" fn BZ2_bzDecompressInitWorkspace(
+    strm: *mut bz_stream,
+    verbosity: c_int,
+    small: c_int,
+    workspace: *mut c_void,
+    workspaceSize: usize,
+) -> c_int {
+    let Some(strm) = (unsafe { BzStream::from_ptr(strm) }) else {
+        return ReturnCode::BZ_PARAM_ERROR as c_int;
+    };
+
+    if workspace.is_null() {
+        return ReturnCode::BZ_PARAM_ERROR as c_int;
+    }
+
+    let Some(opaque) = (unsafe { init(workspace.cast(), workspaceSize) }) else {
+        return ReturnCode::BZ_MEM_ERROR as c_int;
+    };
+
+    configure(strm, opaque);
+    match BZ2_bzDecompressInitHelp(strm, verbosity, small) {
+        ReturnCode::BZ_OK => reserve_block_buffers(strm),
+        ret => return ret as c_int,
+    }
+
+    ReturnCode::BZ_OK as c_int
+}
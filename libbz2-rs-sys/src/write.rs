@@ -0,0 +1,156 @@
+//! # compressing writers
+//!
+//! Types that compress the data written to them, and write the bzip2 stream to an underlying
+//! [`Write`] implementation.
+
+use std::io::{self, Write};
+use std::vec::Vec;
+
+use crate::{Action, Compress, Status};
+
+/// The size of the internal output buffer, the same as the one used by [`BZ2_bzWrite`](crate::BZ2_bzWrite).
+const BUFFER_SIZE: usize = 5000;
+
+/// An encoder that compresses the data written to it into a single bzip2 stream, which is written
+/// to `W`.
+///
+/// Call [`BzEncoder::finish`] to write the end of the stream and get the underlying writer back.
+/// When the encoder is dropped without being finished, the stream is finished on a best-effort
+/// basis: any errors are ignored.
+pub struct BzEncoder<W: Write> {
+    inner: Option<W>,
+    stream: Compress,
+    buf: Vec<u8>,
+    done: bool,
+}
+
+impl<W: Write> BzEncoder<W> {
+    /// Creates a new encoder that writes the compressed data to `inner`, using a block size of
+    /// `block_size100k * 100k`.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `block_size100k` is not in `1..=9`, or when no default allocator is configured.
+    pub fn new(inner: W, block_size100k: u32) -> Self {
+        let stream = match Compress::new(block_size100k, 0) {
+            Ok(stream) => stream,
+            Err(err) => panic!("failed to initialize the compression stream: {err}"),
+        };
+
+        Self {
+            inner: Some(inner),
+            stream,
+            buf: Vec::with_capacity(BUFFER_SIZE),
+            done: false,
+        }
+    }
+
+    /// Gets a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    /// Gets a mutable reference to the underlying writer.
+    ///
+    /// Writing to the underlying writer directly will likely corrupt the compressed output.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.as_mut().unwrap()
+    }
+
+    /// Writes the end of the bzip2 stream, and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.inner.take().unwrap())
+    }
+
+    /// The number of uncompressed bytes written to the encoder so far.
+    pub fn total_in(&self) -> u64 {
+        self.stream.total_in()
+    }
+
+    /// The number of compressed bytes produced by the encoder so far.
+    ///
+    /// Some of these bytes may still be buffered, and not yet written to the underlying writer.
+    pub fn total_out(&self) -> u64 {
+        self.stream.total_out()
+    }
+
+    /// Writes all buffered output to the underlying writer.
+    fn dump(&mut self) -> io::Result<()> {
+        let inner = self.inner.as_mut().unwrap();
+
+        while !self.buf.is_empty() {
+            match inner.write(&self.buf) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => drop(self.buf.drain(..n)),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compresses `input` into the (empty) output buffer, returning the number of bytes consumed.
+    fn compress(&mut self, input: &[u8], action: Action) -> io::Result<(usize, Status)> {
+        debug_assert!(self.buf.is_empty());
+        self.buf.resize(BUFFER_SIZE, 0);
+
+        let before_in = self.stream.total_in();
+        let before_out = self.stream.total_out();
+        let result = self.stream.compress(input, &mut self.buf, action);
+        let consumed = (self.stream.total_in() - before_in) as usize;
+        let produced = (self.stream.total_out() - before_out) as usize;
+
+        self.buf.truncate(produced);
+
+        Ok((consumed, result?))
+    }
+
+    fn try_finish(&mut self) -> io::Result<()> {
+        while !self.done {
+            self.dump()?;
+
+            let (_, status) = self.compress(&[], Action::Finish)?;
+            self.done = status == Status::StreamEnd;
+        }
+
+        self.dump()
+    }
+}
+
+impl<W: Write> Write for BzEncoder<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        loop {
+            self.dump()?;
+
+            let (consumed, _) = self.compress(data, Action::Run)?;
+
+            if consumed > 0 || data.is_empty() {
+                return Ok(consumed);
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        loop {
+            self.dump()?;
+
+            let (_, status) = self.compress(&[], Action::Flush)?;
+            if status == Status::RunOk {
+                break;
+            }
+        }
+
+        self.dump()?;
+        self.inner.as_mut().unwrap().flush()
+    }
+}
+
+impl<W: Write> Drop for BzEncoder<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
@@ -0,0 +1,5 @@
+fn main() {
+    println!("cargo:rerun-if-changed=src/bzprintf.c");
+
+    cc::Build::new().file("src/bzprintf.c").compile("bzprintf");
+}
@@ -1,5 +1,6 @@
 use core::ffi::c_uint;
 
+use libbz2_rs_sys::{compress_bound, Action, Compress, SortAlgorithm, Status};
 use test_libbz2_rs_sys::{compress_c, compress_rs};
 
 fn main() {
@@ -56,6 +57,24 @@ fn main() {
 
             drop(dest_vec)
         }
-        other => panic!("invalid option '{other}', expected one of 'c' or 'rs'"),
+        "rs-sais" => {
+            let level: u32 = it.next().unwrap().parse().unwrap();
+
+            let path = it.next().unwrap();
+            let input = std::fs::read(&path).unwrap();
+
+            let mut dest_vec = vec![0u8; compress_bound(input.len(), level)];
+
+            let mut stream = Compress::new(level, 0).unwrap();
+            stream.set_sort_algorithm(SortAlgorithm::Sais);
+
+            let status = stream.compress(&input, &mut dest_vec, Action::Finish);
+            assert_eq!(status, Ok(Status::StreamEnd));
+
+            dest_vec.truncate(stream.total_out() as usize);
+
+            drop(dest_vec)
+        }
+        other => panic!("invalid option '{other}', expected one of 'c', 'rs' or 'rs-sais'"),
     }
 }
@@ -0,0 +1,16 @@
+#include <stdarg.h>
+
+/* the tests use libbz2-rs-sys with the `testing-prefix` feature */
+int LIBBZ2_RS_SYS_TEST_BZ2_bzvprintf(void *b, const char *format, va_list ap);
+
+/* a C-variadic wrapper around BZ2_bzvprintf, which rust can only define with a nightly compiler */
+int test_bzprintf(void *b, const char *format, ...) {
+    va_list ap;
+    int n;
+
+    va_start(ap, format);
+    n = LIBBZ2_RS_SYS_TEST_BZ2_bzvprintf(b, format, ap);
+    va_end(ap);
+
+    return n;
+}
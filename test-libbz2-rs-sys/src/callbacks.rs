@@ -0,0 +1,289 @@
+use std::ffi::{c_int, c_void};
+
+use crate::{SAMPLE1_BZ2, SAMPLE1_REF};
+
+use libbz2_rs_sys::{
+    compress_to_vec, BZ2_bzRead, BZ2_bzReadClose, BZ2_bzReadGetUnused, BZ2_bzReadOpenCallbacks,
+    BZ2_bzWrite, BZ2_bzWriteClose, BZ2_bzWriteOpenCallbacks, BZ_IO_ERROR, BZ_OK, BZ_PARAM_ERROR,
+    BZ_STREAM_END, BZ_UNEXPECTED_EOF,
+};
+
+/// An in-memory file, that is read or written in chunks of at most `chunk_size` bytes.
+#[derive(Default)]
+pub(crate) struct Memory {
+    pub(crate) data: Vec<u8>,
+    pos: usize,
+    chunk_size: usize,
+    /// the number of calls of `read` or `write` after which they fail
+    fail_after: Option<usize>,
+    calls: usize,
+}
+
+impl Memory {
+    pub(crate) fn new(data: &[u8], chunk_size: usize) -> Self {
+        Self {
+            data: data.to_vec(),
+            chunk_size,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn opaque(&mut self) -> *mut c_void {
+        (self as *mut Self).cast()
+    }
+
+    fn fails(&mut self) -> bool {
+        self.calls += 1;
+        self.fail_after.is_some_and(|n| self.calls > n)
+    }
+}
+
+pub(crate) unsafe extern "C" fn read(opaque: *mut c_void, buf: *mut c_void, len: c_int) -> c_int {
+    let memory = unsafe { &mut *opaque.cast::<Memory>() };
+    if memory.fails() {
+        return -1;
+    }
+
+    let remaining = &memory.data[memory.pos..];
+    let n = remaining.len().min(len as usize).min(memory.chunk_size);
+    unsafe { core::ptr::copy_nonoverlapping(remaining.as_ptr(), buf.cast(), n) };
+    memory.pos += n;
+
+    n as c_int
+}
+
+pub(crate) unsafe extern "C" fn write(
+    opaque: *mut c_void,
+    buf: *const c_void,
+    len: c_int,
+) -> c_int {
+    let memory = unsafe { &mut *opaque.cast::<Memory>() };
+    if memory.fails() {
+        return -1;
+    }
+
+    let bytes = unsafe { core::slice::from_raw_parts(buf.cast::<u8>(), len as usize) };
+    memory.data.extend_from_slice(bytes);
+
+    len
+}
+
+pub(crate) unsafe extern "C" fn no_error(_opaque: *mut c_void) -> c_int {
+    0
+}
+
+/// Reads all of `memory`, and returns the output and the final error code.
+fn read_all(memory: &mut Memory) -> (Vec<u8>, c_int) {
+    let mut output = Vec::new();
+    let mut buffer = [0u8; 1024];
+
+    unsafe {
+        let mut bzerror = 0;
+        let bz_file = BZ2_bzReadOpenCallbacks(
+            &mut bzerror,
+            Some(read),
+            Some(no_error),
+            memory.opaque(),
+            0,
+            0,
+            core::ptr::null_mut(),
+            0,
+        );
+        assert_eq!(bzerror, BZ_OK);
+
+        while bzerror == BZ_OK {
+            let n = BZ2_bzRead(
+                &mut bzerror,
+                bz_file,
+                buffer.as_mut_ptr().cast(),
+                buffer.len() as c_int,
+            );
+            output.extend_from_slice(&buffer[..n as usize]);
+        }
+
+        let after_read = bzerror;
+        BZ2_bzReadClose(&mut bzerror, bz_file);
+        assert_eq!(bzerror, BZ_OK);
+
+        (output, after_read)
+    }
+}
+
+#[test]
+fn read_with_callbacks() {
+    for chunk_size in [1, 100, 5000, usize::MAX] {
+        let mut memory = Memory::new(SAMPLE1_BZ2, chunk_size);
+
+        let (output, bzerror) = read_all(&mut memory);
+        assert_eq!(bzerror, BZ_STREAM_END);
+        assert!(output == SAMPLE1_REF);
+    }
+}
+
+#[test]
+fn read_with_callbacks_get_unused() {
+    let mut input = compress_to_vec(b"first", 9).unwrap();
+    input.extend_from_slice(b"trailing data");
+
+    let mut memory = Memory::new(&input, usize::MAX);
+    let mut buffer = [0u8; 100];
+
+    unsafe {
+        let mut bzerror = 0;
+        let bz_file = BZ2_bzReadOpenCallbacks(
+            &mut bzerror,
+            Some(read),
+            Some(no_error),
+            memory.opaque(),
+            0,
+            0,
+            core::ptr::null_mut(),
+            0,
+        );
+
+        let n = BZ2_bzRead(&mut bzerror, bz_file, buffer.as_mut_ptr().cast(), 100);
+        assert_eq!(bzerror, BZ_STREAM_END);
+        assert_eq!(&buffer[..n as usize], b"first");
+
+        let mut unused = core::ptr::null_mut();
+        let mut n_unused = 0;
+        BZ2_bzReadGetUnused(&mut bzerror, bz_file, &mut unused, &mut n_unused);
+        assert_eq!(bzerror, BZ_OK);
+        let unused = core::slice::from_raw_parts(unused.cast::<u8>(), n_unused as usize);
+        assert_eq!(unused, b"trailing data");
+
+        BZ2_bzReadClose(&mut bzerror, bz_file);
+    }
+}
+
+#[test]
+fn read_with_callbacks_errors() {
+    // the input ends before the end of the stream
+    let mut memory = Memory::new(&SAMPLE1_BZ2[..SAMPLE1_BZ2.len() / 2], 1000);
+    assert_eq!(read_all(&mut memory).1, BZ_UNEXPECTED_EOF);
+
+    // `read` fails halfway
+    let mut memory = Memory::new(SAMPLE1_BZ2, 1000);
+    memory.fail_after = Some(10);
+    assert_eq!(read_all(&mut memory).1, BZ_IO_ERROR);
+
+    // the error indicator is set
+    unsafe extern "C" fn error(_opaque: *mut c_void) -> c_int {
+        1
+    }
+
+    let mut bzerror = 0;
+    let bz_file = unsafe {
+        BZ2_bzReadOpenCallbacks(
+            &mut bzerror,
+            Some(read),
+            Some(error),
+            memory.opaque(),
+            0,
+            0,
+            core::ptr::null_mut(),
+            0,
+        )
+    };
+    assert!(bz_file.is_null());
+    assert_eq!(bzerror, BZ_IO_ERROR);
+
+    // both functions are required
+    for (read, error) in [(None, Some(no_error as _)), (Some(read as _), None)] {
+        let bz_file = unsafe {
+            BZ2_bzReadOpenCallbacks(
+                &mut bzerror,
+                read,
+                error,
+                memory.opaque(),
+                0,
+                0,
+                core::ptr::null_mut(),
+                0,
+            )
+        };
+        assert!(bz_file.is_null());
+        assert_eq!(bzerror, BZ_PARAM_ERROR);
+    }
+}
+
+/// Compresses `input` with `write`, and returns the final error code.
+fn write_all(memory: &mut Memory, input: &[u8], block_size100k: c_int) -> c_int {
+    unsafe {
+        let mut bzerror = 0;
+        let bz_file = BZ2_bzWriteOpenCallbacks(
+            &mut bzerror,
+            Some(write),
+            Some(no_error),
+            memory.opaque(),
+            block_size100k,
+            0,
+            0,
+        );
+        assert_eq!(bzerror, BZ_OK);
+
+        for chunk in input.chunks(1024) {
+            BZ2_bzWrite(
+                &mut bzerror,
+                bz_file,
+                chunk.as_ptr().cast(),
+                chunk.len() as c_int,
+            );
+            if bzerror != BZ_OK {
+                break;
+            }
+        }
+
+        let mut abandon = 0;
+        if bzerror != BZ_OK {
+            abandon = 1;
+        }
+
+        let after_write = bzerror;
+        BZ2_bzWriteClose(
+            &mut bzerror,
+            bz_file,
+            abandon,
+            core::ptr::null_mut(),
+            core::ptr::null_mut(),
+        );
+
+        match after_write {
+            BZ_OK => bzerror,
+            _ => after_write,
+        }
+    }
+}
+
+#[test]
+fn write_with_callbacks() {
+    for block_size100k in [1, 9] {
+        let mut memory = Memory::default();
+        assert_eq!(write_all(&mut memory, SAMPLE1_REF, block_size100k), BZ_OK);
+
+        let expected = compress_to_vec(SAMPLE1_REF, block_size100k as u32).unwrap();
+        assert!(memory.data == expected);
+    }
+}
+
+#[test]
+fn write_with_callbacks_errors() {
+    // `write` fails halfway, while the first block is written
+    let mut memory = Memory {
+        fail_after: Some(2),
+        ..Memory::default()
+    };
+    let input = SAMPLE1_REF.repeat(3);
+    assert_eq!(write_all(&mut memory, &input, 1), BZ_IO_ERROR);
+    assert_eq!(memory.calls, 3);
+
+    // both functions are required
+    for (write, error) in [(None, Some(no_error as _)), (Some(write as _), None)] {
+        let mut bzerror = 0;
+        let bz_file = unsafe {
+            BZ2_bzWriteOpenCallbacks(&mut bzerror, write, error, memory.opaque(), 9, 0, 0)
+        };
+        assert!(bz_file.is_null());
+        assert_eq!(bzerror, BZ_PARAM_ERROR);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{compress_c, decompress_c, SAMPLE1_BZ2, SAMPLE1_REF};
+use crate::{compress_c, decompress_c, Lcg, SAMPLE1_BZ2, SAMPLE1_REF};
 
 fn decompress_rs_chunked_input<'a>(
     dest: &'a mut [u8],
@@ -73,6 +73,37 @@ fn decompress_chunked_input() {
     }
 }
 
+#[test]
+#[cfg_attr(miri, ignore)]
+fn decompress_chunked_input_long_codes() {
+    // byte `k` is about twice as likely as byte `k + 1`, which gives codes of up to 17 bits, that
+    // are longer than the lookup tables of the decoder
+    let input: Vec<u8> = Lcg(0x600d_5eed)
+        .take(200_000)
+        .map(|state| (state | 1).leading_zeros() as u8 * 5)
+        .collect();
+
+    let mut compressed = vec![0; 1 << 18];
+    let mut compressed_len = compressed.len() as _;
+    let err = unsafe {
+        compress_c(
+            compressed.as_mut_ptr(),
+            &mut compressed_len,
+            input.as_ptr(),
+            input.len() as _,
+            9,
+        )
+    };
+    assert_eq!(err, 0);
+    compressed.truncate(compressed_len as usize);
+
+    for chunk_size in [1, 7, 4096, compressed.len()] {
+        let mut dest = vec![0; input.len()];
+        let output = decompress_rs_chunked_input(&mut dest, &compressed, chunk_size).unwrap();
+        assert!(*output == input, "chunk size {chunk_size}");
+    }
+}
+
 fn compress_rs_chunked_input<'a>(
     dest: &'a mut [u8],
     source: &[u8],
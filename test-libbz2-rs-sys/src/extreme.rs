@@ -0,0 +1,104 @@
+use crate::{compress_with, decompress_c, Lcg, SAMPLE1_REF};
+
+use libbz2_rs_sys::{compress_to_vec, decompress_to_vec};
+
+/// Pseudo-random bytes, with a distribution that changes over the input.
+fn varied_bytes(len: usize) -> Vec<u8> {
+    Lcg(0x1357_9bdf)
+        .take(len)
+        .enumerate()
+        .map(|(i, state)| {
+            let alphabet = [4, 40, 250][i / 20_000 % 3];
+            ((state >> 16) % alphabet) as u8
+        })
+        .collect()
+}
+
+fn compress_extreme(input: &[u8], block_size100k: u32) -> Vec<u8> {
+    compress_with(input, block_size100k, |stream| stream.set_extreme(true))
+}
+
+#[test]
+fn extreme_is_never_larger() {
+    let mut inputs = vec![
+        Vec::new(),
+        b"a".to_vec(),
+        b"abracadabra".to_vec(),
+        SAMPLE1_REF.to_vec(),
+        varied_bytes(250_000),
+    ];
+    inputs.extend((0..50).map(|i| varied_bytes(i * 397)));
+
+    for input in &inputs {
+        for block_size100k in [1, 9] {
+            let classic = compress_to_vec(input, block_size100k).unwrap();
+            let extreme = compress_extreme(input, block_size100k);
+
+            assert!(
+                extreme.len() <= classic.len(),
+                "input of length {}",
+                input.len()
+            );
+            assert!(decompress_to_vec(&extreme).unwrap() == *input);
+        }
+    }
+
+    // the data of this input varies enough for extreme mode to make a difference
+    let input = varied_bytes(250_000);
+    assert!(compress_extreme(&input, 9).len() < compress_to_vec(&input, 9).unwrap().len());
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn extreme_decodes_with_c() {
+    for input in [SAMPLE1_REF.to_vec(), varied_bytes(250_000)] {
+        let compressed = compress_extreme(&input, 1);
+
+        let mut dest = vec![0u8; input.len()];
+        let mut dest_len = dest.len() as _;
+        let err = unsafe {
+            decompress_c(
+                dest.as_mut_ptr(),
+                &mut dest_len,
+                compressed.as_ptr(),
+                compressed.len() as _,
+            )
+        };
+        assert_eq!(err, 0);
+        assert_eq!(dest_len as usize, input.len());
+        assert!(dest == input);
+    }
+}
+
+#[test]
+fn extreme_c_api() {
+    use libbz2_rs_sys::{
+        bz_stream, BZ2_bzCompress, BZ2_bzCompressEnd, BZ2_bzCompressInit, BZ2_bzCompressSetExtreme,
+        BZ_FINISH, BZ_OK, BZ_PARAM_ERROR, BZ_STREAM_END,
+    };
+
+    let input = varied_bytes(100_000);
+    let mut output = vec![0u8; input.len() + 1000];
+
+    unsafe {
+        assert_eq!(
+            BZ2_bzCompressSetExtreme(core::ptr::null_mut(), 1),
+            BZ_PARAM_ERROR
+        );
+
+        let mut strm: bz_stream = core::mem::zeroed();
+        assert_eq!(BZ2_bzCompressInit(&mut strm, 9, 0, 0), BZ_OK);
+        assert_eq!(BZ2_bzCompressSetExtreme(&mut strm, 1), BZ_OK);
+
+        strm.next_in = input.as_ptr().cast_mut().cast();
+        strm.avail_in = input.len() as _;
+        strm.next_out = output.as_mut_ptr().cast();
+        strm.avail_out = output.len() as _;
+        assert_eq!(BZ2_bzCompress(&mut strm, BZ_FINISH), BZ_STREAM_END);
+
+        output.truncate(strm.total_out_lo32 as usize);
+        assert_eq!(BZ2_bzCompressEnd(&mut strm), BZ_OK);
+    }
+
+    assert!(output == compress_extreme(&input, 9));
+}
@@ -0,0 +1,112 @@
+use crate::{compress_with, decompress_c, Lcg, SAMPLE1_REF};
+
+use libbz2_rs_sys::{compress_to_vec, decompress_to_vec, HuffmanAlgorithm};
+
+/// Pseudo-random bytes where byte `k` is about twice as likely as byte `k + 1`, so that the
+/// Huffman codes of the rare bytes would be longer than 17 bits without a length limit.
+fn skewed_bytes(len: usize, seed: u32) -> Vec<u8> {
+    Lcg(seed)
+        .take(len)
+        .map(|state| (state | 1).leading_zeros() as u8 * 7)
+        .collect()
+}
+
+fn compress_package_merge(input: &[u8], block_size100k: u32) -> Vec<u8> {
+    compress_with(input, block_size100k, |stream| {
+        stream.set_huffman_algorithm(HuffmanAlgorithm::PackageMerge)
+    })
+}
+
+#[test]
+fn package_merge_is_never_larger() {
+    let mut inputs = vec![
+        Vec::new(),
+        b"a".to_vec(),
+        b"abracadabra".to_vec(),
+        SAMPLE1_REF.to_vec(),
+    ];
+    inputs.extend((0..20).map(|i| skewed_bytes(i * 15_013, i as u32)));
+
+    for input in &inputs {
+        for block_size100k in [1, 9] {
+            let classic = compress_to_vec(input, block_size100k).unwrap();
+            let optimal = compress_package_merge(input, block_size100k);
+
+            assert!(
+                optimal.len() <= classic.len(),
+                "input of length {}",
+                input.len()
+            );
+            assert!(decompress_to_vec(&optimal).unwrap() == *input);
+        }
+    }
+
+    // the length limit is binding for this input
+    let input = skewed_bytes(250_000, 2);
+    let optimal = compress_package_merge(&input, 9);
+    assert!(optimal.len() < compress_to_vec(&input, 9).unwrap().len());
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn package_merge_decodes_with_c() {
+    for input in [SAMPLE1_REF.to_vec(), skewed_bytes(250_000, 2)] {
+        let compressed = compress_package_merge(&input, 1);
+
+        let mut dest = vec![0u8; input.len()];
+        let mut dest_len = dest.len() as _;
+        let err = unsafe {
+            decompress_c(
+                dest.as_mut_ptr(),
+                &mut dest_len,
+                compressed.as_ptr(),
+                compressed.len() as _,
+            )
+        };
+        assert_eq!(err, 0);
+        assert_eq!(dest_len as usize, input.len());
+        assert!(dest == input);
+    }
+}
+
+#[test]
+fn huffman_algorithm_c_api() {
+    use libbz2_rs_sys::{
+        bz_stream, BZ2_bzCompress, BZ2_bzCompressEnd, BZ2_bzCompressInit,
+        BZ2_bzCompressSetHuffmanAlgorithm, BZ_FINISH, BZ_HUFFMAN_PACKAGE_MERGE, BZ_OK,
+        BZ_PARAM_ERROR, BZ_STREAM_END,
+    };
+
+    let input = skewed_bytes(100_000, 3);
+    let mut output = vec![0u8; input.len() + 1000];
+
+    unsafe {
+        assert_eq!(
+            BZ2_bzCompressSetHuffmanAlgorithm(core::ptr::null_mut(), BZ_HUFFMAN_PACKAGE_MERGE),
+            BZ_PARAM_ERROR
+        );
+
+        let mut strm: bz_stream = core::mem::zeroed();
+        assert_eq!(BZ2_bzCompressInit(&mut strm, 9, 0, 0), BZ_OK);
+
+        assert_eq!(
+            BZ2_bzCompressSetHuffmanAlgorithm(&mut strm, 2),
+            BZ_PARAM_ERROR
+        );
+        assert_eq!(
+            BZ2_bzCompressSetHuffmanAlgorithm(&mut strm, BZ_HUFFMAN_PACKAGE_MERGE),
+            BZ_OK
+        );
+
+        strm.next_in = input.as_ptr().cast_mut().cast();
+        strm.avail_in = input.len() as _;
+        strm.next_out = output.as_mut_ptr().cast();
+        strm.avail_out = output.len() as _;
+        assert_eq!(BZ2_bzCompress(&mut strm, BZ_FINISH), BZ_STREAM_END);
+
+        output.truncate(strm.total_out_lo32 as usize);
+        assert_eq!(BZ2_bzCompressEnd(&mut strm), BZ_OK);
+    }
+
+    assert!(output == compress_package_merge(&input, 9));
+}
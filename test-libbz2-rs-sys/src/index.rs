@@ -0,0 +1,180 @@
+use std::ffi::c_void;
+use std::io::{Cursor, ErrorKind, Read, Seek, SeekFrom};
+
+use crate::{random_bytes, SAMPLE1_REF};
+
+use libbz2_rs_sys::index::{index_path, read_index, scan, write_index};
+use libbz2_rs_sys::seek::{BlockIndex, SeekableBzDecoder};
+use libbz2_rs_sys::{Action, BlockInfo, Compress, Status};
+
+/// The seed of the pseudo-random input.
+const SEED: u32 = 0x0bad_f00d;
+
+/// Compresses `chunks`, flushing after every chunk, and records the blocks.
+fn compress_recording(chunks: &[&[u8]], block_size100k: u32) -> (Vec<u8>, Vec<BlockInfo>) {
+    let mut stream = Compress::new(block_size100k, 0).unwrap();
+    stream.record_blocks();
+
+    let mut output = vec![0u8; 1 << 20];
+    let mut written = 0;
+
+    for (i, chunk) in chunks.iter().enumerate() {
+        let action = match i == chunks.len() - 1 {
+            true => Action::Finish,
+            false => Action::Flush,
+        };
+
+        let start = stream.total_in();
+        loop {
+            let consumed = (stream.total_in() - start) as usize;
+            let before = stream.total_out();
+            let status = stream
+                .compress(&chunk[consumed..], &mut output[written..], action)
+                .unwrap();
+            written += (stream.total_out() - before) as usize;
+
+            if matches!(status, Status::RunOk | Status::StreamEnd) {
+                break;
+            }
+        }
+    }
+
+    output.truncate(written);
+    (output, stream.blocks().to_vec())
+}
+
+#[test]
+fn recorded_blocks_match_scan() {
+    let input = random_bytes(300_000, SEED, 64);
+    let input2 = random_bytes(1000, SEED, 64);
+
+    for chunks in [
+        vec![&input[..]],
+        vec![&input[..1234], &[][..], &input[1234..], &input2[..]],
+        vec![SAMPLE1_REF],
+        vec![&[][..]],
+        // runs that are split over blocks
+        vec![&[7u8; 250_000][..], &[3u8; 5][..]],
+    ] {
+        let (compressed, blocks) = compress_recording(&chunks, 1);
+        let uncompressed_len: usize = chunks.iter().map(|chunk| chunk.len()).sum();
+
+        assert_eq!(scan(Cursor::new(&compressed)).unwrap(), blocks);
+
+        let total: u64 = blocks.iter().map(|b| u64::from(b.uncompressed_len)).sum();
+        assert_eq!(total, uncompressed_len as u64);
+    }
+}
+
+#[test]
+fn block_callback_c_api() {
+    use libbz2_rs_sys::{
+        bz_stream, BZ2_bzCompress, BZ2_bzCompressEnd, BZ2_bzCompressInit,
+        BZ2_bzCompressSetBlockCallback, BZ_FINISH, BZ_OK, BZ_PARAM_ERROR, BZ_STREAM_END,
+    };
+
+    unsafe extern "C" fn record(opaque: *mut c_void, info: *const BlockInfo) {
+        unsafe { (*opaque.cast::<Vec<BlockInfo>>()).push(*info) };
+    }
+
+    let input = random_bytes(250_000, SEED, 64);
+    let mut output = vec![0u8; input.len() + 1000];
+    let mut blocks = Vec::<BlockInfo>::new();
+
+    unsafe {
+        assert_eq!(
+            BZ2_bzCompressSetBlockCallback(core::ptr::null_mut(), None, core::ptr::null_mut()),
+            BZ_PARAM_ERROR
+        );
+
+        let mut strm: bz_stream = core::mem::zeroed();
+        assert_eq!(BZ2_bzCompressInit(&mut strm, 2, 0, 0), BZ_OK);
+
+        let opaque = (&mut blocks as *mut Vec<BlockInfo>).cast::<c_void>();
+        assert_eq!(
+            BZ2_bzCompressSetBlockCallback(&mut strm, Some(record), opaque),
+            BZ_OK
+        );
+
+        strm.next_in = input.as_ptr().cast_mut().cast();
+        strm.avail_in = input.len() as _;
+        strm.next_out = output.as_mut_ptr().cast();
+        strm.avail_out = output.len() as _;
+        assert_eq!(BZ2_bzCompress(&mut strm, BZ_FINISH), BZ_STREAM_END);
+
+        output.truncate(strm.total_out_lo32 as usize);
+        assert_eq!(BZ2_bzCompressEnd(&mut strm), BZ_OK);
+    }
+
+    assert_eq!(blocks.len(), 2);
+    assert_eq!(blocks[0].compressed_bit_offset, 32);
+    assert_eq!(blocks[0].block_size100k, 2);
+    assert_eq!(scan(Cursor::new(&output)).unwrap(), blocks);
+}
+
+#[test]
+fn index_file_roundtrip() {
+    let input = random_bytes(250_000, SEED, 64);
+    let (compressed, blocks) = compress_recording(&[&input], 1);
+
+    let mut file = Vec::new();
+    write_index(&mut file, &blocks).unwrap();
+    assert_eq!(file.len(), 16 + 32 * blocks.len() + 4);
+    assert_eq!(read_index(&file[..]).unwrap(), blocks);
+
+    // seek using the loaded index
+    let index = BlockIndex::from_blocks(&read_index(&file[..]).unwrap());
+    assert!(index.is_complete());
+    assert_eq!(index.uncompressed_len(), Some(input.len() as u64));
+
+    let mut decoder = SeekableBzDecoder::with_index(Cursor::new(compressed), index);
+    decoder.seek(SeekFrom::Start(200_000)).unwrap();
+    let mut buf = vec![0u8; 1000];
+    decoder.read_exact(&mut buf).unwrap();
+    assert!(buf == input[200_000..201_000]);
+
+    // an empty index
+    let mut file = Vec::new();
+    write_index(&mut file, &[]).unwrap();
+    assert_eq!(read_index(&file[..]).unwrap(), []);
+}
+
+#[test]
+fn invalid_index_file() {
+    let input = random_bytes(250_000, SEED, 64);
+    let (_, blocks) = compress_recording(&[&input], 1);
+
+    let mut file = Vec::new();
+    write_index(&mut file, &blocks).unwrap();
+
+    let error_kind = |file: &[u8]| read_index(file).unwrap_err().kind();
+
+    assert_eq!(
+        error_kind(&file[..file.len() - 1]),
+        ErrorKind::UnexpectedEof
+    );
+    assert_eq!(error_kind(&[b'B'; 100]), ErrorKind::InvalidData);
+
+    let mut corrupted = file.clone();
+    corrupted[4] = 2;
+    assert_eq!(error_kind(&corrupted), ErrorKind::InvalidData);
+
+    let mut corrupted = file.clone();
+    corrupted[20] ^= 1;
+    assert_eq!(error_kind(&corrupted), ErrorKind::InvalidData);
+
+    // the blocks must be contiguous
+    let mut blocks = blocks;
+    blocks[1].uncompressed_offset += 1;
+    let mut file = Vec::new();
+    write_index(&mut file, &blocks).unwrap();
+    assert_eq!(error_kind(&file), ErrorKind::InvalidData);
+}
+
+#[test]
+fn index_file_path() {
+    assert_eq!(
+        index_path("dir/file.bz2".as_ref()),
+        std::path::Path::new("dir/file.bz2.idx")
+    );
+}
@@ -8,7 +8,20 @@ use std::{
     path::{Path, PathBuf},
 };
 
+mod callbacks;
 mod chunked;
+mod extreme;
+mod huffman;
+mod index;
+mod memory;
+mod open_mode;
+mod parallel;
+mod reset;
+mod rust_api;
+mod seek;
+mod sort;
+mod workspace;
+mod zlib_compat;
 
 const WB_MODE: *const c_char = b"wb\0".as_ptr().cast::<c_char>();
 const RB_MODE: *const c_char = b"rb\0".as_ptr().cast::<c_char>();
@@ -19,6 +32,47 @@ type FreeFunc = unsafe extern "C" fn(*mut c_void, *mut c_void) -> ();
 const SAMPLE1_REF: &[u8] = include_bytes!("../../tests/input/quick/sample1.ref");
 const SAMPLE1_BZ2: &[u8] = include_bytes!("../../tests/input/quick/sample1.bz2");
 
+/// A linear congruential generator, that yields its successive states starting from a seed.
+/// Tests use it to generate reproducible pseudo-random input.
+struct Lcg(u32);
+
+impl Iterator for Lcg {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        self.0 = self.0.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        Some(self.0)
+    }
+}
+
+/// Pseudo-random bytes from an alphabet of `alphabet` bytes. A small alphabet gives short runs of
+/// equal bytes.
+fn random_bytes(len: usize, seed: u32, alphabet: u32) -> Vec<u8> {
+    Lcg(seed)
+        .take(len)
+        .map(|state| ((state >> 24) % alphabet) as u8)
+        .collect()
+}
+
+/// Compresses `input` with the Rust API in a single call, after `configure` has set up the stream.
+fn compress_with(
+    input: &[u8],
+    block_size100k: u32,
+    configure: impl FnOnce(&mut libbz2_rs_sys::Compress),
+) -> Vec<u8> {
+    use libbz2_rs_sys::{compress_bound, Action, Compress, Status};
+
+    let mut stream = Compress::new(block_size100k, 0).unwrap();
+    configure(&mut stream);
+
+    let mut output = vec![0u8; compress_bound(input.len(), block_size100k)];
+    let status = stream.compress(input, &mut output, Action::Finish).unwrap();
+    assert_eq!(status, Status::StreamEnd);
+
+    output.truncate(stream.total_out() as usize);
+    output
+}
+
 #[macro_export]
 macro_rules! assert_eq_rs_c {
     ($tt:tt) => {{
@@ -1329,6 +1383,79 @@ mod high_level_interface {
         );
     }
 
+    #[test]
+    fn bzflush_writes_complete_blocks() {
+        use libbz2_rs_sys::{Action, Compress, Decompress, Status};
+
+        let p = std::env::temp_dir().join("bzflush_writes_complete_blocks.bz2");
+        let path = p.with_extension("bz2\0").display().to_string();
+
+        let chunks: [&[u8]; 3] = [&SAMPLE1_REF[..20_000], &SAMPLE1_REF[20_000..50_000], b"end"];
+
+        // the same input, compressed with a flush after every chunk
+        let mut stream = Compress::new(9, 30).unwrap();
+        let mut expected = vec![0u8; 2 * SAMPLE1_REF.len()];
+
+        let file = unsafe { libc::fopen(path.as_ptr().cast(), WB_MODE) };
+        assert!(!file.is_null());
+
+        let mut bzerror = 0;
+        let ptr = unsafe { libbz2_rs_sys::BZ2_bzWriteOpen(&mut bzerror, file, 9, 0, 30) };
+        assert_eq!(bzerror, 0);
+
+        let mut written = Vec::new();
+        for chunk in chunks {
+            let n =
+                unsafe { libbz2_rs_sys::BZ2_bzwrite(ptr, chunk.as_ptr().cast(), chunk.len() as _) };
+            assert_eq!(n, chunk.len() as c_int);
+            assert_eq!(unsafe { libbz2_rs_sys::BZ2_bzflush(ptr) }, 0);
+            // flushing twice does not write an empty block
+            assert_eq!(unsafe { libbz2_rs_sys::BZ2_bzflush(ptr) }, 0);
+            written.extend_from_slice(chunk);
+
+            let offset = stream.total_out() as usize;
+            let status = stream
+                .compress(chunk, &mut expected[offset..], Action::Flush)
+                .unwrap();
+            assert_eq!(status, Status::RunOk);
+
+            // all compressed data that was produced so far is on disk
+            let on_disk = std::fs::read(&p).unwrap();
+            assert!(on_disk == expected[..stream.total_out() as usize]);
+
+            // so all blocks but the last, whose last bits may be missing, can be decompressed
+            let mut output = vec![0u8; written.len()];
+            let mut decompress = Decompress::new(false).unwrap();
+            let status = decompress.decompress(&on_disk, &mut output).unwrap();
+            assert_eq!(status, Status::Ok);
+
+            let decompressed = decompress.total_out() as usize;
+            assert!(decompressed >= written.len() - chunk.len());
+            assert!(output[..decompressed] == written[..decompressed]);
+        }
+
+        unsafe {
+            let null = core::ptr::null_mut();
+            libbz2_rs_sys::BZ2_bzWriteClose(&mut bzerror, ptr, 0, null, null);
+            assert_eq!(bzerror, 0);
+            assert_eq!(libc::fclose(file), 0);
+        }
+
+        let mut output = vec![0u8; written.len()];
+        let mut decompress = Decompress::new(false).unwrap();
+        let status = decompress
+            .decompress(&std::fs::read(&p).unwrap(), &mut output)
+            .unwrap();
+        assert_eq!(status, Status::StreamEnd);
+        assert!(output == written);
+
+        // flushing a `BZFILE` that is opened for reading does nothing
+        let ptr = unsafe { libbz2_rs_sys::BZ2_bzopen(path.as_ptr().cast(), RB_MODE) };
+        assert!(!ptr.is_null());
+        assert_eq!(unsafe { libbz2_rs_sys::BZ2_bzflush(ptr) }, 0);
+        unsafe { libbz2_rs_sys::BZ2_bzclose(ptr) };
+    }
+
     #[test]
     #[cfg(unix)]
     fn open_and_close() {
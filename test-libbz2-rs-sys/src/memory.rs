@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::ffi::{c_int, c_void};
+
+use crate::SAMPLE1_REF;
+
+use libbz2_rs_sys::{
+    bz_stream, compress_to_vec, BZ2_bzCompressAllocatedMemory, BZ2_bzCompressEnd,
+    BZ2_bzCompressInit, BZ2_bzCompressMemoryUsage, BZ2_bzDecompress,
+    BZ2_bzDecompressAllocatedMemory, BZ2_bzDecompressEnd, BZ2_bzDecompressInit,
+    BZ2_bzDecompressMemoryUsage, Compress, Decompress, Status, BZ_OK, BZ_STREAM_END,
+};
+
+/// The live allocations of a stream, by address.
+#[derive(Default)]
+struct Allocations(HashMap<usize, usize>);
+
+/// The total size of the live allocations of a stream made by [`counting_stream`].
+fn allocated(strm: &bz_stream) -> usize {
+    let allocations = unsafe { &*strm.opaque.cast::<Allocations>() };
+    allocations.0.values().sum()
+}
+
+unsafe extern "C" fn allocate(opaque: *mut c_void, count: c_int, size: c_int) -> *mut c_void {
+    let len = count as usize * size as usize;
+    let ptr = unsafe { libc::malloc(len) };
+
+    let allocations = unsafe { &mut *opaque.cast::<Allocations>() };
+    allocations.0.insert(ptr as usize, len);
+
+    ptr
+}
+
+unsafe extern "C" fn deallocate(opaque: *mut c_void, ptr: *mut c_void) {
+    let allocations = unsafe { &mut *opaque.cast::<Allocations>() };
+    allocations.0.remove(&(ptr as usize)).unwrap();
+
+    unsafe { libc::free(ptr) };
+}
+
+fn counting_stream(allocations: &mut Allocations) -> bz_stream {
+    let mut strm: bz_stream = unsafe { core::mem::zeroed() };
+    strm.bzalloc = Some(allocate);
+    strm.bzfree = Some(deallocate);
+    strm.opaque = (allocations as *mut Allocations).cast();
+    strm
+}
+
+#[test]
+fn compress_memory_usage() {
+    assert_eq!(BZ2_bzCompressMemoryUsage(0), 0);
+    assert_eq!(BZ2_bzCompressMemoryUsage(10), 0);
+
+    for block_size100k in 1..=9 {
+        let mut allocations = Allocations::default();
+        let mut strm = counting_stream(&mut allocations);
+
+        unsafe {
+            assert_eq!(BZ2_bzCompressInit(&mut strm, block_size100k, 0, 0), BZ_OK);
+
+            let expected = BZ2_bzCompressMemoryUsage(block_size100k);
+            assert_eq!(allocated(&strm), expected);
+            assert_eq!(BZ2_bzCompressAllocatedMemory(&mut strm), expected);
+            assert_eq!(Compress::memory_usage(block_size100k as u32), expected);
+
+            assert_eq!(BZ2_bzCompressEnd(&mut strm), BZ_OK);
+            assert_eq!(allocated(&strm), 0);
+            assert_eq!(BZ2_bzCompressAllocatedMemory(&mut strm), 0);
+        }
+    }
+}
+
+#[test]
+fn decompress_memory_usage() {
+    assert_eq!(BZ2_bzDecompressMemoryUsage(0, 0), 0);
+    assert_eq!(BZ2_bzDecompressMemoryUsage(1, 2), 0);
+
+    let input = &SAMPLE1_REF[..10_000];
+
+    for block_size100k in 1..=9 {
+        let compressed = compress_to_vec(input, block_size100k as u32).unwrap();
+
+        for small in [0, 1] {
+            let mut allocations = Allocations::default();
+            let mut strm = counting_stream(&mut allocations);
+            let mut output = vec![0u8; input.len()];
+
+            unsafe {
+                assert_eq!(BZ2_bzDecompressInit(&mut strm, 0, small), BZ_OK);
+
+                // the block buffers are allocated once the header has been read
+                assert_eq!(BZ2_bzDecompressAllocatedMemory(&mut strm), allocated(&strm));
+                assert!(allocated(&strm) < BZ2_bzDecompressMemoryUsage(1, small));
+
+                strm.next_in = compressed.as_ptr().cast_mut().cast();
+                strm.avail_in = compressed.len() as _;
+                strm.next_out = output.as_mut_ptr().cast();
+                strm.avail_out = output.len() as _;
+                assert_eq!(BZ2_bzDecompress(&mut strm), BZ_STREAM_END);
+
+                let expected = BZ2_bzDecompressMemoryUsage(block_size100k, small);
+                assert_eq!(allocated(&strm), expected);
+                assert_eq!(BZ2_bzDecompressAllocatedMemory(&mut strm), expected);
+                assert_eq!(
+                    Decompress::memory_usage(block_size100k as u32, small == 1),
+                    expected
+                );
+
+                assert_eq!(BZ2_bzDecompressEnd(&mut strm), BZ_OK);
+                assert_eq!(allocated(&strm), 0);
+            }
+        }
+    }
+}
+
+#[test]
+fn allocated_memory_rust_api() {
+    let stream = Compress::new(3, 0).unwrap();
+    assert_eq!(stream.allocated_memory(), Compress::memory_usage(3));
+
+    let mut stream = Decompress::new(false).unwrap();
+    let mut output = vec![0u8; SAMPLE1_REF.len()];
+
+    // the buffers of a larger block size are kept for a smaller one
+    for block_size100k in [2, 1] {
+        let compressed = compress_to_vec(SAMPLE1_REF, block_size100k).unwrap();
+        let status = stream.decompress(&compressed, &mut output).unwrap();
+        assert_eq!(status, Status::StreamEnd);
+        assert_eq!(
+            stream.allocated_memory(),
+            Decompress::memory_usage(2, false)
+        );
+
+        stream.reset();
+    }
+}
@@ -0,0 +1,190 @@
+use std::ffi::{c_char, c_int, CString};
+use std::io::Read;
+use std::path::PathBuf;
+
+use crate::SAMPLE1_REF;
+
+use libbz2_rs_sys::{decompress_to_vec, BZ2_bzclose, BZ2_bzdopen, BZ2_bzopen, BZ2_bzwrite, BZFILE};
+
+/// A path in the temporary directory, and that path as a C string. The file does not exist.
+fn temp_path(name: &str) -> (PathBuf, CString) {
+    let p = std::env::temp_dir().join(name);
+    let _ = std::fs::remove_file(&p);
+
+    let path = CString::new(p.display().to_string()).unwrap();
+    (p, path)
+}
+
+fn mode(mode: &str) -> CString {
+    CString::new(mode).unwrap()
+}
+
+fn open(path: &CString, mode: &str) -> *mut BZFILE {
+    unsafe { BZ2_bzopen(path.as_ptr(), self::mode(mode).as_ptr()) }
+}
+
+/// Writes all of `data` to `bz_file`, and closes it.
+fn write_and_close(bz_file: *mut BZFILE, data: &[u8]) {
+    assert!(!bz_file.is_null());
+
+    unsafe {
+        let n = BZ2_bzwrite(bz_file, data.as_ptr().cast(), data.len() as c_int);
+        assert_eq!(n, data.len() as c_int);
+        BZ2_bzclose(bz_file);
+    }
+}
+
+/// Decompresses all streams of `compressed`.
+fn decompress_all(compressed: &[u8]) -> Vec<u8> {
+    let mut decoder = libbz2_rs_sys::read::MultiBzDecoder::new(compressed);
+    let mut output = Vec::new();
+    decoder.read_to_end(&mut output).unwrap();
+    output
+}
+
+#[test]
+fn write_truncates() {
+    let (p, path) = temp_path("write_truncates.bz2");
+
+    write_and_close(open(&path, "w"), SAMPLE1_REF);
+    write_and_close(open(&path, "w"), b"short");
+
+    let compressed = std::fs::read(&p).unwrap();
+    assert_eq!(decompress_all(&compressed), b"short");
+}
+
+#[test]
+fn append_streams() {
+    let (p, path) = temp_path("append_streams.bz2");
+
+    // appending to a file that does not exist creates it
+    write_and_close(open(&path, "a"), b"first\n");
+    write_and_close(open(&path, "a1"), SAMPLE1_REF);
+    write_and_close(open(&path, "wa"), b"third\n");
+
+    let compressed = std::fs::read(&p).unwrap();
+    let mut expected = b"first\n".to_vec();
+    expected.extend_from_slice(SAMPLE1_REF);
+    expected.extend_from_slice(b"third\n");
+    assert!(decompress_all(&compressed) == expected);
+
+    // every stream is a complete `.bz2` file
+    let first = decompress_to_vec(&compressed).unwrap();
+    assert_eq!(first, b"first\n");
+
+    // a later `w` overrides the `a`
+    write_and_close(open(&path, "aw"), b"fourth\n");
+    let compressed = std::fs::read(&p).unwrap();
+    assert_eq!(decompress_all(&compressed), b"fourth\n");
+}
+
+#[test]
+#[cfg(unix)]
+fn append_to_file_descriptor() {
+    use std::os::fd::IntoRawFd;
+
+    let (p, path) = temp_path("append_to_file_descriptor.bz2");
+    write_and_close(open(&path, "w"), b"first\n");
+
+    let file = std::fs::File::options().write(true).open(&p).unwrap();
+    let bz_file = unsafe { BZ2_bzdopen(file.into_raw_fd(), mode("a").as_ptr()) };
+    write_and_close(bz_file, b"second\n");
+
+    let compressed = std::fs::read(&p).unwrap();
+    assert_eq!(decompress_all(&compressed), b"first\nsecond\n");
+}
+
+#[test]
+fn exclusive() {
+    let (p, path) = temp_path("exclusive.bz2");
+
+    write_and_close(open(&path, "wx"), b"first\n");
+
+    // the file now exists, and is left alone
+    for mode in ["wx", "xw", "ax"] {
+        assert!(open(&path, mode).is_null());
+    }
+
+    let compressed = std::fs::read(&p).unwrap();
+    assert_eq!(decompress_all(&compressed), b"first\n");
+
+    // reading ignores `x`
+    let bz_file = open(&path, "rx");
+    assert!(!bz_file.is_null());
+    unsafe { BZ2_bzclose(bz_file) };
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn close_on_exec() {
+    use std::os::fd::IntoRawFd;
+
+    let (p, path) = temp_path("close_on_exec.bz2");
+    write_and_close(open(&path, "we"), b"data");
+
+    for (mode, expected) in [("r", 0), ("re", libc::FD_CLOEXEC)] {
+        // the standard library sets the flag, so it is cleared first
+        let fd = std::fs::File::open(&p).unwrap().into_raw_fd();
+        unsafe { libc::fcntl(fd, libc::F_SETFD, 0) };
+
+        let bz_file = unsafe { BZ2_bzdopen(fd, self::mode(mode).as_ptr()) };
+        assert!(!bz_file.is_null());
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+        assert_eq!(flags & libc::FD_CLOEXEC, expected);
+        unsafe { BZ2_bzclose(bz_file) };
+    }
+}
+
+#[test]
+fn compression_parameters() {
+    let (p, path) = temp_path("compression_parameters.bz2");
+
+    for (mode, block_size) in [("w", b'9'), ("w1", b'1'), ("w5f0", b'5'), ("wf250 3", b'3')] {
+        write_and_close(open(&path, mode), SAMPLE1_REF);
+
+        let compressed = std::fs::read(&p).unwrap();
+        assert_eq!(&compressed[..4], &[b'B', b'Z', b'h', block_size]);
+        assert!(decompress_all(&compressed) == SAMPLE1_REF);
+    }
+
+    // the work factor does not change the output for ordinary data
+    write_and_close(open(&path, "w1f100"), SAMPLE1_REF);
+    let compressed = std::fs::read(&p).unwrap();
+    assert!(compressed == libbz2_rs_sys::compress_to_vec(SAMPLE1_REF, 1).unwrap());
+}
+
+#[test]
+fn small_and_verbosity() {
+    let (p, path) = temp_path("small_and_verbosity.bz2");
+    write_and_close(open(&path, "w"), SAMPLE1_REF);
+
+    for mode in ["rs", "rv", "rsv"] {
+        let bz_file = open(&path, mode);
+        assert!(!bz_file.is_null());
+
+        let mut output = vec![0u8; SAMPLE1_REF.len() + 1];
+        let n = unsafe {
+            libbz2_rs_sys::BZ2_bzread(bz_file, output.as_mut_ptr().cast(), output.len() as c_int)
+        };
+        assert_eq!(n, SAMPLE1_REF.len() as c_int);
+        assert!(output[..n as usize] == *SAMPLE1_REF);
+
+        unsafe { BZ2_bzclose(bz_file) };
+    }
+
+    let _ = std::fs::remove_file(p);
+}
+
+#[test]
+fn invalid_modes() {
+    let (p, path) = temp_path("invalid_modes.bz2");
+
+    // the file is not created when the mode is not valid
+    for mode in ["wvvvvv", "wf", "wf x", "wf251", "wf99999999999", "af1000"] {
+        assert!(open(&path, mode).is_null(), "{mode}");
+        assert!(!p.exists(), "{mode}");
+    }
+
+    let mode: *const c_char = core::ptr::null();
+    assert!(unsafe { BZ2_bzopen(path.as_ptr(), mode) }.is_null());
+}
@@ -0,0 +1,296 @@
+use std::io::{Read, Write};
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::{random_bytes, SAMPLE1_REF};
+
+use libbz2_rs_sys::{compress_to_vec, decompress_to_vec, parallel, write};
+
+/// The seed of the pseudo-random input.
+const SEED: u32 = 0x1234_5678;
+
+fn threads(n: usize) -> NonZeroUsize {
+    NonZeroUsize::new(n).unwrap()
+}
+
+#[test]
+fn compress_to_vec_matches_serial() {
+    let inputs = [
+        Vec::new(),
+        b"a".to_vec(),
+        SAMPLE1_REF.to_vec(),
+        random_bytes(350_000, SEED, 64),
+        vec![0u8; 300_000],
+    ];
+
+    for input in &inputs {
+        for block_size100k in [1, 2] {
+            let expected = compress_to_vec(input, block_size100k).unwrap();
+
+            for n in [1, 3] {
+                let output = parallel::compress_to_vec(input, block_size100k, threads(n)).unwrap();
+                assert!(output == expected, "len {}", input.len());
+            }
+        }
+    }
+
+    // the end of the input coincides with the end of a block
+    let block_capacity = 100_000 - 19;
+    for len in block_capacity - 2..block_capacity + 3 {
+        let input: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+
+        let expected = compress_to_vec(&input, 1).unwrap();
+        let output = parallel::compress_to_vec(&input, 1, threads(2)).unwrap();
+        assert!(output == expected, "len {len}");
+    }
+
+    let output = parallel::compress_to_vec(SAMPLE1_REF, 9, threads(4)).unwrap();
+    assert!(decompress_to_vec(&output).unwrap() == SAMPLE1_REF);
+}
+
+#[test]
+fn encoder_matches_serial() {
+    let input = random_bytes(450_000, SEED, 64);
+
+    for chunk_size in [1000, 99_981, input.len()] {
+        let mut serial = write::BzEncoder::new(Vec::new(), 1);
+        let mut encoder = parallel::BzEncoder::new(Vec::new(), 1, threads(3));
+
+        for (i, chunk) in input.chunks(chunk_size).enumerate() {
+            serial.write_all(chunk).unwrap();
+            encoder.write_all(chunk).unwrap();
+
+            // flushing ends the current block
+            if i == 1 {
+                serial.flush().unwrap();
+                encoder.flush().unwrap();
+                assert_eq!(encoder.get_ref().len() as u64, encoder.total_out());
+            }
+        }
+
+        assert_eq!(encoder.total_in(), input.len() as u64);
+
+        let expected = serial.finish().unwrap();
+        let output = encoder.finish().unwrap();
+        assert!(output == expected, "chunk size {chunk_size}");
+    }
+}
+
+#[test]
+fn encoder_drop() {
+    let mut output = Vec::new();
+
+    {
+        let mut encoder = parallel::BzEncoder::new(&mut output, 1, threads(2));
+        encoder.write_all(SAMPLE1_REF).unwrap();
+    }
+
+    assert!(decompress_to_vec(&output).unwrap() == SAMPLE1_REF);
+}
+
+#[test]
+fn invalid_block_size() {
+    assert_eq!(
+        parallel::compress_to_vec(b"", 0, threads(1)),
+        Err(libbz2_rs_sys::Error::Param)
+    );
+    assert_eq!(
+        parallel::compress_to_vec(b"", 10, threads(1)),
+        Err(libbz2_rs_sys::Error::Param)
+    );
+}
+
+#[test]
+fn decompress_matches_serial() {
+    let inputs = [
+        b"a".to_vec(),
+        SAMPLE1_REF.to_vec(),
+        random_bytes(350_000, SEED, 64),
+        vec![0u8; 300_000],
+    ];
+
+    for input in &inputs {
+        for block_size100k in [1, 9] {
+            let compressed = compress_to_vec(input, block_size100k).unwrap();
+
+            for n in [1, 4] {
+                let output = parallel::decompress_to_vec(&compressed, threads(n)).unwrap();
+                assert!(output == *input, "len {}", input.len());
+            }
+
+            let mut decoder = parallel::BzDecoder::new(compressed, threads(2));
+            let mut output = Vec::new();
+            decoder.read_to_end(&mut output).unwrap();
+            assert!(output == *input);
+            assert_eq!(decoder.total_out(), input.len() as u64);
+        }
+    }
+
+    let empty = compress_to_vec(b"", 9).unwrap();
+    assert_eq!(
+        parallel::decompress_to_vec(&empty, threads(2)).unwrap(),
+        b""
+    );
+}
+
+#[test]
+fn decompress_concatenated_streams() {
+    let first = random_bytes(250_000, SEED, 64);
+    let second = SAMPLE1_REF;
+
+    let mut compressed = compress_to_vec(&first, 1).unwrap();
+    compressed.extend(compress_to_vec(b"", 5).unwrap());
+    compressed.extend(compress_to_vec(second, 9).unwrap());
+
+    let mut expected = first.clone();
+    expected.extend_from_slice(second);
+
+    let output = parallel::decompress_to_vec(&compressed, threads(3)).unwrap();
+    assert!(output == expected);
+
+    // trailing garbage is ignored, even when it contains a block
+    let mut with_garbage = compressed.clone();
+    with_garbage.extend_from_slice(b"garbage");
+    with_garbage.extend(&compress_to_vec(&first, 1).unwrap()[1..]);
+
+    let output = parallel::decompress_to_vec(&with_garbage, threads(3)).unwrap();
+    assert!(output == expected);
+}
+
+/// The number of bit offsets in `data` where the block magic number starts.
+fn count_block_magics(data: &[u8]) -> usize {
+    let mut window = 0u64;
+    let mut count = 0;
+
+    for (i, &byte) in data.iter().enumerate() {
+        window = window << 8 | u64::from(byte);
+
+        for shift in 0..8 {
+            if i >= 6 && (window >> shift) & 0xffff_ffff_ffff == 0x3141_5926_5359 {
+                count += 1;
+            }
+        }
+    }
+
+    count
+}
+
+#[test]
+fn decompress_fake_magics() {
+    // the bytes that are used in a block are stored as a bitmap of 16-byte ranges (here 0x3141),
+    // followed by a 16-bit map of the used bytes for every used range (here 0x5926 and 0x5359, and
+    // more). With exactly these bytes, every block contains the block magic number. Runs would
+    // add their lengths as bytes, so neighbouring bytes differ.
+    let alphabet = [
+        0x21, 0x23, 0x24, 0x27, 0x2a, 0x2d, 0x2e, // 0x5926
+        0x31, 0x33, 0x36, 0x37, 0x39, 0x3b, 0x3c, 0x3f, // 0x5359
+        0x70, 0x90, 0xf0,
+    ];
+    let mut previous = 0;
+    let input: Vec<u8> = random_bytes(2_000_000, SEED, 64)
+        .iter()
+        .map(|b| {
+            let mut i = usize::from(*b) % alphabet.len();
+            if i == previous {
+                i = (i + 1) % alphabet.len();
+            }
+            previous = i;
+            alphabet[i]
+        })
+        .collect();
+
+    let compressed = compress_to_vec(&input, 1).unwrap();
+    let blocks = input.len().div_ceil(100_000 - 19);
+    assert_eq!(count_block_magics(&compressed), 2 * blocks);
+
+    for n in [1, 2, 8] {
+        let output = parallel::decompress_to_vec(&compressed, threads(n)).unwrap();
+        assert!(output == input);
+    }
+
+    /// Compressed data that records when it is dropped.
+    struct Tracked(Vec<u8>, Arc<AtomicBool>);
+
+    impl AsRef<[u8]> for Tracked {
+        fn as_ref(&self) -> &[u8] {
+            &self.0
+        }
+    }
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            self.1.store(true, Ordering::Relaxed);
+        }
+    }
+
+    // no thread that decodes a (fake) block outlives the decoder
+    for read_len in [1, 500_000, input.len()] {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let data = Tracked(compressed.clone(), Arc::clone(&dropped));
+        let mut decoder = parallel::BzDecoder::new(data, threads(4));
+
+        let mut output = vec![0u8; read_len];
+        decoder.read_exact(&mut output).unwrap();
+        assert!(output == input[..read_len]);
+
+        drop(decoder);
+        assert!(dropped.load(Ordering::Relaxed));
+    }
+}
+
+#[test]
+fn decompress_errors() {
+    use libbz2_rs_sys::{DataErrorKind, Error};
+
+    let input = random_bytes(250_000, SEED, 64);
+    let compressed = compress_to_vec(&input, 1).unwrap();
+
+    let data_error_kind = |input: &[u8]| match parallel::decompress_to_vec(input, threads(2)) {
+        Err(Error::Data(error)) => error.kind,
+        other => panic!("unexpected result {other:?}"),
+    };
+
+    assert_eq!(
+        parallel::decompress_to_vec(b"", threads(2)),
+        Err(Error::UnexpectedEof)
+    );
+    assert_eq!(
+        parallel::decompress_to_vec(b"BZ", threads(2)),
+        Err(Error::UnexpectedEof)
+    );
+    assert_eq!(
+        parallel::decompress_to_vec(b"not bzip2", threads(2)),
+        Err(Error::DataMagic)
+    );
+
+    for len in [10, compressed.len() / 2, compressed.len() - 1] {
+        assert_eq!(
+            parallel::decompress_to_vec(&compressed[..len], threads(2)),
+            Err(Error::UnexpectedEof),
+            "len {len}",
+        );
+    }
+
+    // corruption inside a block
+    let mut corrupted = compressed.clone();
+    corrupted[compressed.len() / 2] ^= 0x10;
+    assert!(matches!(
+        parallel::decompress_to_vec(&corrupted, threads(2)),
+        Err(Error::Data(_))
+    ));
+
+    // the combined CRC is checked
+    let mut corrupted = compressed.clone();
+    corrupted[compressed.len() - 2] ^= 0x01;
+    assert_eq!(data_error_kind(&corrupted), DataErrorKind::CombinedCrc);
+    assert!(matches!(
+        decompress_to_vec(&corrupted),
+        Err(Error::Data(error)) if error.kind == DataErrorKind::CombinedCrc
+    ));
+
+    // the block magic of the first block
+    let mut corrupted = compressed.clone();
+    corrupted[4] ^= 0x01;
+    assert_eq!(data_error_kind(&corrupted), DataErrorKind::BlockMagic);
+}
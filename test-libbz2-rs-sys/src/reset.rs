@@ -0,0 +1,169 @@
+use crate::{SAMPLE1_BZ2, SAMPLE1_REF};
+
+use libbz2_rs_sys::{compress_bound, compress_to_vec, Action, Compress, Decompress, Error, Status};
+
+fn compress_all(stream: &mut Compress, input: &[u8]) -> Vec<u8> {
+    let mut output = vec![0u8; compress_bound(input.len(), 1)];
+    let status = stream.compress(input, &mut output, Action::Finish).unwrap();
+    assert_eq!(status, Status::StreamEnd);
+
+    output.truncate(stream.total_out() as usize);
+    output
+}
+
+fn decompress_all(stream: &mut Decompress, input: &[u8], len: usize) -> Vec<u8> {
+    let mut output = vec![0u8; len];
+    let status = stream.decompress(input, &mut output).unwrap();
+    assert_eq!(status, Status::StreamEnd);
+    assert_eq!(stream.total_out(), len as u64);
+
+    output
+}
+
+#[test]
+fn compress_reset() {
+    let messages: [&[u8]; 4] = [b"", b"hello", SAMPLE1_REF, b"aaaaaaaaaaaaaaaaaaaaaaaaaaaa"];
+
+    let mut stream = Compress::new(9, 0).unwrap();
+    for message in messages {
+        assert!(compress_all(&mut stream, message) == compress_to_vec(message, 9).unwrap());
+        stream.reset();
+        assert_eq!((stream.total_in(), stream.total_out()), (0, 0));
+    }
+
+    // a stream that was abandoned halfway leaves no trace
+    let mut output = vec![0u8; 100];
+    stream
+        .compress(SAMPLE1_REF, &mut output, Action::Run)
+        .unwrap();
+    stream.reset();
+    assert!(compress_all(&mut stream, b"hello") == compress_to_vec(b"hello", 9).unwrap());
+}
+
+#[test]
+fn compress_reset_block_size() {
+    let mut stream = Compress::new(5, 0).unwrap();
+
+    for block_size100k in [1, 5, 3] {
+        stream.reset_with_block_size(block_size100k).unwrap();
+        let compressed = compress_all(&mut stream, SAMPLE1_REF);
+        assert!(compressed == compress_to_vec(SAMPLE1_REF, block_size100k).unwrap());
+    }
+
+    // the buffers are too small for a larger block size
+    assert_eq!(stream.reset_with_block_size(6), Err(Error::Param));
+    assert_eq!(stream.reset_with_block_size(0), Err(Error::Param));
+    assert_eq!(stream.reset_with_block_size(10), Err(Error::Param));
+
+    // `reset` keeps the last block size
+    stream.reset();
+    assert!(compress_all(&mut stream, SAMPLE1_REF) == compress_to_vec(SAMPLE1_REF, 3).unwrap());
+}
+
+#[test]
+fn decompress_reset() {
+    let small_blocks = compress_to_vec(SAMPLE1_REF, 1).unwrap();
+
+    for small in [false, true] {
+        let mut stream = Decompress::new(small).unwrap();
+
+        // the buffers grow for the larger block size, and are reused for the smaller one
+        for input in [&small_blocks[..], SAMPLE1_BZ2, &small_blocks[..]] {
+            let output = decompress_all(&mut stream, input, SAMPLE1_REF.len());
+            assert!(output == SAMPLE1_REF);
+            stream.reset();
+        }
+
+        // a stream that was abandoned halfway, or that failed, leaves no trace
+        let mut output = vec![0u8; 100];
+        assert_eq!(
+            stream.decompress(&SAMPLE1_BZ2[..1000], &mut output),
+            Ok(Status::Ok)
+        );
+        stream.reset();
+        assert_eq!(
+            stream.decompress(b"BZh0", &mut output),
+            Err(Error::DataMagic)
+        );
+        stream.reset();
+
+        let output = decompress_all(&mut stream, &small_blocks, SAMPLE1_REF.len());
+        assert!(output == SAMPLE1_REF);
+    }
+}
+
+#[test]
+fn decompress_reset_keeps_limits() {
+    let mut stream = Decompress::with_limits(false, Some(1000), None).unwrap();
+    let mut output = vec![0u8; SAMPLE1_REF.len()];
+
+    for _ in 0..2 {
+        assert_eq!(
+            stream.decompress(SAMPLE1_BZ2, &mut output),
+            Err(Error::OutputLimit)
+        );
+        assert_eq!(stream.total_out(), 1000);
+        stream.reset();
+    }
+}
+
+#[test]
+fn reset_c_api() {
+    use libbz2_rs_sys::{
+        bz_stream, BZ2_bzCompress, BZ2_bzCompressEnd, BZ2_bzCompressInit, BZ2_bzCompressReset,
+        BZ2_bzDecompress, BZ2_bzDecompressEnd, BZ2_bzDecompressInit, BZ2_bzDecompressReset,
+        BZ_FINISH, BZ_OK, BZ_PARAM_ERROR, BZ_STREAM_END,
+    };
+
+    let input = b"a short message";
+
+    unsafe {
+        assert_eq!(
+            BZ2_bzCompressReset(core::ptr::null_mut(), 0),
+            BZ_PARAM_ERROR
+        );
+        assert_eq!(BZ2_bzDecompressReset(core::ptr::null_mut()), BZ_PARAM_ERROR);
+
+        let mut strm: bz_stream = core::mem::zeroed();
+        assert_eq!(BZ2_bzCompressReset(&mut strm, 0), BZ_PARAM_ERROR);
+        assert_eq!(BZ2_bzCompressInit(&mut strm, 2, 0, 0), BZ_OK);
+
+        assert_eq!(BZ2_bzCompressReset(&mut strm, 3), BZ_PARAM_ERROR);
+        assert_eq!(BZ2_bzCompressReset(&mut strm, -1), BZ_PARAM_ERROR);
+
+        let mut compressed = Vec::new();
+        for block_size100k in [0, 1] {
+            assert_eq!(BZ2_bzCompressReset(&mut strm, block_size100k), BZ_OK);
+
+            let mut output = vec![0u8; 1000];
+            strm.next_in = input.as_ptr().cast_mut().cast();
+            strm.avail_in = input.len() as _;
+            strm.next_out = output.as_mut_ptr().cast();
+            strm.avail_out = output.len() as _;
+            assert_eq!(BZ2_bzCompress(&mut strm, BZ_FINISH), BZ_STREAM_END);
+
+            output.truncate(strm.total_out_lo32 as usize);
+            compressed.push(output);
+        }
+        assert_eq!(BZ2_bzCompressEnd(&mut strm), BZ_OK);
+
+        assert!(compressed[0] == compress_to_vec(input, 2).unwrap());
+        assert!(compressed[1] == compress_to_vec(input, 1).unwrap());
+
+        let mut strm: bz_stream = core::mem::zeroed();
+        assert_eq!(BZ2_bzDecompressInit(&mut strm, 0, 0), BZ_OK);
+
+        for compressed in &compressed {
+            assert_eq!(BZ2_bzDecompressReset(&mut strm), BZ_OK);
+
+            let mut output = vec![0u8; 100];
+            strm.next_in = compressed.as_ptr().cast_mut().cast();
+            strm.avail_in = compressed.len() as _;
+            strm.next_out = output.as_mut_ptr().cast();
+            strm.avail_out = output.len() as _;
+            assert_eq!(BZ2_bzDecompress(&mut strm), BZ_STREAM_END);
+            assert_eq!(&output[..strm.total_out_lo32 as usize], input);
+        }
+        assert_eq!(BZ2_bzDecompressEnd(&mut strm), BZ_OK);
+    }
+}
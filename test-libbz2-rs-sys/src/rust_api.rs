@@ -0,0 +1,835 @@
+use crate::{compress_c, SAMPLE1_BZ2, SAMPLE1_REF};
+
+use libbz2_rs_sys::{Action, Compress, DataError, DataErrorKind, Decompress, Error, Status};
+
+/// Compresses `input`, passing it to the stream in chunks of `chunk_size` bytes.
+fn compress_chunked(input: &[u8], block_size100k: u32, chunk_size: usize) -> Vec<u8> {
+    let mut stream = Compress::new(block_size100k, 0).unwrap();
+    let mut output = vec![0u8; 1024];
+    let mut out_pos = 0;
+
+    for chunk in input.chunks(chunk_size) {
+        let mut in_pos = 0;
+        while in_pos < chunk.len() {
+            if out_pos == output.len() {
+                output.resize(output.len() * 2, 0);
+            }
+
+            let before_in = stream.total_in();
+            let before_out = stream.total_out();
+            let status = stream
+                .compress(&chunk[in_pos..], &mut output[out_pos..], Action::Run)
+                .unwrap();
+            assert_eq!(status, Status::RunOk);
+            in_pos += (stream.total_in() - before_in) as usize;
+            out_pos += (stream.total_out() - before_out) as usize;
+        }
+    }
+
+    loop {
+        if out_pos == output.len() {
+            output.resize(output.len() * 2, 0);
+        }
+
+        let before_out = stream.total_out();
+        let status = stream
+            .compress(&[], &mut output[out_pos..], Action::Finish)
+            .unwrap();
+        out_pos += (stream.total_out() - before_out) as usize;
+
+        match status {
+            Status::FinishOk => continue,
+            Status::StreamEnd => break,
+            other => panic!("unexpected status {other:?}"),
+        }
+    }
+
+    assert_eq!(stream.total_in(), input.len() as u64);
+    assert_eq!(stream.total_out(), out_pos as u64);
+
+    output.truncate(out_pos);
+    output
+}
+
+/// Decompresses `input`, passing it to the stream in chunks of `chunk_size` bytes.
+fn decompress_chunked(input: &[u8], small: bool, chunk_size: usize) -> Result<Vec<u8>, Error> {
+    let mut stream = Decompress::new(small)?;
+    let mut output = vec![0u8; 1024];
+    let mut out_pos = 0;
+    let mut in_pos = 0;
+
+    loop {
+        if out_pos == output.len() {
+            output.resize(output.len() * 2, 0);
+        }
+
+        let end = Ord::min(in_pos + chunk_size, input.len());
+        let before_in = stream.total_in();
+        let before_out = stream.total_out();
+        let status = stream.decompress(&input[in_pos..end], &mut output[out_pos..])?;
+        in_pos += (stream.total_in() - before_in) as usize;
+        out_pos += (stream.total_out() - before_out) as usize;
+
+        match status {
+            Status::Ok => continue,
+            Status::StreamEnd => break,
+            other => panic!("unexpected status {other:?}"),
+        }
+    }
+
+    output.truncate(out_pos);
+    Ok(output)
+}
+
+#[test]
+fn compress_matches_c() {
+    for block_size100k in [1, 9] {
+        let mut expected = vec![0u8; 2 * SAMPLE1_REF.len()];
+        let mut expected_len = expected.len() as _;
+        let err = unsafe {
+            compress_c(
+                expected.as_mut_ptr(),
+                &mut expected_len,
+                SAMPLE1_REF.as_ptr(),
+                SAMPLE1_REF.len() as _,
+                block_size100k as i32,
+            )
+        };
+        assert_eq!(err, 0);
+        expected.truncate(expected_len as usize);
+
+        for chunk_size in [1000, SAMPLE1_REF.len()] {
+            let actual = compress_chunked(SAMPLE1_REF, block_size100k, chunk_size);
+            assert_eq!(actual, expected);
+        }
+    }
+}
+
+#[test]
+fn decompress_sample1() {
+    for small in [false, true] {
+        for chunk_size in [1, 1000, SAMPLE1_BZ2.len()] {
+            let actual = decompress_chunked(SAMPLE1_BZ2, small, chunk_size).unwrap();
+            assert!(actual == SAMPLE1_REF);
+        }
+    }
+}
+
+#[test]
+fn flush() {
+    let mut stream = Compress::new(9, 0).unwrap();
+    let mut output = vec![0u8; 1 << 16];
+
+    let status = stream
+        .compress(b"hello world", &mut output, Action::Flush)
+        .unwrap();
+    assert_eq!(status, Status::RunOk);
+    assert_eq!(stream.total_in(), 11);
+
+    let flushed = stream.total_out() as usize;
+    let status = stream
+        .compress(&[], &mut output[flushed..], Action::Finish)
+        .unwrap();
+    assert_eq!(status, Status::StreamEnd);
+
+    output.truncate(stream.total_out() as usize);
+    assert_eq!(
+        decompress_chunked(&output, false, 1).unwrap(),
+        b"hello world"
+    );
+}
+
+#[test]
+fn invalid_parameters() {
+    assert_eq!(Compress::new(0, 0).err(), Some(Error::Param));
+    assert_eq!(Compress::new(10, 0).err(), Some(Error::Param));
+    assert_eq!(Compress::new(9, 251).err(), Some(Error::Param));
+    assert_eq!(Compress::new(u32::MAX, 0).err(), Some(Error::Param));
+}
+
+#[test]
+fn sequence_error() {
+    let mut stream = Compress::new(9, 0).unwrap();
+    let mut output = vec![0u8; 1024];
+
+    let status = stream.compress(b"abc", &mut output, Action::Finish);
+    assert_eq!(status, Ok(Status::StreamEnd));
+
+    let status = stream.compress(b"abc", &mut output, Action::Run);
+    assert_eq!(status, Err(Error::Sequence));
+}
+
+#[test]
+fn no_output_space() {
+    let mut stream = Compress::new(9, 0).unwrap();
+
+    // no input and no output: nothing happens, but that is not an error
+    assert_eq!(
+        stream.compress(&[], &mut [], Action::Run),
+        Ok(Status::RunOk)
+    );
+    assert_eq!(stream.total_in(), 0);
+    assert_eq!(stream.total_out(), 0);
+}
+
+#[test]
+fn decompress_errors() {
+    assert_eq!(
+        decompress_chunked(SAMPLE1_REF, false, 1000),
+        Err(Error::DataMagic)
+    );
+
+    let mut corrupted = SAMPLE1_BZ2.to_vec();
+    corrupted[1000] ^= 0x55;
+    assert!(matches!(
+        decompress_chunked(&corrupted, false, 1000),
+        Err(Error::Data(_))
+    ));
+}
+
+fn data_error(input: &[u8], small: bool) -> DataError {
+    match decompress_chunked(input, small, 1000) {
+        Err(Error::Data(data_error)) => data_error,
+        other => panic!("expected a data error, got {other:?}"),
+    }
+}
+
+#[test]
+fn decompress_data_error_details() {
+    for small in [false, true] {
+        // the stored CRC of the first block
+        let mut corrupted = SAMPLE1_BZ2.to_vec();
+        corrupted[10] ^= 0xff;
+        let error = data_error(&corrupted, small);
+        assert_eq!(error.kind, DataErrorKind::BlockCrc);
+        assert_eq!(error.block_number, 1);
+        assert_eq!(error.stored_crc, 0x33f1b5a5);
+        assert_eq!(error.computed_crc, 0xccf1b5a5);
+
+        // the combined CRC in the stream trailer
+        let mut corrupted = SAMPLE1_BZ2.to_vec();
+        let n = corrupted.len();
+        corrupted[n - 2] ^= 0xff;
+        let error = data_error(&corrupted, small);
+        assert_eq!(error.kind, DataErrorKind::CombinedCrc);
+        assert_ne!(error.stored_crc, error.computed_crc);
+        assert!(error.bit_offset > (n as u64 - 5) * 8);
+        assert!(error.bit_offset <= n as u64 * 8);
+
+        // the magic bytes of the first block header
+        let mut corrupted = SAMPLE1_BZ2.to_vec();
+        corrupted[6] ^= 0xff;
+        let error = data_error(&corrupted, small);
+        assert_eq!(error.kind, DataErrorKind::BlockMagic);
+        assert_eq!(error.block_number, 0);
+        assert_eq!(error.bit_offset, 7 * 8);
+        assert_eq!((error.stored_crc, error.computed_crc), (0, 0));
+
+        // an origPtr that is too large for the block size
+        let mut corrupted = SAMPLE1_BZ2.to_vec();
+        corrupted[14] = 0x7f;
+        let error = data_error(&corrupted, small);
+        assert_eq!(error.kind, DataErrorKind::OrigPtr);
+        assert_eq!(error.block_number, 1);
+    }
+
+    // the error is also described by the `Display` implementation
+    let mut corrupted = SAMPLE1_BZ2.to_vec();
+    corrupted[10] ^= 0xff;
+    let message = decompress_chunked(&corrupted, false, 1000)
+        .unwrap_err()
+        .to_string();
+    assert!(
+        message.contains("block CRC mismatch in block 1"),
+        "{message}"
+    );
+}
+
+#[test]
+fn decompress_data_error_c_api() {
+    use libbz2_rs_sys::*;
+
+    let mut corrupted = SAMPLE1_BZ2.to_vec();
+    corrupted[10] ^= 0xff;
+
+    let mut output = vec![0u8; SAMPLE1_REF.len()];
+    let mut error = std::mem::MaybeUninit::<DataError>::uninit();
+
+    unsafe {
+        let mut strm = bz_stream::zeroed();
+        assert_eq!(BZ2_bzDecompressInit(&mut strm, 0, 0), BZ_OK);
+
+        assert_eq!(
+            BZ2_bzDecompressGetDataError(&mut strm, error.as_mut_ptr()),
+            BZ_OK
+        );
+        assert_eq!(error.assume_init().kind, DataErrorKind::None);
+
+        strm.next_in = corrupted.as_ptr().cast();
+        strm.avail_in = corrupted.len() as _;
+        strm.next_out = output.as_mut_ptr().cast();
+        strm.avail_out = output.len() as _;
+        assert_eq!(BZ2_bzDecompress(&mut strm), BZ_DATA_ERROR);
+
+        assert_eq!(
+            BZ2_bzDecompressGetDataError(&mut strm, error.as_mut_ptr()),
+            BZ_OK
+        );
+        let error = error.assume_init();
+        assert_eq!(error.kind, DataErrorKind::BlockCrc);
+        assert_eq!(error.block_number, 1);
+
+        assert_eq!(
+            BZ2_bzDecompressGetDataError(&mut strm, core::ptr::null_mut()),
+            BZ_PARAM_ERROR
+        );
+        assert_eq!(BZ2_bzDecompressEnd(&mut strm), BZ_OK);
+
+        let mut error = std::mem::MaybeUninit::<DataError>::uninit();
+        assert_eq!(
+            BZ2_bzDecompressGetDataError(core::ptr::null_mut(), error.as_mut_ptr()),
+            BZ_PARAM_ERROR
+        );
+        assert_eq!(
+            BZ2_bzDecompressGetDataError(&mut strm, error.as_mut_ptr()),
+            BZ_PARAM_ERROR
+        );
+    }
+}
+
+#[test]
+fn drop_unfinished() {
+    let mut compress = Compress::new(1, 0).unwrap();
+    let mut output = vec![0u8; 1024];
+    compress
+        .compress(&SAMPLE1_REF[..50_000], &mut output, Action::Run)
+        .unwrap();
+    drop(compress);
+
+    let mut decompress = Decompress::new(false).unwrap();
+    decompress
+        .decompress(&SAMPLE1_BZ2[..1000], &mut output)
+        .unwrap();
+    drop(decompress);
+}
+
+#[test]
+fn send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    assert_send_sync::<Compress>();
+    assert_send_sync::<Decompress>();
+}
+
+/// A reader that returns at most `chunk_size` bytes per call.
+struct ChunkedReader<'a> {
+    data: &'a [u8],
+    chunk_size: usize,
+}
+
+impl std::io::Read for ChunkedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = Ord::min(Ord::min(buf.len(), self.chunk_size), self.data.len());
+        buf[..n].copy_from_slice(&self.data[..n]);
+        self.data = &self.data[n..];
+        Ok(n)
+    }
+}
+
+#[test]
+fn read_decoder() {
+    use std::io::Read;
+
+    for chunk_size in [1, 1000, SAMPLE1_BZ2.len()] {
+        let reader = ChunkedReader {
+            data: SAMPLE1_BZ2,
+            chunk_size,
+        };
+
+        let mut decoder = libbz2_rs_sys::read::BzDecoder::new(reader);
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert!(output == SAMPLE1_REF);
+        assert_eq!(decoder.total_in(), SAMPLE1_BZ2.len() as u64);
+        assert_eq!(decoder.total_out(), SAMPLE1_REF.len() as u64);
+
+        // reading past the end of the stream keeps returning 0
+        assert_eq!(decoder.read(&mut [0; 16]).unwrap(), 0);
+    }
+
+    let mut decoder = libbz2_rs_sys::read::BzDecoder::with_small(SAMPLE1_BZ2, true);
+    let mut byte = [0u8];
+    for expected in &SAMPLE1_REF[..1000] {
+        assert_eq!(decoder.read(&mut byte).unwrap(), 1);
+        assert_eq!(byte[0], *expected);
+    }
+}
+
+#[test]
+fn read_decoder_errors() {
+    use std::io::{ErrorKind, Read};
+
+    let mut decoder = libbz2_rs_sys::read::BzDecoder::new(SAMPLE1_REF);
+    let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    let mut corrupted = SAMPLE1_BZ2.to_vec();
+    corrupted[1000] ^= 0x55;
+    let mut decoder = libbz2_rs_sys::read::BzDecoder::new(corrupted.as_slice());
+    let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    let truncated = &SAMPLE1_BZ2[..SAMPLE1_BZ2.len() - 10];
+    let mut decoder = libbz2_rs_sys::read::BzDecoder::new(truncated);
+    let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+    let mut decoder = libbz2_rs_sys::read::BzDecoder::new(&[][..]);
+    let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+}
+
+/// A writer that accepts at most `chunk_size` bytes per call.
+struct ChunkedWriter {
+    data: Vec<u8>,
+    chunk_size: usize,
+    flushes: usize,
+}
+
+impl std::io::Write for ChunkedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = Ord::min(buf.len(), self.chunk_size);
+        self.data.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.flushes += 1;
+        Ok(())
+    }
+}
+
+#[test]
+fn write_encoder() {
+    use std::io::Write;
+
+    for block_size100k in [1, 9] {
+        let mut expected = vec![0u8; 2 * SAMPLE1_REF.len()];
+        let mut expected_len = expected.len() as _;
+        let err = unsafe {
+            compress_c(
+                expected.as_mut_ptr(),
+                &mut expected_len,
+                SAMPLE1_REF.as_ptr(),
+                SAMPLE1_REF.len() as _,
+                block_size100k as i32,
+            )
+        };
+        assert_eq!(err, 0);
+        expected.truncate(expected_len as usize);
+
+        for chunk_size in [7, 1000, SAMPLE1_REF.len()] {
+            let writer = ChunkedWriter {
+                data: Vec::new(),
+                chunk_size,
+                flushes: 0,
+            };
+
+            let mut encoder = libbz2_rs_sys::write::BzEncoder::new(writer, block_size100k);
+            for chunk in SAMPLE1_REF.chunks(chunk_size) {
+                encoder.write_all(chunk).unwrap();
+            }
+            assert_eq!(encoder.total_in(), SAMPLE1_REF.len() as u64);
+
+            let writer = encoder.finish().unwrap();
+            assert_eq!(writer.data, expected);
+        }
+    }
+}
+
+#[test]
+fn write_encoder_flush() {
+    use std::io::Write;
+
+    let writer = ChunkedWriter {
+        data: Vec::new(),
+        chunk_size: 3,
+        flushes: 0,
+    };
+
+    let mut encoder = libbz2_rs_sys::write::BzEncoder::new(writer, 9);
+    encoder.write_all(b"hello ").unwrap();
+    encoder.flush().unwrap();
+    assert_eq!(encoder.get_ref().flushes, 1);
+    assert_eq!(encoder.get_ref().data.len() as u64, encoder.total_out());
+
+    encoder.write_all(b"world").unwrap();
+    encoder.flush().unwrap();
+    encoder.flush().unwrap();
+
+    let writer = encoder.finish().unwrap();
+    assert_eq!(
+        decompress_chunked(&writer.data, false, 1).unwrap(),
+        b"hello world"
+    );
+}
+
+#[test]
+fn write_encoder_drop() {
+    use std::io::Write;
+
+    let mut output = Vec::new();
+
+    {
+        let mut encoder = libbz2_rs_sys::write::BzEncoder::new(&mut output, 1);
+        encoder.write_all(SAMPLE1_REF).unwrap();
+    }
+
+    assert_eq!(
+        decompress_chunked(&output, false, 1000).unwrap(),
+        SAMPLE1_REF
+    );
+}
+
+#[test]
+fn write_encoder_empty() {
+    let encoder = libbz2_rs_sys::write::BzEncoder::new(Vec::new(), 9);
+    let output = encoder.finish().unwrap();
+    assert_eq!(decompress_chunked(&output, false, 1).unwrap(), b"");
+}
+
+fn concatenated_streams() -> (Vec<u8>, Vec<u8>) {
+    let hello = compress_chunked(b"hello ", 9, 100);
+    let empty = compress_chunked(b"", 9, 100);
+
+    let mut compressed = Vec::new();
+    compressed.extend_from_slice(SAMPLE1_BZ2);
+    compressed.extend_from_slice(&hello);
+    compressed.extend_from_slice(&empty);
+    compressed.extend_from_slice(SAMPLE1_BZ2);
+
+    let mut expected = Vec::new();
+    expected.extend_from_slice(SAMPLE1_REF);
+    expected.extend_from_slice(b"hello ");
+    expected.extend_from_slice(SAMPLE1_REF);
+
+    (compressed, expected)
+}
+
+#[test]
+fn multi_stream_decoders() {
+    use std::io::Read;
+
+    let (compressed, expected) = concatenated_streams();
+
+    for chunk_size in [1, 1000, compressed.len()] {
+        let reader = ChunkedReader {
+            data: &compressed,
+            chunk_size,
+        };
+        let mut decoder = libbz2_rs_sys::read::MultiBzDecoder::new(reader);
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert!(output == expected);
+        assert_eq!(decoder.total_in(), compressed.len() as u64);
+        assert_eq!(decoder.total_out(), expected.len() as u64);
+
+        let reader = std::io::BufReader::with_capacity(chunk_size, compressed.as_slice());
+        let mut decoder = libbz2_rs_sys::bufread::MultiBzDecoder::with_small(reader, true);
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert!(output == expected);
+    }
+
+    // the single-stream decoder stops after the first stream
+    let mut decoder = libbz2_rs_sys::read::BzDecoder::new(compressed.as_slice());
+    let mut output = Vec::new();
+    decoder.read_to_end(&mut output).unwrap();
+    assert!(output == SAMPLE1_REF);
+}
+
+#[test]
+fn multi_stream_trailing_garbage() {
+    use std::io::{ErrorKind, Read};
+
+    let (compressed, expected) = concatenated_streams();
+
+    // trailing garbage is ignored, like `bzip2` does
+    for garbage in [&b"\0"[..], b"garbage", b"\0\0\0\0\0\0\0\0\0\0\0\0", b"BZ"] {
+        let mut input = compressed.clone();
+        input.extend_from_slice(garbage);
+
+        let mut decoder = libbz2_rs_sys::read::MultiBzDecoder::new(input.as_slice());
+        let mut output = Vec::new();
+
+        // a prefix of the magic bytes is a truncated stream
+        if garbage == b"BZ" {
+            let err = decoder.read_to_end(&mut output).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        } else {
+            decoder.read_to_end(&mut output).unwrap();
+            assert!(output == expected);
+        }
+    }
+
+    // a truncated or corrupted stream after the first one is an error
+    let mut input = compressed.clone();
+    input.extend_from_slice(&SAMPLE1_BZ2[..100]);
+    let mut decoder = libbz2_rs_sys::read::MultiBzDecoder::new(input.as_slice());
+    let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+    let mut input = compressed.clone();
+    input.extend_from_slice(b"BZh9garbage garbage garbage");
+    let mut decoder = libbz2_rs_sys::read::MultiBzDecoder::new(input.as_slice());
+    let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    // garbage at the start is not a bzip2 file at all
+    let mut decoder = libbz2_rs_sys::read::MultiBzDecoder::new(&b"garbage"[..]);
+    let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn bufread_decoder_leaves_trailing_data() {
+    use std::io::{BufRead, Read};
+
+    let mut container = Vec::new();
+    container.extend_from_slice(b"header");
+    container.extend_from_slice(SAMPLE1_BZ2);
+    container.extend_from_slice(b"trailer");
+
+    for capacity in [1, 7, 1000, container.len()] {
+        let mut reader = std::io::BufReader::with_capacity(capacity, container.as_slice());
+
+        let mut header = [0u8; 6];
+        reader.read_exact(&mut header).unwrap();
+        assert_eq!(&header, b"header");
+
+        let mut decoder = libbz2_rs_sys::bufread::BzDecoder::new(&mut reader);
+        assert!(!decoder.is_finished());
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert!(output == SAMPLE1_REF);
+        assert!(decoder.is_finished());
+        assert_eq!(decoder.total_in(), SAMPLE1_BZ2.len() as u64);
+
+        // reading past the end of the stream does not touch the underlying reader
+        assert_eq!(decoder.read(&mut [0; 16]).unwrap(), 0);
+        drop(decoder);
+
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"trailer");
+        assert!(reader.fill_buf().unwrap().is_empty());
+    }
+
+    // two streams back to back
+    let mut input = SAMPLE1_BZ2.to_vec();
+    input.extend_from_slice(&compress_chunked(b"second", 9, 100));
+    let mut reader = input.as_slice();
+
+    let mut output = Vec::new();
+    libbz2_rs_sys::bufread::BzDecoder::with_small(&mut reader, true)
+        .read_to_end(&mut output)
+        .unwrap();
+    assert!(output == SAMPLE1_REF);
+
+    let mut output = Vec::new();
+    libbz2_rs_sys::bufread::BzDecoder::new(&mut reader)
+        .read_to_end(&mut output)
+        .unwrap();
+    assert_eq!(output, b"second");
+    assert!(reader.is_empty());
+}
+
+#[test]
+fn to_vec_roundtrip() {
+    use libbz2_rs_sys::{compress_to_vec, decompress_to_vec};
+
+    for block_size100k in [1, 9] {
+        let compressed = compress_to_vec(SAMPLE1_REF, block_size100k).unwrap();
+        assert_eq!(
+            compressed,
+            compress_chunked(SAMPLE1_REF, block_size100k, 1000)
+        );
+        assert!(decompress_to_vec(&compressed).unwrap() == SAMPLE1_REF);
+    }
+
+    let compressed = compress_to_vec(b"", 9).unwrap();
+    assert_eq!(decompress_to_vec(&compressed).unwrap(), b"");
+
+    // data after the end of the stream is ignored
+    let mut input = SAMPLE1_BZ2.to_vec();
+    input.extend_from_slice(b"trailing data");
+    assert!(decompress_to_vec(&input).unwrap() == SAMPLE1_REF);
+
+    assert_eq!(compress_to_vec(b"", 0), Err(Error::Param));
+    assert_eq!(compress_to_vec(b"", 10), Err(Error::Param));
+}
+
+#[test]
+fn to_vec_errors() {
+    use libbz2_rs_sys::decompress_to_vec;
+
+    assert_eq!(decompress_to_vec(b""), Err(Error::UnexpectedEof));
+    assert_eq!(
+        decompress_to_vec(&SAMPLE1_BZ2[..SAMPLE1_BZ2.len() - 1]),
+        Err(Error::UnexpectedEof)
+    );
+    assert_eq!(decompress_to_vec(SAMPLE1_REF), Err(Error::DataMagic));
+
+    let mut corrupted = SAMPLE1_BZ2.to_vec();
+    corrupted[1000] ^= 0x55;
+    assert!(matches!(decompress_to_vec(&corrupted), Err(Error::Data(_))));
+}
+
+#[test]
+fn decompress_to_vec_limit() {
+    use libbz2_rs_sys::{compress_to_vec, decompress_to_vec_with_limit};
+
+    let len = SAMPLE1_REF.len();
+    let output = decompress_to_vec_with_limit(SAMPLE1_BZ2, len).unwrap();
+    assert!(output == SAMPLE1_REF);
+
+    assert_eq!(
+        decompress_to_vec_with_limit(SAMPLE1_BZ2, len - 1),
+        Err(Error::OutputLimit)
+    );
+    assert_eq!(
+        decompress_to_vec_with_limit(SAMPLE1_BZ2, 0),
+        Err(Error::OutputLimit)
+    );
+
+    // a tiny input that expands to a lot of output
+    let zeros = vec![0u8; 10_000_000];
+    let compressed = compress_to_vec(&zeros, 9).unwrap();
+    assert!(compressed.len() < 100);
+    assert_eq!(
+        decompress_to_vec_with_limit(&compressed, 1_000_000),
+        Err(Error::OutputLimit)
+    );
+
+    let empty = compress_to_vec(b"", 9).unwrap();
+    assert_eq!(decompress_to_vec_with_limit(&empty, 0).unwrap(), b"");
+}
+
+#[test]
+fn decompress_with_limits() {
+    use libbz2_rs_sys::compress_to_vec;
+
+    let len = SAMPLE1_REF.len();
+
+    let mut output = vec![0u8; len + 100];
+    let mut stream = Decompress::with_limits(false, Some(len as u64), None).unwrap();
+    let status = stream.decompress(SAMPLE1_BZ2, &mut output).unwrap();
+    assert_eq!(status, Status::StreamEnd);
+    assert!(output[..len] == *SAMPLE1_REF);
+
+    // the output never exceeds the limit, and the error is sticky
+    let mut stream = Decompress::with_limits(false, Some(len as u64 - 1), None).unwrap();
+    let result = stream.decompress(SAMPLE1_BZ2, &mut output);
+    assert_eq!(result, Err(Error::OutputLimit));
+    assert_eq!(stream.total_out(), len as u64 - 1);
+    assert!(output[..len - 1] == SAMPLE1_REF[..len - 1]);
+
+    let consumed = stream.total_in() as usize;
+    let result = stream.decompress(&SAMPLE1_BZ2[consumed..], &mut output);
+    assert_eq!(result, Err(Error::OutputLimit));
+    assert_eq!(stream.total_out(), len as u64 - 1);
+
+    // a tiny input that expands to a lot of output
+    let zeros = vec![0u8; 10_000_000];
+    let compressed = compress_to_vec(&zeros, 9).unwrap();
+
+    let mut output = vec![0u8; zeros.len()];
+    let mut stream = Decompress::with_limits(true, None, Some(1000)).unwrap();
+    let result = stream.decompress(&compressed, &mut output);
+    assert_eq!(result, Err(Error::OutputLimit));
+    assert!(stream.total_out() <= stream.total_in() * 1000);
+
+    let mut stream = Decompress::with_limits(false, None, Some(1_000_000)).unwrap();
+    let status = stream.decompress(&compressed, &mut output).unwrap();
+    assert_eq!(status, Status::StreamEnd);
+
+    assert!(matches!(
+        Decompress::with_limits(false, None, Some(0)),
+        Err(Error::Param)
+    ));
+}
+
+#[test]
+fn decompress_with_limits_c_api() {
+    use libbz2_rs_sys::*;
+
+    let len = SAMPLE1_REF.len();
+    let mut output = vec![0u8; len];
+
+    unsafe {
+        let mut strm = bz_stream::zeroed();
+        assert_eq!(
+            BZ2_bzDecompressInitWithLimits(&mut strm, 0, 0, 1000, 0),
+            BZ_OK
+        );
+
+        strm.next_in = SAMPLE1_BZ2.as_ptr().cast_mut().cast();
+        strm.avail_in = SAMPLE1_BZ2.len() as _;
+        strm.next_out = output.as_mut_ptr().cast();
+        strm.avail_out = output.len() as _;
+        assert_eq!(BZ2_bzDecompress(&mut strm), BZ_OUTPUT_LIMIT);
+        assert_eq!(strm.total_out_lo32, 1000);
+        assert_eq!(strm.avail_out as usize, len - 1000);
+        assert!(output[..1000] == SAMPLE1_REF[..1000]);
+
+        assert_eq!(BZ2_bzDecompress(&mut strm), BZ_OUTPUT_LIMIT);
+        assert_eq!(BZ2_bzDecompressEnd(&mut strm), BZ_OK);
+
+        // a limit of 0 means no limit
+        let mut strm = bz_stream::zeroed();
+        assert_eq!(BZ2_bzDecompressInitWithLimits(&mut strm, 0, 0, 0, 0), BZ_OK);
+
+        strm.next_in = SAMPLE1_BZ2.as_ptr().cast_mut().cast();
+        strm.avail_in = SAMPLE1_BZ2.len() as _;
+        strm.next_out = output.as_mut_ptr().cast();
+        strm.avail_out = output.len() as _;
+        assert_eq!(BZ2_bzDecompress(&mut strm), BZ_STREAM_END);
+        assert!(output == SAMPLE1_REF);
+        assert_eq!(BZ2_bzDecompressEnd(&mut strm), BZ_OK);
+
+        assert_eq!(
+            BZ2_bzDecompressInitWithLimits(core::ptr::null_mut(), 0, 0, 0, 0),
+            BZ_PARAM_ERROR
+        );
+    }
+}
+
+#[test]
+fn compress_bound() {
+    use libbz2_rs_sys::{compress_bound, compress_to_vec};
+
+    // a simple pseudo-random generator, because random data does not compress
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut random = |n: usize| -> Vec<u8> {
+        (0..n)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 40) as u8
+            })
+            .collect()
+    };
+
+    for len in [0, 1, 100, 10_000, 250_000] {
+        let input = random(len);
+
+        for block_size100k in [1, 9] {
+            let compressed = compress_to_vec(&input, block_size100k).unwrap();
+            assert!(compressed.len() <= compress_bound(len, block_size100k));
+        }
+    }
+
+    assert_eq!(compress_bound(0, 9), 600);
+    assert_eq!(compress_bound(usize::MAX, 1), usize::MAX);
+}
@@ -0,0 +1,192 @@
+use std::io::{Cursor, ErrorKind, Read, Seek, SeekFrom};
+
+use crate::{random_bytes, SAMPLE1_REF};
+
+use libbz2_rs_sys::seek::SeekableBzDecoder;
+use libbz2_rs_sys::{compress_to_vec, DataErrorKind, Error};
+
+/// The seed of the pseudo-random input.
+const SEED: u32 = 0x8765_4321;
+
+fn read_exact_at(
+    decoder: &mut SeekableBzDecoder<Cursor<Vec<u8>>>,
+    pos: u64,
+    len: usize,
+) -> Vec<u8> {
+    assert_eq!(decoder.seek(SeekFrom::Start(pos)).unwrap(), pos);
+
+    let mut buf = vec![0u8; len];
+    decoder.read_exact(&mut buf).unwrap();
+    buf
+}
+
+#[test]
+fn sequential_read() {
+    for input in [
+        Vec::new(),
+        b"a".to_vec(),
+        SAMPLE1_REF.to_vec(),
+        random_bytes(250_000, SEED, 64),
+    ] {
+        let compressed = compress_to_vec(&input, 1).unwrap();
+
+        let mut decoder = SeekableBzDecoder::new(Cursor::new(compressed));
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert!(output == input);
+
+        let index = decoder.index();
+        assert!(index.is_complete());
+        assert_eq!(index.uncompressed_len(), Some(input.len() as u64));
+        assert_eq!(index.entries().len(), input.len().div_ceil(99_981));
+    }
+}
+
+#[test]
+fn seek_within_blocks() {
+    let input = random_bytes(450_000, SEED, 64);
+    let compressed = compress_to_vec(&input, 1).unwrap();
+    let mut decoder = SeekableBzDecoder::new(Cursor::new(compressed));
+
+    // forward, backward, and across block boundaries
+    for (pos, len) in [
+        (300_000, 1000),
+        (10, 100),
+        (99_900, 200),
+        (449_000, 1000),
+        (0, 5),
+        (199_962, 1),
+    ] {
+        let output = read_exact_at(&mut decoder, pos, len);
+        assert!(output == input[pos as usize..][..len], "pos {pos}");
+    }
+
+    assert_eq!(decoder.seek(SeekFrom::Current(-500)).unwrap(), 199_463);
+    let mut buf = [0u8; 10];
+    decoder.read_exact(&mut buf).unwrap();
+    assert!(buf == input[199_463..][..10]);
+
+    assert_eq!(decoder.seek(SeekFrom::End(-3)).unwrap(), 449_997);
+    let mut output = Vec::new();
+    decoder.read_to_end(&mut output).unwrap();
+    assert!(output == input[449_997..]);
+
+    assert_eq!(decoder.stream_position().unwrap(), 450_000);
+}
+
+#[test]
+fn build_index() {
+    let input = random_bytes(250_000, SEED, 64);
+    let compressed = compress_to_vec(&input, 1).unwrap();
+    let mut decoder = SeekableBzDecoder::new(Cursor::new(compressed));
+
+    let mut buf = [0u8; 100];
+    decoder.read_exact(&mut buf).unwrap();
+
+    let index = decoder.build_index().unwrap().clone();
+    assert_eq!(index.uncompressed_len(), Some(250_000));
+    assert_eq!(index.entries().len(), 3);
+    assert_eq!(index.entries()[0].compressed_bit_offset, 32);
+    for (entry, number) in index.entries().iter().zip(0..) {
+        assert_eq!(entry.uncompressed_offset, number * 99_981);
+        assert_eq!(entry.block_size100k, 1);
+    }
+
+    // the position is unchanged
+    decoder.read_exact(&mut buf).unwrap();
+    assert!(buf == input[100..200]);
+}
+
+#[test]
+fn concatenated_streams() {
+    let first = random_bytes(150_000, SEED, 64);
+    let second = SAMPLE1_REF;
+
+    let mut compressed = compress_to_vec(&first, 1).unwrap();
+    compressed.extend(compress_to_vec(&[], 9).unwrap());
+    compressed.extend(compress_to_vec(second, 2).unwrap());
+    compressed.extend(b"trailing garbage");
+
+    let mut input = first.clone();
+    input.extend_from_slice(second);
+
+    let mut decoder = SeekableBzDecoder::new(Cursor::new(compressed));
+
+    let output = read_exact_at(&mut decoder, 149_990, 20);
+    assert!(output == input[149_990..][..20]);
+
+    let index = decoder.build_index().unwrap();
+    assert_eq!(index.uncompressed_len(), Some(input.len() as u64));
+    let sizes: Vec<u8> = index.entries().iter().map(|e| e.block_size100k).collect();
+    assert_eq!(sizes, [1, 1, 2]);
+
+    decoder.rewind().unwrap();
+    let mut output = Vec::new();
+    decoder.read_to_end(&mut output).unwrap();
+    assert!(output == input);
+
+    // read past the start of the second stream before the index is complete
+    let mut decoder = SeekableBzDecoder::new(decoder.into_inner());
+    let output = read_exact_at(&mut decoder, 0, 150_010);
+    assert!(output == input[..150_010]);
+    assert!(!decoder.index().is_complete());
+
+    decoder.rewind().unwrap();
+    let mut output = Vec::new();
+    decoder.read_to_end(&mut output).unwrap();
+    assert!(output == input);
+}
+
+#[test]
+fn seek_beyond_end() {
+    let compressed = compress_to_vec(SAMPLE1_REF, 9).unwrap();
+    let mut decoder = SeekableBzDecoder::new(Cursor::new(compressed));
+
+    let pos = SAMPLE1_REF.len() as u64 + 10;
+    assert_eq!(decoder.seek(SeekFrom::Start(pos)).unwrap(), pos);
+
+    let mut buf = [0u8; 10];
+    assert_eq!(decoder.read(&mut buf).unwrap(), 0);
+    assert_eq!(decoder.stream_position().unwrap(), pos);
+
+    let err = decoder
+        .seek(SeekFrom::Current(-(pos as i64) - 1))
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn errors() {
+    let compressed = compress_to_vec(SAMPLE1_REF, 9).unwrap();
+
+    let read_all = |data: Vec<u8>| {
+        let mut output = Vec::new();
+        SeekableBzDecoder::new(Cursor::new(data))
+            .read_to_end(&mut output)
+            .map_err(|err| *err.into_inner().unwrap().downcast::<Error>().unwrap())
+    };
+
+    assert_eq!(read_all(b"BZ".to_vec()), Err(Error::UnexpectedEof));
+    assert_eq!(read_all(b"PK\x03\x04".to_vec()), Err(Error::DataMagic));
+
+    let truncated = compressed[..compressed.len() - 2].to_vec();
+    assert_eq!(read_all(truncated), Err(Error::UnexpectedEof));
+
+    let mut corrupted = compressed.clone();
+    let last = corrupted.len() - 2;
+    corrupted[last] ^= 0x10;
+    match read_all(corrupted) {
+        Err(Error::Data(error)) => assert_eq!(error.kind, DataErrorKind::CombinedCrc),
+        other => panic!("{other:?}"),
+    }
+
+    let mut corrupted = compressed.clone();
+    corrupted[4] ^= 0x01;
+    match read_all(corrupted) {
+        Err(Error::Data(error)) => {
+            assert_eq!(error.kind, DataErrorKind::BlockMagic);
+            assert_eq!(error.block_number, 0);
+        }
+        other => panic!("{other:?}"),
+    }
+}
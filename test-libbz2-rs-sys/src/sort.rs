@@ -0,0 +1,108 @@
+use crate::{compress_with, random_bytes, SAMPLE1_REF};
+
+use libbz2_rs_sys::{compress_to_vec, SortAlgorithm};
+
+/// The seed of the pseudo-random input.
+const SEED: u32 = 0x2468_ace0;
+
+fn repeat(pattern: &[u8], len: usize) -> Vec<u8> {
+    pattern.iter().copied().cycle().take(len).collect()
+}
+
+fn assert_same_output(input: &[u8], block_size100k: u32) {
+    let classic = compress_to_vec(input, block_size100k).unwrap();
+    let sais = compress_with(input, block_size100k, |stream| {
+        stream.set_sort_algorithm(SortAlgorithm::Sais)
+    });
+
+    assert!(classic == sais, "input of length {}", input.len());
+}
+
+#[test]
+fn sais_matches_classic() {
+    for len in 0..40 {
+        assert_same_output(&random_bytes(len, SEED, 3), 9);
+    }
+
+    // the classic algorithm uses fallbackSort for blocks below 10000 bytes
+    for len in [9_999, 10_000, 250_000] {
+        for alphabet in [2, 4, 26, 200] {
+            assert_same_output(&random_bytes(len, SEED, alphabet), 1);
+        }
+    }
+
+    assert_same_output(SAMPLE1_REF, 9);
+}
+
+#[test]
+fn sais_matches_classic_repetitive() {
+    // nearly periodic blocks, which make the main sort of the classic algorithm give up
+    for pattern in [&b"ab"[..], b"abracadabra", b"the quick brown fox "] {
+        let mut input = repeat(pattern, 300_000);
+        input[1000] = b'!';
+        assert_same_output(&input, 2);
+
+        input.push(b'?');
+        assert_same_output(&input, 9);
+    }
+
+    // a Fibonacci word
+    let (mut a, mut b) = (b"a".to_vec(), b"ab".to_vec());
+    while b.len() < 200_000 {
+        (a, b) = (b.clone(), [b, a].concat());
+    }
+    assert_same_output(&b, 9);
+
+    // long runs
+    let input: Vec<u8> = (0..100_000u32).map(|i| (i / 1000 % 3) as u8).collect();
+    assert_same_output(&input, 1);
+}
+
+#[test]
+fn sais_matches_classic_periodic() {
+    // blocks that are a repetition of a shorter string contain identical rotations
+    for pattern in [&b"ab"[..], b"ba", b"abaab", b"abracadabra", b"cab"] {
+        for len in [2 * pattern.len(), 7 * pattern.len(), 5000 * pattern.len()] {
+            assert_same_output(&repeat(pattern, len), 9);
+        }
+    }
+}
+
+#[test]
+fn sort_algorithm_c_api() {
+    use libbz2_rs_sys::{
+        bz_stream, BZ2_bzCompress, BZ2_bzCompressEnd, BZ2_bzCompressInit,
+        BZ2_bzCompressSetSortAlgorithm, BZ_FINISH, BZ_OK, BZ_PARAM_ERROR, BZ_SORT_SAIS,
+        BZ_STREAM_END,
+    };
+
+    let input = random_bytes(150_000, SEED, 16);
+    let mut output = vec![0u8; input.len() + 1000];
+
+    unsafe {
+        assert_eq!(
+            BZ2_bzCompressSetSortAlgorithm(core::ptr::null_mut(), BZ_SORT_SAIS),
+            BZ_PARAM_ERROR
+        );
+
+        let mut strm: bz_stream = core::mem::zeroed();
+        assert_eq!(BZ2_bzCompressInit(&mut strm, 9, 0, 0), BZ_OK);
+
+        assert_eq!(BZ2_bzCompressSetSortAlgorithm(&mut strm, 2), BZ_PARAM_ERROR);
+        assert_eq!(
+            BZ2_bzCompressSetSortAlgorithm(&mut strm, BZ_SORT_SAIS),
+            BZ_OK
+        );
+
+        strm.next_in = input.as_ptr().cast_mut().cast();
+        strm.avail_in = input.len() as _;
+        strm.next_out = output.as_mut_ptr().cast();
+        strm.avail_out = output.len() as _;
+        assert_eq!(BZ2_bzCompress(&mut strm, BZ_FINISH), BZ_STREAM_END);
+
+        output.truncate(strm.total_out_lo32 as usize);
+        assert_eq!(BZ2_bzCompressEnd(&mut strm), BZ_OK);
+    }
+
+    assert!(output == compress_to_vec(&input, 9).unwrap());
+}
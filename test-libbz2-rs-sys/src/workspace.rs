@@ -0,0 +1,196 @@
+use crate::{SAMPLE1_BZ2, SAMPLE1_REF};
+
+use libbz2_rs_sys::{compress_bound, compress_to_vec, Action, Compress, Decompress, Error, Status};
+
+#[test]
+fn compress_in_workspace() {
+    for block_size100k in [1, 2] {
+        let size = Compress::workspace_size(block_size100k);
+        let expected = compress_to_vec(SAMPLE1_REF, block_size100k).unwrap();
+
+        // the workspace does not need to be aligned
+        let mut buffer = vec![0u8; size + 16];
+        for offset in [0, 1, 8, 15] {
+            let workspace = &mut buffer[offset..][..size];
+            let mut stream = Compress::in_workspace(workspace, block_size100k, 0).unwrap();
+
+            let mut output = vec![0u8; compress_bound(SAMPLE1_REF.len(), block_size100k)];
+            let status = stream
+                .compress(SAMPLE1_REF, &mut output, Action::Finish)
+                .unwrap();
+            assert_eq!(status, Status::StreamEnd);
+
+            output.truncate(stream.total_out() as usize);
+            assert!(output == expected);
+
+            // the buffers are reused for the next stream
+            stream.reset();
+        }
+    }
+}
+
+#[test]
+fn compress_workspace_too_small() {
+    let size = Compress::workspace_size(1);
+    let mut buffer = vec![0u8; size];
+
+    for len in [0, 16, size / 2, size - 32] {
+        let result = Compress::in_workspace(&mut buffer[..len], 1, 0);
+        assert!(matches!(result, Err(Error::Mem)));
+    }
+}
+
+#[test]
+fn decompress_in_workspace() {
+    for small in [false, true] {
+        let mut buffer = vec![0u8; Decompress::workspace_size(1, small)];
+        let mut stream = Decompress::in_workspace(&mut buffer, small).unwrap();
+
+        let mut output = vec![0u8; SAMPLE1_REF.len()];
+        let status = stream.decompress(SAMPLE1_BZ2, &mut output).unwrap();
+        assert_eq!(status, Status::StreamEnd);
+        assert!(output == SAMPLE1_REF);
+
+        // streams with a larger block size do not fit in the workspace
+        let compressed = compress_to_vec(SAMPLE1_REF, 2).unwrap();
+        stream.reset();
+        assert_eq!(stream.decompress(&compressed, &mut output), Err(Error::Mem));
+    }
+}
+
+#[test]
+fn decompress_in_workspace_after_reset() {
+    let mut input = SAMPLE1_REF.repeat(20);
+    input.truncate(850_000);
+
+    let small_blocks = compress_to_vec(&input, 1).unwrap();
+    let large_blocks = compress_to_vec(&input, 9).unwrap();
+
+    for small in [false, true] {
+        let mut buffer = vec![0u8; Decompress::workspace_size(9, small)];
+        let mut stream = Decompress::in_workspace(&mut buffer, small).unwrap();
+        let mut output = vec![0u8; input.len()];
+
+        // the buffers of the first stream are large enough for the second
+        for compressed in [&small_blocks, &large_blocks, &small_blocks] {
+            let status = stream.decompress(compressed, &mut output).unwrap();
+            assert_eq!(status, Status::StreamEnd);
+            assert!(output == input);
+
+            stream.reset();
+        }
+    }
+
+    // the same through the C interface
+    use libbz2_rs_sys::{
+        bz_stream, BZ2_bzDecompress, BZ2_bzDecompressEnd, BZ2_bzDecompressInitWorkspace,
+        BZ2_bzDecompressReset, BZ2_bzDecompressWorkspaceSize, BZ_OK, BZ_STREAM_END,
+    };
+
+    let mut workspace = vec![0u8; BZ2_bzDecompressWorkspaceSize(9, 0)];
+    let mut output = vec![0u8; input.len()];
+
+    unsafe {
+        let mut strm: bz_stream = core::mem::zeroed();
+        let ret = BZ2_bzDecompressInitWorkspace(
+            &mut strm,
+            0,
+            0,
+            workspace.as_mut_ptr().cast(),
+            workspace.len(),
+        );
+        assert_eq!(ret, BZ_OK);
+
+        for compressed in [&small_blocks, &large_blocks] {
+            strm.next_in = compressed.as_ptr().cast_mut().cast();
+            strm.avail_in = compressed.len() as _;
+            strm.next_out = output.as_mut_ptr().cast();
+            strm.avail_out = output.len() as _;
+            assert_eq!(BZ2_bzDecompress(&mut strm), BZ_STREAM_END);
+            assert!(output == input);
+
+            assert_eq!(BZ2_bzDecompressReset(&mut strm), BZ_OK);
+        }
+
+        assert_eq!(BZ2_bzDecompressEnd(&mut strm), BZ_OK);
+    }
+}
+
+#[test]
+fn workspace_c_api() {
+    use libbz2_rs_sys::{
+        bz_stream, BZ2_bzCompress, BZ2_bzCompressEnd, BZ2_bzCompressInitWorkspace,
+        BZ2_bzCompressWorkspaceSize, BZ2_bzDecompress, BZ2_bzDecompressEnd,
+        BZ2_bzDecompressInitWorkspace, BZ2_bzDecompressWorkspaceSize, BZ_FINISH, BZ_MEM_ERROR,
+        BZ_OK, BZ_PARAM_ERROR, BZ_STREAM_END,
+    };
+
+    assert_eq!(BZ2_bzCompressWorkspaceSize(0), 0);
+    assert_eq!(BZ2_bzCompressWorkspaceSize(10), 0);
+    assert_eq!(BZ2_bzDecompressWorkspaceSize(0, 0), 0);
+    assert_eq!(BZ2_bzDecompressWorkspaceSize(1, 2), 0);
+
+    let mut workspace = vec![0u8; BZ2_bzCompressWorkspaceSize(1)];
+    let mut compressed = vec![0u8; compress_bound(SAMPLE1_REF.len(), 1)];
+
+    unsafe {
+        let mut strm: bz_stream = core::mem::zeroed();
+        assert_eq!(
+            BZ2_bzCompressInitWorkspace(&mut strm, 1, 0, 0, core::ptr::null_mut(), 0),
+            BZ_PARAM_ERROR
+        );
+        assert_eq!(
+            BZ2_bzCompressInitWorkspace(&mut strm, 1, 0, 0, workspace.as_mut_ptr().cast(), 1000),
+            BZ_MEM_ERROR
+        );
+        assert_eq!(
+            BZ2_bzCompressInitWorkspace(
+                &mut strm,
+                1,
+                0,
+                0,
+                workspace.as_mut_ptr().cast(),
+                workspace.len()
+            ),
+            BZ_OK
+        );
+
+        strm.next_in = SAMPLE1_REF.as_ptr().cast_mut().cast();
+        strm.avail_in = SAMPLE1_REF.len() as _;
+        strm.next_out = compressed.as_mut_ptr().cast();
+        strm.avail_out = compressed.len() as _;
+        assert_eq!(BZ2_bzCompress(&mut strm, BZ_FINISH), BZ_STREAM_END);
+
+        compressed.truncate(strm.total_out_lo32 as usize);
+        assert_eq!(BZ2_bzCompressEnd(&mut strm), BZ_OK);
+    }
+
+    assert!(compressed == compress_to_vec(SAMPLE1_REF, 1).unwrap());
+
+    // the workspace can be reused once the stream has ended
+    assert!(BZ2_bzDecompressWorkspaceSize(1, 0) <= workspace.len());
+    let mut output = vec![0u8; SAMPLE1_REF.len()];
+
+    unsafe {
+        let mut strm: bz_stream = core::mem::zeroed();
+        assert_eq!(
+            BZ2_bzDecompressInitWorkspace(
+                &mut strm,
+                0,
+                0,
+                workspace.as_mut_ptr().cast(),
+                workspace.len()
+            ),
+            BZ_OK
+        );
+
+        strm.next_in = compressed.as_ptr().cast_mut().cast();
+        strm.avail_in = compressed.len() as _;
+        strm.next_out = output.as_mut_ptr().cast();
+        strm.avail_out = output.len() as _;
+        assert_eq!(BZ2_bzDecompress(&mut strm), BZ_STREAM_END);
+        assert_eq!(BZ2_bzDecompressEnd(&mut strm), BZ_OK);
+    }
+
+    assert!(output == SAMPLE1_REF);
+}
@@ -0,0 +1,482 @@
+use std::ffi::{c_char, c_int, c_long, CStr};
+
+use crate::callbacks::{no_error, read, write, Memory};
+use crate::SAMPLE1_REF;
+
+use libbz2_rs_sys::{
+    compress_to_vec, decompress_to_vec, BZ2_bzReadClose, BZ2_bzReadOpenCallbacks, BZ2_bzWriteClose,
+    BZ2_bzWriteOpenCallbacks, BZ2_bzclose, BZ2_bzeof, BZ2_bzgetc, BZ2_bzgets, BZ2_bzopen,
+    BZ2_bzputc, BZ2_bzputs, BZ2_bzread, BZ2_bzrewind, BZ2_bzseek, BZ2_bztell, BZ2_bzungetc, BZFILE,
+    BZ_OK,
+};
+use libc::{SEEK_CUR, SEEK_SET};
+
+/// Opens the compressed data in `memory` for reading.
+fn open_read(memory: &mut Memory) -> *mut BZFILE {
+    let mut bzerror = 0;
+    let bz_file = unsafe {
+        BZ2_bzReadOpenCallbacks(
+            &mut bzerror,
+            Some(read),
+            Some(no_error),
+            memory.opaque(),
+            0,
+            0,
+            core::ptr::null_mut(),
+            0,
+        )
+    };
+    assert_eq!(bzerror, BZ_OK);
+
+    bz_file
+}
+
+fn close_read(bz_file: *mut BZFILE) {
+    let mut bzerror = 0;
+    unsafe { BZ2_bzReadClose(&mut bzerror, bz_file) };
+    assert_eq!(bzerror, BZ_OK);
+}
+
+fn open_write(memory: &mut Memory) -> *mut BZFILE {
+    let mut bzerror = 0;
+    let bz_file = unsafe {
+        BZ2_bzWriteOpenCallbacks(
+            &mut bzerror,
+            Some(write),
+            Some(no_error),
+            memory.opaque(),
+            9,
+            0,
+            0,
+        )
+    };
+    assert_eq!(bzerror, BZ_OK);
+
+    bz_file
+}
+
+fn close_write(bz_file: *mut BZFILE) {
+    let mut bzerror = 0;
+    let null = core::ptr::null_mut();
+    unsafe { BZ2_bzWriteClose(&mut bzerror, bz_file, 0, null, null) };
+    assert_eq!(bzerror, BZ_OK);
+}
+
+/// Reads a line with [`BZ2_bzgets`], into a buffer of `len` bytes.
+fn gets(bz_file: *mut BZFILE, len: usize) -> Option<Vec<u8>> {
+    let mut buf = vec![b'?'; len];
+    let ptr = unsafe { BZ2_bzgets(bz_file, buf.as_mut_ptr().cast(), len as c_int) };
+    if ptr.is_null() {
+        return None;
+    }
+
+    assert_eq!(ptr, buf.as_mut_ptr().cast::<c_char>());
+    let line = unsafe { CStr::from_ptr(ptr) };
+    Some(line.to_bytes().to_vec())
+}
+
+#[test]
+fn read_lines() {
+    let compressed = compress_to_vec(SAMPLE_TEXT, 9).unwrap();
+    let mut memory = Memory::new(&compressed, usize::MAX);
+    let bz_file = open_read(&mut memory);
+
+    let mut lines = Vec::new();
+    while let Some(line) = gets(bz_file, 100) {
+        // the end of the file is reached while reading the last line
+        let eof = !line.ends_with(b"\n");
+        assert_eq!(unsafe { BZ2_bzeof(bz_file) }, eof as c_int);
+        lines.push(line);
+    }
+    assert_eq!(unsafe { BZ2_bzeof(bz_file) }, 1);
+
+    let expected: Vec<_> = SAMPLE_TEXT.split_inclusive(|&c| c == b'\n').collect();
+    assert_eq!(lines, expected);
+
+    close_read(bz_file);
+}
+
+#[test]
+fn read_lines_short_buffer() {
+    let compressed = compress_to_vec(b"abcdefg\nh", 9).unwrap();
+    let mut memory = Memory::new(&compressed, usize::MAX);
+    let bz_file = open_read(&mut memory);
+
+    // a buffer of one byte only holds the null byte
+    assert_eq!(gets(bz_file, 1).as_deref(), Some(&b""[..]));
+    assert_eq!(gets(bz_file, 0), None);
+
+    assert_eq!(gets(bz_file, 4).as_deref(), Some(&b"abc"[..]));
+    assert_eq!(gets(bz_file, 5).as_deref(), Some(&b"defg"[..]));
+    assert_eq!(gets(bz_file, 5).as_deref(), Some(&b"\n"[..]));
+    assert_eq!(gets(bz_file, 5).as_deref(), Some(&b"h"[..]));
+    assert_eq!(gets(bz_file, 5), None);
+
+    close_read(bz_file);
+}
+
+#[test]
+fn read_bytes() {
+    let compressed = compress_to_vec(b"xyz", 9).unwrap();
+    let mut memory = Memory::new(&compressed, usize::MAX);
+    let bz_file = open_read(&mut memory);
+
+    unsafe {
+        assert_eq!(BZ2_bzgetc(bz_file), b'x' as c_int);
+
+        // one byte can be pushed back, and is read again by every kind of read
+        assert_eq!(BZ2_bzungetc(b'w' as c_int, bz_file), b'w' as c_int);
+        assert_eq!(BZ2_bzungetc(b'v' as c_int, bz_file), -1);
+        assert_eq!(BZ2_bzgetc(bz_file), b'w' as c_int);
+
+        assert_eq!(BZ2_bzungetc(-1, bz_file), -1);
+        assert_eq!(BZ2_bzungetc(0x1ff, bz_file), 0xff);
+
+        let mut buf = [0u8; 10];
+        assert_eq!(BZ2_bzread(bz_file, buf.as_mut_ptr().cast(), 10), 3);
+        assert_eq!(&buf[..3], b"\xffyz");
+
+        assert_eq!(BZ2_bzgetc(bz_file), -1);
+        assert_eq!(BZ2_bzeof(bz_file), 1);
+
+        // pushing back a byte at the end of the file clears the end-of-file indicator
+        assert_eq!(BZ2_bzungetc(b'!' as c_int, bz_file), b'!' as c_int);
+        assert_eq!(BZ2_bzeof(bz_file), 0);
+        assert_eq!(gets(bz_file, 10).as_deref(), Some(&b"!"[..]));
+        assert_eq!(BZ2_bzeof(bz_file), 1);
+        assert_eq!(BZ2_bzgetc(bz_file), -1);
+    }
+
+    close_read(bz_file);
+}
+
+#[test]
+fn write_lines() {
+    let mut memory = Memory::default();
+    let bz_file = open_write(&mut memory);
+
+    unsafe {
+        for line in SAMPLE_TEXT.split_inclusive(|&c| c == b'\n') {
+            let (last, rest) = line.split_last().unwrap();
+
+            let rest = std::ffi::CString::new(rest).unwrap();
+            assert_eq!(
+                BZ2_bzputs(bz_file, rest.as_ptr()),
+                rest.as_bytes().len() as c_int
+            );
+            assert_eq!(BZ2_bzputc(bz_file, *last as c_int), *last as c_int);
+        }
+
+        // only the lowest 8 bits are written
+        assert_eq!(BZ2_bzputc(bz_file, 0x121), 0x21);
+        assert_eq!(BZ2_bzputs(bz_file, b"\0".as_ptr().cast()), 0);
+        assert_eq!(BZ2_bzputs(bz_file, core::ptr::null()), -1);
+    }
+
+    close_write(bz_file);
+
+    let mut expected = SAMPLE_TEXT.to_vec();
+    expected.push(b'!');
+    assert_eq!(decompress_to_vec(&memory.data).unwrap(), expected);
+}
+
+#[test]
+fn read_and_write_mode() {
+    let mut memory = Memory::default();
+    let bz_file = open_write(&mut memory);
+
+    // nothing can be read from a file that is opened for writing
+    unsafe {
+        assert_eq!(BZ2_bzgetc(bz_file), -1);
+        assert_eq!(gets(bz_file, 10), None);
+        assert_eq!(BZ2_bzungetc(b'a' as c_int, bz_file), -1);
+        assert_eq!(BZ2_bzeof(bz_file), 0);
+    }
+
+    close_write(bz_file);
+
+    let compressed = compress_to_vec(b"abc", 9).unwrap();
+    let mut memory = Memory::new(&compressed, usize::MAX);
+    let bz_file = open_read(&mut memory);
+
+    // and nothing can be written to a file that is opened for reading
+    unsafe {
+        assert_eq!(BZ2_bzputc(bz_file, b'a' as c_int), -1);
+        assert_eq!(BZ2_bzputs(bz_file, b"a\0".as_ptr().cast()), -1);
+    }
+
+    close_read(bz_file);
+}
+
+#[test]
+fn null_file() {
+    let null = core::ptr::null_mut();
+
+    unsafe {
+        assert_eq!(BZ2_bzgetc(null), -1);
+        assert_eq!(BZ2_bzungetc(b'a' as c_int, null), -1);
+        assert_eq!(BZ2_bzeof(null), 0);
+        assert_eq!(BZ2_bzputc(null, b'a' as c_int), -1);
+        assert_eq!(BZ2_bzputs(null, b"a\0".as_ptr().cast()), -1);
+
+        let mut buf = [0 as c_char; 10];
+        assert!(BZ2_bzgets(null, buf.as_mut_ptr(), 10).is_null());
+    }
+}
+
+type PrintfFn = unsafe extern "C" fn(*mut BZFILE, *const c_char, ...) -> c_int;
+
+// the C code only passes the `BZFILE` pointer on
+#[allow(improper_ctypes)]
+extern "C" {
+    /// Calls `BZ2_bzvprintf` from C, see `bzprintf.c`.
+    fn test_bzprintf(b: *mut BZFILE, format: *const c_char, ...) -> c_int;
+}
+
+fn check_printf(printf: PrintfFn) {
+    let mut memory = Memory::default();
+    let bz_file = open_write(&mut memory);
+
+    let long = vec![b'a'; 9000];
+    let long = std::ffi::CString::new(long).unwrap();
+
+    unsafe {
+        let n = printf(
+            bz_file,
+            b"%d %s %5.2f|%c\n\0".as_ptr().cast(),
+            42 as c_int,
+            b"bzip2\0".as_ptr(),
+            1.005f64,
+            b'x' as c_int,
+        );
+        assert_eq!(n, 17);
+
+        // output that is longer than 8191 bytes is not written
+        assert_eq!(printf(bz_file, b"%s\0".as_ptr().cast(), long.as_ptr()), 0);
+        assert_eq!(printf(bz_file, b"\0".as_ptr().cast()), 0);
+        assert_eq!(printf(bz_file, core::ptr::null()), -1);
+    }
+
+    close_write(bz_file);
+
+    assert_eq!(
+        decompress_to_vec(&memory.data).unwrap(),
+        b"42 bzip2  1.00|x\n"
+    );
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn write_formatted_va_list() {
+    check_printf(test_bzprintf);
+}
+
+#[cfg(libbz2_rs_sys_printf)]
+#[test]
+fn write_formatted() {
+    check_printf(libbz2_rs_sys::BZ2_bzprintf);
+}
+
+const SAMPLE_TEXT: &[u8] = b"The first line\n\
+    \n\
+    a line after an empty line\n\
+    a line that does not end in a newline";
+
+/// Writes `data` to a temporary file, and opens it for reading with [`BZ2_bzopen`].
+fn open_file(name: &str, data: &[u8]) -> *mut BZFILE {
+    let p = std::env::temp_dir().join(name);
+    std::fs::write(&p, data).unwrap();
+
+    let path = std::ffi::CString::new(p.display().to_string()).unwrap();
+    let bz_file = unsafe { BZ2_bzopen(path.as_ptr(), b"rb\0".as_ptr().cast()) };
+    assert!(!bz_file.is_null());
+
+    bz_file
+}
+
+/// Reads `len` bytes, which must be available.
+fn read_exact(bz_file: *mut BZFILE, len: usize) -> Vec<u8> {
+    let mut buf = vec![0u8; len];
+    let n = unsafe { BZ2_bzread(bz_file, buf.as_mut_ptr().cast(), len as c_int) };
+    assert_eq!(n, len as c_int);
+
+    buf
+}
+
+#[test]
+fn seek_in_file() {
+    // several blocks, so that seeks cross block boundaries
+    let input = SAMPLE1_REF.repeat(3);
+    let compressed = compress_to_vec(&input, 1).unwrap();
+    let bz_file = open_file("seek_in_file.bz2", &compressed);
+
+    unsafe {
+        assert_eq!(BZ2_bztell(bz_file), 0);
+
+        // forward
+        assert_eq!(BZ2_bzseek(bz_file, 150_000, SEEK_SET), 150_000);
+        assert_eq!(BZ2_bztell(bz_file), 150_000);
+        assert!(read_exact(bz_file, 10) == input[150_000..][..10]);
+
+        assert_eq!(BZ2_bzseek(bz_file, 50_000, SEEK_CUR), 200_010);
+        assert!(read_exact(bz_file, 10) == input[200_010..][..10]);
+
+        // backward
+        assert_eq!(BZ2_bzseek(bz_file, -100_000, SEEK_CUR), 100_020);
+        assert!(read_exact(bz_file, 10) == input[100_020..][..10]);
+
+        assert_eq!(BZ2_bzseek(bz_file, 10, SEEK_SET), 10);
+        assert!(read_exact(bz_file, 10) == input[10..][..10]);
+
+        // a byte that was pushed back is part of the position
+        assert_eq!(BZ2_bzgetc(bz_file), input[20] as c_int);
+        assert_eq!(BZ2_bzungetc(b'!' as c_int, bz_file), b'!' as c_int);
+        assert_eq!(BZ2_bztell(bz_file), 20);
+        assert_eq!(BZ2_bzseek(bz_file, 0, SEEK_CUR), 20);
+        assert_eq!(BZ2_bzgetc(bz_file), b'!' as c_int);
+        assert_eq!(BZ2_bzungetc(b'!' as c_int, bz_file), b'!' as c_int);
+        assert_eq!(BZ2_bzseek(bz_file, 1, SEEK_CUR), 21);
+        assert_eq!(BZ2_bzgetc(bz_file), input[21] as c_int);
+
+        // past the end, and back
+        assert_eq!(
+            BZ2_bzseek(bz_file, 1_000_000, SEEK_SET),
+            input.len() as c_long
+        );
+        assert_eq!(BZ2_bzeof(bz_file), 1);
+        assert_eq!(BZ2_bzrewind(bz_file), 0);
+        assert_eq!(BZ2_bzeof(bz_file), 0);
+        assert!(read_exact(bz_file, 10) == input[..10]);
+
+        // invalid seeks leave the position unchanged
+        assert_eq!(BZ2_bzseek(bz_file, -1, SEEK_SET), -1);
+        assert_eq!(BZ2_bzseek(bz_file, -11, SEEK_CUR), -1);
+        assert_eq!(BZ2_bzseek(bz_file, 0, libc::SEEK_END), -1);
+        assert_eq!(BZ2_bztell(bz_file), 10);
+
+        // and do not affect the end-of-file indicator
+        assert_eq!(
+            BZ2_bzseek(bz_file, 1_000_000, SEEK_SET),
+            input.len() as c_long
+        );
+        assert_eq!(BZ2_bzseek(bz_file, 1, SEEK_CUR), input.len() as c_long);
+        assert_eq!(BZ2_bzseek(bz_file, -1, SEEK_SET), -1);
+        assert_eq!(BZ2_bzeof(bz_file), 1);
+        assert_eq!(BZ2_bzgetc(bz_file), -1);
+
+        BZ2_bzclose(bz_file);
+    }
+}
+
+#[test]
+fn seek_after_data_error() {
+    let mut compressed = compress_to_vec(SAMPLE1_REF, 9).unwrap();
+    let len = compressed.len();
+    compressed[len / 2] ^= 0xff;
+    let bz_file = open_file("seek_after_data_error.bz2", &compressed);
+
+    unsafe {
+        let mut buf = vec![0u8; SAMPLE1_REF.len()];
+        assert_eq!(
+            BZ2_bzread(bz_file, buf.as_mut_ptr().cast(), buf.len() as c_int),
+            -1
+        );
+
+        // going back to the start recovers from the error
+        assert_eq!(BZ2_bzrewind(bz_file), 0);
+        assert_eq!(BZ2_bzgetc(bz_file), SAMPLE1_REF[0] as c_int);
+
+        BZ2_bzclose(bz_file);
+    }
+}
+
+#[test]
+fn seek_with_unused_bytes() {
+    use libbz2_rs_sys::{BZ2_bzRead, BZ2_bzReadGetUnused, BZ2_bzReadOpen, BZ_STREAM_END};
+
+    // two concatenated streams, where the second is opened with the bytes that were read past the
+    // end of the first
+    let mut compressed = compress_to_vec(b"first", 9).unwrap();
+    compressed.extend(compress_to_vec(SAMPLE1_REF, 9).unwrap());
+
+    let p = std::env::temp_dir().join("seek_with_unused_bytes.bz2");
+    std::fs::write(&p, &compressed).unwrap();
+    let path = std::ffi::CString::new(p.display().to_string()).unwrap();
+
+    unsafe {
+        let file = libc::fopen(path.as_ptr(), b"rb\0".as_ptr().cast());
+        assert!(!file.is_null());
+
+        let mut bzerror = 0;
+        let null = core::ptr::null_mut();
+        let first = BZ2_bzReadOpen(&mut bzerror, file, 0, 0, null, 0);
+        let mut buf = [0u8; 10];
+        BZ2_bzRead(&mut bzerror, first, buf.as_mut_ptr().cast(), 10);
+        assert_eq!(bzerror, BZ_STREAM_END);
+
+        let mut unused = null;
+        let mut n_unused = 0;
+        BZ2_bzReadGetUnused(&mut bzerror, first, &mut unused, &mut n_unused);
+        assert!(n_unused > 0);
+        let unused = core::slice::from_raw_parts(unused.cast::<u8>(), n_unused as usize).to_vec();
+        BZ2_bzReadClose(&mut bzerror, first);
+
+        let mut unused = unused;
+        let second = BZ2_bzReadOpen(
+            &mut bzerror,
+            file,
+            0,
+            0,
+            unused.as_mut_ptr().cast(),
+            unused.len() as c_int,
+        );
+        assert_eq!(bzerror, BZ_OK);
+
+        assert!(read_exact(second, 1000) == SAMPLE1_REF[..1000]);
+        assert_eq!(BZ2_bzrewind(second), 0);
+        assert!(read_exact(second, 1000) == SAMPLE1_REF[..1000]);
+
+        BZ2_bzReadClose(&mut bzerror, second);
+        libc::fclose(file);
+    }
+}
+
+#[test]
+fn seek_without_seekable_handle() {
+    let compressed = compress_to_vec(SAMPLE1_REF, 9).unwrap();
+    let mut memory = Memory::new(&compressed, usize::MAX);
+    let bz_file = open_read(&mut memory);
+
+    unsafe {
+        // forward seeks only decompress
+        assert_eq!(BZ2_bzseek(bz_file, 1000, SEEK_CUR), 1000);
+        assert!(read_exact(bz_file, 10) == SAMPLE1_REF[1000..][..10]);
+
+        // going back is not possible
+        assert_eq!(BZ2_bzseek(bz_file, 0, SEEK_SET), -1);
+        assert_eq!(BZ2_bzrewind(bz_file), -1);
+
+        // the position is unchanged
+        assert_eq!(BZ2_bztell(bz_file), 1010);
+        assert!(read_exact(bz_file, 10) == SAMPLE1_REF[1010..][..10]);
+    }
+
+    close_read(bz_file);
+
+    // a file that is opened for writing cannot seek, but knows its position
+    let mut memory = Memory::default();
+    let bz_file = open_write(&mut memory);
+
+    unsafe {
+        assert_eq!(BZ2_bzputs(bz_file, b"hello\0".as_ptr().cast()), 5);
+        assert_eq!(BZ2_bztell(bz_file), 5);
+
+        assert_eq!(BZ2_bzseek(bz_file, 10, SEEK_SET), -1);
+        assert_eq!(BZ2_bzrewind(bz_file), -1);
+        assert_eq!(BZ2_bztell(core::ptr::null()), -1);
+    }
+
+    let mut bzerror = 0;
+    let null = core::ptr::null_mut();
+    unsafe { libbz2_rs_sys::BZ2_bzWriteClose(&mut bzerror, bz_file, 1, null, null) };
+}
@@ -1802,3 +1802,115 @@ mod compress_command {
         );
     }
 }
+
+mod index_command {
+    use super::*;
+
+    use libbz2_rs_sys::index::{read_index, scan};
+
+    #[test]
+    fn compress_with_index() {
+        let tmpdir = tempfile::tempdir().unwrap();
+
+        let sample1_ref = tmpdir.path().join("sample1.ref");
+        std::fs::copy("tests/input/quick/sample1.ref", &sample1_ref).unwrap();
+
+        let mut cmd = command();
+        expect_success!(cmd.arg("-z").arg("-1").arg("--index").arg(&sample1_ref), "");
+
+        let sample1_bz2 = tmpdir.path().join("sample1.ref.bz2");
+        let sample1_idx = tmpdir.path().join("sample1.ref.bz2.idx");
+        assert!(!sample1_ref.exists());
+
+        let blocks = read_index(std::fs::File::open(&sample1_idx).unwrap()).unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(
+            blocks,
+            scan(std::fs::File::open(&sample1_bz2).unwrap()).unwrap()
+        );
+
+        // the index of an existing file is the same
+        let copy_bz2 = tmpdir.path().join("copy.bz2");
+        std::fs::copy(&sample1_bz2, &copy_bz2).unwrap();
+
+        let mut cmd = command();
+        expect_success!(
+            cmd.arg("--build-index").arg("-v").arg(&copy_bz2),
+            format!("  {in_file}: 1 blocks\n", in_file = copy_bz2.display(),),
+        );
+
+        assert_eq!(
+            std::fs::read(tmpdir.path().join("copy.bz2.idx")).unwrap(),
+            std::fs::read(&sample1_idx).unwrap(),
+        );
+        assert!(copy_bz2.exists());
+    }
+
+    #[test]
+    fn index_to_stdout() {
+        let mut cmd = command();
+
+        expect_failure!(
+            cmd.arg("-z")
+                .arg("-c")
+                .arg("--index")
+                .arg("tests/input/quick/sample1.ref"),
+            "bzip2: --index can only be used when compressing to files.\n",
+        );
+
+        let mut cmd = command();
+
+        expect_failure!(
+            cmd.arg("--build-index")
+                .arg("-c")
+                .arg("tests/input/quick/sample1.bz2"),
+            "bzip2: --build-index needs input files, and cannot be used with -c.\n",
+        );
+    }
+
+    #[test]
+    fn index_file_exists() {
+        let tmpdir = tempfile::tempdir().unwrap();
+
+        let sample1_bz2 = tmpdir.path().join("sample1.bz2");
+        std::fs::copy("tests/input/quick/sample1.bz2", &sample1_bz2).unwrap();
+
+        let sample1_idx = tmpdir.path().join("sample1.bz2.idx");
+        std::fs::write(&sample1_idx, [1, 2, 3]).unwrap();
+
+        let mut cmd = command();
+
+        expect_failure!(
+            cmd.arg("--build-index").arg(&sample1_bz2),
+            format!(
+                "bzip2: Output file {out_file} already exists.\n",
+                out_file = sample1_idx.display(),
+            ),
+        );
+
+        let mut cmd = command();
+
+        expect_success!(cmd.arg("--build-index").arg("-f").arg(&sample1_bz2), "");
+        assert!(read_index(std::fs::File::open(&sample1_idx).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn input_file_is_not_bzip2_data() {
+        let tmpdir = tempfile::tempdir().unwrap();
+
+        let sample1_bz2 = tmpdir.path().join("sample1.bz2");
+        std::fs::copy("tests/input/quick/sample1.ref", &sample1_bz2).unwrap();
+
+        let mut cmd = command();
+
+        expect_failure!(
+            cmd.arg("--build-index").arg(&sample1_bz2),
+            format!(
+                "bzip2: {in_file}: compressed stream does not start with the bzip2 magic bytes\n",
+                in_file = sample1_bz2.display(),
+            ),
+        );
+
+        assert!(!tmpdir.path().join("sample1.bz2.idx").exists());
+    }
+}